@@ -0,0 +1,251 @@
+// BACKUP
+// The JSON format of `super_v export` and `super_v import`.
+
+// Standard Crates
+use std::{
+    borrow::Cow,
+    num::NonZeroU32,
+    time::{Duration, UNIX_EPOCH},
+};
+
+// External Crates
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// My Crates
+use crate::{
+    common::{ClipboardItem, ImportError, Selection},
+    history::{ClipboardHistory, EntryMeta},
+    image_codec::decode_png,
+};
+
+/// Version written to and accepted from the `version` field of a backup.
+pub const BACKUP_VERSION: u64 = 1;
+
+/// One entry of a backup, as written to and read from JSON.
+#[derive(Serialize, Deserialize)]
+struct BackupEntry<'a> {
+    #[serde(flatten)]
+    content: Content<'a>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    html: Option<Cow<'a, str>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ocr_text: Option<Cow<'a, str>>,
+    copied_at: u64,
+    #[serde(default = "one")]
+    copy_count: NonZeroU32,
+    #[serde(default)]
+    selection: Selection,
+}
+
+/// What an entry holds, told apart by its `kind` field.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum Content<'a> {
+    Text {
+        text: Cow<'a, str>,
+    },
+    Image {
+        width: usize,
+        height: usize,
+        encoding: Encoding,
+        #[serde(with = "base64_data")]
+        data: Cow<'a, [u8]>,
+    },
+}
+
+/// How the `data` of an image entry is stored.
+#[derive(Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Encoding {
+    Rgba,
+    Png,
+}
+
+fn one() -> NonZeroU32 {
+    NonZeroU32::MIN
+}
+
+/// Formats a whole history as a JSON backup, one entry per line, most recent first:
+///
+/// ```text
+/// {"version":1,"entries":[
+/// {"kind":"text","text":"Hello","html":"<b>Hello</b>","copied_at":1700000000000,"copy_count":1,"selection":"clipboard"},
+/// {"kind":"image","width":2,"height":1,"encoding":"rgba","data":"/wAA//8AAP8=","copied_at":1700000000000,"copy_count":1,"selection":"clipboard"}
+/// ]}
+/// ```
+///
+/// Unlike `entry_to_json`, images keep their content: `data` is the base64 of the raw
+/// RGBA pixels (`encoding` `rgba`) or of the PNG a compressed image holds (`png`).
+/// `html` and `ocr_text` are only written when set. `copied_at` is in Unix milliseconds.
+/// Sensitive entries are left out, see `EntryMeta::sensitive`.
+pub fn history_to_json(history: &ClipboardHistory) -> String {
+    let entries: Vec<String> = history
+        .get_items()
+        .iter()
+        .zip(history.get_meta())
+        .filter(|(_, meta)| !meta.sensitive)
+        .map(|(item, meta)| {
+            // Only strings, integers and byte buffers, none of which can fail to serialize
+            serde_json::to_string(&BackupEntry::new(item, meta))
+                .expect("backup entries always serialize")
+        })
+        .collect();
+
+    format!(
+        "{{\"version\":{BACKUP_VERSION},\"entries\":[\n{}\n]}}\n",
+        entries.join(",\n")
+    )
+}
+
+/// Reads the entries of a backup made by `history_to_json`, most recent first.
+///
+/// Every entry is checked before any is returned, so a bad backup imports nothing.
+/// `html`, `ocr_text`, `copy_count` (1) and `selection` (clipboard) may be left out.
+///
+/// # Errors
+///
+/// * `ImportError::Syntax` - If the text is not valid JSON
+/// * `ImportError::Invalid` - If it is JSON but not a backup of this version, e.g. an
+///   entry misses a field, or an image's data doesn't match its size
+pub fn history_from_json(json: &str) -> Result<Vec<(ClipboardItem, EntryMeta)>, ImportError> {
+    let root: Value =
+        serde_json::from_str(json).map_err(|err| ImportError::Syntax(err.to_string()))?;
+
+    // Checked before the entries, whose format may differ in other versions
+    match root.get("version").and_then(Value::as_u64) {
+        Some(BACKUP_VERSION) => {}
+        Some(version) => {
+            return Err(ImportError::Invalid(format!(
+                "version {version} is not supported, expected {BACKUP_VERSION}"
+            )));
+        }
+        None => return Err(invalid("missing \"version\"")),
+    }
+
+    let Some(Value::Array(entries)) = root.get("entries") else {
+        return Err(invalid("missing \"entries\" list"));
+    };
+    entries
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            BackupEntry::deserialize(entry)
+                .map_err(|err| err.to_string())
+                .and_then(BackupEntry::into_entry)
+                .map_err(|err| ImportError::Invalid(format!("entry {index}: {err}")))
+        })
+        .collect()
+}
+
+fn invalid(msg: &str) -> ImportError {
+    ImportError::Invalid(msg.to_string())
+}
+
+fn rgba_len(width: usize, height: usize) -> Option<usize> {
+    width.checked_mul(height)?.checked_mul(4)
+}
+
+impl<'a> BackupEntry<'a> {
+    fn new(item: &'a ClipboardItem, meta: &'a EntryMeta) -> Self {
+        let content = match item {
+            ClipboardItem::Text(text) => Content::Text {
+                text: Cow::Borrowed(text),
+            },
+            ClipboardItem::Image {
+                width,
+                height,
+                bytes,
+            } => Content::Image {
+                width: *width,
+                height: *height,
+                encoding: Encoding::Rgba,
+                data: Cow::Borrowed(bytes),
+            },
+            ClipboardItem::CompressedImage { width, height, png } => Content::Image {
+                width: *width,
+                height: *height,
+                encoding: Encoding::Png,
+                data: Cow::Borrowed(png),
+            },
+        };
+        let copied_at = meta
+            .copied_at
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_millis() as u64)
+            .unwrap_or(0);
+
+        Self {
+            content,
+            html: meta.html.as_deref().map(Cow::Borrowed),
+            ocr_text: meta.ocr_text.as_deref().map(Cow::Borrowed),
+            copied_at,
+            copy_count: NonZeroU32::new(meta.copy_count).unwrap_or(NonZeroU32::MIN),
+            selection: meta.selection,
+        }
+    }
+
+    /// Checks what the JSON types alone can't, i.e. that image data matches its size.
+    fn into_entry(self) -> Result<(ClipboardItem, EntryMeta), String> {
+        let item = match self.content {
+            Content::Text { text } => ClipboardItem::Text(text.into_owned()),
+            Content::Image {
+                width,
+                height,
+                encoding: Encoding::Rgba,
+                data,
+            } => {
+                if Some(data.len()) != rgba_len(width, height) {
+                    return Err(format!("\"data\" is not {width}x{height} pixels"));
+                }
+                ClipboardItem::Image {
+                    width,
+                    height,
+                    bytes: data.into_owned(),
+                }
+            }
+            Content::Image {
+                width,
+                height,
+                encoding: Encoding::Png,
+                data,
+            } => match decode_png(&data) {
+                Some((w, h, _)) if (w, h) == (width, height) => ClipboardItem::CompressedImage {
+                    width,
+                    height,
+                    png: data.into_owned(),
+                },
+                _ => return Err(format!("\"data\" is not a {width}x{height} PNG")),
+            },
+        };
+
+        let mut meta = EntryMeta::new(UNIX_EPOCH + Duration::from_millis(self.copied_at));
+        meta.html = self.html.map(Cow::into_owned);
+        meta.ocr_text = self.ocr_text.map(Cow::into_owned);
+        meta.copy_count = self.copy_count.get();
+        meta.selection = self.selection;
+        Ok((item, meta))
+    }
+}
+
+/// Writes bytes as a standard base64 string, with `=` padding, and reads them back.
+mod base64_data {
+    use std::borrow::Cow;
+
+    use base64::{Engine, engine::general_purpose::STANDARD};
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(data))
+    }
+
+    pub fn deserialize<'de, 'a, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Cow<'a, [u8]>, D::Error> {
+        let text = Cow::<str>::deserialize(deserializer)?;
+        STANDARD
+            .decode(text.as_bytes())
+            .map(Cow::Owned)
+            .map_err(|err| D::Error::custom(format!("\"data\" is not valid base64: {err}")))
+    }
+}
@@ -0,0 +1,355 @@
+// CLI
+// The command line of the `super_v` binary: its arguments and the dispatcher main calls.
+
+// Standard Crates
+use std::{
+    io::{self, Read},
+    path::PathBuf,
+    process,
+};
+
+// External Crates
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+
+// My Crates
+use super_v::{
+    common::{DEFAULT_PROFILE, is_valid_profile, log_path},
+    config::Config,
+    gui::clipboard_gui::InitialTab,
+};
+
+use crate::{
+    add_text, clean_leftovers, clear_history, copy_stdin, daemonize, doctor, export_history,
+    import_history, list_history, mark_sensitive, open_gui, peek_item, print_stats,
+    start_manager_daemon, stop_manager_daemon, watch_history,
+};
+
+/// Every subcommand. A new one is added here and handled in `run`, nowhere else.
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Start the process
+    Start {
+        /// Run in the background, logging next to the socket (super_v[-<PROFILE>].log)
+        #[arg(short, long)]
+        daemon: bool,
+
+        /// Remove history entries older than this many seconds
+        #[arg(long, value_name = "SECONDS")]
+        max_age: Option<u64>,
+
+        /// Drop the oldest entries once their content takes more than this many bytes
+        #[arg(long, value_name = "BYTES")]
+        max_total_bytes: Option<usize>,
+
+        /// Also capture the primary selection (middle-click paste)
+        #[arg(long)]
+        track_primary: bool,
+
+        /// Poll the clipboard less often while nothing is copied, to save power
+        #[arg(long)]
+        adaptive_poll: bool,
+
+        /// Keep history in a SQLite database at this path instead of in memory
+        #[cfg(feature = "sqlite")]
+        #[arg(long, value_name = "PATH")]
+        db: Option<std::path::PathBuf>,
+    },
+
+    /// Stop the running process
+    Stop,
+
+    /// Add text to the history without touching the system clipboard
+    Add {
+        /// Text to add
+        #[arg(required_unless_present = "stdin", conflicts_with = "stdin")]
+        text: Option<String>,
+
+        /// Read the text from stdin instead
+        #[arg(long)]
+        stdin: bool,
+    },
+
+    /// Add what is piped to stdin to the history, like copying it
+    Copy {
+        /// Read raw RGBA pixels instead of text, 4 bytes per pixel
+        #[arg(long, requires_all = ["width", "height"])]
+        image: bool,
+
+        /// Width of the piped image
+        #[arg(long, value_name = "PIXELS", requires = "image")]
+        width: Option<usize>,
+
+        /// Height of the piped image
+        #[arg(long, value_name = "PIXELS", requires = "image")]
+        height: Option<usize>,
+
+        /// Also put it on the system clipboard
+        #[arg(long)]
+        clipboard: bool,
+    },
+
+    /// Print the history every time it changes
+    Watch,
+
+    /// Print the history as JSON Lines, one entry per line, most recent first
+    List {
+        /// Keep running and print every entry copied from now on
+        #[arg(short, long)]
+        follow: bool,
+    },
+
+    /// Print item counts and approximate size as JSON
+    Stats,
+
+    /// Print the most recent history item, or `[Image WxH]` for images
+    Peek {
+        /// Slot to print instead, 0 being the most recent
+        #[arg(long, value_name = "N", default_value_t = 0)]
+        index: usize,
+    },
+
+    /// Keep an entry off disk and drop it when the daemon stops
+    MarkSensitive {
+        /// Slot to mark, 0 being the most recent
+        index: usize,
+    },
+
+    /// Remove every entry from the history and print what is left
+    Clear,
+
+    /// Write the whole history, images included, to a JSON file
+    Export {
+        /// File to write
+        file: PathBuf,
+    },
+
+    /// Add the entries of a JSON file made by `export` to the history
+    Import {
+        /// File to read
+        file: PathBuf,
+
+        /// Clear the history first instead of adding on top of it
+        #[arg(long)]
+        replace: bool,
+    },
+
+    /// Open the GUI
+    OpenGui {
+        #[command(flatten)]
+        paste: PasteArgs,
+    },
+
+    /// Opens the emoji screen
+    OpenEmoji {
+        #[command(flatten)]
+        paste: PasteArgs,
+    },
+
+    /// Check the session, ydotool and the daemon, and report what auto-paste is missing
+    Doctor,
+
+    /// Removes the socket and lock file left behind by a crashed daemon
+    Clean {
+        /// Only print what would be removed
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Inspect the config file
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+
+    /// Print a shell completion script to stdout
+    Completions {
+        /// Shell to generate completions for
+        shell: Shell,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigAction {
+    /// Print where the config file is read from
+    Path,
+}
+
+/// How the GUI pastes a picked entry, overriding the config file.
+#[derive(clap::Args, Debug)]
+pub struct PasteArgs {
+    /// Wait this long after the window closes before pasting
+    #[arg(long, value_name = "MS")]
+    pub paste_delay_ms: Option<u64>,
+
+    /// Only put the picked entry on the clipboard, without pasting it
+    #[arg(long)]
+    pub no_auto_paste: bool,
+
+    /// Log the paste command instead of running it
+    #[arg(long)]
+    pub paste_dry_run: bool,
+
+    /// Paste picked emojis with a space after them
+    #[arg(long)]
+    pub emoji_trailing_space: bool,
+
+    /// Exit as soon as the window closes, once the paste has been sent
+    #[arg(long)]
+    pub oneshot: bool,
+}
+
+/// Command line of the `super_v` binary.
+#[derive(Parser, Debug)]
+#[command(
+    name = "super_v",
+    version = "1.0.0",
+    about = "Clipboard Service that looks like Win11",
+    long_about = None
+)]
+pub struct Args {
+    /// Named history profile. Each profile has its own daemon, socket and lock file
+    #[arg(long, global = true, value_name = "NAME")]
+    #[arg(default_value = DEFAULT_PROFILE, value_parser = parse_profile)]
+    profile: String,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+fn parse_profile(name: &str) -> Result<String, String> {
+    if is_valid_profile(name) {
+        Ok(name.to_string())
+    } else {
+        Err("only letters, digits, '-' and '_' are allowed".into())
+    }
+}
+
+/// Runs the subcommand in `args`.
+pub fn run(args: Args) {
+    let profile = args.profile.as_str();
+    match args.command {
+        Command::Start {
+            daemon,
+            max_age,
+            max_total_bytes,
+            track_primary,
+            adaptive_poll,
+            #[cfg(feature = "sqlite")]
+            db,
+        } => {
+            let mut config = match Config::load() {
+                Ok(config) => config,
+                Err(err) => {
+                    eprintln!("{err}");
+                    process::exit(1);
+                }
+            };
+
+            // Flags win over the config file
+            if max_age.is_some() {
+                config.max_age_secs = max_age;
+            }
+            if max_total_bytes.is_some() {
+                config.max_total_bytes = max_total_bytes;
+            }
+            if track_primary {
+                config.track_primary = true;
+            }
+            if adaptive_poll {
+                config.adaptive_poll = true;
+            }
+            #[cfg(feature = "sqlite")]
+            if db.is_some() {
+                config.db_path = db;
+            }
+
+            // Fork before the Manager exists, so the lock and its pid belong to the daemon
+            if daemon {
+                let log = log_path(profile);
+                println!("Starting in the background, logging to {}", log.display());
+                if let Err(err) = daemonize(&log) {
+                    eprintln!("Could not start in the background: {err}");
+                    process::exit(1);
+                }
+            }
+
+            start_manager_daemon(profile, &config);
+        }
+        Command::Stop => {
+            stop_manager_daemon(profile);
+        }
+        Command::Add { text, stdin } => {
+            let text = if stdin {
+                let mut buf = String::new();
+                if let Err(err) = io::stdin().read_to_string(&mut buf) {
+                    eprintln!("Could not read stdin: {err}");
+                    process::exit(1);
+                }
+                buf
+            } else {
+                text.unwrap_or_default()
+            };
+
+            add_text(profile, text);
+        }
+        Command::Copy {
+            image,
+            width,
+            height,
+            clipboard,
+        } => {
+            let size = width.zip(height).filter(|_| image);
+            copy_stdin(profile, size, clipboard);
+        }
+        Command::Watch => {
+            watch_history(profile);
+        }
+        Command::List { follow } => {
+            list_history(profile, follow);
+        }
+        Command::Stats => {
+            print_stats(profile);
+        }
+        Command::Peek { index } => {
+            peek_item(profile, index);
+        }
+        Command::MarkSensitive { index } => {
+            mark_sensitive(profile, index);
+        }
+        Command::Clear => {
+            clear_history(profile);
+        }
+        Command::Export { file } => {
+            export_history(profile, &file);
+        }
+        Command::Import { file, replace } => {
+            import_history(profile, &file, replace);
+        }
+        Command::OpenGui { paste } => {
+            open_gui(profile, InitialTab::Clipboard, paste);
+        }
+        Command::OpenEmoji { paste } => {
+            open_gui(profile, InitialTab::Emoji, paste);
+        }
+        Command::Config {
+            action: ConfigAction::Path,
+        } => match Config::path() {
+            Some(path) => println!("{}", path.display()),
+            None => {
+                eprintln!("No config directory found. Set HOME or XDG_CONFIG_HOME.");
+                process::exit(1);
+            }
+        },
+        Command::Completions { shell } => {
+            // Built from Args itself, so new subcommands and flags are picked up automatically
+            clap_complete::generate(shell, &mut Args::command(), "super_v", &mut io::stdout());
+        }
+        Command::Doctor => {
+            doctor(profile);
+        }
+        Command::Clean { dry_run } => {
+            clean_leftovers(profile, dry_run);
+        }
+    }
+}
@@ -0,0 +1,331 @@
+// External Crates
+use tracing::debug;
+
+// My Crates
+use crate::{
+    common::{ClientError, ClipboardFilter, ClipboardItem, DEFAULT_PROFILE},
+    history::{ClipboardHistory, EntryMeta, HistoryStats},
+    services::clipboard_ipc_server::{
+        CmdIPC, IPCRequest, IPCResponse, Payload, create_stream, try_read_payload, try_send_payload,
+    },
+};
+
+/// Typed client for a running daemon.
+///
+/// Wraps the socket, the MessagePack framing and the `Payload` enums, so frontends
+/// and scripts don't have to. The daemon answers one command per connection, so every
+/// call opens a fresh one. A `Client` is cheap to create and keeps no connection open.
+///
+/// Edits return the history as it is after the change.
+///
+/// # Example
+/// ```no_run
+/// use super_v::client::Client;
+///
+/// let client = Client::new();
+/// let history = client.snapshot()?;
+/// if !history.is_empty() {
+///     client.promote(history.len() - 1)?;
+/// }
+/// # Ok::<(), super_v::common::ClientError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Client {
+    profile: String,
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Client {
+    /// Client for the daemon of the default profile.
+    pub fn new() -> Self {
+        Self::with_profile(DEFAULT_PROFILE)
+    }
+
+    /// Client for the daemon of `profile`.
+    pub fn with_profile(profile: &str) -> Self {
+        Self {
+            profile: profile.to_string(),
+        }
+    }
+
+    /// The profile this client talks to.
+    pub fn profile(&self) -> &str {
+        &self.profile
+    }
+
+    /// Sends a command and returns the daemon's response as is.
+    ///
+    /// The typed methods below cover every command. This is for new commands the
+    /// client doesn't know yet.
+    ///
+    /// # Errors
+    /// - Returns `ClientError::IPCErr` if the daemon isn't running or the exchange fails.
+    /// - Returns `ClientError::Rejected` if the response carries an error code.
+    /// - Returns `ClientError::UnexpectedResponse` if the daemon sent back a request.
+    pub fn request(&self, cmd: CmdIPC) -> Result<IPCResponse, ClientError> {
+        debug!("Sending {cmd} to profile {}", self.profile);
+
+        let mut stream = create_stream(&self.profile).map_err(ClientError::IPCErr)?;
+        try_send_payload(&mut stream, Payload::Request(IPCRequest { cmd }))
+            .map_err(ClientError::IPCErr)?;
+
+        match try_read_payload(&mut stream).map_err(ClientError::IPCErr)? {
+            Payload::Response(IPCResponse {
+                error: Some(code),
+                message,
+                ..
+            }) => Err(ClientError::Rejected {
+                code: Some(code),
+                message: message.unwrap_or_default(),
+            }),
+            Payload::Response(response) => Ok(response),
+            Payload::Request(_) => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    /// Sends a command that is answered with a snapshot.
+    fn request_history(&self, cmd: CmdIPC) -> Result<ClipboardHistory, ClientError> {
+        match self.request(cmd)? {
+            IPCResponse {
+                history_snapshot: Some(history),
+                ..
+            } => Ok(history),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    /// Sends a command that is answered with a page, where its entries are in the whole
+    /// history, and how many entries are listed in all.
+    fn request_page(
+        &self,
+        cmd: CmdIPC,
+    ) -> Result<(ClipboardHistory, Vec<usize>, usize), ClientError> {
+        match self.request(cmd)? {
+            IPCResponse {
+                history_snapshot: Some(history),
+                total: Some(total),
+                positions: Some(positions),
+                ..
+            } => Ok((history, positions, total)),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    /// Current history.
+    pub fn snapshot(&self) -> Result<ClipboardHistory, ClientError> {
+        self.request_history(CmdIPC::Snapshot)
+    }
+
+    /// A page of the history: the entries at `offset..offset + limit` (0 = most recent),
+    /// and how many entries the whole history has.
+    ///
+    /// The page may be shorter than `limit`, or empty past the end of the history.
+    pub fn snapshot_range(
+        &self,
+        offset: usize,
+        limit: usize,
+    ) -> Result<(ClipboardHistory, usize), ClientError> {
+        self.query(offset, limit, ClipboardFilter::All, false)
+            .map(|(history, _, total)| (history, total))
+    }
+
+    /// A page of the entries `filter` lists, the most copied first with `by_frequency`,
+    /// see `ClipboardHistory::query`.
+    ///
+    /// Returns the page, where each of its entries is in the whole history, and how many
+    /// entries are listed in all.
+    pub fn query(
+        &self,
+        offset: usize,
+        limit: usize,
+        filter: ClipboardFilter,
+        by_frequency: bool,
+    ) -> Result<(ClipboardHistory, Vec<usize>, usize), ClientError> {
+        let cmd = CmdIPC::SnapshotRange {
+            offset,
+            limit,
+            filter,
+            by_frequency,
+        };
+        self.request_page(cmd)
+    }
+
+    /// A page of the entries containing `text`, most recent first, see
+    /// `ClipboardHistory::search`.
+    ///
+    /// Returns the page, where each of its entries is in the whole history, and how many
+    /// entries match in all.
+    pub fn search(
+        &self,
+        text: &str,
+        offset: usize,
+        limit: usize,
+    ) -> Result<(ClipboardHistory, Vec<usize>, usize), ClientError> {
+        self.request_page(CmdIPC::Search {
+            text: text.to_string(),
+            offset,
+            limit,
+        })
+    }
+
+    /// The entry at `index` (0 = most recent) with its metadata, without the rest of
+    /// the history.
+    ///
+    /// # Errors
+    /// Returns `ClientError::Rejected` with `IpcErrorCode::IndexOutOfBounds` if there is
+    /// no entry at `index`, otherwise the same errors as `request`.
+    pub fn get(&self, index: usize) -> Result<(ClipboardItem, EntryMeta), ClientError> {
+        let entry = self.request_history(CmdIPC::Get(index))?;
+        match (entry.get_items().front(), entry.get_meta().front()) {
+            (Some(item), Some(meta)) => Ok((item.clone(), meta.clone())),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    /// Tells the daemon not to capture the next clipboard change if it has this
+    /// `ClipboardItem::clipboard_hash`. Send it right before putting an entry that is in
+    /// history back on the clipboard, and promote the entry with `promote_this`.
+    pub fn expect_next(&self, hash: u64) -> Result<(), ClientError> {
+        self.request(CmdIPC::ExpectNext(hash)).map(|_| ())
+    }
+
+    /// Announces that `item` is about to go back on the clipboard, `raw` being the form
+    /// it reads back as: promotes it, then sends `expect_next` for `raw`.
+    ///
+    /// Call it right before setting the clipboard. If `item` is no longer in history
+    /// nothing is announced, so the daemon captures the paste as a new copy.
+    ///
+    /// # Errors
+    /// Same as `request`, `IpcErrorCode::ItemNotFound` meaning `item` is not in history.
+    pub fn paste_back(&self, item: &ClipboardItem, raw: &ClipboardItem) -> Result<(), ClientError> {
+        self.promote_this(item.clone())?;
+        self.expect_next(raw.clipboard_hash())
+    }
+
+    /// Item counts and size, without transferring the items.
+    pub fn stats(&self) -> Result<HistoryStats, ClientError> {
+        self.request(CmdIPC::Stats)?
+            .message
+            .as_deref()
+            .and_then(|json| serde_json::from_str(json).ok())
+            .ok_or(ClientError::UnexpectedResponse)
+    }
+
+    /// The whole history as a JSON backup, see `backup::history_to_json`.
+    pub fn export(&self) -> Result<String, ClientError> {
+        self.request(CmdIPC::Export)?
+            .message
+            .ok_or(ClientError::UnexpectedResponse)
+    }
+
+    /// Adds the entries of a JSON backup on top of history, or in place of it with
+    /// `replace`. Returns how many entries were imported.
+    ///
+    /// # Errors
+    /// Returns `ClientError::Rejected` with `IpcErrorCode::InvalidImport` if `json` is not a
+    /// valid backup, in which case nothing was imported, otherwise the same errors as `request`.
+    pub fn import(&self, json: &str, replace: bool) -> Result<usize, ClientError> {
+        self.request(CmdIPC::Import {
+            json: json.to_string(),
+            replace,
+        })?
+        .count
+        .ok_or(ClientError::UnexpectedResponse)
+    }
+
+    /// Adds an item to the top of history without touching the system clipboard.
+    pub fn add(&self, item: ClipboardItem) -> Result<ClipboardHistory, ClientError> {
+        self.request_history(CmdIPC::Add(item))
+    }
+
+    /// Adds an item to the top of history and has the daemon put it on the system
+    /// clipboard, where it stays after this process exits.
+    ///
+    /// # Errors
+    /// Returns `ClientError::Rejected` with `IpcErrorCode::ClipboardUnavailable` if the
+    /// daemon could not write the clipboard, in which case the item was still added,
+    /// otherwise the same errors as `request`.
+    pub fn copy(&self, item: ClipboardItem) -> Result<ClipboardHistory, ClientError> {
+        self.request_history(CmdIPC::Copy(item))
+    }
+
+    /// Moves the item at `index` to the top.
+    pub fn promote(&self, index: usize) -> Result<ClipboardHistory, ClientError> {
+        self.request_history(CmdIPC::Promote(index))
+    }
+
+    /// Moves `item` to the top, wherever it is now.
+    pub fn promote_this(&self, item: ClipboardItem) -> Result<ClipboardHistory, ClientError> {
+        self.request_history(CmdIPC::PromoteThis(item))
+    }
+
+    /// Moves the item at `from` so it ends up at `to`.
+    pub fn move_item(&self, from: usize, to: usize) -> Result<ClipboardHistory, ClientError> {
+        self.request_history(CmdIPC::Move { from, to })
+    }
+
+    /// Marks the item at `index` as sensitive: it is never written to disk and is dropped
+    /// when the daemon stops.
+    pub fn mark_sensitive(&self, index: usize) -> Result<ClipboardHistory, ClientError> {
+        self.request_history(CmdIPC::MarkSensitive(index))
+    }
+
+    /// Deletes the item at `index`.
+    pub fn delete(&self, index: usize) -> Result<ClipboardHistory, ClientError> {
+        self.request_history(CmdIPC::Delete(index))
+    }
+
+    /// Deletes the items at `start..end`.
+    pub fn delete_range(&self, start: usize, end: usize) -> Result<ClipboardHistory, ClientError> {
+        self.request_history(CmdIPC::DeleteRange { start, end })
+    }
+
+    /// Deletes `item`, wherever it is now. Only the most recent copy goes if it is in
+    /// history more than once.
+    pub fn delete_this(&self, item: ClipboardItem) -> Result<ClipboardHistory, ClientError> {
+        self.request_history(CmdIPC::DeleteThis(item))
+    }
+
+    /// Deletes the entry at `index` if it is `item`, otherwise the most recent copy of
+    /// `item`. Picks the right one of several equal entries, see `CmdIPC::DeleteAt`.
+    pub fn delete_at(
+        &self,
+        index: usize,
+        item: ClipboardItem,
+    ) -> Result<ClipboardHistory, ClientError> {
+        self.request_history(CmdIPC::DeleteAt { pos: index, item })
+    }
+
+    /// Deletes everything.
+    pub fn clear(&self) -> Result<ClipboardHistory, ClientError> {
+        self.request_history(CmdIPC::Clear)
+    }
+
+    /// Deletes every entry `filter` lists, in one step `undo` reverts. Returns how many
+    /// entries went.
+    pub fn clear_matching(&self, filter: ClipboardFilter) -> Result<usize, ClientError> {
+        self.request(CmdIPC::ClearMatching(filter))?
+            .count
+            .ok_or(ClientError::UnexpectedResponse)
+    }
+
+    /// Reverts the most recent delete or clear. See `ClipboardHistory::undo`.
+    pub fn undo(&self) -> Result<ClipboardHistory, ClientError> {
+        self.request_history(CmdIPC::Undo)
+    }
+
+    /// Applies several edits as one change. If any fails, none are applied.
+    pub fn batch(&self, cmds: Vec<CmdIPC>) -> Result<ClipboardHistory, ClientError> {
+        self.request_history(CmdIPC::Batch(cmds))
+    }
+
+    /// Asks the daemon to shut down.
+    pub fn stop(&self) -> Result<(), ClientError> {
+        self.request(CmdIPC::Stop).map(|_| ())
+    }
+}
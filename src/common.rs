@@ -1,14 +1,273 @@
 // Standard Crates
 #[allow(unused)]
-use std::{error::Error, fmt};
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    env,
+    error::Error,
+    fmt,
+    hash::{DefaultHasher, Hash, Hasher},
+    io,
+    path::PathBuf,
+    sync::{Arc, Mutex, PoisonError},
+};
 
 // External Crates
-use arboard::Clipboard;
+use arboard::{Clipboard, GetExtLinux, ImageData, LinuxClipboardKind};
 use serde::{Deserialize, Serialize};
 
+// My Crates
+use crate::{
+    image_codec::{decode_png, encode_png},
+    services::clipboard_ipc_server::IpcErrorCode,
+};
+
 // ------------------------- Constants --------------------------------
-pub const SOCKET_PATH: &str = "/tmp/super_v.sock";
-pub const LOCK_PATH: &str = "/tmp/super_v.lock";
+/// Profile used when none is given. Keeps the original socket and lock paths.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Room a request needs besides the items it carries: the command and the JSON around
+/// a backup's entries.
+pub const PAYLOAD_OVERHEAD_BYTES: usize = 64 * 1024;
+
+/// Room a backup entry needs besides its content: its other fields, OCR text included.
+pub const ENTRY_OVERHEAD_BYTES: usize = 1024;
+
+/// Environment variable that overrides the socket path, see `socket_path`.
+pub const SOCKET_ENV: &str = "SUPER_V_SOCKET";
+
+/// Environment variable that overrides the lock file path, see `lock_path`.
+pub const LOCK_ENV: &str = "SUPER_V_LOCK";
+// --------------------------------------------------------------------
+
+/// Largest request the daemon reads, so a peer can't make it buffer more than a full
+/// history, see `try_read_payload_within`.
+///
+/// Sized for an `Import` of `history_size` entries of the largest kind, the biggest
+/// request there is. In a backup an image is base64, and a text and its HTML may have
+/// every character escaped as `\u00XX`, six bytes each. Other requests carry items as
+/// MessagePack binary, which is barely bigger than the item.
+///
+/// # Arguments
+///
+/// * `max_text_bytes` - Largest text the daemon stores
+/// * `max_image_bytes` - Largest image the daemon stores, as raw pixels
+/// * `history_size` - Most entries the history keeps
+pub fn max_request_bytes(
+    max_text_bytes: usize,
+    max_image_bytes: usize,
+    history_size: usize,
+) -> usize {
+    let image = max_image_bytes.div_ceil(3).saturating_mul(4);
+    let text = max_text_bytes.saturating_mul(2 * 6);
+    image
+        .max(text)
+        .saturating_add(ENTRY_OVERHEAD_BYTES)
+        .saturating_mul(history_size.max(1))
+        .saturating_add(PAYLOAD_OVERHEAD_BYTES)
+}
+
+// --------------------------- Profiles ------------------------------
+/// Checks that a profile name is safe to use in a file name.
+///
+/// Only ASCII letters, digits, `-` and `_` are allowed.
+pub fn is_valid_profile(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Path of the IPC socket for the given profile.
+///
+/// The default profile uses `$XDG_RUNTIME_DIR/super_v.sock`, others
+/// `$XDG_RUNTIME_DIR/super_v-<profile>.sock`. Without `XDG_RUNTIME_DIR` the files go in
+/// `/tmp` with the uid in the name, e.g. `/tmp/super_v-1000.sock`, so users don't collide.
+///
+/// `SUPER_V_SOCKET` overrides the path. Named profiles then get `-<profile>` added to
+/// its file name, e.g. `/run/clip.sock` becomes `/run/clip-work.sock`.
+pub fn socket_path(profile: &str) -> PathBuf {
+    env_path(SOCKET_ENV, profile).unwrap_or_else(|| profile_path(profile, "sock"))
+}
+
+/// Path of the process lock file for the given profile.
+///
+/// Lives next to the socket, see `socket_path`. `SUPER_V_LOCK` overrides it the same
+/// way `SUPER_V_SOCKET` overrides the socket.
+pub fn lock_path(profile: &str) -> PathBuf {
+    env_path(LOCK_ENV, profile).unwrap_or_else(|| profile_path(profile, "lock"))
+}
+
+/// Path of the log file a backgrounded daemon writes to, for the given profile.
+///
+/// Lives next to the socket, see `socket_path`.
+pub fn log_path(profile: &str) -> PathBuf {
+    profile_path(profile, "log")
+}
+
+/// The path in `var` if it is set and not empty, with the profile added for named profiles.
+fn env_path(var: &str, profile: &str) -> Option<PathBuf> {
+    let path = PathBuf::from(env::var_os(var).filter(|path| !path.is_empty())?);
+    if profile == DEFAULT_PROFILE {
+        return Some(path);
+    }
+
+    // clip.sock -> clip-work.sock
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push(format!("-{profile}"));
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    Some(path.with_file_name(name))
+}
+
+fn profile_path(profile: &str, extension: &str) -> PathBuf {
+    // XDG_RUNTIME_DIR is private to the user already. /tmp is shared, so add the uid.
+    let prefix = match env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir).join("super_v"),
+        // SAFETY: getuid has no preconditions and cannot fail
+        None => PathBuf::from(format!("/tmp/super_v-{}", unsafe { libc::getuid() })),
+    };
+
+    let mut name = prefix.into_os_string();
+    if profile != DEFAULT_PROFILE {
+        name.push(format!("-{profile}"));
+    }
+    name.push(format!(".{extension}"));
+    PathBuf::from(name)
+}
+// --------------------------------------------------------------------
+
+// --------------------------- Display -------------------------------
+/// Shortens `text` to its first `max_chars` characters, adding `...` if anything was cut.
+///
+/// Counts characters rather than bytes, so emoji and CJK text are never split
+/// in the middle of a character.
+pub fn preview_text(text: &str, max_chars: usize) -> Cow<'_, str> {
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => Cow::Owned(format!("{}...", &text[..end])),
+        None => Cow::Borrowed(text),
+    }
+}
+
+/// Returns true for text that is a single link, e.g. `https://example.com/page`.
+///
+/// Only checks the scheme (or a leading `www.`) and that there is no whitespace
+/// inside, which is enough to pick an icon for it.
+pub fn looks_like_url(text: &str) -> bool {
+    const PREFIXES: [&str; 6] = [
+        "http://", "https://", "ftp://", "file://", "mailto:", "www.",
+    ];
+
+    let text = text.trim();
+    !text.contains(char::is_whitespace)
+        && PREFIXES
+            .iter()
+            .any(|prefix| text.len() > prefix.len() && text.starts_with(prefix))
+}
+
+/// Parses text that is a CSS color: `#rgb`, `#rrggbb`, `#rrggbbaa`, or `rgb(...)` /
+/// `rgba(...)` with comma or space separated channels, e.g. `rgb(255, 136, 0)` or
+/// `rgb(100% 50% 0% / 0.5)`.
+///
+/// # Returns
+///
+/// * `Some((r, g, b))` - The color, alpha dropped
+/// * `None` - If the text is anything else
+pub fn parse_color(text: &str) -> Option<(u8, u8, u8)> {
+    let text = text.trim();
+    match text.strip_prefix('#') {
+        Some(digits) => parse_hex_digits(digits),
+        None => parse_rgb_function(text),
+    }
+}
+
+/// The part of a hex color after the `#`.
+fn parse_hex_digits(digits: &str) -> Option<(u8, u8, u8)> {
+    if !digits.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let channel = |range| u8::from_str_radix(&digits[range], 16).ok();
+    match digits.len() {
+        // Each digit doubled, #f80 is #ff8800
+        3 => {
+            let [r, g, b] = [0, 1, 2].map(|i| channel(i..i + 1).map(|value| value * 17));
+            Some((r?, g?, b?))
+        }
+        6 | 8 => Some((channel(0..2)?, channel(2..4)?, channel(4..6)?)),
+        _ => None,
+    }
+}
+
+/// `rgb(r, g, b)` or `rgba(r, g, b, a)`, channels as 0-255 or percentages.
+fn parse_rgb_function(text: &str) -> Option<(u8, u8, u8)> {
+    // Only the prefix is compared, a long text isn't copied just to lowercase it
+    let args = ["rgba(", "rgb("]
+        .into_iter()
+        .find_map(|prefix| {
+            text.get(..prefix.len())
+                .filter(|head| head.eq_ignore_ascii_case(prefix))
+                .map(|_| &text[prefix.len()..])
+        })?
+        .strip_suffix(')')?;
+
+    let parts: Vec<&str> = args
+        .split(|c: char| c == ',' || c == '/' || c.is_whitespace())
+        .filter(|part| !part.is_empty())
+        .collect();
+    // The alpha is checked so `rgb(1, 2, 3, junk)` isn't a color, then dropped
+    if let Some(alpha) = parts.get(3) {
+        alpha.trim_end_matches('%').parse::<f64>().ok()?;
+    }
+    let [r, g, b] = match parts.len() {
+        3 | 4 => [parts[0], parts[1], parts[2]].map(parse_rgb_channel),
+        _ => return None,
+    };
+    Some((r?, g?, b?))
+}
+
+/// One channel of `rgb(...)`: `0`-`255`, or `0%`-`100%`.
+fn parse_rgb_channel(part: &str) -> Option<u8> {
+    match part.strip_suffix('%') {
+        Some(percent) => {
+            let percent: f64 = percent.parse().ok()?;
+            (0.0..=100.0)
+                .contains(&percent)
+                .then(|| (percent * 255.0 / 100.0).round() as u8)
+        }
+        None => part.parse().ok(),
+    }
+}
+// --------------------------------------------------------------------
+
+// --------------------------- Filtering ------------------------------
+/// Share of characters in a text that may be unprintable before it counts as binary.
+const MAX_UNPRINTABLE_RATIO: f64 = 0.1;
+
+/// Returns false for text that looks like a binary blob rather than something typed.
+///
+/// Some apps put their own binary formats on the clipboard, which come through as
+/// text full of control characters and U+FFFD replacements for invalid UTF-8. Text
+/// with a NUL, or where more than a tenth of the characters are unprintable, is
+/// rejected. Tabs and line breaks count as printable.
+pub fn is_storable_text(text: &str) -> bool {
+    let mut total = 0usize;
+    let mut unprintable = 0usize;
+    for ch in text.chars() {
+        if ch == '\0' {
+            return false;
+        }
+        total += 1;
+        if ch == char::REPLACEMENT_CHARACTER
+            || (ch.is_control() && !matches!(ch, '\n' | '\r' | '\t'))
+        {
+            unprintable += 1;
+        }
+    }
+    unprintable as f64 <= total as f64 * MAX_UNPRINTABLE_RATIO
+}
 // --------------------------------------------------------------------
 
 // --------------------------- Errors --------------------------------
@@ -21,14 +280,26 @@ pub enum ClipboardError {
 
     /// Returned when trying to access an item out of bounds.
     IndexOutOfBound,
+
+    /// Returned by `undo` when no removal is left to revert.
+    NothingToUndo,
+
+    /// Returned when a clipboard backend can't be opened or written, with its message.
+    Backend(String),
 }
 
 /// Error Type for Clipboard Manager Daemon
-#[derive(Debug, PartialEq)]
+#[derive(Debug)]
 #[allow(unused)]
 pub enum DaemonError {
     /// Returned when attempting to spawn Manager but an instance is already running.
-    ManagerMultiSpawn,
+    /// `pid` is the one the running instance wrote to the lock file, if readable.
+    ManagerMultiSpawn {
+        pid: Option<u32>,
+    },
+
+    /// Returned when the lock file can't be opened, e.g. its directory isn't writable.
+    LockFileOpen(io::Error),
 
     IPCErr(IPCServerError),
 }
@@ -42,9 +313,73 @@ pub enum IPCServerError {
     BindError(String),
     SendError(String),
     RecieveError(String),
+
+    /// Returned when a length prefix exceeds the limit of the read, see `max_request_bytes`.
+    /// The daemon answers such a request with `IpcErrorCode::RequestTooLarge`.
+    PayloadTooLarge {
+        len: usize,
+        limit: usize,
+    },
+}
+
+/// Error Type for the IPC `Client`
+#[derive(Debug, PartialEq)]
+#[allow(unused)]
+pub enum ClientError {
+    /// Returned when the daemon can't be reached or the exchange fails midway.
+    IPCErr(IPCServerError),
+
+    /// Returned when the daemon refused the command.
+    /// `code` says why, `message` is only meant for display.
+    Rejected {
+        code: Option<IpcErrorCode>,
+        message: String,
+    },
+
+    /// Returned when the daemon answered with something other than what was asked for.
+    UnexpectedResponse,
+}
+
+/// Error Type for loading the config file
+#[derive(Debug, PartialEq)]
+#[allow(unused)]
+pub enum ConfigError {
+    /// Returned when the config file exists but can't be read.
+    ReadError(String),
+
+    /// Returned when the config file is not valid TOML or has bad values.
+    ParseError(String),
+}
+
+/// Error Type for reading a JSON backup, see `backup::history_from_json`
+#[derive(Debug, PartialEq)]
+#[allow(unused)]
+pub enum ImportError {
+    /// Returned when the text is not valid JSON.
+    Syntax(String),
+
+    /// Returned when the JSON is not a backup, e.g. an entry misses a field.
+    Invalid(String),
 }
 
 // Displays for the Errors
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::IPCErr(ipc_error) => {
+                write!(f, "IPC Error: {}", ipc_error)
+            }
+            ClientError::Rejected { code, message } => match code {
+                Some(code) => write!(f, "Daemon rejected the command ({:?}): {}", code, message),
+                None => write!(f, "Daemon rejected the command: {}", message),
+            },
+            ClientError::UnexpectedResponse => {
+                write!(f, "Unexpected response from the daemon.")
+            }
+        }
+    }
+}
+
 impl fmt::Display for ClipboardError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -57,6 +392,8 @@ impl fmt::Display for ClipboardError {
             ClipboardError::IndexOutOfBound => {
                 write!(f, "Item position not found or out-of-bounds.")
             }
+            ClipboardError::NothingToUndo => write!(f, "Nothing to undo."),
+            ClipboardError::Backend(msg) => write!(f, "Clipboard backend failed: {msg}"),
         }
     }
 }
@@ -64,9 +401,15 @@ impl fmt::Display for ClipboardError {
 impl fmt::Display for DaemonError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            DaemonError::ManagerMultiSpawn => {
+            DaemonError::ManagerMultiSpawn { pid: Some(pid) } => {
+                write!(f, "An instance of the Manager is already open (pid {pid}).")
+            }
+            DaemonError::ManagerMultiSpawn { pid: None } => {
                 write!(f, "An instance of the Manager is already open.")
             }
+            DaemonError::LockFileOpen(io_error) => {
+                write!(f, "Could not open lock file: {}", io_error)
+            }
             DaemonError::IPCErr(ipc_error) => {
                 write!(f, "IPC Error: {}", ipc_error)
             }
@@ -92,17 +435,180 @@ impl fmt::Display for IPCServerError {
             IPCServerError::FileNotFound => {
                 write!(f, "Sock file is missing?")
             }
+            IPCServerError::PayloadTooLarge { len, limit } => {
+                write!(
+                    f,
+                    "Payload of {} bytes exceeds the limit of {} bytes",
+                    len, limit
+                )
+            }
+        }
+    }
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::Syntax(string) => write!(f, "Not valid JSON: {}", string),
+            ImportError::Invalid(string) => write!(f, "Not a super_v backup: {}", string),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::ReadError(string) => {
+                write!(f, "Could not read config: {}", string)
+            }
+            ConfigError::ParseError(string) => {
+                write!(f, "Invalid config: {}", string)
+            }
         }
     }
 }
 
 // Implement the structs as Errors
+impl Error for ClientError {}
 impl Error for ClipboardError {}
+impl Error for ConfigError {}
 impl Error for DaemonError {}
+impl Error for ImportError {}
 impl Error for IPCServerError {}
 // -------------------------------------------------------------------
 
 // ----------------------- Clipboard Item ----------------------------
+/// How the whitespace of copied text is cleaned up before it is stored.
+///
+/// * **Off** - Stored exactly as copied.
+/// * **Trim** - Leading and trailing whitespace, newlines included, is removed.
+/// * **Collapse** - Trimmed, and every run of whitespace inside, newlines included,
+///   becomes a single space. Multi-line text ends up on one line.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TextNormalization {
+    #[default]
+    Off,
+    Trim,
+    Collapse,
+}
+
+/// The X11/Wayland selection an entry was copied from.
+///
+/// * **Clipboard** - Explicit copies (Ctrl+C), pasted with Ctrl+V.
+/// * **Primary** - Selected text, pasted with a middle click. Only read when
+///   `track_primary` is on.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Selection {
+    #[default]
+    Clipboard,
+    Primary,
+}
+
+impl Selection {
+    /// Lowercase name, as in the config file and `super_v list` output.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Selection::Clipboard => "clipboard",
+            Selection::Primary => "primary",
+        }
+    }
+}
+
+/// Where the GUI window sits on screen when it is placed with the layer shell.
+///
+/// * **Center** - The middle of the screen (default).
+/// * **Top** / **Bottom** - Centered along the top or bottom edge.
+/// * **TopLeft** / **TopRight** / **BottomLeft** / **BottomRight** - In a corner.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum PopupAnchor {
+    #[default]
+    Center,
+    Top,
+    Bottom,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl PopupAnchor {
+    /// Screen edges the window is anchored to, as `[top, bottom, left, right]`.
+    /// No edge at all centers it.
+    pub fn edges(&self) -> [bool; 4] {
+        match self {
+            PopupAnchor::Center => [false, false, false, false],
+            PopupAnchor::Top => [true, false, false, false],
+            PopupAnchor::Bottom => [false, true, false, false],
+            PopupAnchor::TopLeft => [true, false, true, false],
+            PopupAnchor::TopRight => [true, false, false, true],
+            PopupAnchor::BottomLeft => [false, true, true, false],
+            PopupAnchor::BottomRight => [false, true, false, true],
+        }
+    }
+}
+
+/// Which kinds of entries are merged with an earlier copy when copied again.
+///
+/// * **All** - Text and images (default).
+/// * **Text** - Only text. Every image copy is a new entry, even a byte-identical one.
+/// * **Images** - Only images. Every text copy is a new entry.
+/// * **Off** - Nothing, every copy is a new entry.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DedupScope {
+    #[default]
+    All,
+    Text,
+    Images,
+    Off,
+}
+
+impl DedupScope {
+    /// Whether a copy of `item` is merged with an earlier copy of it.
+    pub fn covers(&self, item: &ClipboardItem) -> bool {
+        match self {
+            DedupScope::All => true,
+            DedupScope::Text => !item.is_image(),
+            DedupScope::Images => item.is_image(),
+            DedupScope::Off => false,
+        }
+    }
+}
+
+/// Which kinds of entries a listing holds, see `CmdIPC::SnapshotRange`.
+///
+/// * **All** - Text and images (default).
+/// * **Text** - Only text.
+/// * **Images** - Only images.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum ClipboardFilter {
+    #[default]
+    All,
+    Text,
+    Images,
+}
+
+impl ClipboardFilter {
+    /// Every filter, in the order the GUI shows them.
+    pub const ALL: [ClipboardFilter; 3] = [
+        ClipboardFilter::All,
+        ClipboardFilter::Text,
+        ClipboardFilter::Images,
+    ];
+
+    /// Returns true if the item should be listed under this filter.
+    pub fn matches(&self, item: &ClipboardItem) -> bool {
+        match self {
+            ClipboardFilter::All => true,
+            ClipboardFilter::Text => matches!(item, ClipboardItem::Text(_)),
+            ClipboardFilter::Images => item.is_image(),
+        }
+    }
+}
+
 /// Represents an item that can be stored in the clipboard.
 ///
 /// This enum supports both text and image data types, allowing the clipboard
@@ -117,8 +623,133 @@ pub enum ClipboardItem {
     Image {
         width: usize,
         height: usize,
+        // As one binary blob, a list of numbers takes up to twice the bytes in MessagePack
+        #[serde(with = "serde_bytes")]
         bytes: Vec<u8>,
     },
+
+    /// Image content with dimensions and PNG-encoded bytes.
+    /// Made by `ClipboardItem::compress`, read back with `ClipboardItem::pixels`.
+    CompressedImage {
+        width: usize,
+        height: usize,
+        #[serde(with = "serde_bytes")]
+        png: Vec<u8>,
+    },
+}
+
+impl ClipboardItem {
+    /// Short description of the item's type, without its contents.
+    ///
+    /// Safe to log, unlike `Display` which prints the whole text.
+    pub fn kind(&self) -> String {
+        match self {
+            ClipboardItem::Text(_) => "Text".to_string(),
+            ClipboardItem::Image { width, height, .. }
+            | ClipboardItem::CompressedImage { width, height, .. } => {
+                format!("Image {width}x{height}")
+            }
+        }
+    }
+
+    /// The text cut to `max_chars` (see `preview_text`), or `kind` for images.
+    pub fn preview(&self, max_chars: usize) -> Cow<'_, str> {
+        match self {
+            ClipboardItem::Text(text) => preview_text(text, max_chars),
+            item => Cow::Owned(item.kind()),
+        }
+    }
+
+    /// Returns true for text that is empty or only whitespace. Such items are never added.
+    pub fn is_empty_text(&self) -> bool {
+        match self {
+            ClipboardItem::Text(text) => text.trim().is_empty(),
+            ClipboardItem::Image { .. } | ClipboardItem::CompressedImage { .. } => false,
+        }
+    }
+
+    /// Bytes of content the item holds: the UTF-8 length of text, the buffer of an image.
+    pub fn size_bytes(&self) -> usize {
+        match self {
+            ClipboardItem::Text(text) => text.len(),
+            ClipboardItem::Image { bytes, .. } => bytes.len(),
+            ClipboardItem::CompressedImage { png, .. } => png.len(),
+        }
+    }
+
+    /// Returns true for both raw and compressed images.
+    pub fn is_image(&self) -> bool {
+        !matches!(self, ClipboardItem::Text(_))
+    }
+
+    /// PNG-compresses a raw image. Anything else, or an image that fails to encode
+    /// or doesn't get smaller, is returned unchanged.
+    pub fn compress(self) -> Self {
+        match self {
+            ClipboardItem::Image {
+                width,
+                height,
+                bytes,
+            } => match encode_png(width, height, &bytes) {
+                Some(png) if png.len() < bytes.len() => {
+                    ClipboardItem::CompressedImage { width, height, png }
+                }
+                _ => ClipboardItem::Image {
+                    width,
+                    height,
+                    bytes,
+                },
+            },
+            item => item,
+        }
+    }
+
+    /// Cleans up the whitespace of a text item as `mode` says. Images are returned unchanged.
+    pub fn normalize(self, mode: TextNormalization) -> Self {
+        match (self, mode) {
+            (ClipboardItem::Text(text), TextNormalization::Trim) => {
+                ClipboardItem::Text(text.trim().to_string())
+            }
+            (ClipboardItem::Text(text), TextNormalization::Collapse) => {
+                ClipboardItem::Text(text.split_whitespace().collect::<Vec<_>>().join(" "))
+            }
+            (item, _) => item,
+        }
+    }
+
+    /// Raw RGBA pixels of an image, decoding it first if it is compressed.
+    ///
+    /// # Returns
+    ///
+    /// * `Some(pixels)` - Borrowed for a raw image, decoded for a compressed one
+    /// * `None` - For text, or a compressed image that fails to decode to its declared size
+    pub fn pixels(&self) -> Option<Cow<'_, [u8]>> {
+        match self {
+            ClipboardItem::Text(_) => None,
+            ClipboardItem::Image { bytes, .. } => Some(Cow::Borrowed(bytes)),
+            ClipboardItem::CompressedImage { width, height, png } => match decode_png(png)? {
+                (w, h, rgba) if w == *width && h == *height => Some(Cow::Owned(rgba)),
+                _ => None,
+            },
+        }
+    }
+
+    /// Hash of the item as it reads back from the clipboard, for `CmdIPC::ExpectNext`.
+    ///
+    /// A compressed image hashes like the raw image it decodes to, so this decodes it.
+    /// Only stable within one build, so don't store it.
+    pub fn clipboard_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        match self {
+            ClipboardItem::Text(text) => text.hash(&mut hasher),
+            ClipboardItem::Image { width, height, .. }
+            | ClipboardItem::CompressedImage { width, height, .. } => {
+                (width, height).hash(&mut hasher);
+                self.pixels().hash(&mut hasher);
+            }
+        }
+        hasher.finish()
+    }
 }
 
 // Make the item printable
@@ -126,7 +757,10 @@ impl fmt::Display for ClipboardItem {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             ClipboardItem::Text(s) => write!(f, "{}", s.replace('\n', "\r\n")),
-            ClipboardItem::Image { width, height, .. } => write!(f, "[Image: {width}x{height}]"),
+            ClipboardItem::Image { width, height, .. }
+            | ClipboardItem::CompressedImage { width, height, .. } => {
+                write!(f, "[Image: {width}x{height}]")
+            }
         }
     }
 }
@@ -144,6 +778,14 @@ pub trait GetItem {
     /// * `Ok(ClipboardItem)` - The clipboard content as either Text or Image
     /// * `Err(ClipboardErr::ClipboardEmpty)` - If the clipboard is empty
     fn get_item(&mut self) -> Result<ClipboardItem, ClipboardError>;
+
+    /// Retrieves the HTML the clipboard offers next to its text.
+    ///
+    /// # Returns
+    ///
+    /// * `Some(String)` - The HTML, if the copying app offered it
+    /// * `None` - If there is no HTML or the clipboard can't be read
+    fn get_html(&mut self) -> Option<String>;
 }
 
 impl GetItem for Clipboard {
@@ -166,5 +808,256 @@ impl GetItem for Clipboard {
             Err(ClipboardError::ClipboardEmpty)
         }
     }
+
+    fn get_html(&mut self) -> Option<String> {
+        self.get().html().ok()
+    }
+}
+
+/// A clipboard the daemon can read and the GUI can paste from.
+///
+/// `arboard::Clipboard` is the one used for the system clipboard, see
+/// `system_clipboard`. `MockBackend` stands in for it where there is no display.
+pub trait ClipboardBackend: GetItem + Send {
+    /// Puts text on the clipboard.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClipboardError::Backend` if the clipboard can't be written.
+    fn set_text(&mut self, text: &str) -> Result<(), ClipboardError>;
+
+    /// Puts text on the clipboard together with its HTML, so the app pasting it picks
+    /// the one it prefers. Backends without HTML only set the text.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClipboardError::Backend` if the clipboard can't be written.
+    fn set_html(&mut self, html: &str, text: &str) -> Result<(), ClipboardError> {
+        let _ = html;
+        self.set_text(text)
+    }
+
+    /// Puts an image on the clipboard, given as raw RGBA pixels.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClipboardError::Backend` if the clipboard can't be written.
+    fn set_image(
+        &mut self,
+        width: usize,
+        height: usize,
+        bytes: &[u8],
+    ) -> Result<(), ClipboardError>;
+
+    /// Reads the primary selection (middle-click paste), like `GetItem::get_item` reads
+    /// the clipboard. Backends without a primary selection are always empty.
+    fn get_primary_item(&mut self) -> Result<ClipboardItem, ClipboardError> {
+        Err(ClipboardError::ClipboardEmpty)
+    }
+
+    /// Like `GetItem::get_html`, for the primary selection.
+    fn get_primary_html(&mut self) -> Option<String> {
+        None
+    }
+}
+
+impl ClipboardBackend for Clipboard {
+    fn set_text(&mut self, text: &str) -> Result<(), ClipboardError> {
+        Clipboard::set_text(self, text).map_err(|err| ClipboardError::Backend(err.to_string()))
+    }
+
+    fn set_html(&mut self, html: &str, text: &str) -> Result<(), ClipboardError> {
+        Clipboard::set_html(self, html, Some(text))
+            .map_err(|err| ClipboardError::Backend(err.to_string()))
+    }
+
+    fn set_image(
+        &mut self,
+        width: usize,
+        height: usize,
+        bytes: &[u8],
+    ) -> Result<(), ClipboardError> {
+        Clipboard::set_image(
+            self,
+            ImageData {
+                width,
+                height,
+                bytes: Cow::Borrowed(bytes),
+            },
+        )
+        .map_err(|err| ClipboardError::Backend(err.to_string()))
+    }
+
+    /// Same order as for the clipboard: image data first, then text.
+    ///
+    /// Not every Wayland compositor offers the primary selection, reading it then fails
+    /// like an empty clipboard does.
+    fn get_primary_item(&mut self) -> Result<ClipboardItem, ClipboardError> {
+        let primary = LinuxClipboardKind::Primary;
+        if let Ok(img_dat) = self.get().clipboard(primary).image() {
+            Ok(ClipboardItem::Image {
+                width: img_dat.width,
+                height: img_dat.height,
+                bytes: img_dat.bytes.to_vec(),
+            })
+        } else if let Ok(str_data) = self.get().clipboard(primary).text() {
+            Ok(ClipboardItem::Text(str_data))
+        } else {
+            Err(ClipboardError::ClipboardEmpty)
+        }
+    }
+
+    fn get_primary_html(&mut self) -> Option<String> {
+        self.get()
+            .clipboard(LinuxClipboardKind::Primary)
+            .html()
+            .ok()
+    }
+}
+
+/// Opens the system clipboard through arboard.
+///
+/// # Errors
+///
+/// Returns `ClipboardError::Backend` if there is no clipboard to connect to, e.g.
+/// no display.
+pub fn system_clipboard() -> Result<Box<dyn ClipboardBackend>, ClipboardError> {
+    match Clipboard::new() {
+        Ok(clipboard) => Ok(Box::new(clipboard)),
+        Err(err) => Err(ClipboardError::Backend(err.to_string())),
+    }
+}
+
+/// Reads the primary selection (middle-click paste) of a backend instead of its clipboard.
+pub struct PrimarySelection<'a>(pub &'a mut dyn ClipboardBackend);
+
+impl GetItem for PrimarySelection<'_> {
+    fn get_item(&mut self) -> Result<ClipboardItem, ClipboardError> {
+        self.0.get_primary_item()
+    }
+
+    fn get_html(&mut self) -> Option<String> {
+        self.0.get_primary_html()
+    }
+}
+
+/// What a `MockBackend` holds, shared by its clones.
+#[derive(Debug, Default)]
+struct MockState {
+    clipboard: Option<ClipboardItem>,
+    html: Option<String>,
+    primary: Option<ClipboardItem>,
+    script: VecDeque<ClipboardItem>,
+}
+
+/// An in-memory clipboard backend for tests, no display needed.
+///
+/// Clones share their contents, so a test can keep one and hand another to a
+/// `Manager` or `Poller`, then copy things as if another app did.
+///
+/// ```
+/// use super_v::common::{ClipboardBackend, ClipboardItem, GetItem, MockBackend};
+///
+/// let mock = MockBackend::default();
+/// let mut backend: Box<dyn ClipboardBackend> = Box::new(mock.clone());
+///
+/// mock.script(["first", "second"].map(|text| ClipboardItem::Text(text.into())));
+/// assert_eq!(backend.get_item(), Ok(ClipboardItem::Text("first".into())));
+/// assert_eq!(backend.get_item(), Ok(ClipboardItem::Text("second".into())));
+///
+/// // The last scripted item stays on the clipboard
+/// assert_eq!(backend.get_item(), Ok(ClipboardItem::Text("second".into())));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MockBackend {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockBackend {
+    fn state(&self) -> std::sync::MutexGuard<'_, MockState> {
+        // Plain data, still usable after a panicking test thread
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Puts an item on the clipboard, as if another app copied it.
+    pub fn copy(&self, item: ClipboardItem) {
+        let mut state = self.state();
+        state.clipboard = Some(item);
+        state.html = None;
+        state.script.clear();
+    }
+
+    /// Puts text on the clipboard together with its HTML.
+    pub fn copy_html(&self, text: &str, html: &str) {
+        self.copy(ClipboardItem::Text(text.to_string()));
+        self.state().html = Some(html.to_string());
+    }
+
+    /// Puts an item in the primary selection, as if it was selected with the mouse.
+    pub fn select(&self, item: ClipboardItem) {
+        self.state().primary = Some(item);
+    }
+
+    /// Queues clipboard contents, each read takes the next one off the queue.
+    /// Once the queue runs out, the last item stays on the clipboard.
+    pub fn script(&self, items: impl IntoIterator<Item = ClipboardItem>) {
+        self.state().script.extend(items);
+    }
+
+    /// What is on the clipboard now, e.g. after the GUI pasted into it.
+    pub fn contents(&self) -> Option<ClipboardItem> {
+        self.state().clipboard.clone()
+    }
+}
+
+impl GetItem for MockBackend {
+    fn get_item(&mut self) -> Result<ClipboardItem, ClipboardError> {
+        let mut state = self.state();
+        if let Some(next) = state.script.pop_front() {
+            state.clipboard = Some(next);
+            state.html = None;
+        }
+        state
+            .clipboard
+            .clone()
+            .ok_or(ClipboardError::ClipboardEmpty)
+    }
+
+    fn get_html(&mut self) -> Option<String> {
+        self.state().html.clone()
+    }
+}
+
+impl ClipboardBackend for MockBackend {
+    fn set_text(&mut self, text: &str) -> Result<(), ClipboardError> {
+        self.copy(ClipboardItem::Text(text.to_string()));
+        Ok(())
+    }
+
+    fn set_html(&mut self, html: &str, text: &str) -> Result<(), ClipboardError> {
+        self.copy_html(text, html);
+        Ok(())
+    }
+
+    fn set_image(
+        &mut self,
+        width: usize,
+        height: usize,
+        bytes: &[u8],
+    ) -> Result<(), ClipboardError> {
+        self.copy(ClipboardItem::Image {
+            width,
+            height,
+            bytes: bytes.to_vec(),
+        });
+        Ok(())
+    }
+
+    fn get_primary_item(&mut self) -> Result<ClipboardItem, ClipboardError> {
+        self.state()
+            .primary
+            .clone()
+            .ok_or(ClipboardError::ClipboardEmpty)
+    }
 }
 // -------------------------------------------------------------------
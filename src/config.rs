@@ -0,0 +1,281 @@
+// Standard Crates
+use std::{
+    env, fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+// External Crates
+use serde::{Deserialize, Serialize};
+
+// My Crates
+use crate::common::{ConfigError, DedupScope, PopupAnchor, TextNormalization};
+
+/// Daemon settings, read from `config.toml` at startup.
+///
+/// Every field is optional in the file. Anything missing takes the default listed
+/// below, and a missing file means all defaults.
+///
+/// ```toml
+/// history_size = 25       # entries kept in history, 0 turns history off
+/// max_total_bytes = 100000000 # drop the oldest entries past this much content (default: no limit)
+/// max_text_bytes = 10000000 # copied text bigger than this isn't stored
+/// max_image_bytes = 100000000 # copied images bigger than this (as raw pixels) aren't stored
+/// poll_interval_ms = 100  # how often the clipboard is checked
+/// adaptive_poll = false   # poll less often while the clipboard doesn't change
+/// max_age_secs = 3600     # drop entries older than this (default: never)
+/// db_path = "/home/me/.local/share/super_v/history.db"  # needs the `sqlite` feature
+/// compress_images = false # store images as PNG to save memory
+/// dedup_window_secs = 600 # re-copies older than this become new entries (default: always promote)
+/// dedup_scope = "all"     # "text", "images" or "off" to only merge re-copies of that kind
+/// ocr_images = false      # read the text in copied images, needs the `ocr` feature
+/// preview_chars = 60      # characters of a text entry shown in the GUI
+/// type_icons = true       # icon in front of each GUI entry showing text or image
+/// normalize_text = "off"  # "trim" or "collapse" whitespace of copied text first
+/// track_primary = false   # also capture the primary selection (middle-click paste)
+/// auto_paste = true       # paste the entry picked in the GUI, not just copy it
+/// paste_delay_ms = 50     # wait before that paste (default: paste right away)
+/// emoji_trailing_space = false # paste emojis with a space after them
+/// window_modal = true     # mark the GUI window modal
+/// grab_focus = true       # ask for keyboard focus again once the GUI window is shown
+/// close_on_focus_loss = true # close the GUI window when another window takes focus
+/// layer_shell = false     # place the GUI window as a Wayland overlay, needs the `layer-shell` feature
+/// popup_anchor = "center" # where that overlay sits: "top", "bottom-right", ...
+/// popup_margin = 12       # gap in pixels between the overlay and the screen edges it is anchored to
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Number of entries kept in history. Default: 25.
+    ///
+    /// 0 is allowed and turns history off: every copy is evicted as soon as it is
+    /// stored, and the GUI says so instead of showing an empty list.
+    pub history_size: usize,
+
+    /// Bytes of content (text and image data) kept in history. Once an add goes over,
+    /// the oldest entries are dropped, but never the newest. Default: none, only
+    /// `history_size` limits the history.
+    pub max_total_bytes: Option<usize>,
+
+    /// Largest copied text, in bytes, that is stored. Bigger text is skipped with a
+    /// warning, and so is HTML of this size that comes with smaller text. Default: 10 MB.
+    pub max_text_bytes: usize,
+
+    /// Largest copied image, in bytes of raw pixels (4 per pixel), that is stored.
+    /// Bigger images are skipped with a warning. Default: 100 MB, about a 5K screenshot.
+    pub max_image_bytes: usize,
+
+    /// Milliseconds between clipboard polls. Default: 100.
+    pub poll_interval_ms: u64,
+
+    /// Poll less often while the clipboard doesn't change, to save power. After 30 s
+    /// without a change the interval ramps up to 1 s, and the next change brings it back
+    /// to `poll_interval_ms`. Default: false, always poll at `poll_interval_ms`.
+    pub adaptive_poll: bool,
+
+    /// Seconds after which an entry is removed. Default: none, entries never expire.
+    pub max_age_secs: Option<u64>,
+
+    /// SQLite database to keep history in. Default: none, history lives in memory.
+    /// Only used when built with the `sqlite` feature.
+    pub db_path: Option<PathBuf>,
+
+    /// Store images PNG-compressed instead of as raw RGBA. Default: false.
+    /// Uses far less memory for screenshots, at the cost of encoding on capture
+    /// and decoding on paste.
+    pub compress_images: bool,
+
+    /// Seconds within which copying an item again promotes its existing entry.
+    /// Older duplicates are added as new entries. Default: none, duplicates are always promoted.
+    pub dedup_window_secs: Option<u64>,
+
+    /// Kinds of entries merged with an earlier copy when copied again, e.g. `"text"`
+    /// keeps every screenshot as its own entry. Default: `"all"`.
+    pub dedup_scope: DedupScope,
+
+    /// Run OCR on copied images so their text can be pasted. Default: false.
+    /// Only used when built with the `ocr` feature, and needs `tesseract` installed.
+    pub ocr_images: bool,
+
+    /// Characters of a text entry the GUI shows before cutting it off. Default: 60.
+    pub preview_chars: usize,
+
+    /// Show an icon for the kind of entry (text, rich text, image) at the start of each
+    /// row in the GUI. Default: true.
+    pub type_icons: bool,
+
+    /// Whitespace cleanup for copied text, see `TextNormalization`. Applied before
+    /// duplicates are looked for, so copies that only differ in it are one entry.
+    /// Default: off.
+    pub normalize_text: TextNormalization,
+
+    /// Also capture the primary selection, the text that is pasted with a middle click.
+    /// Entries are tagged with the selection they came from. Default: false.
+    pub track_primary: bool,
+
+    /// Paste the entry picked in the GUI into the app that had focus, with ydotool.
+    /// When off, picking an entry only puts it on the clipboard. Default: true.
+    pub auto_paste: bool,
+
+    /// Milliseconds to wait after the GUI has given up focus before pasting, for
+    /// compositors that are slow to refocus the app. Default: none.
+    pub paste_delay_ms: Option<u64>,
+
+    /// Paste a picked emoji with a space after it, to keep typing right away.
+    /// Default: false.
+    pub emoji_trailing_space: bool,
+
+    /// Mark the GUI window modal. Most compositors then center it and keep it above
+    /// the app it was opened from. Default: true.
+    pub window_modal: bool,
+
+    /// Ask for keyboard focus again once the GUI window is shown, for compositors that
+    /// ignore the first request of a new window. Keyboard navigation and search only
+    /// work with focus. Default: true.
+    pub grab_focus: bool,
+
+    /// Close the GUI window when another window takes focus. Turn off on compositors
+    /// that take focus away from new windows, where it closes right after opening.
+    /// Escape still closes it. Default: true.
+    pub close_on_focus_loss: bool,
+
+    /// Show the GUI as a layer shell overlay on Wayland compositors that support it
+    /// (Sway, Hyprland, KDE), placed by `popup_anchor` instead of by the compositor.
+    /// Only used when built with the `layer-shell` feature. Default: false.
+    pub layer_shell: bool,
+
+    /// Where the layer shell overlay sits on screen. Default: `"center"`.
+    pub popup_anchor: PopupAnchor,
+
+    /// Gap in pixels between the layer shell overlay and the screen edges it is
+    /// anchored to. Default: 12.
+    pub popup_margin: i32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            history_size: 25,
+            max_total_bytes: None,
+            max_text_bytes: 10_000_000,
+            max_image_bytes: 100_000_000,
+            poll_interval_ms: 100,
+            adaptive_poll: false,
+            max_age_secs: None,
+            db_path: None,
+            compress_images: false,
+            dedup_window_secs: None,
+            dedup_scope: DedupScope::All,
+            ocr_images: false,
+            preview_chars: 60,
+            type_icons: true,
+            normalize_text: TextNormalization::Off,
+            track_primary: false,
+            auto_paste: true,
+            paste_delay_ms: None,
+            emoji_trailing_space: false,
+            window_modal: true,
+            grab_focus: true,
+            close_on_focus_loss: true,
+            layer_shell: false,
+            popup_anchor: PopupAnchor::Center,
+            popup_margin: 12,
+        }
+    }
+}
+
+impl Config {
+    const FILE_NAME: &str = "config.toml";
+
+    /// Location of the config file: `$XDG_CONFIG_HOME/super_v/config.toml`,
+    /// falling back to `~/.config/super_v/config.toml`.
+    ///
+    /// Returns None if neither variable is set.
+    pub fn path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join(Self::FILE_NAME))
+    }
+
+    /// Load the config from `Config::path`. A missing file gives the defaults.
+    ///
+    /// # Errors
+    /// - Returns `ConfigError::ReadError` if the file exists but can't be read.
+    /// - Returns `ConfigError::ParseError` if the contents are invalid.
+    pub fn load() -> Result<Self, ConfigError> {
+        match Self::path() {
+            Some(path) => Self::load_from(&path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Load the config from `path`. A missing file gives the defaults.
+    ///
+    /// # Errors
+    /// - Same as `Config::load`.
+    pub fn load_from(path: &Path) -> Result<Self, ConfigError> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Self::from_toml(&contents),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(ConfigError::ReadError(format!(
+                "{}: {}",
+                path.display(),
+                err
+            ))),
+        }
+    }
+
+    /// Parse a config from TOML text.
+    ///
+    /// # Errors
+    /// - Returns `ConfigError::ParseError` on invalid TOML, unknown keys or
+    ///   a zero `poll_interval_ms` or `preview_chars`. A zero `history_size` is valid,
+    ///   it turns history off.
+    pub fn from_toml(contents: &str) -> Result<Self, ConfigError> {
+        let config: Self =
+            toml::from_str(contents).map_err(|err| ConfigError::ParseError(err.to_string()))?;
+
+        if config.poll_interval_ms == 0 {
+            return Err(ConfigError::ParseError(
+                "poll_interval_ms must be greater than 0".into(),
+            ));
+        }
+
+        if config.preview_chars == 0 {
+            return Err(ConfigError::ParseError(
+                "preview_chars must be greater than 0".into(),
+            ));
+        }
+
+        Ok(config)
+    }
+
+    /// `poll_interval_ms` as a Duration.
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_interval_ms)
+    }
+
+    /// `max_age_secs` as a Duration.
+    pub fn max_age(&self) -> Option<Duration> {
+        self.max_age_secs.map(Duration::from_secs)
+    }
+
+    /// `dedup_window_secs` as a Duration.
+    pub fn dedup_window(&self) -> Option<Duration> {
+        self.dedup_window_secs.map(Duration::from_secs)
+    }
+
+    /// `paste_delay_ms` as a Duration.
+    pub fn paste_delay(&self) -> Option<Duration> {
+        self.paste_delay_ms.map(Duration::from_millis)
+    }
+}
+
+/// The `super_v` directory under `$XDG_CONFIG_HOME`, or `~/.config` if that is unset.
+pub fn config_dir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+
+    Some(base.join("super_v"))
+}
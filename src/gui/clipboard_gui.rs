@@ -1,15 +1,59 @@
 use crate::{
-    common::ClipboardItem,
-    history::ClipboardHistory,
+    client::Client,
+    common::{
+        ClipboardBackend, ClipboardError, ClipboardItem, DEFAULT_PROFILE, IPCServerError,
+        Selection, looks_like_url, parse_color, preview_text, system_clipboard,
+    },
+    config::Config,
+    gui::{
+        settings::{ClipboardFilter, GuiSettings},
+        thumbnails,
+    },
+    history::{ClipboardHistory, EntryMeta},
     services::clipboard_ipc_server::{
-        CmdIPC, IPCRequest, Payload, create_default_stream, read_payload, send_payload,
+        CmdIPC, IPCRequest, IpcErrorCode, Payload, create_stream, read_payload, send_payload,
     },
 };
-use arboard::{Clipboard, ImageData};
 use gdk_pixbuf::{InterpType, Pixbuf};
 use gtk::gdk::Texture;
 use gtk4::{self as gtk, Application, gdk::Key, prelude::*};
-use std::{borrow::Cow, collections::HashMap, rc::Rc, sync::mpsc::Sender, thread, time::Duration};
+use lru::LruCache;
+use qrcode::{Color, EcLevel, QrCode};
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    num::NonZeroUsize,
+    rc::Rc,
+    sync::{Mutex, OnceLock, mpsc::Sender},
+    thread,
+    time::{Duration, SystemTime},
+};
+use tracing::{debug, warn};
+
+// Profile whose daemon the GUI talks to. Set once in run_gui.
+static PROFILE: OnceLock<String> = OnceLock::new();
+
+// Settings from config.toml. Set once in run_gui.
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+// Thread removing a pasted emoji from history. run_gui waits for it before returning.
+static EMOJI_CLEANUP: Mutex<Option<thread::JoinHandle<()>>> = Mutex::new(None);
+
+// Preview textures kept in memory. They are at most 200x200 RGBA (~160KB), so this
+// caps the cache around 20MB. Evicted previews are reloaded from the disk thumbnails.
+const IMAGE_CACHE_CAPACITY: NonZeroUsize = NonZeroUsize::new(128).unwrap();
+
+// How often to try reaching the daemon again while it is down
+const DAEMON_RETRY_INTERVAL: Duration = Duration::from_secs(2);
+
+// How long the undo toast stays up after a delete or clear
+const UNDO_TOAST_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Longest wait for the window to give up focus before auto paste fires anyway
+const PASTE_FOCUS_TIMEOUT: Duration = Duration::from_millis(500);
+
+// Entries fetched per page, "Show more" loads the next one
+const GUI_PAGE_SIZE: usize = 50;
 
 pub enum MainThreadMsg {
     AutoPaste,
@@ -22,14 +66,40 @@ pub enum InitialTab {
     Emoji,
 }
 
+/// A listed entry: its row, the position it had in the daemon's history, the item and
+/// its metadata.
+type Row = (gtk::Revealer, usize, ClipboardItem, EntryMeta);
+
 struct Gui {
     window: gtk::ApplicationWindow,
     stack: gtk::Stack,
     clear_all_btn: gtk::Button,
+    filter_box: gtk::Box,
+    filter_buttons: Vec<(ClipboardFilter, gtk::ToggleButton)>,
+    clipboard_filter: Rc<Cell<ClipboardFilter>>,
+    sort_button: gtk::ToggleButton,
+    sort_by_frequency: Rc<Cell<bool>>,
     search_entry: gtk::Entry,
+    items_scrolled_window: gtk::ScrolledWindow,
     items_box: gtk::Box,
-    emoji_flow_box: gtk::FlowBox,
-    image_cache: Rc<std::cell::RefCell<HashMap<Vec<u8>, Texture>>>,
+    clipboard_rows: Rc<RefCell<Vec<Row>>>,
+    selected_row: Rc<Cell<usize>>,
+    // How many of the most recent entries are fetched, and how many the daemon holds
+    loaded_limit: Rc<Cell<usize>>,
+    history_total: Rc<Cell<usize>>,
+    // Hides the emojis that don't match the search, see emoji_grid
+    emoji_filter: gtk::CustomFilter,
+    // Scheduled reconnect while the daemon is down
+    daemon_retry: Rc<RefCell<Option<gtk::glib::SourceId>>>,
+    image_cache: Rc<RefCell<LruCache<String, Texture>>>,
+    // Whether this launch removed the stale thumbnails already
+    thumbs_pruned: Cell<bool>,
+    // Undo toast, how many removals its button reverts, and its pending hide
+    undo_toast: gtk::Revealer,
+    undo_label: gtk::Label,
+    undo_btn: gtk::Button,
+    undo_steps: Rc<Cell<usize>>,
+    undo_hide: Rc<RefCell<Option<gtk::glib::SourceId>>>,
     main_thread_tx: Sender<MainThreadMsg>,
 }
 
@@ -47,11 +117,11 @@ impl Gui {
         const WIDTH: i32 = 360;
         const HEIGHT: i32 = 400;
         const TOP_PANEL: bool = false;
-        const MODAL: bool = true;
 
         window.set_default_size(WIDTH, HEIGHT);
         window.set_decorated(TOP_PANEL);
-        window.set_modal(MODAL);
+        window.set_modal(Self::config().window_modal);
+        Self::place_window(&window);
         // -----------------------------------------------------------
 
         // ------------------------ CSS ------------------------------
@@ -74,6 +144,35 @@ impl Gui {
         let stack_switcher = gtk::StackSwitcher::new();
         header_box.append(&stack_switcher);
 
+        // All / Text / Images toggle and sort order, restored from the last launch
+        let GuiSettings {
+            clipboard_filter,
+            sort_by_frequency,
+        } = GuiSettings::load();
+        let filter_box = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+        filter_box.add_css_class("linked");
+        filter_box.add_css_class("filter-box");
+
+        let mut filter_buttons: Vec<(ClipboardFilter, gtk::ToggleButton)> = Vec::new();
+        for filter in ClipboardFilter::ALL {
+            let button = gtk::ToggleButton::with_label(filter.label());
+            button.add_css_class("filter-btn");
+            if let Some((_, first)) = filter_buttons.first() {
+                button.set_group(Some(first));
+            }
+            button.set_active(filter == clipboard_filter);
+            filter_box.append(&button);
+            filter_buttons.push((filter, button));
+        }
+
+        let sort_button = gtk::ToggleButton::with_label("Most used");
+        sort_button.add_css_class("filter-btn");
+        sort_button.add_css_class("sort-btn");
+        sort_button.set_tooltip_text(Some("List the most copied entries first"));
+        sort_button.set_active(sort_by_frequency);
+        filter_box.append(&sort_button);
+        header_box.append(&filter_box);
+
         let clear_all_btn = gtk::Button::new();
         clear_all_btn.set_label("Clear All");
         clear_all_btn.add_css_class("clear-all-btn");
@@ -110,30 +209,40 @@ impl Gui {
         clipboard_page.set_icon_name("edit-paste-symbolic");
 
         // Page 2: Emoji
-        let emoji_flow_box = gtk::FlowBox::new();
-        emoji_flow_box.add_css_class("emoji-box");
-        emoji_flow_box.set_hexpand(false);
-        emoji_flow_box.set_valign(gtk::Align::Start);
-        emoji_flow_box.set_vexpand(true);
-        emoji_flow_box.set_max_children_per_line(7);
-        emoji_flow_box.set_min_children_per_line(4);
-        emoji_flow_box.set_selection_mode(gtk::SelectionMode::None);
-        emoji_flow_box.set_homogeneous(true);
-        emoji_flow_box.set_row_spacing(1);
-        emoji_flow_box.set_column_spacing(1);
+        let (emoji_grid, emoji_filter) = Self::emoji_grid(&search_entry, &window, &main_thread_tx);
 
         let emoji_scrolled_window = gtk::ScrolledWindow::new();
         emoji_scrolled_window.add_css_class("scrollable-window");
         emoji_scrolled_window.set_vexpand(true);
         emoji_scrolled_window.set_hexpand(true);
-        emoji_scrolled_window.set_child(Some(&emoji_flow_box));
+        emoji_scrolled_window.set_child(Some(&emoji_grid));
 
         stack.add_titled(&emoji_scrolled_window, Some("emoji"), "Emoji");
         let emoji_page = stack.page(&emoji_scrolled_window);
         emoji_page.set_icon_name("face-smile-symbolic");
 
+        // Undo toast, hidden until something is deleted
+        let undo_toast = gtk::Revealer::new();
+        undo_toast.set_transition_type(gtk::RevealerTransitionType::SlideUp);
+        undo_toast.set_transition_duration(200);
+
+        let undo_box = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+        undo_box.add_css_class("undo-toast");
+
+        let undo_label = gtk::Label::new(None);
+        undo_label.set_hexpand(true);
+        undo_label.set_xalign(0.0);
+
+        let undo_btn = gtk::Button::with_label("Undo");
+        undo_btn.add_css_class("undo-btn");
+
+        undo_box.append(&undo_label);
+        undo_box.append(&undo_btn);
+        undo_toast.set_child(Some(&undo_box));
+
         // Final Layout Assembly
         main_box.append(&stack);
+        main_box.append(&undo_toast);
         window.set_child(Some(&main_box));
         stack_switcher.set_stack(Some(&stack));
         // ------------------------------------------------------------
@@ -143,46 +252,136 @@ impl Gui {
             window: window.clone(), // Clone for the struct
             stack: stack.clone(),   // Clone for the struct
             clear_all_btn,
+            filter_box,
+            filter_buttons,
+            clipboard_filter: Rc::new(Cell::new(clipboard_filter)),
+            sort_button,
+            sort_by_frequency: Rc::new(Cell::new(sort_by_frequency)),
             search_entry,
+            items_scrolled_window: scrolled_window.clone(),
             items_box: items_box.clone(), // Clone for the struct
-            emoji_flow_box,
-            image_cache: Rc::new(std::cell::RefCell::new(HashMap::new())),
+            clipboard_rows: Rc::new(RefCell::new(Vec::new())),
+            selected_row: Rc::new(Cell::new(0)),
+            loaded_limit: Rc::new(Cell::new(GUI_PAGE_SIZE)),
+            history_total: Rc::new(Cell::new(0)),
+            emoji_filter,
+            daemon_retry: Rc::new(RefCell::new(None)),
+            image_cache: Rc::new(RefCell::new(LruCache::new(IMAGE_CACHE_CAPACITY))),
+            thumbs_pruned: Cell::new(false),
+            undo_toast,
+            undo_label,
+            undo_btn,
+            undo_steps: Rc::new(Cell::new(0)),
+            undo_hide: Rc::new(RefCell::new(None)),
             main_thread_tx,
         })
     }
 
     fn signal_auto_paste(tx: Sender<MainThreadMsg>) {
         if let Err(err) = tx.send(MainThreadMsg::AutoPaste) {
-            eprintln!("auto paste signal dropped: {err}");
+            warn!("auto paste signal dropped: {err}");
         }
     }
 
-    fn schedule_emoji_cleanup(tx: Sender<MainThreadMsg>, emoji_text: String) {
-        thread::spawn(move || {
-            let target_item = ClipboardItem::Text(emoji_text);
+    /// Hides the window and signals auto paste once focus has left it, then runs `then`.
+    ///
+    /// The compositor only hands focus back to the previous app after the window is
+    /// unmapped and deactivated, and a paste sent before that lands nowhere. How long
+    /// that takes varies, so this waits for both instead of sleeping. Compositors that
+    /// never report it get `PASTE_FOCUS_TIMEOUT`.
+    ///
+    /// With `auto_paste` off in the config, nothing is signaled and `then` runs right away.
+    ///
+    /// # Arguments
+    /// * `window` - The window to hide
+    /// * `tx` - Where to send `MainThreadMsg::AutoPaste`
+    /// * `then` - Runs right after the signal, usually to close the window
+    fn paste_when_hidden(
+        window: &gtk::ApplicationWindow,
+        tx: &Sender<MainThreadMsg>,
+        then: impl FnOnce() + 'static,
+    ) {
+        if !Self::config().auto_paste {
+            then();
+            return;
+        }
+
+        // Whichever fires first pastes, the rest find it taken
+        let pending = Rc::new(Cell::new(Some(then)));
+        let tx = tx.clone();
+        let fire = move || {
+            if let Some(then) = pending.take() {
+                Self::signal_auto_paste(tx.clone());
+                then();
+            }
+        };
+
+        // Unmap and deactivation arrive in either order depending on the compositor
+        let on_change = {
+            let fire = fire.clone();
+            move |window: &gtk::ApplicationWindow| {
+                if !window.is_mapped() && !window.is_active() {
+                    fire();
+                }
+            }
+        };
+        window.connect_unmap(on_change.clone());
+        window.connect_is_active_notify(on_change.clone());
+        gtk::glib::timeout_add_local_once(PASTE_FOCUS_TIMEOUT, fire);
+
+        window.set_visible(false);
+        on_change(window);
+    }
+
+    /// Removes a pasted emoji from history again if the daemon captured it anyway,
+    /// then tells the main thread the GUI is done.
+    ///
+    /// Only an entry the paste created is removed: copied once, no earlier than
+    /// `pasted_at`. An entry the user had copied before the paste is left alone.
+    ///
+    /// # Arguments
+    /// * `tx` - Where to send `MainThreadMsg::Close` when done
+    /// * `target_item` - The pasted emoji as the daemon would store it
+    /// * `pasted_at` - When the emoji was put on the clipboard
+    fn schedule_emoji_cleanup(
+        tx: Sender<MainThreadMsg>,
+        target_item: ClipboardItem,
+        pasted_at: SystemTime,
+    ) {
+        let cleanup = thread::spawn(move || {
             for attempt in 0..5 {
                 thread::sleep(Duration::from_millis(120 * (attempt + 1) as u64));
-                if let Some(history) = Self::send_command(CmdIPC::Snapshot)
-                    && history.get_items().iter().any(|item| item == &target_item)
-                {
-                    // If emoji is found, delete that
-                    let _ = Self::send_command(CmdIPC::DeleteThis(target_item.clone()));
-
-                    // break out of the for loop
-                    break;
+                let Some(history) = Self::send_command(CmdIPC::Snapshot) else {
+                    continue;
+                };
+                let Some(pos) = history.position(&target_item) else {
+                    continue;
+                };
+
+                if history.get_meta()[pos].is_new_since(pasted_at) {
+                    let _ = Self::send_command(CmdIPC::DeleteAt {
+                        pos,
+                        item: target_item.clone(),
+                    });
+                } else {
+                    debug!("Emoji was in history before it was pasted, keeping it");
                 }
+                break;
             }
 
             // close that gui process
             // without this the process would be dangling...
             if let Err(err) = tx.send(MainThreadMsg::Close) {
-                eprintln!("close signal dropped: {err}");
+                warn!("close signal dropped: {err}");
             }
         });
+        if let Ok(mut slot) = EMOJI_CLEANUP.lock() {
+            *slot = Some(cleanup);
+        }
     }
 
-    fn get_clipboard() -> Result<Clipboard, arboard::Error> {
-        Clipboard::new()
+    fn get_clipboard() -> Result<Box<dyn ClipboardBackend>, ClipboardError> {
+        system_clipboard()
     }
 
     fn clear_items_box(items_box: &gtk::Box) {
@@ -193,60 +392,392 @@ impl Gui {
 
     fn close_window(window: gtk::ApplicationWindow, tx: Sender<MainThreadMsg>) {
         if let Err(err) = tx.send(MainThreadMsg::Close) {
-            eprintln!("close signal dropped: {err}");
+            warn!("close signal dropped: {err}");
         }
         window.close();
     }
 
-    fn fetch_history() -> ClipboardHistory {
-        let new_clipboard = ClipboardHistory::new(25);
+    fn profile() -> &'static str {
+        PROFILE.get().map(String::as_str).unwrap_or(DEFAULT_PROFILE)
+    }
 
-        match create_default_stream() {
-            Ok(mut stream) => {
-                send_payload(
-                    &mut stream,
-                    Payload::Request(IPCRequest {
-                        cmd: CmdIPC::Snapshot,
-                    }),
-                );
+    /// Offers to revert the last `steps` removals for a few seconds.
+    fn show_undo_toast(&self, text: &str, steps: usize) {
+        self.hide_undo_toast();
 
-                let received_payload = read_payload(&mut stream);
-                match received_payload {
-                    Payload::Response(ipc_resp) => {
-                        ipc_resp.history_snapshot.unwrap_or(new_clipboard)
-                    }
-                    _ => new_clipboard,
-                }
+        self.undo_label.set_text(text);
+        self.undo_steps.set(steps);
+        self.undo_toast.set_reveal_child(true);
+
+        let undo_toast = self.undo_toast.clone();
+        let undo_hide = self.undo_hide.clone();
+        let pending = gtk::glib::timeout_add_local_once(UNDO_TOAST_TIMEOUT, move || {
+            // Fired, so there is nothing left to cancel
+            undo_hide.borrow_mut().take();
+            undo_toast.set_reveal_child(false);
+        });
+        *self.undo_hide.borrow_mut() = Some(pending);
+    }
+
+    fn hide_undo_toast(&self) {
+        if let Some(pending) = self.undo_hide.borrow_mut().take() {
+            pending.remove();
+        }
+        self.undo_toast.set_reveal_child(false);
+    }
+
+    fn save_settings(&self) {
+        GuiSettings {
+            clipboard_filter: self.clipboard_filter.get(),
+            sort_by_frequency: self.sort_by_frequency.get(),
+        }
+        .save();
+    }
+
+    fn config() -> &'static Config {
+        CONFIG.get_or_init(Config::default)
+    }
+
+    /// Turns the window into a layer shell overlay when `layer_shell` is set, so it
+    /// opens at `popup_anchor` on the active output instead of wherever the
+    /// compositor puts new windows.
+    ///
+    /// Falls back to a regular window, with a warning, when the compositor doesn't
+    /// support the protocol (GNOME, X11) or the `layer-shell` feature is off.
+    /// Must run before the window is realized.
+    fn place_window(window: &gtk::ApplicationWindow) {
+        let config = Self::config();
+        if !config.layer_shell {
+            return;
+        }
+
+        #[cfg(feature = "layer-shell")]
+        {
+            use gtk4_layer_shell::{Edge, KeyboardMode, Layer, LayerShell};
+
+            if !gtk4_layer_shell::is_supported() {
+                warn!("Compositor has no layer shell support, opening a regular window");
+                return;
+            }
+
+            window.init_layer_shell();
+            window.set_layer(Layer::Overlay);
+            window.set_namespace(Some("super_v"));
+
+            let [top, bottom, left, right] = config.popup_anchor.edges();
+            for (edge, anchored) in [
+                (Edge::Top, top),
+                (Edge::Bottom, bottom),
+                (Edge::Left, left),
+                (Edge::Right, right),
+            ] {
+                window.set_anchor(edge, anchored);
+                window.set_margin(edge, config.popup_margin);
+            }
+
+            // Exclusive keeps focus on the overlay until it closes, so keyboard
+            // navigation works without the compositor having to hand it over
+            window.set_keyboard_mode(if config.grab_focus {
+                KeyboardMode::Exclusive
+            } else {
+                KeyboardMode::OnDemand
+            });
+            debug!(
+                "Placed window as layer shell overlay at {:?}",
+                config.popup_anchor
+            );
+        }
+
+        #[cfg(not(feature = "layer-shell"))]
+        {
+            let _ = window;
+            warn!("Ignoring layer_shell: built without the layer-shell feature");
+        }
+    }
+
+    /// Asks the daemon for its most recent entries.
+    ///
+    /// # Arguments
+    /// * `limit` - How many entries to fetch at most
+    ///
+    /// # Returns
+    /// The first `limit` entries `filter` lists, in the order they are shown, where each
+    /// of them is in the daemon's history, and how many entries are listed in all.
+    ///
+    /// The daemon filters and sorts, so the first page already holds the most copied
+    /// entries of the whole history.
+    ///
+    /// # Errors
+    /// Returns the `IPCServerError` from connecting when the daemon isn't running,
+    /// so the caller can tell "no daemon" apart from "no history".
+    fn fetch_history(
+        limit: usize,
+        filter: ClipboardFilter,
+        by_frequency: bool,
+    ) -> Result<(ClipboardHistory, Vec<usize>, usize), IPCServerError> {
+        let new_clipboard = ClipboardHistory::new(Self::config().history_size);
+
+        let mut stream = create_stream(Self::profile())?;
+        send_payload(
+            &mut stream,
+            Payload::Request(IPCRequest {
+                cmd: CmdIPC::SnapshotRange {
+                    offset: 0,
+                    limit,
+                    filter,
+                    by_frequency,
+                },
+            }),
+        );
+
+        let received_payload = read_payload(&mut stream);
+        match received_payload {
+            Payload::Response(ipc_resp) => {
+                let history = ipc_resp.history_snapshot.unwrap_or(new_clipboard);
+                let positions = ipc_resp
+                    .positions
+                    .unwrap_or_else(|| (0..history.len()).collect());
+                let total = ipc_resp.total.unwrap_or(history.len());
+                Ok((history, positions, total))
             }
-            Err(_) => new_clipboard,
+            _ => Ok((new_clipboard, Vec::new(), 0)),
         }
     }
 
     pub fn send_command(cmd: CmdIPC) -> Option<ClipboardHistory> {
-        match create_default_stream() {
+        match create_stream(Self::profile()) {
             Ok(mut stream) => {
+                let cmd_label = cmd.to_string();
                 send_payload(&mut stream, Payload::Request(IPCRequest { cmd }));
 
                 let received_payload = read_payload(&mut stream);
                 if let Payload::Response(ipc_resp) = received_payload {
+                    match ipc_resp.error {
+                        // Someone else removed it first, nothing to do
+                        Some(IpcErrorCode::ItemNotFound) => {
+                            debug!("{cmd_label}: item already gone");
+                        }
+                        Some(code) => warn!("{cmd_label} failed: {code:?}"),
+                        None => {}
+                    }
                     return ipc_resp.history_snapshot;
                 }
                 None
             }
-            Err(_) => None,
+            Err(err) => {
+                warn!("Could not reach the clipboard service: {err}");
+                None
+            }
+        }
+    }
+
+    /// Moves `item` to the top of history and tells the daemon the clipboard is about to
+    /// hold it (`raw` being the form it reads back as), so the daemon doesn't capture it
+    /// as a new copy. Must run before the clipboard is set.
+    ///
+    /// An item that is gone from history (e.g. deleted from another window) is not
+    /// announced, so the paste records it again.
+    fn announce_paste_back(item: &ClipboardItem, raw: &ClipboardItem) {
+        if let Err(err) = Client::with_profile(Self::profile()).paste_back(item, raw) {
+            debug!("Paste back not announced: {err}");
+        }
+    }
+
+    /// Puts the item on the system clipboard, signals auto paste and closes the window.
+    ///
+    /// Text copied with HTML is offered as both, so the receiving app picks its preferred one.
+    fn paste_item(
+        item: &ClipboardItem,
+        html: Option<&str>,
+        window: &gtk::ApplicationWindow,
+        tx: &Sender<MainThreadMsg>,
+    ) {
+        if let ClipboardItem::Text(text) = item
+            && let Ok(mut clipboard) = Self::get_clipboard()
+            && !text.trim().is_empty()
+        {
+            Self::announce_paste_back(item, item);
+
+            // Update system clipboard
+            // This says I'm dropping the clipboard too fast (5ms)
+            // eh... should be just fine.
+            let _ = match html {
+                Some(html) => clipboard.set_html(html, text),
+                None => clipboard.set_text(text),
+            };
+
+            // Signal for auto paste once the window is gone, then close it
+            let (window, tx) = (window.clone(), tx.clone());
+            Self::paste_when_hidden(&window.clone(), &tx.clone(), move || {
+                Self::close_window(window, tx);
+            });
+            return;
+        }
+
+        if let ClipboardItem::Image { width, height, .. }
+        | ClipboardItem::CompressedImage { width, height, .. } = item
+            && let Some(bytes) = item.pixels()
+            && !bytes.is_empty()
+            && let Ok(mut clipboard) = Self::get_clipboard()
+        {
+            // The clipboard gives back raw pixels, even for a compressed entry
+            let raw = ClipboardItem::Image {
+                width: *width,
+                height: *height,
+                bytes: bytes.to_vec(),
+            };
+            Self::announce_paste_back(item, &raw);
+
+            // Same 5ms drop here...
+            let _ = clipboard.set_image(*width, *height, &bytes);
+
+            // Signal for auto paste once the window is gone, then close it
+            let (window, tx) = (window.clone(), tx.clone());
+            Self::paste_when_hidden(&window.clone(), &tx.clone(), move || {
+                Self::close_window(window, tx);
+            });
+            return;
+        }
+
+        // Close the window
+        Self::close_window(window.clone(), tx.clone());
+    }
+
+    /// Symbolic icon for the kind of entry, shown in front of its row.
+    fn type_icon_name(item: &ClipboardItem, meta: &EntryMeta) -> &'static str {
+        match item {
+            ClipboardItem::Text(text) if looks_like_url(text) => "insert-link-symbolic",
+            ClipboardItem::Text(_) if meta.html.is_some() => "text-html-symbolic",
+            ClipboardItem::Text(_) => "text-x-generic-symbolic",
+            ClipboardItem::Image { .. } | ClipboardItem::CompressedImage { .. } => {
+                "image-x-generic-symbolic"
+            }
+        }
+    }
+
+    /// A small square filled with the color a text entry names, if it is one
+    /// (see `parse_color`).
+    fn color_swatch(item: &ClipboardItem) -> Option<gtk::Widget> {
+        const SWATCH_SIZE: i32 = 14;
+
+        let (r, g, b) = match item {
+            ClipboardItem::Text(text) => parse_color(text)?,
+            ClipboardItem::Image { .. } | ClipboardItem::CompressedImage { .. } => return None,
+        };
+
+        let swatch = gtk::DrawingArea::new();
+        swatch.set_content_width(SWATCH_SIZE);
+        swatch.set_content_height(SWATCH_SIZE);
+        swatch.set_draw_func(move |_, cr, width, height| {
+            cr.set_source_rgb(
+                f64::from(r) / 255.0,
+                f64::from(g) / 255.0,
+                f64::from(b) / 255.0,
+            );
+            cr.rectangle(0.0, 0.0, f64::from(width), f64::from(height));
+            let _ = cr.fill();
+        });
+        swatch.add_css_class("color-swatch");
+        swatch.set_valign(gtk::Align::Start);
+        Some(swatch.upcast())
+    }
+
+    /// What goes in front of a row: a swatch for a color, otherwise the icon from
+    /// `type_icon_name`.
+    fn type_icon(item: &ClipboardItem, meta: &EntryMeta) -> gtk::Widget {
+        let icon = Self::color_swatch(item).unwrap_or_else(|| {
+            gtk::Image::from_icon_name(Self::type_icon_name(item, meta)).upcast()
+        });
+        icon.add_css_class("type-icon");
+        if meta.selection == Selection::Primary {
+            icon.set_tooltip_text(Some("Selected text (middle-click paste)"));
+        }
+        icon.set_valign(gtk::Align::Start);
+        icon
+    }
+
+    /// Highlights the row at `index` (clamped to the last row) and scrolls it into view.
+    fn select_row(&self, index: usize) {
+        let rows = self.clipboard_rows.borrow();
+        let Some(last) = rows.len().checked_sub(1) else {
+            return;
+        };
+        let index = index.min(last);
+
+        for (pos, (revealer, _, _, _)) in rows.iter().enumerate() {
+            if let Some(row) = revealer.child() {
+                if pos == index {
+                    row.add_css_class("selected");
+                } else {
+                    row.remove_css_class("selected");
+                }
+            }
+        }
+        self.selected_row.set(index);
+
+        // Keep the selected row inside the visible part of the list
+        if let Some(bounds) = rows[index].0.compute_bounds(&self.items_box) {
+            let adjustment = self.items_scrolled_window.vadjustment();
+            let top = bounds.y() as f64;
+            let bottom = top + bounds.height() as f64;
+
+            if top < adjustment.value() {
+                adjustment.set_value(top);
+            } else if bottom > adjustment.value() + adjustment.page_size() {
+                adjustment.set_value(bottom - adjustment.page_size());
+            }
+        }
+    }
+
+    /// Pastes the row at `index`, if it exists.
+    fn paste_row(&self, index: usize) {
+        let row = self
+            .clipboard_rows
+            .borrow()
+            .get(index)
+            .map(|(_, _, item, meta)| (item.clone(), meta.html.clone()));
+
+        if let Some((item, html)) = row {
+            Self::paste_item(&item, html.as_deref(), &self.window, &self.main_thread_tx);
         }
     }
 
-    fn clipboard_empty_state(items_box: &gtk::Box) {
+    /// Keyboard handling for the clipboard list.
+    ///
+    /// * **Up/Down** - Move the selection.
+    /// * **Enter** - Paste the selected row.
+    /// * **1-9** - Paste the matching row directly.
+    fn handle_clipboard_key(&self, key: Key) -> gtk::glib::Propagation {
+        if self.clipboard_rows.borrow().is_empty() {
+            return gtk::glib::Propagation::Proceed;
+        }
+
+        let selected = self.selected_row.get();
+        match key {
+            Key::Down => self.select_row(selected + 1),
+            Key::Up => self.select_row(selected.saturating_sub(1)),
+            Key::Return | Key::KP_Enter => self.paste_row(selected),
+            _ => match key.to_unicode().and_then(|c| c.to_digit(10)) {
+                Some(digit @ 1..=9) => self.paste_row(digit as usize - 1),
+                _ => return gtk::glib::Propagation::Proceed,
+            },
+        }
+
+        gtk::glib::Propagation::Stop
+    }
+
+    fn clipboard_empty_state(items_box: &gtk::Box, filter: ClipboardFilter) {
+        let (title, subtitle) = filter.empty_message();
+
         let empty_box = gtk::Box::new(gtk::Orientation::Vertical, 8);
         empty_box.set_valign(gtk::Align::Center);
         empty_box.set_vexpand(true);
         empty_box.set_margin_top(-10);
 
-        let empty_title = gtk::Label::new(Some("Clipboard empty"));
+        let empty_title = gtk::Label::new(Some(title));
         empty_title.add_css_class("empty-title");
 
-        let empty_subtitle = gtk::Label::new(Some("Copy something and come back here"));
+        let empty_subtitle = gtk::Label::new(Some(subtitle));
         empty_subtitle.add_css_class("empty-subtitle");
 
         empty_box.append(&empty_title);
@@ -254,17 +785,63 @@ impl Gui {
         items_box.append(&empty_box);
     }
 
-    fn construct_image(
+    /// Shown instead of the empty state when the daemon can't be reached.
+    fn daemon_down_state(items_box: &gtk::Box) {
+        let down_box = gtk::Box::new(gtk::Orientation::Vertical, 8);
+        down_box.set_valign(gtk::Align::Center);
+        down_box.set_vexpand(true);
+        down_box.add_css_class("daemon-down");
+
+        let down_title = gtk::Label::new(Some("Clipboard service not running"));
+        down_title.add_css_class("empty-title");
+
+        let command = match Self::profile() {
+            DEFAULT_PROFILE => "super_v start".to_string(),
+            profile => format!("super_v --profile {profile} start"),
+        };
+        let down_subtitle = gtk::Label::new(Some(&format!("Run `{command}` to start it")));
+        down_subtitle.add_css_class("empty-subtitle");
+        down_subtitle.set_selectable(true);
+
+        down_box.append(&down_title);
+        down_box.append(&down_subtitle);
+        items_box.append(&down_box);
+    }
+
+    /// Shown instead of the empty state when the daemon keeps no history at all.
+    fn history_off_state(items_box: &gtk::Box) {
+        let off_box = gtk::Box::new(gtk::Orientation::Vertical, 8);
+        off_box.set_valign(gtk::Align::Center);
+        off_box.set_vexpand(true);
+
+        let off_title = gtk::Label::new(Some("Clipboard history is off"));
+        off_title.add_css_class("empty-title");
+
+        let off_subtitle = gtk::Label::new(Some("history_size is 0, so copies aren't kept"));
+        off_subtitle.add_css_class("empty-subtitle");
+
+        off_box.append(&off_title);
+        off_box.append(&off_subtitle);
+        items_box.append(&off_box);
+    }
+
+    /// Preview of an image entry, `None` if its size is unusable.
+    ///
+    /// A `sensitive` entry is never written to the thumbnail cache, and a preview
+    /// cached before it was marked is removed.
+    fn construct_image<'a>(
         width: usize,
         height: usize,
-        bytes: Vec<u8>,
-        cache: &Rc<std::cell::RefCell<HashMap<Vec<u8>, Texture>>>,
+        thumb_key: &str,
+        sensitive: bool,
+        pixels: impl FnOnce() -> Option<Cow<'a, [u8]>>,
+        cache: &Rc<RefCell<LruCache<String, Texture>>>,
     ) -> Option<gtk::Picture> {
         const IMAGE_PREVIEW_TEXTURE_MAX_SIZE: usize = 200;
         const IMAGE_PREVIEW_DISPLAY_SIZE: i32 = 50;
 
-        // 1. Check cache first
-        if let Some(texture) = cache.borrow().get(&bytes) {
+        // 1. Check cache first. A hit marks the texture as recently used.
+        if let Some(texture) = cache.borrow_mut().get(thumb_key) {
             let picture = gtk::Picture::for_paintable(texture);
             picture.set_can_shrink(true);
             picture.set_keep_aspect_ratio(true);
@@ -275,36 +852,76 @@ impl Gui {
         }
 
         // 2. If not in cache, create it
+        // Entries come over IPC, so don't trust the declared size.
+        // Returning None makes the caller fall back to the "Image: W x H" label.
         let stride = width.checked_mul(4)?;
         let expected_len = stride.checked_mul(height)?;
+        if width == 0
+            || height == 0
+            || i32::try_from(stride).is_err()
+            || i32::try_from(height).is_err()
+        {
+            warn!("Image {width}x{height} has unusable dimensions, skipping preview");
+            return None;
+        }
 
-        let bytes_owned = gtk::glib::Bytes::from_owned(bytes[..expected_len].to_vec());
-        let mut pixbuf = Pixbuf::from_bytes(
-            &bytes_owned,
-            gdk_pixbuf::Colorspace::Rgb,
-            true,
-            8,
-            width as i32,
-            height as i32,
-            stride as i32,
-        );
+        // 3. Scaling full RGBA is slow, so reuse the preview from an earlier launch.
+        // Pixels are only fetched here, compressed images get decoded on this path alone.
+        // Sensitive entries stay off disk, previews included
+        if sensitive {
+            thumbnails::remove(Self::profile(), thumb_key);
+        }
+        let cached = (!sensitive)
+            .then(|| thumbnails::load(Self::profile(), thumb_key))
+            .flatten();
+        let pixbuf = match cached {
+            Some(pixbuf) => pixbuf,
+            None => {
+                let bytes = pixels()?;
+                if bytes.len() < expected_len {
+                    warn!(
+                        "Image {width}x{height} needs {expected_len} bytes but has {}, skipping preview",
+                        bytes.len()
+                    );
+                    return None;
+                }
+
+                let bytes_owned = gtk::glib::Bytes::from_owned(bytes[..expected_len].to_vec());
+                let mut pixbuf = Pixbuf::from_bytes(
+                    &bytes_owned,
+                    gdk_pixbuf::Colorspace::Rgb,
+                    true,
+                    8,
+                    width as i32,
+                    height as i32,
+                    stride as i32,
+                );
 
-        let max_dim = width.max(height) as f32;
-        if max_dim > IMAGE_PREVIEW_TEXTURE_MAX_SIZE as f32 {
-            let scale = IMAGE_PREVIEW_TEXTURE_MAX_SIZE as f32 / max_dim;
-            let target_width = (width as f32 * scale).round().max(1.0) as i32;
-            let target_height = (height as f32 * scale).round().max(1.0) as i32;
-            if let Some(resized) =
-                pixbuf.scale_simple(target_width, target_height, InterpType::Hyper)
-            {
-                pixbuf = resized;
+                let max_dim = width.max(height) as f32;
+                if max_dim > IMAGE_PREVIEW_TEXTURE_MAX_SIZE as f32 {
+                    let scale = IMAGE_PREVIEW_TEXTURE_MAX_SIZE as f32 / max_dim;
+                    let target_width = (width as f32 * scale).round().max(1.0) as i32;
+                    let target_height = (height as f32 * scale).round().max(1.0) as i32;
+                    if let Some(resized) =
+                        pixbuf.scale_simple(target_width, target_height, InterpType::Hyper)
+                    {
+                        pixbuf = resized;
+                    }
+                }
+
+                if !sensitive {
+                    thumbnails::store(Self::profile(), thumb_key, &pixbuf);
+                }
+                pixbuf
             }
-        }
+        };
 
         let texture = gtk::gdk::Texture::for_pixbuf(&pixbuf);
 
-        // 3. Add the new texture to the cache
-        cache.borrow_mut().insert(bytes, texture.clone()); // <-- Store it
+        // 4. Add the new texture to the cache, evicting the least recently used one when full
+        cache
+            .borrow_mut()
+            .put(thumb_key.to_string(), texture.clone());
 
         let picture = gtk::Picture::for_paintable(&texture);
         picture.set_can_shrink(true);
@@ -315,102 +932,352 @@ impl Gui {
         Some(picture)
     }
 
-    fn render_emojis(&self) {
-        // Clear all widgets instantly
-        while let Some(child) = self.emoji_flow_box.first_child() {
-            self.emoji_flow_box.remove(&child);
-        }
+    /// Opens an editor pre-filled with `text` next to `anchor`.
+    ///
+    /// Paste (or Ctrl+Enter) pastes the edited text, the history entry itself is left
+    /// as it was. Escape or Cancel only closes the editor.
+    fn open_editor(
+        anchor: &gtk::Button,
+        text: &str,
+        window: &gtk::ApplicationWindow,
+        tx: &Sender<MainThreadMsg>,
+    ) {
+        let popover = gtk::Popover::new();
+        popover.add_css_class("edit-popover");
+
+        // TextView rather than Entry so multi-line text keeps its lines
+        let text_view = gtk::TextView::new();
+        text_view.set_wrap_mode(gtk::WrapMode::WordChar);
+        text_view.set_monospace(true);
+        text_view.buffer().set_text(text);
+        text_view.add_css_class("edit-view");
+
+        let scrolled = gtk::ScrolledWindow::new();
+        scrolled.set_child(Some(&text_view));
+        scrolled.set_size_request(280, 140);
+
+        let cancel_btn = gtk::Button::with_label("Cancel");
+        let paste_btn = gtk::Button::with_label("Paste");
+        paste_btn.add_css_class("suggested-action");
+
+        let buttons = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+        buttons.set_halign(gtk::Align::End);
+        buttons.append(&cancel_btn);
+        buttons.append(&paste_btn);
+
+        let editor_box = gtk::Box::new(gtk::Orientation::Vertical, 6);
+        editor_box.append(&scrolled);
+        editor_box.append(&buttons);
+        popover.set_child(Some(&editor_box));
+
+        // Paste the edited text through the usual flow
+        let paste = {
+            let buffer = text_view.buffer();
+            let window = window.clone();
+            let tx = tx.clone();
+            move || {
+                let (start, end) = buffer.bounds();
+                let edited = buffer.text(&start, &end, false).to_string();
+                Self::paste_item(&ClipboardItem::Text(edited), None, &window, &tx);
+            }
+        };
 
-        let search_filter = self.search_entry.text().to_string();
+        let paste_clone = paste.clone();
+        paste_btn.connect_clicked(move |_| paste_clone());
 
-        // 1. Get the full list of emoji strings (this is fast)
-        let emojis: Vec<String> = if !search_filter.trim().is_empty() {
-            emojis::iter()
-                .filter(|e| e.name().contains(&search_filter) && e.as_str() != "🧑‍🩰")
-                .map(|e| e.as_str().to_string())
-                .collect()
-        } else {
-            emojis::iter()
-                .filter(|e| e.as_str() != "🧑‍🩰")
-                .map(|e| e.as_str().to_string())
-                .collect()
+        let popover_clone = popover.clone();
+        cancel_btn.connect_clicked(move |_| popover_clone.popdown());
+
+        // Handle keys here so they never reach the window,
+        // where Escape would close everything
+        let key_controller = gtk::EventControllerKey::new();
+        let popover_clone = popover.clone();
+        key_controller.connect_key_pressed(move |_, key, _, state| match key {
+            Key::Escape => {
+                popover_clone.popdown();
+                gtk::glib::Propagation::Stop
+            }
+            Key::Return | Key::KP_Enter if state.contains(gtk::gdk::ModifierType::CONTROL_MASK) => {
+                paste();
+                gtk::glib::Propagation::Stop
+            }
+            _ => gtk::glib::Propagation::Proceed,
+        });
+        key_controller.set_propagation_phase(gtk::PropagationPhase::Capture);
+        popover.add_controller(key_controller);
+
+        // Built on every click, so drop it once closed
+        popover.set_parent(anchor);
+        popover.connect_closed(|popover| popover.unparent());
+        popover.popup();
+        text_view.grab_focus();
+    }
+
+    /// Longest text offered as a QR code. Phones struggle to scan denser codes.
+    const QR_MAX_TEXT_LEN: usize = 500;
+
+    /// Renders text as a QR code, or a message saying why it can't be.
+    fn construct_qr(text: &str) -> gtk::Widget {
+        const QR_DISPLAY_SIZE: usize = 220;
+        const QUIET_ZONE: usize = 4;
+
+        let message = |msg: &str| {
+            let label = gtk::Label::new(Some(msg));
+            label.add_css_class("qr-message");
+            label.set_wrap(true);
+            label.set_max_width_chars(30);
+            label.upcast::<gtk::Widget>()
         };
 
-        // 2. Wrap the list in Rc for the async loader
-        let emoji_list = Rc::new(emojis);
-        let progress = Rc::new(std::cell::Cell::new(0usize));
-        let chunk_size = 100; // Add 100 emojis per frame
-
-        // 3. Clone everything needed for the async task
-        let emoji_flow_box = self.emoji_flow_box.clone();
-        let window = self.window.clone();
-        let tx = self.main_thread_tx.clone();
-
-        // 4. Start the async loader
-        gtk::glib::idle_add_local(move || {
-            let start = progress.get();
-            let end = (start + chunk_size).min(emoji_list.len());
-
-            // Get the chunk of emojis to add
-            if let Some(emojis_to_add) = emoji_list.get(start..end) {
-                for emoji in emojis_to_add {
-                    let emoji_entry = gtk::Button::with_label(emoji);
-                    emoji_entry.add_css_class("emoji-btn");
-
-                    let window_clone = window.clone();
-                    let tx_clone = tx.clone();
-                    let emoji_str = emoji.clone(); // Clone for the closure
-
-                    emoji_entry.connect_clicked(move |_| {
-                        if let Ok(mut clipboard) = Self::get_clipboard() {
-                            let emoji_str = emoji_str.clone();
-                            let _ = clipboard.set_text(&emoji_str);
-
-                            Self::schedule_emoji_cleanup(tx_clone.clone(), emoji_str.clone());
-                            Self::signal_auto_paste(tx_clone.clone());
-
-                            // manually close window, but don't quit program
-                            // This quits GUI but keeps main thread running
-                            // because of Ydotool thread
-                            // let that be handled by emoji cleanup thread
-                            window_clone.close();
-
-                            // This quits program
-                            // Self::close_window(window_clone.clone(), tx_clone.clone());
-                        }
-                    });
-                    emoji_flow_box.insert(&emoji_entry, -1);
-                }
+        if text.len() > Self::QR_MAX_TEXT_LEN {
+            return message("Too long for a QR code");
+        }
+
+        let code = match QrCode::with_error_correction_level(text.as_bytes(), EcLevel::L) {
+            Ok(code) => code,
+            Err(err) => {
+                warn!("Could not encode QR code: {err}");
+                return message("Could not make a QR code from this text");
             }
+        };
 
-            // Update progress
-            progress.set(end);
+        // Draw each module as a square of pixels, with a white border scanners need
+        let modules = code.width();
+        let colors = code.to_colors();
+        let side = modules + QUIET_ZONE * 2;
+        let scale = (QR_DISPLAY_SIZE / side).max(1);
+        let size = side * scale;
+
+        let mut rgb = vec![0xFFu8; size * size * 3];
+        for (i, color) in colors.iter().enumerate() {
+            if *color != Color::Dark {
+                continue;
+            }
+            let (mx, my) = (i % modules + QUIET_ZONE, i / modules + QUIET_ZONE);
+            for y in my * scale..(my + 1) * scale {
+                let row = y * size * 3;
+                rgb[row + mx * scale * 3..row + (mx + 1) * scale * 3].fill(0);
+            }
+        }
 
-            // If we're done, stop. Otherwise, run again.
-            if end == emoji_list.len() {
-                gtk::glib::ControlFlow::Break
-            } else {
-                gtk::glib::ControlFlow::Continue
+        let pixbuf = Pixbuf::from_bytes(
+            &gtk::glib::Bytes::from_owned(rgb),
+            gdk_pixbuf::Colorspace::Rgb,
+            false,
+            8,
+            size as i32,
+            size as i32,
+            (size * 3) as i32,
+        );
+        let picture = gtk::Picture::for_paintable(&Texture::for_pixbuf(&pixbuf));
+        picture.set_size_request(size as i32, size as i32);
+        picture.set_can_shrink(false);
+        picture.upcast()
+    }
+
+    /// Builds the emoji page: one list of every emoji, shown through a filter on the
+    /// search text.
+    ///
+    /// The grid only creates buttons for the cells on screen and rebinds them while
+    /// scrolling, so searching just refilters the list (see `filter_emojis`) instead
+    /// of rebuilding thousands of buttons.
+    ///
+    /// # Returns
+    /// The grid, and the filter to refresh when the search text changes.
+    fn emoji_grid(
+        search_entry: &gtk::Entry,
+        window: &gtk::ApplicationWindow,
+        tx: &Sender<MainThreadMsg>,
+    ) -> (gtk::GridView, gtk::CustomFilter) {
+        let all_emojis: Vec<&str> = emojis::iter()
+            .map(|e| e.as_str())
+            .filter(|e| *e != "🧑‍🩰")
+            .collect();
+        let emoji_list = gtk::StringList::new(&all_emojis);
+
+        let search_entry = search_entry.clone();
+        let emoji_filter = gtk::CustomFilter::new(move |object| {
+            let query = search_entry.text();
+            if query.trim().is_empty() {
+                return true;
+            }
+            object
+                .downcast_ref::<gtk::StringObject>()
+                .and_then(|emoji| emojis::get(&emoji.string()))
+                .is_some_and(|emoji| emoji.name().contains(query.as_str()))
+        });
+        let filtered = gtk::FilterListModel::new(Some(emoji_list), Some(emoji_filter.clone()));
+
+        // Each button is made once and only relabeled when it scrolls onto another emoji,
+        // so the click handler reads the emoji from its label
+        let factory = gtk::SignalListItemFactory::new();
+        let window = window.clone();
+        let tx = tx.clone();
+        factory.connect_setup(move |_, object| {
+            let Some(list_item) = object.downcast_ref::<gtk::ListItem>() else {
+                return;
+            };
+            let emoji_entry = gtk::Button::new();
+            emoji_entry.add_css_class("emoji-btn");
+
+            let window = window.clone();
+            let tx = tx.clone();
+            emoji_entry.connect_clicked(move |button| {
+                if let Some(emoji) = button.label() {
+                    Self::pick_emoji(&window, &tx, emoji.to_string());
+                }
+            });
+            list_item.set_child(Some(&emoji_entry));
+        });
+        factory.connect_bind(|_, object| {
+            let Some(list_item) = object.downcast_ref::<gtk::ListItem>() else {
+                return;
+            };
+            let emoji = list_item.item().and_downcast::<gtk::StringObject>();
+            let button = list_item.child().and_downcast::<gtk::Button>();
+            if let (Some(emoji), Some(button)) = (emoji, button) {
+                button.set_label(&emoji.string());
             }
         });
+
+        let emoji_grid =
+            gtk::GridView::new(Some(gtk::NoSelection::new(Some(filtered))), Some(factory));
+        emoji_grid.add_css_class("emoji-box");
+        emoji_grid.set_max_columns(7);
+        emoji_grid.set_min_columns(4);
+        emoji_grid.set_vexpand(true);
+
+        (emoji_grid, emoji_filter)
     }
 
-    fn render_clipboard_items(&self) {
-        let history = Self::fetch_history();
-        let items = history.get_items();
+    /// Puts `emoji` on the clipboard, with a space after it if `emoji_trailing_space`
+    /// is set, pastes it and closes the window.
+    ///
+    /// The daemon is told not to capture it (see `CmdIPC::ExpectNext`), so picking an
+    /// emoji leaves history as it was.
+    fn pick_emoji(window: &gtk::ApplicationWindow, tx: &Sender<MainThreadMsg>, emoji: String) {
+        let config = Self::config();
+        let text = if config.emoji_trailing_space {
+            format!("{emoji} ")
+        } else {
+            emoji
+        };
+
+        if let Ok(mut clipboard) = Self::get_clipboard() {
+            let pasted = ClipboardItem::Text(text);
+            let _ = Self::send_command(CmdIPC::ExpectNext(pasted.clipboard_hash()));
+            let pasted_at = SystemTime::now();
+            if let ClipboardItem::Text(text) = &pasted {
+                let _ = clipboard.set_text(text);
+            }
+
+            // If the daemon captures it anyway, it stores it normalized (the trailing
+            // space may be trimmed), so that is what cleanup looks for
+            let stored = pasted.normalize(config.normalize_text);
+
+            // Cleanup sends Close, so it only starts after the paste signal
+            let tx_cleanup = tx.clone();
+            let window_clone = window.clone();
+            Self::paste_when_hidden(window, tx, move || {
+                Self::schedule_emoji_cleanup(tx_cleanup, stored, pasted_at);
+
+                // manually close window, but don't quit program
+                // This quits GUI but keeps main thread running
+                // because of Ydotool thread
+                // let that be handled by emoji cleanup thread
+                window_clone.close();
+            });
+        }
+    }
+
+    /// Shows only the emojis matching the current search text.
+    fn filter_emojis(&self) {
+        self.emoji_filter.changed(gtk::FilterChange::Different);
+    }
+
+    fn render_clipboard_items(self: &Rc<Self>) {
+        // A pending retry is superseded by this render
+        if let Some(retry) = self.daemon_retry.borrow_mut().take() {
+            retry.remove();
+        }
+
+        let filter = self.clipboard_filter.get();
+        let fetched = Self::fetch_history(
+            self.loaded_limit.get(),
+            filter,
+            self.sort_by_frequency.get(),
+        );
+        let (history, positions) = match fetched {
+            Ok((history, positions, total)) => {
+                self.history_total.set(total);
+                (history, positions)
+            }
+            Err(err) => {
+                debug!("Clipboard service unreachable: {err}");
+                Self::clear_items_box(&self.items_box);
+                self.clipboard_rows.borrow_mut().clear();
+                Self::daemon_down_state(&self.items_box);
+
+                // Keep trying so the list shows up once the daemon is started
+                let gui = self.clone();
+                let retry = gtk::glib::timeout_add_local_once(DAEMON_RETRY_INTERVAL, move || {
+                    gui.daemon_retry.borrow_mut().take();
+                    gui.render_clipboard_items();
+                });
+                *self.daemon_retry.borrow_mut() = Some(retry);
+                return;
+            }
+        };
+
+        // Thumbnail keys for every image still in history, the rest can go
+        let thumb_keys: Vec<Option<String>> = history
+            .get_items()
+            .iter()
+            .map(thumbnails::item_key)
+            .collect();
+        // Only every image in history tells which thumbnails are unused. Once per launch
+        // is enough, renders after that only add previews for new copies.
+        let has_every_image =
+            filter != ClipboardFilter::Text && history.len() >= self.history_total.get();
+        if !self.thumbs_pruned.get() && has_every_image {
+            self.thumbs_pruned.set(true);
+            thumbnails::prune(
+                Self::profile(),
+                &thumb_keys.iter().flatten().cloned().collect(),
+            );
+        }
+
+        // Already filtered and in order
+        let items: Vec<(usize, &ClipboardItem, &Option<String>, &EntryMeta)> = positions
+            .into_iter()
+            .zip(history.get_items())
+            .zip(&thumb_keys)
+            .zip(history.get_meta())
+            .map(|(((pos, item), thumb_key), meta)| (pos, item, thumb_key, meta))
+            .collect();
 
         // Clear all items
         // much easier to just clear and update
         // Than to manage the items in GUI and re-order
         Self::clear_items_box(&self.items_box);
+        self.clipboard_rows.borrow_mut().clear();
 
         // Check if it's empty
+        let loaded = history.len();
+        if history.capacity() == 0 {
+            // Everything is evicted right away, "copy something" would never come true
+            Self::history_off_state(&self.items_box);
+            return;
+        }
         if items.is_empty() {
-            Self::clipboard_empty_state(&self.items_box);
+            Self::clipboard_empty_state(&self.items_box, filter);
+            if loaded < self.history_total.get() {
+                self.show_more_button(loaded);
+            }
             return;
         }
 
-        for item in items.iter() {
+        for (pos, item, thumb_key, meta) in items {
             let revealer = gtk::Revealer::new();
             revealer.set_transition_type(gtk::RevealerTransitionType::SlideUp);
             revealer.set_transition_duration(220);
@@ -424,11 +1291,7 @@ impl Gui {
 
             match item {
                 ClipboardItem::Text(text) => {
-                    let preview = if text.len() > 60 {
-                        format!("{}...", &text[..60])
-                    } else {
-                        text.clone()
-                    };
+                    let preview = preview_text(text, Self::config().preview_chars);
 
                     let content_label = gtk::Label::new(Some(&preview));
                     content_label.set_valign(gtk::Align::Center);
@@ -439,15 +1302,17 @@ impl Gui {
 
                     content_box.append(&content_label);
                 }
-                ClipboardItem::Image {
-                    width,
-                    height,
-                    bytes,
-                } => {
+                ClipboardItem::Image { width, height, .. }
+                | ClipboardItem::CompressedImage { width, height, .. } => {
                     // Replace with image preview
-                    if let Some(picture) =
-                        Self::construct_image(*width, *height, bytes.clone(), &self.image_cache)
-                    {
+                    if let Some(picture) = Self::construct_image(
+                        *width,
+                        *height,
+                        thumb_key.as_deref().unwrap_or_default(),
+                        meta.sensitive,
+                        || item.pixels(),
+                        &self.image_cache,
+                    ) {
                         content_box.append(&picture);
                     } else {
                         let preview = format!("Image: {width} x {height}");
@@ -465,51 +1330,64 @@ impl Gui {
             // Make each item clickable
             let gesture = gtk::GestureClick::new();
             let item_clone = item.clone();
+            let html = meta.html.clone();
             let window_clone = self.window.clone();
             let tx = self.main_thread_tx.clone();
 
             gesture.connect_released(move |_, _, _, _| {
-                if let ClipboardItem::Text(text) = &item_clone
-                    && let Ok(mut clipboard) = Self::get_clipboard()
-                    && !text.trim().is_empty()
-                {
-                    // Update system clipboard
-                    // This says I'm dropping the clipboard too fast (5ms)
-                    // eh... should be just fine.
-                    let _ = clipboard.set_text(text);
+                Self::paste_item(&item_clone, html.as_deref(), &window_clone, &tx);
+            });
 
-                    // Signal for auto paste and close the window
-                    Self::signal_auto_paste(tx.clone());
-                    Self::close_window(window_clone.clone(), tx.clone());
-                    return;
-                }
+            item_box.add_controller(gesture);
 
-                if let ClipboardItem::Image {
-                    width,
-                    height,
-                    bytes,
-                } = &item_clone
-                    && let Ok(mut clipboard) = Self::get_clipboard()
-                    && !bytes.is_empty()
-                {
-                    // Same 5ms drop here...
-                    let _ = clipboard.set_image(ImageData {
-                        width: *width,
-                        height: *height,
-                        bytes: Cow::from(bytes),
+            // Edit button, for text and for images with recognized text
+            let edit_btn = gtk::Button::new();
+            edit_btn.set_icon_name("document-edit-symbolic");
+            edit_btn.set_tooltip_text(Some("Edit before pasting"));
+            edit_btn.add_css_class("delete-btn");
+            edit_btn.set_valign(gtk::Align::Start);
+            let editable_text = match item {
+                ClipboardItem::Text(text) => Some(text),
+                _ => {
+                    edit_btn.set_tooltip_text(Some("Paste text from image"));
+                    meta.ocr_text.as_ref()
+                }
+            };
+            match editable_text {
+                Some(text) => {
+                    let text = text.clone();
+                    let window = self.window.clone();
+                    let tx = self.main_thread_tx.clone();
+                    edit_btn.connect_clicked(move |btn| {
+                        Self::open_editor(btn, &text, &window, &tx);
                     });
-
-                    // Signal for auto paste and close the window
-                    Self::signal_auto_paste(tx.clone());
-                    Self::close_window(window_clone.clone(), tx.clone());
-                    return;
                 }
+                None => edit_btn.set_visible(false),
+            }
 
-                // Close the window
-                Self::close_window(window_clone.clone(), tx.clone());
-            });
-
-            item_box.add_controller(gesture);
+            // QR button, only for text short enough to scan
+            let qr_btn = gtk::Button::new();
+            qr_btn.set_icon_name("view-grid-symbolic");
+            qr_btn.set_tooltip_text(Some("Show QR code"));
+            qr_btn.add_css_class("delete-btn");
+            qr_btn.add_css_class("qr-btn");
+            qr_btn.set_valign(gtk::Align::Start);
+            match item {
+                ClipboardItem::Text(text) if text.len() <= Self::QR_MAX_TEXT_LEN => {
+                    let text = text.clone();
+                    qr_btn.connect_clicked(move |btn| {
+                        let popover = gtk::Popover::new();
+                        popover.add_css_class("qr-popover");
+                        popover.set_child(Some(&Self::construct_qr(&text)));
+                        popover.set_parent(btn);
+
+                        // Built on every click, so drop it once closed
+                        popover.connect_closed(|popover| popover.unparent());
+                        popover.popup();
+                    });
+                }
+                _ => qr_btn.set_visible(false),
+            }
 
             // Delete button for each item
             let delete_btn = gtk::Button::new();
@@ -520,59 +1398,134 @@ impl Gui {
             // Make the delete button functional
             let items_box = self.items_box.clone();
             let item_revealer = revealer.clone();
+            let clipboard_rows = self.clipboard_rows.clone();
+            let selected_row = self.selected_row.clone();
+            let item_to_delete = item.clone();
+            let gui = self.clone();
 
             delete_btn.connect_clicked(move |_| {
-                let current_index = (0..items_box.observe_children().n_items())
-                    .find(|&i| {
-                        items_box
-                            .observe_children()
-                            .item(i)
-                            .and_then(|obj| obj.downcast::<gtk::Revealer>().ok())
-                            .as_ref()
-                            == Some(&item_revealer)
-                    })
-                    .unwrap_or(0) as usize;
-
                 item_revealer.set_reveal_child(false);
 
+                // Forget the row for keyboard navigation
+                // Rows below the deleted one move up, keep the selection on the same row
+                let mut rows = clipboard_rows.borrow_mut();
+                let selected = selected_row.get();
+                if let Some(pos) = rows
+                    .iter()
+                    .position(|(revealer, _, _, _)| revealer == &item_revealer)
+                {
+                    rows.remove(pos);
+                    let selected = if pos < selected {
+                        selected - 1
+                    } else {
+                        selected
+                    };
+                    drop(rows);
+                    gui.select_row(selected);
+                }
+
                 let items_box_for_removal = items_box.clone();
                 let item_revealer_for_removal = item_revealer.clone();
+                let item_to_delete = item_to_delete.clone();
+                let gui = gui.clone();
 
                 gtk::glib::timeout_add_local_once(Duration::from_millis(220), move || {
                     items_box_for_removal.remove(&item_revealer_for_removal);
 
                     if items_box_for_removal.first_child().is_none() {
-                        Self::clipboard_empty_state(&items_box_for_removal);
+                        Self::clipboard_empty_state(&items_box_for_removal, filter);
                     }
 
+                    // Checked against the item, so entries added by the poller since
+                    // the last render can't shift us onto the wrong one
                     thread::spawn(move || {
-                        Self::send_command(CmdIPC::Delete(current_index));
+                        Self::send_command(CmdIPC::DeleteAt {
+                            pos,
+                            item: item_to_delete,
+                        });
                     });
+                    gui.show_undo_toast("Item deleted", 1);
                 });
             });
 
+            // Colors keep their swatch without type icons, it shows more than the text
+            if Self::config().type_icons {
+                item_box.append(&Self::type_icon(item, meta));
+            } else if let Some(swatch) = Self::color_swatch(item) {
+                item_box.append(&swatch);
+            }
             item_box.append(&content_box);
+
+            if meta.copy_count > 1 {
+                let count_label = gtk::Label::new(Some(&format!("×{}", meta.copy_count)));
+                count_label.add_css_class("copy-count");
+                count_label.set_valign(gtk::Align::Start);
+                count_label.set_tooltip_text(Some(&format!("Copied {} times", meta.copy_count)));
+                item_box.append(&count_label);
+            }
+            if meta.sensitive {
+                let lock = gtk::Image::from_icon_name("changes-prevent-symbolic");
+                lock.add_css_class("copy-count");
+                lock.set_valign(gtk::Align::Start);
+                lock.set_tooltip_text(Some("Sensitive: kept off disk, gone when super_v stops"));
+                item_box.append(&lock);
+            }
+            item_box.append(&edit_btn);
+            item_box.append(&qr_btn);
             item_box.append(&delete_btn);
 
             revealer.set_child(Some(&item_box));
             self.items_box.append(&revealer);
+            self.clipboard_rows
+                .borrow_mut()
+                .push((revealer, pos, item.clone(), meta.clone()));
+        }
+
+        // Older entries the daemon holds are fetched on demand
+        if loaded < self.history_total.get() {
+            self.show_more_button(loaded);
         }
+
+        // Start keyboard navigation at the most recent item
+        self.select_row(0);
+    }
+
+    /// Appends a button that fetches the next page of older entries.
+    ///
+    /// # Arguments
+    /// * `loaded` - How many entries are fetched now
+    fn show_more_button(self: &Rc<Self>, loaded: usize) {
+        let remaining = self.history_total.get() - loaded;
+        let show_more_btn = gtk::Button::with_label(&format!("Show more ({remaining} older)"));
+        show_more_btn.add_css_class("show-more-btn");
+        show_more_btn.set_halign(gtk::Align::Center);
+
+        let gui = self.clone();
+        show_more_btn.connect_clicked(move |_| {
+            // Keep the selection on the first newly shown row
+            let shown = gui.clipboard_rows.borrow().len();
+            gui.loaded_limit.set(loaded + GUI_PAGE_SIZE);
+            gui.render_clipboard_items();
+            gui.select_row(shown);
+        });
+        self.items_box.append(&show_more_btn);
     }
 
     /// Handles logic for when the active tab (Stack page) changes.
-    fn handle_tab_switch(&self, stack: &gtk::Stack) {
+    fn handle_tab_switch(self: &Rc<Self>, stack: &gtk::Stack) {
         if let Some(name) = stack.visible_child_name() {
             let is_clipboard = name == "clipboard";
 
             // Toggle visibility of page-specific controls
             self.clear_all_btn.set_visible(is_clipboard);
+            self.filter_box.set_visible(is_clipboard);
             self.search_entry.set_visible(!is_clipboard);
 
             // Call the appropriate render function
             if is_clipboard {
                 self.render_clipboard_items();
             } else {
-                self.render_emojis();
+                self.filter_emojis();
             }
         }
     }
@@ -582,9 +1535,21 @@ impl Gui {
     fn build(self: Rc<Self>, initial_tab: InitialTab) {
         // -------------------- Connect Events ------------------------
         let all_items = self.items_box.clone();
+        let clipboard_rows = self.clipboard_rows.clone();
+        let clipboard_filter = self.clipboard_filter.clone();
+        let gui_clone_clear = self.clone();
 
         // Clear all btn connector
         self.clear_all_btn.connect_clicked(move |_| {
+            // With a filter on, every entry it lists goes, loaded or not. Either way
+            // one undo step puts them back.
+            let filter = clipboard_filter.get();
+            let clear_cmd = match filter {
+                ClipboardFilter::All => CmdIPC::Clear,
+                filter => CmdIPC::ClearMatching(filter),
+            };
+            clipboard_rows.borrow_mut().clear();
+
             let observer = all_items.observe_children();
             let mut revealers: Vec<gtk::Revealer> = Vec::new();
 
@@ -599,9 +1564,9 @@ impl Gui {
 
             if revealers.is_empty() {
                 Self::clear_items_box(&all_items);
-                Self::clipboard_empty_state(&all_items);
-                thread::spawn(|| {
-                    Self::send_command(CmdIPC::Clear);
+                Self::clipboard_empty_state(&all_items, filter);
+                thread::spawn(move || {
+                    Self::send_command(clear_cmd);
                 });
                 return;
             }
@@ -620,6 +1585,7 @@ impl Gui {
             let items_box_after = all_items.clone();
             let spacing_restore = original_spacing;
             let total_delay = 240 + (revealers.len() as u64 * 16);
+            let gui = gui_clone_clear.clone();
 
             gtk::glib::timeout_add_local_once(Duration::from_millis(total_delay), move || {
                 while let Some(child) = items_box_after.first_child() {
@@ -628,14 +1594,54 @@ impl Gui {
 
                 items_box_after.set_spacing(spacing_restore);
 
-                thread::spawn(|| {
-                    Self::send_command(CmdIPC::Clear);
+                thread::spawn(move || {
+                    Self::send_command(clear_cmd);
                 });
 
-                Self::clipboard_empty_state(&items_box_after);
+                Self::clipboard_empty_state(&items_box_after, filter);
+
+                gui.show_undo_toast("History cleared", 1);
             });
         });
 
+        // Undo toast
+        let gui_clone_undo = self.clone();
+        self.undo_btn.connect_clicked(move |_| {
+            gui_clone_undo.hide_undo_toast();
+
+            let steps = gui_clone_undo.undo_steps.get();
+            let undo_cmd = match steps {
+                1 => CmdIPC::Undo,
+                _ => CmdIPC::Batch(vec![CmdIPC::Undo; steps]),
+            };
+            Self::send_command(undo_cmd);
+            gui_clone_undo.render_clipboard_items();
+        });
+
+        // Content filter
+        for (filter, button) in &self.filter_buttons {
+            let filter = *filter;
+            let gui_clone_filter = self.clone();
+            button.connect_toggled(move |button| {
+                // Grouped buttons also fire when they get switched off
+                if !button.is_active() || gui_clone_filter.clipboard_filter.get() == filter {
+                    return;
+                }
+
+                gui_clone_filter.clipboard_filter.set(filter);
+                gui_clone_filter.save_settings();
+                gui_clone_filter.render_clipboard_items();
+            });
+        }
+
+        // Sort order
+        let gui_clone_sort = self.clone();
+        self.sort_button.connect_toggled(move |button| {
+            gui_clone_sort.sort_by_frequency.set(button.is_active());
+            gui_clone_sort.save_settings();
+            gui_clone_sort.render_clipboard_items();
+        });
+
         // Tab Switching
         // `self` is Rc<GUI>, so `self.clone()` clones the Rc
         let gui_clone_stack = self.clone();
@@ -652,13 +1658,18 @@ impl Gui {
 
         // Quit Events
         // Quit when "esc" is pressed
-        let window_clone = self.window.clone(); // Need a new clone for this closure
-        let tx = self.main_thread_tx.clone();
+        // Other keys navigate the clipboard list while it is visible
+        let gui_clone_keys = self.clone();
         let key_controller = gtk::EventControllerKey::new();
         key_controller.connect_key_pressed(move |_, key, _, _| {
             if key == Key::Escape {
-                Self::close_window(window_clone.clone(), tx.clone());
+                Self::close_window(
+                    gui_clone_keys.window.clone(),
+                    gui_clone_keys.main_thread_tx.clone(),
+                );
                 gtk::glib::Propagation::Stop
+            } else if gui_clone_keys.stack.visible_child_name().as_deref() == Some("clipboard") {
+                gui_clone_keys.handle_clipboard_key(key)
             } else {
                 gtk::glib::Propagation::Proceed
             }
@@ -666,20 +1677,40 @@ impl Gui {
         self.window.add_controller(key_controller);
 
         // Quit when focus is lost
-        let window_clone = self.window.clone(); // Need a new clone for this closure
-        let tx = self.main_thread_tx.clone();
-        self.window.connect_is_active_notify(move |window| {
-            if !window.is_active() {
-                Self::close_window(window_clone.clone(), tx.clone());
-            }
-        });
+        // A hidden window is waiting to paste, see `paste_when_hidden`
+        // Some compositors deactivate a window before it ever got focus, so only a
+        // window that had focus closes
+        if Self::config().close_on_focus_loss {
+            let window_clone = self.window.clone(); // Need a new clone for this closure
+            let tx = self.main_thread_tx.clone();
+            let had_focus = Cell::new(false);
+            self.window.connect_is_active_notify(move |window| {
+                if window.is_active() {
+                    had_focus.set(true);
+                } else if had_focus.get() && window.is_visible() {
+                    Self::close_window(window_clone.clone(), tx.clone());
+                }
+            });
+        }
+
+        // Ask for focus again once mapped, the first request of a new window is
+        // dropped by some compositors
+        if Self::config().grab_focus {
+            self.window.connect_map(|window| {
+                let window = window.clone();
+                gtk::glib::idle_add_local_once(move || {
+                    if !window.is_active() {
+                        window.present();
+                    }
+                });
+            });
+        }
 
         // Emoji Search
-        // Clone the Rc for the search entry closure
+        // Refiltering is cheap with the grid's list model, so it runs on every keystroke
         let gui_clone_search = self.clone();
         self.search_entry.connect_changed(move |_| {
-            // Re-render the emoji list every time the text changes
-            gui_clone_search.render_emojis();
+            gui_clone_search.filter_emojis();
         });
         // -----------------------------------------------------------
 
@@ -696,7 +1727,10 @@ fn build_ui(app: &Application, tx: Sender<MainThreadMsg>, initial_tab: InitialTa
     gui.build(initial_tab);
 }
 
-pub fn run_gui(tx: Sender<MainThreadMsg>, tab: InitialTab) {
+pub fn run_gui(tx: Sender<MainThreadMsg>, tab: InitialTab, profile: &str, config: Config) {
+    let _ = PROFILE.set(profile.to_string());
+    let _ = CONFIG.set(config);
+
     gtk::glib::set_application_name("Super V");
     gtk::glib::set_prgname(Some("super_v"));
 
@@ -706,4 +1740,10 @@ pub fn run_gui(tx: Sender<MainThreadMsg>, tab: InitialTab) {
         build_ui(app, tx.clone(), tab);
     });
     app.run_with_args(&Vec::<String>::new());
+
+    // The window is closed before the picked emoji is out of history
+    let cleanup = EMOJI_CLEANUP.lock().ok().and_then(|mut slot| slot.take());
+    if let Some(cleanup) = cleanup {
+        let _ = cleanup.join();
+    }
 }
@@ -1 +1,3 @@
 pub mod clipboard_gui;
+pub mod settings;
+pub mod thumbnails;
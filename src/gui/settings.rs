@@ -0,0 +1,115 @@
+// Standard Crates
+use std::{fs, path::PathBuf};
+
+// External Crates
+use tracing::warn;
+
+// My Crates
+use crate::config::config_dir;
+
+// Filtered by the daemon, so it lives with the IPC types
+pub use crate::common::ClipboardFilter;
+
+// GUI side of the filter
+impl ClipboardFilter {
+    /// Button label.
+    pub fn label(&self) -> &'static str {
+        match self {
+            ClipboardFilter::All => "All",
+            ClipboardFilter::Text => "Text",
+            ClipboardFilter::Images => "Images",
+        }
+    }
+
+    /// Title and subtitle shown when nothing matches.
+    pub fn empty_message(&self) -> (&'static str, &'static str) {
+        match self {
+            ClipboardFilter::All => ("Clipboard empty", "Copy something and come back here"),
+            ClipboardFilter::Text => ("No text in history", "Copied text will show up here"),
+            ClipboardFilter::Images => ("No images in history", "Copied images will show up here"),
+        }
+    }
+
+    fn key(&self) -> &'static str {
+        match self {
+            ClipboardFilter::All => "all",
+            ClipboardFilter::Text => "text",
+            ClipboardFilter::Images => "images",
+        }
+    }
+
+    fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|filter| filter.key() == key)
+    }
+}
+
+/// GUI state that survives between launches.
+///
+/// Stored as `key=value` lines in `$XDG_CONFIG_HOME/super_v/gui_settings`
+/// (falling back to `~/.config`). Unknown keys and bad values are ignored.
+#[derive(Debug, Default)]
+pub struct GuiSettings {
+    pub clipboard_filter: ClipboardFilter,
+
+    /// List the most copied entries first instead of the most recent.
+    pub sort_by_frequency: bool,
+}
+
+impl GuiSettings {
+    const FILE_NAME: &str = "gui_settings";
+
+    /// Location of the settings file, if a config directory can be found.
+    pub fn path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join(Self::FILE_NAME))
+    }
+
+    /// Load the settings, using defaults for anything missing.
+    pub fn load() -> Self {
+        let mut settings = Self::default();
+
+        let Some(contents) = Self::path().and_then(|path| fs::read_to_string(path).ok()) else {
+            return settings;
+        };
+
+        for line in contents.lines() {
+            match line.split_once('=') {
+                Some(("clipboard_filter", value)) => {
+                    if let Some(filter) = ClipboardFilter::from_key(value.trim()) {
+                        settings.clipboard_filter = filter;
+                    }
+                }
+                Some(("sort_by_frequency", value)) => {
+                    if let Ok(sort_by_frequency) = value.trim().parse() {
+                        settings.sort_by_frequency = sort_by_frequency;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        settings
+    }
+
+    /// Write the settings. Failures are logged and otherwise ignored.
+    pub fn save(&self) {
+        let Some(path) = Self::path() else {
+            return;
+        };
+
+        if let Some(dir) = path.parent()
+            && let Err(err) = fs::create_dir_all(dir)
+        {
+            warn!("Could not create {}: {err}", dir.display());
+            return;
+        }
+
+        let contents = format!(
+            "clipboard_filter={}\nsort_by_frequency={}\n",
+            self.clipboard_filter.key(),
+            self.sort_by_frequency
+        );
+        if let Err(err) = fs::write(&path, contents) {
+            warn!("Could not save GUI settings to {}: {err}", path.display());
+        }
+    }
+}
@@ -0,0 +1,132 @@
+// Standard Crates
+use std::{
+    collections::HashSet,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+// External Crates
+use gdk_pixbuf::Pixbuf;
+use tracing::{debug, warn};
+
+// My Crates
+use crate::common::ClipboardItem;
+
+/// Directory holding the scaled image previews of `profile`:
+/// `$XDG_CACHE_HOME/super_v/thumbs/<profile>`, falling back to `~/.cache/super_v/thumbs/<profile>`.
+///
+/// The GUI is a fresh process on every open, so previews only survive in here. Each
+/// profile has its own directory, so pruning one never touches the previews of another.
+pub fn thumbs_dir(profile: &str) -> Option<PathBuf> {
+    let base = env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+
+    Some(base.join("super_v").join("thumbs").join(profile))
+}
+
+/// Cache key for an image, derived from its size and pixels.
+///
+/// 64-bit FNV-1a, which unlike `DefaultHasher` gives the same key in every build, so
+/// previews cached by one version are still found by the next.
+pub fn key(width: usize, height: usize, bytes: &[u8]) -> String {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    let hash = [width as u64, height as u64]
+        .iter()
+        .flat_map(|n| n.to_le_bytes())
+        .chain(bytes.iter().copied())
+        .fold(OFFSET_BASIS, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(PRIME)
+        });
+    format!("{hash:016x}")
+}
+
+/// Cache key for an image item, `None` for text.
+pub fn item_key(item: &ClipboardItem) -> Option<String> {
+    match item {
+        ClipboardItem::Image {
+            width,
+            height,
+            bytes,
+        } => Some(key(*width, *height, bytes)),
+        ClipboardItem::CompressedImage { width, height, png } => Some(key(*width, *height, png)),
+        ClipboardItem::Text(_) => None,
+    }
+}
+
+fn path_for(profile: &str, key: &str) -> Option<PathBuf> {
+    thumbs_dir(profile).map(|dir| dir.join(format!("{key}.png")))
+}
+
+/// Load a cached preview, if there is one.
+pub fn load(profile: &str, key: &str) -> Option<Pixbuf> {
+    let path = path_for(profile, key)?;
+    if !path.exists() {
+        return None;
+    }
+
+    match Pixbuf::from_file(&path) {
+        Ok(pixbuf) => Some(pixbuf),
+        Err(err) => {
+            // Broken file, drop it so it gets rebuilt
+            warn!("Could not load thumbnail {}: {err}", path.display());
+            let _ = fs::remove_file(&path);
+            None
+        }
+    }
+}
+
+/// Save a preview as PNG. Failures are logged and otherwise ignored.
+pub fn store(profile: &str, key: &str, pixbuf: &Pixbuf) {
+    let Some(path) = path_for(profile, key) else {
+        return;
+    };
+
+    if let Some(dir) = path.parent()
+        && let Err(err) = fs::create_dir_all(dir)
+    {
+        warn!("Could not create {}: {err}", dir.display());
+        return;
+    }
+
+    if let Err(err) = pixbuf.savev(&path, "png", &[]) {
+        warn!("Could not save thumbnail {}: {err}", path.display());
+    }
+}
+
+/// Remove the cached preview of `key`, e.g. once its entry is marked sensitive.
+pub fn remove(profile: &str, key: &str) {
+    if let Some(path) = path_for(profile, key)
+        && path.exists()
+    {
+        debug!("Removing thumbnail {}", path.display());
+        if let Err(err) = fs::remove_file(&path) {
+            warn!("Could not remove thumbnail {}: {err}", path.display());
+        }
+    }
+}
+
+/// Remove every cached preview of `profile` whose key is not in `keep`.
+///
+/// `keep` has to cover the whole history. The GUI runs this once per launch.
+pub fn prune(profile: &str, keep: &HashSet<String>) {
+    let Some(entries) = thumbs_dir(profile).and_then(|dir| fs::read_dir(dir).ok()) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let is_kept = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .is_some_and(|stem| keep.contains(stem));
+
+        if !is_kept && path.extension().is_some_and(|ext| ext == "png") {
+            debug!("Removing stale thumbnail {}", path.display());
+            let _ = fs::remove_file(&path);
+        }
+    }
+}
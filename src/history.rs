@@ -1,12 +1,201 @@
 // Standard Crates
 #[allow(unused)]
-use std::{collections::VecDeque, fmt};
+use std::{
+    cmp::Reverse,
+    collections::VecDeque,
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 // External Crates
-use crate::common::{ClipboardError, ClipboardItem};
+use crate::common::{ClipboardError, ClipboardFilter, ClipboardItem, DedupScope, Selection};
 use serde::{Deserialize, Serialize};
 
 // --------------------- Hist Implementation -------------------------
+/// Bookkeeping kept alongside each item in the history.
+///
+/// Stored in a deque parallel to the items so `get_items` keeps returning plain
+/// `ClipboardItem`s.
+#[allow(unused)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct EntryMeta {
+    /// When the item was last copied.
+    pub copied_at: SystemTime,
+
+    /// Text recognized in an image entry. Only set by daemons built with the `ocr` feature.
+    #[serde(default)]
+    pub ocr_text: Option<String>,
+
+    /// HTML the clipboard offered next to a text entry, so a paste can offer both.
+    #[serde(default)]
+    pub html: Option<String>,
+
+    /// Selection the item was last copied from.
+    #[serde(default)]
+    pub selection: Selection,
+
+    /// How many times the item was copied. Promoting a duplicate counts as a copy.
+    #[serde(default = "EntryMeta::first_copy")]
+    pub copy_count: u32,
+
+    /// Marked with `ClipboardHistory::mark_sensitive`: never written to disk (see
+    /// `SqliteStore` and `backup`), and dropped when the daemon stops.
+    #[serde(default)]
+    pub sensitive: bool,
+}
+
+impl EntryMeta {
+    /// Meta for an item copied for the first time.
+    pub fn new(copied_at: SystemTime) -> Self {
+        Self {
+            copied_at,
+            ocr_text: None,
+            html: None,
+            selection: Selection::Clipboard,
+            copy_count: Self::first_copy(),
+            sensitive: false,
+        }
+    }
+
+    fn first_copy() -> u32 {
+        1
+    }
+
+    /// Whether the entry was new at `since`: copied once, at or after that time.
+    ///
+    /// False for an entry that was only promoted since, e.g. text the user had copied
+    /// before a tool put it on the clipboard again.
+    pub fn is_new_since(&self, since: SystemTime) -> bool {
+        self.copy_count == Self::first_copy() && self.copied_at >= since
+    }
+}
+
+/// Most recent removals `ClipboardHistory::undo` can revert. Older ones are forgotten.
+pub const UNDO_LIMIT: usize = 10;
+
+/// Items taken out of a history by one delete or clear, so they can be put back.
+#[derive(PartialEq, Debug, Clone)]
+pub(crate) struct Removal {
+    /// The removed items with the position each had, in their original order.
+    entries: Vec<(usize, ClipboardItem, EntryMeta)>,
+}
+
+/// The last `UNDO_LIMIT` removals, most recent last.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub(crate) struct UndoStack(VecDeque<Removal>);
+
+impl UndoStack {
+    /// Remembers that `entries` were removed from `start` on.
+    pub(crate) fn record(&mut self, start: usize, entries: Vec<(ClipboardItem, EntryMeta)>) {
+        let entries = (start..)
+            .zip(entries)
+            .map(|(pos, (item, meta))| (pos, item, meta))
+            .collect();
+        self.push(Removal { entries });
+    }
+
+    /// Remembers a removal, forgetting the oldest one past `UNDO_LIMIT`.
+    /// Removing nothing is not recorded.
+    pub(crate) fn push(&mut self, removal: Removal) {
+        if removal.entries.is_empty() {
+            return;
+        }
+
+        self.0.push_back(removal);
+        if self.0.len() > UNDO_LIMIT {
+            self.0.pop_front();
+        }
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<Removal> {
+        self.0.pop_back()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+/// Formats an entry as a one line JSON object, e.g. for `super_v list`.
+///
+/// Text is included in full, images only by their size. `copied_at` is in Unix
+/// milliseconds:
+///
+/// ```text
+/// {"kind":"text","text":"Hello","copied_at":1700000000000,"copy_count":1,"selection":"clipboard"}
+/// {"kind":"image","width":1920,"height":1080,"copied_at":1700000000000,"copy_count":2,"selection":"clipboard"}
+/// ```
+///
+/// # Arguments
+///
+/// * `item` - The entry's item
+/// * `meta` - The entry's meta
+pub fn entry_to_json(item: &ClipboardItem, meta: &EntryMeta) -> String {
+    let content = match item {
+        ClipboardItem::Text(text) => ListContent::Text { text },
+        ClipboardItem::Image { width, height, .. }
+        | ClipboardItem::CompressedImage { width, height, .. } => ListContent::Image {
+            width: *width,
+            height: *height,
+        },
+    };
+    let entry = ListEntry {
+        content,
+        copied_at: meta
+            .copied_at
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_millis())
+            .unwrap_or(0),
+        copy_count: meta.copy_count,
+        selection: meta.selection,
+    };
+
+    // Only strings and integers, none of which can fail to serialize
+    serde_json::to_string(&entry).expect("list entries always serialize")
+}
+
+/// One line of `entry_to_json`.
+#[derive(Serialize)]
+struct ListEntry<'a> {
+    #[serde(flatten)]
+    content: ListContent<'a>,
+    copied_at: u128,
+    copy_count: u32,
+    selection: Selection,
+}
+
+/// What a line shows of the item, told apart by its `kind` field.
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum ListContent<'a> {
+    Text { text: &'a str },
+    Image { width: usize, height: usize },
+}
+
+/// Item counts and approximate size of a history.
+///
+/// `bytes` sums text lengths and raw image buffers, so it ignores bookkeeping overhead.
+/// `max_age_secs` is the daemon's `max_age_secs` setting, a history alone leaves it `None`.
+#[allow(unused)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct HistoryStats {
+    pub total: usize,
+    pub text: usize,
+    pub images: usize,
+    pub bytes: usize,
+    #[serde(default)]
+    pub max_age_secs: Option<u64>,
+}
+
+impl HistoryStats {
+    /// Formats the stats as a flat JSON object, e.g. for a status bar.
+    /// `max_age_secs` is `null` when entries never expire.
+    pub fn to_json(&self) -> String {
+        // Only integers, which can't fail to serialize
+        serde_json::to_string(self).expect("stats always serialize")
+    }
+}
+
 /// A clipboard history manager that maintains a fixed-size queue of clipboard items.
 ///
 /// This structure keeps track of clipboard items in a VecDeque, automatically managing
@@ -15,7 +204,20 @@ use serde::{Deserialize, Serialize};
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct ClipboardHistory {
     history: VecDeque<ClipboardItem>,
+    meta: VecDeque<EntryMeta>,
     max_size: usize,
+
+    // Daemon settings rather than state, so they are not sent over IPC
+    #[serde(skip)]
+    dedup_window: Option<Duration>,
+    #[serde(skip)]
+    dedup_scope: DedupScope,
+    #[serde(skip)]
+    max_total_bytes: Option<usize>,
+
+    // Only the daemon can undo, so clients don't need it
+    #[serde(skip)]
+    undo: UndoStack,
 }
 
 #[allow(unused)]
@@ -28,73 +230,282 @@ impl ClipboardHistory {
     pub fn new(max_size: usize) -> Self {
         Self {
             history: VecDeque::with_capacity(max_size),
+            meta: VecDeque::with_capacity(max_size),
             max_size,
+            dedup_window: None,
+            dedup_scope: DedupScope::All,
+            max_total_bytes: None,
+            undo: UndoStack::default(),
         }
     }
 
+    /// Limits duplicate promotion to recent copies.
+    ///
+    /// A duplicate last copied more than `window` ago is added as a new entry instead
+    /// of being promoted, so re-copying something long after counts as a new event.
+    /// `None` (the default) always promotes.
+    ///
+    /// # Arguments
+    ///
+    /// * `window` - How long a copy stays eligible for promotion
+    pub fn with_dedup_window(mut self, window: Option<Duration>) -> Self {
+        self.dedup_window = window;
+        self
+    }
+
+    /// Picks which kinds of items are promoted when copied again.
+    ///
+    /// Items outside `scope` are always added as new entries, e.g. with
+    /// `DedupScope::Text` two copies of the same screenshot are kept apart.
+    /// `DedupScope::All` (the default) promotes every kind.
+    ///
+    /// # Arguments
+    ///
+    /// * `scope` - The kinds of items to deduplicate
+    pub fn with_dedup_scope(mut self, scope: DedupScope) -> Self {
+        self.dedup_scope = scope;
+        self
+    }
+
+    /// Caps the total size of the history, on top of the item count.
+    ///
+    /// When an add takes the history over `max_total_bytes` (as counted by
+    /// `ClipboardItem::size_bytes`), the oldest items are dropped until it fits. The most
+    /// recent item is always kept, even if it alone is over the budget.
+    /// `None` (the default) only limits the count.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_total_bytes` - How many bytes of content the history may hold
+    pub fn with_max_total_bytes(mut self, max_total_bytes: Option<usize>) -> Self {
+        self.max_total_bytes = max_total_bytes;
+        self
+    }
+
     /// Adds a new clipboard item to the history.
     ///
     /// If the item already exists in history, it will be promoted to the front
-    /// instead of creating a duplicate. If the history exceeds max_size after
-    /// adding, the oldest item is removed.
+    /// instead of creating a duplicate, unless it is older than the dedup window or
+    /// outside the dedup scope.
+    /// If the history exceeds max_size or the byte budget after adding, the oldest
+    /// items are removed.
     ///
     /// # Arguments
     ///
     /// * `item` - The ClipboardItem to add to history
     pub fn add(&mut self, item: ClipboardItem) {
-        // Check for item duplicates
-        if let Some(pos) = self.history.iter().position(|i| i == &item) {
+        self.add_at(item, SystemTime::now());
+    }
+
+    /// Adds a new clipboard item to the history, recording it as copied at `copied_at`.
+    ///
+    /// Behaves like `add`. A promoted duplicate has its timestamp refreshed and its
+    /// copy count increased.
+    ///
+    /// Forgets what `undo` could restore, see `undo`.
+    ///
+    /// # Arguments
+    ///
+    /// * `item` - The ClipboardItem to add to history
+    /// * `copied_at` - The time the item was copied
+    pub fn add_at(&mut self, item: ClipboardItem, copied_at: SystemTime) {
+        self.undo.clear();
+
+        // Check for item duplicates. The first match is the most recent copy.
+        if self.dedup_scope.covers(&item)
+            && let Some(pos) = self.position(&item)
+            && is_recent_duplicate(self.dedup_window, self.meta[pos].copied_at, copied_at)
+        {
             // It already exists. Promote it.
             self.promote(pos);
+            if let Some(meta) = self.meta.front_mut() {
+                meta.copied_at = copied_at;
+                meta.copy_count = meta.copy_count.saturating_add(1);
+            }
             return;
         }
 
+        self.push_at(item, EntryMeta::new(copied_at));
+    }
+
+    /// Puts an item at the front without checking for duplicates.
+    ///
+    /// For rebuilding a history that may already hold copies outside the dedup window.
+    /// If the history exceeds max_size or the byte budget after adding, the oldest
+    /// items are removed.
+    pub(crate) fn push_at(&mut self, item: ClipboardItem, meta: EntryMeta) {
         // Add to 0 (front)
         self.history.push_front(item);
+        self.meta.push_front(meta);
 
         // Remove old items as size exceeds
-        if self.history.len() > self.max_size {
-            self.history.pop_back();
+        self.evict_over_capacity();
+    }
+
+    /// Drops the oldest items until the history fits `max_size` and the byte budget.
+    /// The most recent item stays, see `with_max_total_bytes`.
+    fn evict_over_capacity(&mut self) {
+        self.history.truncate(self.max_size);
+        self.meta.truncate(self.max_size);
+
+        let Some(budget) = self.max_total_bytes else {
+            return;
+        };
+
+        let mut total: usize = self.history.iter().map(ClipboardItem::size_bytes).sum();
+        while total > budget && self.history.len() > 1 {
+            if let Some(oldest) = self.history.pop_back() {
+                total -= oldest.size_bytes();
+            }
+            self.meta.pop_back();
         }
     }
 
     /// Promotes an item at the given position to the front of the history.
     ///
+    /// Promoting the front item (`pos == 0`) is a no-op. Any other promotion forgets
+    /// what `undo` could restore.
+    ///
     /// # Arguments
     ///
     /// * `pos` - The index of the item to promote
     ///
-    /// # Panics
+    /// # Returns
     ///
-    /// Panics if the position is out of bounds
-    pub fn promote(&mut self, pos: usize) -> Result<(), ClipboardError> {
-        // Remove item as 'pos'th index
-        match self.history.remove(pos) {
-            Some(item) => {
-                self.history.push_front(item);
-                Ok(())
+    /// The item's new index, always 0. Use `preview` to describe it without a copy.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClipboardError::IndexOutOfBound` if the position is out of bounds
+    pub fn promote(&mut self, pos: usize) -> Result<usize, ClipboardError> {
+        // Remove item as 'pos'th index, unless it is already at the front
+        if pos != 0 {
+            match (self.history.remove(pos), self.meta.remove(pos)) {
+                (Some(item), Some(meta)) => {
+                    self.history.push_front(item);
+                    self.meta.push_front(meta);
+                    self.undo.clear();
+                }
+                _ => return Err(ClipboardError::IndexOutOfBound),
             }
+        }
+
+        match self.history.front() {
+            Some(_) => Ok(0),
             None => Err(ClipboardError::IndexOutOfBound),
         }
     }
 
+    /// Short description of the item at `pos`, see `ClipboardItem::preview`.
+    ///
+    /// # Arguments
+    ///
+    /// * `pos` - The index of the item, 0 being the most recent
+    /// * `max_chars` - How many characters of text to keep
+    ///
+    /// # Returns
+    ///
+    /// `None` if the position is out of bounds.
+    pub fn preview(&self, pos: usize, max_chars: usize) -> Option<String> {
+        self.history
+            .get(pos)
+            .map(|item| item.preview(max_chars).into_owned())
+    }
+
+    /// Moves the item at `from` so that it ends up at index `to`.
+    ///
+    /// Items between the two positions shift by one to make room.
+    /// Moving an item onto its own position is a no-op. Any other move forgets what
+    /// `undo` could restore.
+    ///
+    /// # Arguments
+    ///
+    /// * `from` - The current index of the item
+    /// * `to` - The index the item should end up at
+    ///
+    /// # Returns
+    ///
+    /// The index the item ended up at, which is `to`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClipboardError::IndexOutOfBound` if either index is out of bounds.
+    pub fn move_to(&mut self, from: usize, to: usize) -> Result<usize, ClipboardError> {
+        if from >= self.history.len() || to >= self.history.len() {
+            return Err(ClipboardError::IndexOutOfBound);
+        }
+
+        if from == to {
+            return Ok(to);
+        }
+
+        match (self.history.remove(from), self.meta.remove(from)) {
+            (Some(item), Some(meta)) => {
+                self.history.insert(to, item);
+                self.meta.insert(to, meta);
+                self.undo.clear();
+                Ok(to)
+            }
+            _ => Err(ClipboardError::IndexOutOfBound),
+        }
+    }
+
     /// Delets an item at the given position from history.
     ///
     /// # Arguments
     ///
     /// * `pos` - The index of the item to delete
     ///
-    /// # Panics
+    /// # Returns
     ///
-    /// Panics if the position is out of bounds
-    pub fn delete(&mut self, pos: usize) -> Result<(), ClipboardError> {
-        match self.history.remove(pos) {
-            Some(_) => Ok(()),
-            None => Err(ClipboardError::IndexOutOfBound),
+    /// The deleted item.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClipboardError::IndexOutOfBound` if the position is out of bounds
+    pub fn delete(&mut self, pos: usize) -> Result<ClipboardItem, ClipboardError> {
+        match (self.history.remove(pos), self.meta.remove(pos)) {
+            (Some(item), Some(meta)) => {
+                self.undo.record(pos, vec![(item.clone(), meta)]);
+                Ok(item)
+            }
+            _ => Err(ClipboardError::IndexOutOfBound),
         }
     }
 
-    /// Delets an item at the given item from history.
+    /// Deletes the items in positions `start..end` from history.
+    ///
+    /// The range is half-open like a Rust range: `start` is removed, `end` is not.
+    /// `delete_range(5, len)` keeps the top five, `delete_range(0, len)` removes everything
+    /// and `start == end` removes nothing.
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - The index of the first item to delete
+    /// * `end` - The index one past the last item to delete
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClipboardError::IndexOutOfBound` if `start > end` or `end` is past the
+    /// end of the history. Nothing is deleted in that case.
+    pub fn delete_range(&mut self, start: usize, end: usize) -> Result<(), ClipboardError> {
+        if start > end || end > self.history.len() {
+            return Err(ClipboardError::IndexOutOfBound);
+        }
+
+        let entries = self
+            .history
+            .drain(start..end)
+            .zip(self.meta.drain(start..end))
+            .collect();
+        self.undo.record(start, entries);
+        Ok(())
+    }
+
+    /// Deletes the given item from history.
+    ///
+    /// If the item is in history more than once (see `DedupScope`), only the first,
+    /// most recent match is deleted.
     ///
     /// # Arguments
     ///
@@ -104,14 +515,201 @@ impl ClipboardHistory {
     ///
     /// Returns `ClipboardError::IndexOutOfBound` if the item does not exist in the history.
     pub fn delete_this(&mut self, item_to_remove: ClipboardItem) -> Result<(), ClipboardError> {
-        if let Some(index_to_remove) = self.history.iter().position(|item| *item == item_to_remove)
-        {
-            self.delete(index_to_remove)
+        if let Some(index_to_remove) = self.position(&item_to_remove) {
+            self.delete(index_to_remove).map(|_| ())
         } else {
             Err(ClipboardError::IndexOutOfBound)
         }
     }
 
+    /// Deletes the entry at `pos` if it holds `item`, otherwise the most recent copy of `item`.
+    ///
+    /// Unlike `delete_this`, this picks the right one of several equal entries (see
+    /// `DedupScope`), and unlike `delete` it still finds the item if the history shifted
+    /// since `pos` was read.
+    ///
+    /// # Arguments
+    ///
+    /// * `pos` - Where the item was in the caller's snapshot
+    /// * `item` - The item to delete
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClipboardError::IndexOutOfBound` if the item does not exist in the history.
+    pub fn delete_at(&mut self, pos: usize, item: &ClipboardItem) -> Result<(), ClipboardError> {
+        let pos = if self.history.get(pos) == Some(item) {
+            pos
+        } else {
+            self.position(item).ok_or(ClipboardError::IndexOutOfBound)?
+        };
+        self.delete(pos).map(|_| ())
+    }
+
+    /// Promotes the given item to the front of the history.
+    ///
+    /// Unlike `promote`, this does not depend on the item's position, so it still
+    /// targets the right entry if the history changed since the caller looked at it.
+    ///
+    /// # Arguments
+    ///
+    /// * `item` - The item to promote
+    ///
+    /// # Returns
+    ///
+    /// The index the item ended up at.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClipboardError::IndexOutOfBound` if the item does not exist in the history.
+    pub fn promote_this(
+        &mut self,
+        item_to_promote: ClipboardItem,
+    ) -> Result<usize, ClipboardError> {
+        if let Some(index_to_promote) = self.position(&item_to_promote) {
+            self.promote(index_to_promote)
+        } else {
+            Err(ClipboardError::IndexOutOfBound)
+        }
+    }
+
+    /// Stores the text recognized in `item`, wherever it is now.
+    ///
+    /// # Arguments
+    ///
+    /// * `item` - The image the text was read from
+    /// * `text` - The recognized text
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClipboardError::IndexOutOfBound` if the item does not exist in the history.
+    pub fn set_ocr_text(
+        &mut self,
+        item: &ClipboardItem,
+        text: String,
+    ) -> Result<(), ClipboardError> {
+        match self.position(item) {
+            Some(pos) => {
+                self.meta[pos].ocr_text = Some(text);
+                Ok(())
+            }
+            None => Err(ClipboardError::IndexOutOfBound),
+        }
+    }
+
+    /// Stores the HTML copied along with the entry at `pos`, which must hold `item`.
+    ///
+    /// Replaces what an earlier copy of the item offered, so `None` clears it.
+    ///
+    /// # Arguments
+    ///
+    /// * `pos` - The index of the entry, 0 right after it was added
+    /// * `item` - The text the HTML was copied with
+    /// * `html` - The HTML, if the clipboard offered any
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClipboardError::IndexOutOfBound` if the position is out of bounds or
+    /// holds another item.
+    pub fn set_html(
+        &mut self,
+        pos: usize,
+        item: &ClipboardItem,
+        html: Option<String>,
+    ) -> Result<(), ClipboardError> {
+        self.meta_holding(pos, item)?.html = html;
+        Ok(())
+    }
+
+    /// Records the selection the entry at `pos`, which must hold `item`, was last
+    /// copied from.
+    ///
+    /// # Arguments
+    ///
+    /// * `pos` - The index of the entry, 0 right after it was added
+    /// * `item` - The copied item
+    /// * `selection` - The selection it was copied from
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClipboardError::IndexOutOfBound` if the position is out of bounds or
+    /// holds another item.
+    pub fn set_selection(
+        &mut self,
+        pos: usize,
+        item: &ClipboardItem,
+        selection: Selection,
+    ) -> Result<(), ClipboardError> {
+        self.meta_holding(pos, item)?.selection = selection;
+        Ok(())
+    }
+
+    // Metadata of the entry at `pos`, if that entry is `item`
+    fn meta_holding(
+        &mut self,
+        pos: usize,
+        item: &ClipboardItem,
+    ) -> Result<&mut EntryMeta, ClipboardError> {
+        match (self.history.get(pos), self.meta.get_mut(pos)) {
+            (Some(found), Some(meta)) if found == item => Ok(meta),
+            _ => Err(ClipboardError::IndexOutOfBound),
+        }
+    }
+
+    /// Marks the entry at `pos` as sensitive, see `EntryMeta::sensitive`. Copying it
+    /// again keeps the mark.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClipboardError::IndexOutOfBound` if the position is out of bounds
+    pub fn mark_sensitive(&mut self, pos: usize) -> Result<(), ClipboardError> {
+        let meta = self
+            .meta
+            .get_mut(pos)
+            .ok_or(ClipboardError::IndexOutOfBound)?;
+        meta.sensitive = true;
+        Ok(())
+    }
+
+    /// Removes every sensitive entry, as the daemon does when it stops.
+    ///
+    /// # Returns
+    ///
+    /// The number of removed entries.
+    pub fn drop_sensitive(&mut self) -> usize {
+        let before = self.history.len();
+        let mut keep = self.meta.iter().map(|meta| !meta.sensitive);
+        self.history.retain(|_| keep.next().unwrap_or(true));
+        self.meta.retain(|meta| !meta.sensitive);
+        before - self.history.len()
+    }
+
+    /// Returns whether `item` is anywhere in the history.
+    pub fn contains(&self, item: &ClipboardItem) -> bool {
+        self.position(item).is_some()
+    }
+
+    /// Returns the index of the most recent entry equal to `item`, if any.
+    ///
+    /// 0 is the front (most recent), same as the indices `promote` and `delete` take.
+    pub fn position(&self, item: &ClipboardItem) -> Option<usize> {
+        self.history.iter().position(|i| i == item)
+    }
+
+    /// Returns the number of items in the history.
+    pub fn len(&self) -> usize {
+        self.history.len()
+    }
+
+    /// Returns whether the history has no items.
+    pub fn is_empty(&self) -> bool {
+        self.history.is_empty()
+    }
+
+    /// Returns the most items the history keeps, the `max_size` it was created with.
+    pub fn capacity(&self) -> usize {
+        self.max_size
+    }
+
     /// Returns a reference to all items in the clipboard history.
     ///
     /// Items are ordered from most recent (front) to oldest (back).
@@ -119,9 +717,676 @@ impl ClipboardHistory {
         &self.history
     }
 
+    /// Returns the bookkeeping for every item, in the same order as `get_items`.
+    pub fn get_meta(&self) -> &VecDeque<EntryMeta> {
+        &self.meta
+    }
+
+    /// Copies the entries at `offset..offset + limit` (0 = most recent) into a history
+    /// of their own, with the same settings.
+    ///
+    /// The range is cut off at the end of the history, so the page may hold fewer than
+    /// `limit` entries, or none if `offset` is past the end.
+    ///
+    /// # Arguments
+    ///
+    /// * `offset` - Position of the first entry
+    /// * `limit` - How many entries to copy at most
+    pub fn range(&self, offset: usize, limit: usize) -> ClipboardHistory {
+        ClipboardHistory {
+            history: self
+                .history
+                .iter()
+                .skip(offset)
+                .take(limit)
+                .cloned()
+                .collect(),
+            meta: self.meta.iter().skip(offset).take(limit).cloned().collect(),
+            max_size: self.max_size,
+            dedup_window: self.dedup_window,
+            dedup_scope: self.dedup_scope,
+            max_total_bytes: self.max_total_bytes,
+            undo: UndoStack::default(),
+        }
+    }
+
+    /// Like `range`, but over the entries `filter` lists, the most copied first with
+    /// `by_frequency`. Equally copied entries stay most recent first.
+    ///
+    /// # Arguments
+    ///
+    /// * `offset` - How many of the listed entries to skip
+    /// * `limit` - How many entries to copy at most
+    /// * `filter` - Which kinds of entries are listed
+    /// * `by_frequency` - Whether to list by `EntryMeta::copy_count` instead of recency
+    ///
+    /// # Returns
+    ///
+    /// The page, the position of each of its entries in the whole history (what
+    /// `delete` and `promote` take), and how many entries are listed in all.
+    pub fn query(
+        &self,
+        offset: usize,
+        limit: usize,
+        filter: ClipboardFilter,
+        by_frequency: bool,
+    ) -> (ClipboardHistory, Vec<usize>, usize) {
+        let mut listed: Vec<usize> = (0..self.history.len())
+            .filter(|&pos| filter.matches(&self.history[pos]))
+            .collect();
+        // Stable, so the order of equally copied entries is kept
+        if by_frequency {
+            listed.sort_by_key(|&pos| Reverse(self.meta[pos].copy_count));
+        }
+        self.page_of(listed, offset, limit)
+    }
+
+    /// Like `query`, but lists the entries whose text contains `text`, most recent first.
+    ///
+    /// Text entries match on their text, images on their OCR text (see
+    /// `EntryMeta::ocr_text`). Case is ignored for ASCII letters only. Sensitive entries
+    /// never match. An empty `text` lists every entry.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - What to look for
+    /// * `offset` - How many of the matching entries to skip
+    /// * `limit` - How many entries to copy at most
+    ///
+    /// # Returns
+    ///
+    /// Same as `query`: the page, the position of each of its entries in the whole
+    /// history, and how many entries match in all.
+    pub fn search(
+        &self,
+        text: &str,
+        offset: usize,
+        limit: usize,
+    ) -> (ClipboardHistory, Vec<usize>, usize) {
+        let needle = text.to_ascii_lowercase();
+        let listed: Vec<usize> = (0..self.history.len())
+            .filter(|&pos| {
+                let meta = &self.meta[pos];
+                let haystack = match &self.history[pos] {
+                    ClipboardItem::Text(text) => Some(text.as_str()),
+                    _ => meta.ocr_text.as_deref(),
+                };
+                needle.is_empty()
+                    || (!meta.sensitive
+                        && haystack.is_some_and(|text| text.to_ascii_lowercase().contains(&needle)))
+            })
+            .collect();
+        self.page_of(listed, offset, limit)
+    }
+
+    /// The entries at `listed[offset..offset + limit]`, with their positions and how many
+    /// were listed.
+    fn page_of(
+        &self,
+        listed: Vec<usize>,
+        offset: usize,
+        limit: usize,
+    ) -> (ClipboardHistory, Vec<usize>, usize) {
+        let total = listed.len();
+        let positions: Vec<usize> = listed.into_iter().skip(offset).take(limit).collect();
+        let page = ClipboardHistory {
+            history: positions
+                .iter()
+                .map(|&pos| self.history[pos].clone())
+                .collect(),
+            meta: positions
+                .iter()
+                .map(|&pos| self.meta[pos].clone())
+                .collect(),
+            max_size: self.max_size,
+            dedup_window: self.dedup_window,
+            dedup_scope: self.dedup_scope,
+            max_total_bytes: self.max_total_bytes,
+            undo: UndoStack::default(),
+        };
+        (page, positions, total)
+    }
+
+    /// Counts the items by kind and sums their approximate size.
+    pub fn stats(&self) -> HistoryStats {
+        let mut stats = HistoryStats {
+            total: self.history.len(),
+            ..Default::default()
+        };
+
+        for item in &self.history {
+            if item.is_image() {
+                stats.images += 1;
+            } else {
+                stats.text += 1;
+            }
+            stats.bytes += item.size_bytes();
+        }
+
+        stats
+    }
+
     /// Clears all items from the clipboard history.
+    ///
+    /// `undo` puts them all back.
     pub fn clear(&mut self) {
-        self.history.clear();
+        let entries = self.history.drain(..).zip(self.meta.drain(..)).collect();
+        self.undo.record(0, entries);
+    }
+
+    /// Removes every entry `filter` lists, e.g. all images.
+    ///
+    /// `undo` puts them all back in one step.
+    ///
+    /// # Returns
+    ///
+    /// How many entries were removed.
+    pub fn clear_matching(&mut self, filter: ClipboardFilter) -> usize {
+        let mut entries = Vec::new();
+        let mut kept = VecDeque::new();
+        let mut kept_meta = VecDeque::new();
+        for (pos, (item, meta)) in self.history.drain(..).zip(self.meta.drain(..)).enumerate() {
+            if filter.matches(&item) {
+                entries.push((pos, item, meta));
+            } else {
+                kept.push_back(item);
+                kept_meta.push_back(meta);
+            }
+        }
+        (self.history, self.meta) = (kept, kept_meta);
+
+        let removed = entries.len();
+        self.undo.push(Removal { entries });
+        removed
+    }
+
+    /// Adds entries read from a backup (see `backup::history_from_json`) on top of the
+    /// history, or in place of it.
+    ///
+    /// **Behavior**:
+    /// - Entries are given most recent first and keep that order, on top of what was there.
+    /// - A merged entry stays sensitive if it was, imported ones never are.
+    /// - A duplicate within the dedup window and scope is merged into the existing entry,
+    ///   which moves up with the imported ones: copy counts add up, the later `copied_at`
+    ///   is kept and so is OCR text or HTML only one of them has. Blank text is skipped.
+    /// - `max_size` and the byte budget apply as for copies, so the oldest entries may
+    ///   be dropped, imported ones too.
+    /// - Forgets what `undo` could restore.
+    ///
+    /// # Arguments
+    ///
+    /// * `entries` - The entries to import, most recent first
+    /// * `replace` - Clear the history first
+    ///
+    /// # Returns
+    ///
+    /// The number of entries imported, blank text aside.
+    pub fn import(&mut self, entries: Vec<(ClipboardItem, EntryMeta)>, replace: bool) -> usize {
+        if replace {
+            self.history.clear();
+            self.meta.clear();
+        }
+        self.undo.clear();
+
+        let mut imported = 0;
+        for (item, mut meta) in entries.into_iter().rev() {
+            if item.is_empty_text() {
+                continue;
+            }
+            imported += 1;
+
+            if self.dedup_scope.covers(&item)
+                && let Some(pos) = self.position(&item)
+                && is_recent_duplicate(self.dedup_window, self.meta[pos].copied_at, meta.copied_at)
+                && let Some(existing) = self.meta.remove(pos)
+            {
+                self.history.remove(pos);
+                meta.copy_count = meta.copy_count.saturating_add(existing.copy_count);
+                meta.copied_at = meta.copied_at.max(existing.copied_at);
+                meta.ocr_text = meta.ocr_text.or(existing.ocr_text);
+                meta.html = meta.html.or(existing.html);
+                meta.sensitive = existing.sensitive;
+            }
+            self.push_at(item, meta);
+        }
+        imported
+    }
+
+    /// Reverts the most recent delete, range delete or clear.
+    ///
+    /// **Behavior**:
+    /// - Deleted items go back to the positions they were deleted from, a cleared
+    ///   history comes back whole. Each call reverts one removal, up to `UNDO_LIMIT`.
+    /// - Adding, promoting or moving an item forgets all removals, since their positions
+    ///   no longer point at the same place. No-op promotes and moves keep them.
+    /// - Items dropped by `remove_expired` or by going over `max_size` can't be restored,
+    ///   and don't affect what can.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClipboardError::NothingToUndo` if there is no removal to revert.
+    pub fn undo(&mut self) -> Result<(), ClipboardError> {
+        let removal = self.undo.pop().ok_or(ClipboardError::NothingToUndo)?;
+        self.restore(removal);
+        Ok(())
+    }
+
+    /// Puts removed items back where they were, or at the end if the history has shrunk since.
+    pub(crate) fn restore(&mut self, removal: Removal) {
+        // In order, so each one lands where it was once those before it are back
+        for (pos, item, meta) in removal.entries {
+            let pos = pos.min(self.history.len());
+            self.history.insert(pos, item);
+            self.meta.insert(pos, meta);
+        }
+
+        // Expired items make room, but stay within capacity anyway
+        self.evict_over_capacity();
+    }
+
+    /// Takes the removal the last edit recorded, for stores that keep their own undo stack.
+    pub(crate) fn take_removal(&mut self) -> Option<Removal> {
+        self.undo.pop()
+    }
+
+    /// Removes every item that was copied more than `max_age` before `now`.
+    ///
+    /// Items with a timestamp in the future (clock changes) are kept.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_age` - The maximum age an item may have before it is removed
+    /// * `now` - The reference time to measure ages against
+    ///
+    /// # Returns
+    ///
+    /// The number of removed items.
+    pub fn remove_expired(&mut self, max_age: Duration, now: SystemTime) -> usize {
+        let mut removed = 0;
+
+        // Walk backwards so removals don't shift the indices still to visit
+        for pos in (0..self.meta.len()).rev() {
+            let expired = match now.duration_since(self.meta[pos].copied_at) {
+                Ok(age) => age > max_age,
+                Err(_) => false,
+            };
+
+            if expired {
+                self.history.remove(pos);
+                self.meta.remove(pos);
+                removed += 1;
+            }
+        }
+
+        removed
+    }
+}
+
+/// Whether a duplicate last copied at `previous` should be promoted by a copy at `now`.
+///
+/// Without a window every duplicate is promoted. A copy exactly `window` old still is.
+pub(crate) fn is_recent_duplicate(
+    window: Option<Duration>,
+    previous: SystemTime,
+    now: SystemTime,
+) -> bool {
+    match (window, now.duration_since(previous)) {
+        (Some(window), Ok(age)) => age <= window,
+        // No window, or the previous copy is in the future (clock change)
+        _ => true,
+    }
+}
+
+/// A storage backend for clipboard history.
+///
+/// `ClipboardHistory` is the default, in-memory store. Other backends (see the
+/// `sqlite` feature) only need to provide `snapshot` and `replace`; every mutation
+/// falls back to "snapshot, mutate in memory, replace" unless the backend overrides
+/// it with something more efficient.
+pub trait HistoryStore: Send {
+    /// Returns the full history, ordered from most recent to oldest.
+    fn snapshot(&self) -> ClipboardHistory;
+
+    /// Replaces the stored history with `history`.
+    fn replace(&mut self, history: ClipboardHistory);
+
+    /// See `ClipboardHistory::stats`.
+    fn stats(&self) -> HistoryStats {
+        self.snapshot().stats()
+    }
+
+    /// See `ClipboardHistory::capacity`.
+    fn capacity(&self) -> usize {
+        self.snapshot_range(0, 0).0.capacity()
+    }
+
+    /// Returns the entries at `offset..offset + limit` (see `ClipboardHistory::range`),
+    /// and how many entries the whole history has.
+    fn snapshot_range(&self, offset: usize, limit: usize) -> (ClipboardHistory, usize) {
+        let history = self.snapshot();
+        (history.range(offset, limit), history.len())
+    }
+
+    /// See `ClipboardHistory::query`.
+    fn query(
+        &self,
+        offset: usize,
+        limit: usize,
+        filter: ClipboardFilter,
+        by_frequency: bool,
+    ) -> (ClipboardHistory, Vec<usize>, usize) {
+        // Listing everything by recency is just a page
+        if filter == ClipboardFilter::All && !by_frequency {
+            let (page, total) = self.snapshot_range(offset, limit);
+            let positions = (offset..offset + page.len()).collect();
+            return (page, positions, total);
+        }
+        self.snapshot().query(offset, limit, filter, by_frequency)
+    }
+
+    /// See `ClipboardHistory::search`.
+    fn search(
+        &self,
+        text: &str,
+        offset: usize,
+        limit: usize,
+    ) -> (ClipboardHistory, Vec<usize>, usize) {
+        self.snapshot().search(text, offset, limit)
+    }
+
+    /// See `ClipboardHistory::add_at`.
+    fn add_at(&mut self, item: ClipboardItem, copied_at: SystemTime) {
+        let mut history = self.snapshot();
+        history.add_at(item, copied_at);
+        self.replace(history);
+    }
+
+    /// See `ClipboardHistory::add`.
+    fn add(&mut self, item: ClipboardItem) {
+        self.add_at(item, SystemTime::now());
+    }
+
+    /// See `ClipboardHistory::promote`.
+    fn promote(&mut self, pos: usize) -> Result<usize, ClipboardError> {
+        let mut history = self.snapshot();
+        let index = history.promote(pos)?;
+        self.replace(history);
+        Ok(index)
+    }
+
+    /// See `ClipboardHistory::preview`.
+    fn preview(&self, pos: usize, max_chars: usize) -> Option<String> {
+        self.snapshot_range(pos, 1).0.preview(0, max_chars)
+    }
+
+    /// See `ClipboardHistory::promote_this`.
+    fn promote_this(&mut self, item: ClipboardItem) -> Result<usize, ClipboardError> {
+        let mut history = self.snapshot();
+        let index = history.promote_this(item)?;
+        self.replace(history);
+        Ok(index)
+    }
+
+    /// See `ClipboardHistory::move_to`.
+    fn move_to(&mut self, from: usize, to: usize) -> Result<usize, ClipboardError> {
+        let mut history = self.snapshot();
+        let index = history.move_to(from, to)?;
+        self.replace(history);
+        Ok(index)
+    }
+
+    /// See `ClipboardHistory::delete`.
+    fn delete(&mut self, pos: usize) -> Result<ClipboardItem, ClipboardError> {
+        let mut history = self.snapshot();
+        let item = history.delete(pos)?;
+        self.replace(history);
+        Ok(item)
+    }
+
+    /// See `ClipboardHistory::delete_range`.
+    fn delete_range(&mut self, start: usize, end: usize) -> Result<(), ClipboardError> {
+        let mut history = self.snapshot();
+        history.delete_range(start, end)?;
+        self.replace(history);
+        Ok(())
+    }
+
+    /// See `ClipboardHistory::delete_this`.
+    fn delete_this(&mut self, item: ClipboardItem) -> Result<(), ClipboardError> {
+        let mut history = self.snapshot();
+        history.delete_this(item)?;
+        self.replace(history);
+        Ok(())
+    }
+
+    /// See `ClipboardHistory::delete_at`.
+    fn delete_at(&mut self, pos: usize, item: &ClipboardItem) -> Result<(), ClipboardError> {
+        let mut history = self.snapshot();
+        history.delete_at(pos, item)?;
+        self.replace(history);
+        Ok(())
+    }
+
+    /// See `ClipboardHistory::set_ocr_text`.
+    fn set_ocr_text(&mut self, item: &ClipboardItem, text: String) -> Result<(), ClipboardError> {
+        let mut history = self.snapshot();
+        history.set_ocr_text(item, text)?;
+        self.replace(history);
+        Ok(())
+    }
+
+    /// See `ClipboardHistory::set_html`.
+    fn set_html(
+        &mut self,
+        pos: usize,
+        item: &ClipboardItem,
+        html: Option<String>,
+    ) -> Result<(), ClipboardError> {
+        let mut history = self.snapshot();
+        history.set_html(pos, item, html)?;
+        self.replace(history);
+        Ok(())
+    }
+
+    /// See `ClipboardHistory::set_selection`.
+    fn set_selection(
+        &mut self,
+        pos: usize,
+        item: &ClipboardItem,
+        selection: Selection,
+    ) -> Result<(), ClipboardError> {
+        let mut history = self.snapshot();
+        history.set_selection(pos, item, selection)?;
+        self.replace(history);
+        Ok(())
+    }
+
+    /// See `ClipboardHistory::clear_matching`.
+    fn clear_matching(&mut self, filter: ClipboardFilter) -> usize {
+        let mut history = self.snapshot();
+        let removed = history.clear_matching(filter);
+        self.replace(history);
+        removed
+    }
+
+    /// See `ClipboardHistory::import`.
+    fn import(&mut self, entries: Vec<(ClipboardItem, EntryMeta)>, replace: bool) -> usize {
+        let mut history = self.snapshot();
+        let imported = history.import(entries, replace);
+        self.replace(history);
+        imported
+    }
+
+    /// See `ClipboardHistory::mark_sensitive`.
+    fn mark_sensitive(&mut self, pos: usize) -> Result<(), ClipboardError> {
+        let mut history = self.snapshot();
+        history.mark_sensitive(pos)?;
+        self.replace(history);
+        Ok(())
+    }
+
+    /// See `ClipboardHistory::drop_sensitive`.
+    fn drop_sensitive(&mut self) -> usize {
+        let mut history = self.snapshot();
+        let dropped = history.drop_sensitive();
+        if dropped > 0 {
+            self.replace(history);
+        }
+        dropped
+    }
+
+    /// See `ClipboardHistory::clear`.
+    fn clear(&mut self) {
+        let mut history = self.snapshot();
+        history.clear();
+        self.replace(history);
+    }
+
+    /// See `ClipboardHistory::undo`.
+    fn undo(&mut self) -> Result<(), ClipboardError> {
+        let mut history = self.snapshot();
+        history.undo()?;
+        self.replace(history);
+        Ok(())
+    }
+
+    /// See `ClipboardHistory::remove_expired`.
+    fn remove_expired(&mut self, max_age: Duration, now: SystemTime) -> usize {
+        let mut history = self.snapshot();
+        let removed = history.remove_expired(max_age, now);
+        if removed > 0 {
+            self.replace(history);
+        }
+        removed
+    }
+}
+
+// The in-memory store. Every method maps straight onto the inherent one.
+impl HistoryStore for ClipboardHistory {
+    fn snapshot(&self) -> ClipboardHistory {
+        self.clone()
+    }
+
+    fn replace(&mut self, history: ClipboardHistory) {
+        *self = history;
+    }
+
+    fn stats(&self) -> HistoryStats {
+        ClipboardHistory::stats(self)
+    }
+
+    fn capacity(&self) -> usize {
+        ClipboardHistory::capacity(self)
+    }
+
+    // Skips cloning the whole history
+    fn snapshot_range(&self, offset: usize, limit: usize) -> (ClipboardHistory, usize) {
+        (self.range(offset, limit), self.history.len())
+    }
+
+    fn query(
+        &self,
+        offset: usize,
+        limit: usize,
+        filter: ClipboardFilter,
+        by_frequency: bool,
+    ) -> (ClipboardHistory, Vec<usize>, usize) {
+        ClipboardHistory::query(self, offset, limit, filter, by_frequency)
+    }
+
+    fn search(
+        &self,
+        text: &str,
+        offset: usize,
+        limit: usize,
+    ) -> (ClipboardHistory, Vec<usize>, usize) {
+        ClipboardHistory::search(self, text, offset, limit)
+    }
+
+    fn add_at(&mut self, item: ClipboardItem, copied_at: SystemTime) {
+        ClipboardHistory::add_at(self, item, copied_at);
+    }
+
+    fn promote(&mut self, pos: usize) -> Result<usize, ClipboardError> {
+        ClipboardHistory::promote(self, pos)
+    }
+
+    fn preview(&self, pos: usize, max_chars: usize) -> Option<String> {
+        ClipboardHistory::preview(self, pos, max_chars)
+    }
+
+    fn promote_this(&mut self, item: ClipboardItem) -> Result<usize, ClipboardError> {
+        ClipboardHistory::promote_this(self, item)
+    }
+
+    fn move_to(&mut self, from: usize, to: usize) -> Result<usize, ClipboardError> {
+        ClipboardHistory::move_to(self, from, to)
+    }
+
+    fn delete(&mut self, pos: usize) -> Result<ClipboardItem, ClipboardError> {
+        ClipboardHistory::delete(self, pos)
+    }
+
+    fn delete_range(&mut self, start: usize, end: usize) -> Result<(), ClipboardError> {
+        ClipboardHistory::delete_range(self, start, end)
+    }
+
+    fn delete_this(&mut self, item: ClipboardItem) -> Result<(), ClipboardError> {
+        ClipboardHistory::delete_this(self, item)
+    }
+
+    fn delete_at(&mut self, pos: usize, item: &ClipboardItem) -> Result<(), ClipboardError> {
+        ClipboardHistory::delete_at(self, pos, item)
+    }
+
+    fn set_ocr_text(&mut self, item: &ClipboardItem, text: String) -> Result<(), ClipboardError> {
+        ClipboardHistory::set_ocr_text(self, item, text)
+    }
+
+    fn set_html(
+        &mut self,
+        pos: usize,
+        item: &ClipboardItem,
+        html: Option<String>,
+    ) -> Result<(), ClipboardError> {
+        ClipboardHistory::set_html(self, pos, item, html)
+    }
+
+    fn set_selection(
+        &mut self,
+        pos: usize,
+        item: &ClipboardItem,
+        selection: Selection,
+    ) -> Result<(), ClipboardError> {
+        ClipboardHistory::set_selection(self, pos, item, selection)
+    }
+
+    fn clear_matching(&mut self, filter: ClipboardFilter) -> usize {
+        ClipboardHistory::clear_matching(self, filter)
+    }
+
+    fn import(&mut self, entries: Vec<(ClipboardItem, EntryMeta)>, replace: bool) -> usize {
+        ClipboardHistory::import(self, entries, replace)
+    }
+
+    fn mark_sensitive(&mut self, pos: usize) -> Result<(), ClipboardError> {
+        ClipboardHistory::mark_sensitive(self, pos)
+    }
+
+    fn drop_sensitive(&mut self) -> usize {
+        ClipboardHistory::drop_sensitive(self)
+    }
+
+    fn clear(&mut self) {
+        ClipboardHistory::clear(self);
+    }
+
+    fn undo(&mut self) -> Result<(), ClipboardError> {
+        ClipboardHistory::undo(self)
+    }
+
+    fn remove_expired(&mut self, max_age: Duration, now: SystemTime) -> usize {
+        ClipboardHistory::remove_expired(self, max_age, now)
     }
 }
 
@@ -138,7 +1403,8 @@ impl fmt::Display for ClipboardHistory {
         // No sorting needed! Just iterate.
         for (pos, item) in self.history.iter().enumerate() {
             match item {
-                ClipboardItem::Image { width, height, .. } => {
+                ClipboardItem::Image { width, height, .. }
+                | ClipboardItem::CompressedImage { width, height, .. } => {
                     printable += &format!("\r\n{}       | Image ({}, {})     ", pos, width, height);
                 }
                 ClipboardItem::Text(string) => {
@@ -1,5 +1,11 @@
 // Module declarations - these make your modules available to tests and other crates
+pub mod backup;
+pub mod client;
 pub mod common;
+pub mod config;
 pub mod gui;
 pub mod history;
+pub mod image_codec;
 pub mod services;
+#[cfg(feature = "sqlite")]
+pub mod sqlite_store;
@@ -1,14 +1,41 @@
+// Binary Modules
+mod cli;
+
 // Standard Crates
-use std::{fs, process, thread, time::Duration};
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    os::fd::AsRawFd,
+    path::Path,
+    process, thread,
+    time::SystemTime,
+};
 
 // External Crates
-use clap::{Parser, Subcommand};
+use clap::Parser;
+use fs2::FileExt;
+use tracing_subscriber::EnvFilter;
 
 // My Crates
+use cli::{Args, PasteArgs};
 use super_v::{
-    common::{LOCK_PATH, SOCKET_PATH},
-    gui::clipboard_gui::{InitialTab, MainThreadMsg, run_gui},
-    services::{clipboard_manager::Manager, ydotool::send_shift_insert},
+    client::Client,
+    common::{ClientError, ClipboardItem, DaemonError, IPCServerError, lock_path, socket_path},
+    config::Config,
+    gui::{
+        clipboard_gui::{InitialTab, MainThreadMsg, run_gui},
+        thumbnails,
+    },
+    history::{ClipboardHistory, HistoryStore, entry_to_json},
+    services::{
+        clipboard_ipc_server::{
+            CmdIPC, IPCRequest, IPCResponse, IpcErrorCode, Payload, create_stream, send_payload,
+            try_read_payload,
+        },
+        clipboard_manager::Manager,
+        ydotool::{self, send_shift_insert},
+    },
 };
 
 /*
@@ -21,108 +48,611 @@ Notes:
 - Emoji screen
 */
 
-#[derive(Subcommand, Debug)]
-enum Command {
-    /// Start the process
-    Start,
-
-    /// Open the GUI
-    OpenGui,
+/// Opens the history store the config asks for. Exits if the database can't be opened.
+fn open_store(config: &Config) -> Box<dyn HistoryStore> {
+    #[cfg(feature = "sqlite")]
+    if let Some(path) = &config.db_path {
+        match super_v::sqlite_store::SqliteStore::open(path, config.history_size) {
+            Ok(sqlite_store) => {
+                return Box::new(
+                    sqlite_store
+                        .with_dedup_window(config.dedup_window())
+                        .with_dedup_scope(config.dedup_scope)
+                        .with_max_total_bytes(config.max_total_bytes),
+                );
+            }
+            Err(err) => {
+                eprintln!("Could not open history database {}: {err}", path.display());
+                process::exit(1);
+            }
+        }
+    }
 
-    /// Opens the emoji screen
-    OpenEmoji,
+    #[cfg(not(feature = "sqlite"))]
+    if let Some(path) = &config.db_path {
+        eprintln!(
+            "Ignoring db_path {}: built without the sqlite feature.",
+            path.display()
+        );
+    }
 
-    /// Cleans any leftovers
-    Clean,
+    Box::new(
+        ClipboardHistory::new(config.history_size)
+            .with_dedup_window(config.dedup_window())
+            .with_dedup_scope(config.dedup_scope)
+            .with_max_total_bytes(config.max_total_bytes),
+    )
 }
 
-#[derive(Parser, Debug)]
-#[command(
-    name = "super_v",
-    version = "1.0.0",
-    about = "Clipboard Service that looks like Win11",
-    long_about = None
-)]
-struct Args {
-    #[command(subcommand)]
-    command: Command,
-}
+fn start_manager_daemon(profile: &str, config: &Config) {
+    let manager = Manager::with_profile(open_store(config), profile);
 
-fn start_manager_daemon() {
-    let mut c_manager = match Manager::new() {
+    let mut c_manager = match manager {
         Ok(manager) => {
             println!("Starting service...");
             manager
         }
-        Err(_) => {
-            eprintln!("Another instance of Manager already running.");
+        Err(DaemonError::ManagerMultiSpawn { pid }) => {
+            match pid {
+                Some(pid) => eprintln!("Another instance of Manager already running (pid {pid})."),
+                None => eprintln!("Another instance of Manager already running."),
+            }
             process::exit(0);
         }
+        Err(err) => {
+            eprintln!("Could not start service: {err}");
+            process::exit(1);
+        }
     };
 
+    c_manager.apply_config(config);
     c_manager.start_daemon();
 }
 
-// ----------------------------- Main --------------------------------
-fn main() {
-    // Daemon
-    let args = Args::parse();
-    match args.command {
-        Command::Start => {
-            start_manager_daemon();
-        }
-        Command::OpenGui => {
-            use std::sync::mpsc::channel;
-
-            // Create a simple streaming channel
-            let (tx, rx) = channel::<MainThreadMsg>();
-
-            let ydotool_handle = std::thread::spawn(move || {
-                while let Ok(msg) = rx.recv() {
-                    match msg {
-                        MainThreadMsg::AutoPaste => {
-                            thread::sleep(Duration::from_millis(100));
-                            send_shift_insert();
-                        }
-                        MainThreadMsg::Close => {
-                            break;
-                        }
-                    }
+/// Detaches from the terminal with the usual double fork.
+///
+/// Only the final grandchild returns, the original process exits. stdin is pointed at
+/// /dev/null and stdout/stderr are appended to `log`.
+/// Must be called before any thread is spawned.
+fn daemonize(log: &Path) -> io::Result<()> {
+    // Open both first, so a bad log path is still reported on the terminal
+    let log_file = OpenOptions::new().create(true).append(true).open(log)?;
+    let dev_null = File::open("/dev/null")?;
+
+    fn fork_and_exit_parent() -> io::Result<()> {
+        // SAFETY: still single threaded, so the child gets a consistent copy of the process
+        match unsafe { libc::fork() } {
+            -1 => Err(io::Error::last_os_error()),
+            0 => Ok(()),
+            _ => process::exit(0),
+        }
+    }
+
+    // Leave the shell's process group, then drop the controlling terminal.
+    // The second fork makes sure we can never get a terminal back.
+    fork_and_exit_parent()?;
+    if unsafe { libc::setsid() } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    fork_and_exit_parent()?;
+
+    for (from, to) in [
+        (dev_null.as_raw_fd(), libc::STDIN_FILENO),
+        (log_file.as_raw_fd(), libc::STDOUT_FILENO),
+        (log_file.as_raw_fd(), libc::STDERR_FILENO),
+    ] {
+        // SAFETY: both descriptors are open for the duration of the call
+        if unsafe { libc::dup2(from, to) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+/// Reports a failed request and exits.
+fn exit_with(err: ClientError) -> ! {
+    match err {
+        ClientError::IPCErr(IPCServerError::FileNotFound | IPCServerError::ConnectionError(_)) => {
+            eprintln!("Service is not running.")
+        }
+        err => eprintln!("{err}"),
+    }
+    process::exit(1);
+}
+
+fn stop_manager_daemon(profile: &str) {
+    // Ask the daemon to stop and wait for it to acknowledge
+    match Client::with_profile(profile).request(CmdIPC::Stop) {
+        Ok(IPCResponse {
+            message: Some(msg), ..
+        }) => {
+            println!("{msg}");
+        }
+        Ok(_) => exit_with(ClientError::UnexpectedResponse),
+        Err(err) => exit_with(err),
+    }
+}
+
+/// Prints one line of the `doctor` report and returns whether the check passed.
+fn report(ok: bool, line: &str) -> bool {
+    println!("[{}] {line}", if ok { "ok" } else { "!!" });
+    ok
+}
+
+/// Checks everything picking an entry depends on, printing a line for each.
+///
+/// Exits non-zero if any check fails, so the report can gate a setup script.
+fn doctor(profile: &str) {
+    let var = |name| env::var(name).ok().filter(|value| !value.is_empty());
+    let session = var("XDG_SESSION_TYPE").unwrap_or_else(|| "unknown".to_string());
+    let display = var("DISPLAY");
+    println!(
+        "Session: {session} (WAYLAND_DISPLAY={}, DISPLAY={})",
+        var("WAYLAND_DISPLAY").as_deref().unwrap_or("unset"),
+        display.as_deref().unwrap_or("unset")
+    );
+
+    let mut ok = true;
+
+    // arboard only speaks X11, on Wayland that means XWayland
+    ok &= report(
+        display.is_some(),
+        match display {
+            Some(_) => "DISPLAY is set, the clipboard can be read",
+            None => "DISPLAY is unset, the clipboard can't be read (on Wayland, enable XWayland)",
+        },
+    );
+
+    ok &= match ydotool::find_program("ydotool") {
+        Some(path) => report(true, &format!("ydotool found at {}", path.display())),
+        None => report(false, "ydotool not found on PATH, auto-paste needs it"),
+    };
+
+    let socket = Path::new(ydotool::SOCKET_PATH);
+    ok &= if socket.exists() {
+        report(
+            true,
+            &format!("ydotool socket found at {}", socket.display()),
+        )
+    } else {
+        report(
+            false,
+            &format!(
+                "ydotool socket missing at {}, start ydotoold",
+                socket.display()
+            ),
+        )
+    };
+
+    // Not used for pasting, but tells X11 users why it isn't enough
+    match ydotool::find_program("xdotool") {
+        Some(path) => println!("[--] xdotool found at {}, not used", path.display()),
+        None => println!("[--] xdotool not found, not needed"),
+    }
+
+    ok &= match Client::with_profile(profile).stats() {
+        Ok(stats) => report(
+            true,
+            &format!(
+                "Service running ({profile} profile, {} entries)",
+                stats.total
+            ),
+        ),
+        Err(_) => report(
+            false,
+            &format!("Service not running ({profile} profile), start it with `super_v start`"),
+        ),
+    };
+
+    ok &= match Config::load() {
+        Ok(_) => report(true, "Config loads"),
+        Err(err) => report(false, &err.to_string()),
+    };
+
+    if !ok {
+        process::exit(1);
+    }
+}
+
+/// Removes the socket and lock file, saying what happened to each.
+///
+/// Refuses while a daemon answers on the socket or holds the lock file, since removing
+/// its files under it leaves it unreachable. A daemon that is busy or still starting
+/// may not answer yet, but it has the lock.
+fn clean_leftovers(profile: &str, dry_run: bool) {
+    if create_stream(profile).is_ok() {
+        eprintln!("The service is running. Use `super_v stop` to shut it down instead.");
+        process::exit(1);
+    }
+
+    // Held until the files are gone, so a daemon starting now can't take over in between
+    let lock = OpenOptions::new().write(true).open(lock_path(profile));
+    if let Ok(lock) = &lock
+        && lock.try_lock_exclusive().is_err()
+    {
+        eprintln!(
+            "A daemon holds {}, it may be busy or still starting. Not removing anything.",
+            lock_path(profile).display()
+        );
+        process::exit(1);
+    }
+
+    let mut failed = false;
+    for path in [socket_path(profile), lock_path(profile)] {
+        let path_str = path.display();
+        if !path.exists() {
+            println!("{path_str} is not there");
+        } else if dry_run {
+            println!("Would remove {path_str}");
+        } else {
+            match fs::remove_file(&path) {
+                Ok(()) => println!("Removed {path_str}"),
+                Err(err) => {
+                    eprintln!("Could not remove {path_str}: {err}");
+                    failed = true;
                 }
-            });
-
-            // Should be in main thread
-            run_gui(tx, InitialTab::Clipboard);
-            let _ = ydotool_handle.join();
-        }
-        Command::OpenEmoji => {
-            use std::sync::mpsc::channel;
-
-            // Create a simple streaming channel
-            let (tx, rx) = channel::<MainThreadMsg>();
-
-            let ydotool_handle = std::thread::spawn(move || {
-                while let Ok(msg) = rx.recv() {
-                    match msg {
-                        MainThreadMsg::AutoPaste => {
-                            thread::sleep(Duration::from_millis(100));
-                            send_shift_insert();
-                        }
-                        MainThreadMsg::Close => {
-                            break;
-                        }
-                    }
+            }
+        }
+    }
+
+    if failed {
+        process::exit(1);
+    }
+}
+
+fn add_text(profile: &str, text: String) {
+    let item = ClipboardItem::Text(text);
+    if item.is_empty_text() {
+        eprintln!("Nothing to add, the text is empty.");
+        process::exit(1);
+    }
+
+    if let Err(err) = Client::with_profile(profile).add(item) {
+        exit_with(err);
+    }
+}
+
+/// Reads stdin as text, or as raw RGBA pixels of an image of `size`, and adds it to the
+/// history. With `clipboard` the daemon also puts it on the system clipboard (see
+/// `CmdIPC::Copy`), so it is still there after this process exits.
+fn copy_stdin(profile: &str, size: Option<(usize, usize)>, clipboard: bool) {
+    let mut bytes = Vec::new();
+    if let Err(err) = io::stdin().read_to_end(&mut bytes) {
+        eprintln!("Could not read stdin: {err}");
+        process::exit(1);
+    }
+
+    let item = match size {
+        Some((width, height)) => {
+            let expected = width.checked_mul(height).and_then(|n| n.checked_mul(4));
+            if !bytes.is_empty() && expected != Some(bytes.len()) {
+                eprintln!(
+                    "Got {} bytes, but a {width}x{height} RGBA image is {} bytes.",
+                    bytes.len(),
+                    width.saturating_mul(height).saturating_mul(4)
+                );
+                process::exit(1);
+            }
+            ClipboardItem::Image {
+                width,
+                height,
+                bytes,
+            }
+        }
+        None => match String::from_utf8(bytes) {
+            Ok(text) => ClipboardItem::Text(text),
+            Err(_) => {
+                eprintln!("stdin is not UTF-8 text. Pipe images with --image.");
+                process::exit(1);
+            }
+        },
+    };
+
+    if item.is_empty_text() || item.size_bytes() == 0 {
+        println!("Nothing to copy, stdin is empty.");
+        return;
+    }
+
+    let client = Client::with_profile(profile);
+    let result = if clipboard {
+        client.copy(item)
+    } else {
+        client.add(item)
+    };
+    if let Err(err) = result {
+        exit_with(err);
+    }
+}
+
+fn print_stats(profile: &str) {
+    match Client::with_profile(profile).stats() {
+        Ok(stats) => println!("{}", stats.to_json()),
+        Err(err) => exit_with(err),
+    }
+}
+
+fn peek_item(profile: &str, index: usize) {
+    // Only that entry crosses the socket, not every image in history
+    let item = match Client::with_profile(profile).get(index) {
+        Ok((item, _)) => item,
+        Err(ClientError::Rejected {
+            code: Some(IpcErrorCode::IndexOutOfBounds),
+            message,
+        }) => {
+            eprintln!("{message}");
+            process::exit(1);
+        }
+        Err(err) => exit_with(err),
+    };
+
+    match item {
+        // Printed as is, so pipelines get the exact text
+        ClipboardItem::Text(text) => print!("{text}"),
+        ClipboardItem::Image { width, height, .. }
+        | ClipboardItem::CompressedImage { width, height, .. } => {
+            println!("[Image {width}x{height}]")
+        }
+    }
+}
+
+fn mark_sensitive(profile: &str, index: usize) {
+    match Client::with_profile(profile).mark_sensitive(index) {
+        // A preview cached by the GUI would keep the image on disk
+        Ok(history) => {
+            if let Some(key) = history
+                .get_items()
+                .get(index)
+                .and_then(thumbnails::item_key)
+            {
+                thumbnails::remove(profile, &key);
+            }
+        }
+        Err(ClientError::Rejected {
+            code: Some(IpcErrorCode::IndexOutOfBounds),
+            message,
+        }) => {
+            eprintln!("{message}");
+            process::exit(1);
+        }
+        Err(err) => exit_with(err),
+    }
+}
+
+fn clear_history(profile: &str) {
+    match Client::with_profile(profile).clear() {
+        Ok(history) => println!("{history}"),
+        Err(err) => exit_with(err),
+    }
+}
+
+fn export_history(profile: &str, file: &Path) {
+    let json = match Client::with_profile(profile).export() {
+        Ok(json) => json,
+        Err(err) => exit_with(err),
+    };
+
+    if let Err(err) = fs::write(file, json) {
+        eprintln!("Could not write {}: {err}", file.display());
+        process::exit(1);
+    }
+}
+
+fn import_history(profile: &str, file: &Path, replace: bool) {
+    let json = match fs::read_to_string(file) {
+        Ok(json) => json,
+        Err(err) => {
+            eprintln!("Could not read {}: {err}", file.display());
+            process::exit(1);
+        }
+    };
+
+    match Client::with_profile(profile).import(&json, replace) {
+        Ok(imported) => println!("Imported {imported} entries."),
+        Err(ClientError::Rejected {
+            code: Some(IpcErrorCode::InvalidImport),
+            message,
+        }) => {
+            eprintln!("{message}");
+            process::exit(1);
+        }
+        Err(err) => exit_with(err),
+    }
+}
+
+/// Prints one line to stdout. Exits quietly once the reader went away (e.g. `| head -1`).
+fn print_line(line: &str) {
+    if writeln!(io::stdout(), "{line}").is_err() {
+        process::exit(0);
+    }
+}
+
+/// Prints the entries copied after `since`, oldest first. Returns the newest copy time seen.
+fn print_entries_since(
+    history: &ClipboardHistory,
+    since: Option<SystemTime>,
+) -> Option<SystemTime> {
+    let entries = history.get_items().iter().zip(history.get_meta());
+    let mut newest = since;
+
+    for (item, meta) in entries.rev() {
+        if since.is_none_or(|since| meta.copied_at > since) {
+            print_line(&entry_to_json(item, meta));
+        }
+        newest = newest.max(Some(meta.copied_at));
+    }
+    newest
+}
+
+fn list_history(profile: &str, follow: bool) {
+    if !follow {
+        let history = match Client::with_profile(profile).snapshot() {
+            Ok(history) => history,
+            Err(err) => exit_with(err),
+        };
+        for (item, meta) in history.get_items().iter().zip(history.get_meta()) {
+            print_line(&entry_to_json(item, meta));
+        }
+        return;
+    }
+
+    let mut stream = match create_stream(profile) {
+        Ok(stream) => stream,
+        Err(_) => {
+            eprintln!("Service is not running.");
+            process::exit(1);
+        }
+    };
+
+    // Snapshots are pushed on every change, not only on copies. Entries copied after
+    // the newest one printed so far are the new ones, deletes and moves print nothing.
+    send_payload(
+        &mut stream,
+        Payload::Request(IPCRequest {
+            cmd: CmdIPC::Subscribe,
+        }),
+    );
+
+    let mut newest = None;
+    let mut first = true;
+    loop {
+        match try_read_payload(&mut stream) {
+            Ok(Payload::Response(IPCResponse {
+                history_snapshot: Some(history),
+                ..
+            })) if first => {
+                // The current history comes first, in the same order as without --follow
+                for (item, meta) in history.get_items().iter().zip(history.get_meta()) {
+                    print_line(&entry_to_json(item, meta));
                 }
-            });
+                newest = history.get_meta().iter().map(|meta| meta.copied_at).max();
+                first = false;
+            }
+            Ok(Payload::Response(IPCResponse {
+                history_snapshot: Some(history),
+                ..
+            })) => {
+                newest = print_entries_since(&history, newest);
+            }
+            Ok(_) => {}
+            Err(_) => {
+                eprintln!("Service stopped.");
+                process::exit(1);
+            }
+        }
+    }
+}
 
-            // Should be in main thread
-            run_gui(tx, InitialTab::Emoji);
-            let _ = ydotool_handle.join();
+fn watch_history(profile: &str) {
+    let mut stream = match create_stream(profile) {
+        Ok(stream) => stream,
+        Err(_) => {
+            eprintln!("Service is not running.");
+            process::exit(1);
+        }
+    };
+
+    // The daemon answers with the current snapshot and then pushes one on every change.
+    // Ctrl-C to quit.
+    send_payload(
+        &mut stream,
+        Payload::Request(IPCRequest {
+            cmd: CmdIPC::Subscribe,
+        }),
+    );
+
+    loop {
+        match try_read_payload(&mut stream) {
+            Ok(Payload::Response(IPCResponse {
+                history_snapshot: Some(history),
+                ..
+            })) => {
+                // Clear the screen and move the cursor home before redrawing
+                print!("\x1B[2J\x1B[H");
+                println!("{history}");
+            }
+            Ok(_) => {}
+            Err(_) => {
+                eprintln!("Service stopped.");
+                process::exit(1);
+            }
         }
-        Command::Clean => {
-            let _ = fs::remove_file(SOCKET_PATH);
-            let _ = fs::remove_file(LOCK_PATH);
+    }
+}
+
+/// Logs go to stderr. The level comes from `SUPER_V_LOG`, then `RUST_LOG`, defaulting to `info`.
+fn init_logging() {
+    let filter = EnvFilter::try_from_env("SUPER_V_LOG")
+        .or_else(|_| EnvFilter::try_from_default_env())
+        .unwrap_or_else(|_| EnvFilter::new("info"));
+
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .init();
+}
+
+/// Runs the GUI on this thread and pastes with ydotool when it asks to.
+fn open_gui(profile: &str, tab: InitialTab, paste: PasteArgs) {
+    use std::sync::mpsc::channel;
+
+    // A broken config shouldn't keep the window from opening
+    let mut config = Config::load().unwrap_or_else(|err| {
+        eprintln!("{err}, using the default settings");
+        Config::default()
+    });
+
+    // Flags win over the config file
+    if paste.paste_delay_ms.is_some() {
+        config.paste_delay_ms = paste.paste_delay_ms;
+    }
+    if paste.no_auto_paste {
+        config.auto_paste = false;
+    }
+    if paste.emoji_trailing_space {
+        config.emoji_trailing_space = true;
+    }
+    let paste_delay = config.paste_delay();
+    let dry_run = paste.paste_dry_run;
+
+    // Create a simple streaming channel
+    let (tx, rx) = channel::<MainThreadMsg>();
+    let close_tx = tx.clone();
+
+    let ydotool_handle = thread::spawn(move || {
+        while let Ok(msg) = rx.recv() {
+            match msg {
+                // Sent once the GUI window has given up focus
+                MainThreadMsg::AutoPaste => {
+                    if let Some(delay) = paste_delay {
+                        thread::sleep(delay);
+                    }
+                    send_shift_insert(dry_run);
+                }
+                MainThreadMsg::Close => {
+                    break;
+                }
+            }
         }
+    });
+
+    // Should be in main thread
+    run_gui(tx, tab, profile, config);
+
+    // The window is gone, but the GUI only sends Close on some paths. With --oneshot
+    // the paste thread is told to stop once it has handled what is already queued,
+    // so the process ends here even if the paste failed.
+    if paste.oneshot {
+        let _ = close_tx.send(MainThreadMsg::Close);
     }
+    drop(close_tx);
+    let _ = ydotool_handle.join();
+}
+
+// ----------------------------- Main --------------------------------
+fn main() {
+    init_logging();
+
+    cli::run(Args::parse());
 }
 // -------------------------------------------------------------------
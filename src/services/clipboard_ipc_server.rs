@@ -1,8 +1,13 @@
 // System Crates
 use std::{
-    fs::remove_file,
-    io::{Read, Write},
-    os::unix::net::{UnixListener, UnixStream},
+    fmt,
+    fs::{Permissions, metadata, remove_file, set_permissions},
+    io::{self, Read, Write},
+    os::unix::{
+        fs::PermissionsExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::Path,
 };
 
 // External Crates
@@ -11,7 +16,7 @@ use serde::{Deserialize, Serialize};
 
 // My Crates
 use crate::{
-    common::{ClipboardItem, IPCServerError, SOCKET_PATH},
+    common::{ClipboardFilter, ClipboardItem, DEFAULT_PROFILE, IPCServerError, socket_path},
     history::ClipboardHistory,
 };
 
@@ -19,31 +24,203 @@ use crate::{
 /// Represents the commands that IPC Supports
 ///
 /// This enum allows for the following commands:
+/// * **Add(ClipboardItem)** - Command that adds the given item to the top of history,
+///   without touching the system clipboard. Blank text is ignored.
+/// * **Copy(ClipboardItem)** - Command that adds the item like Add, then has the daemon
+///   put it on the system clipboard without capturing it again. The daemon keeps
+///   serving it, so it outlives the client. Answers `ClipboardUnavailable` if the
+///   clipboard can't be written, the item is in history then.
 /// * **Promote(usize)** - Command that promotes and item to top of history.
+/// * **PromoteThis(ClipboardItem)** - Command that promotes the given item to top of history.
+/// * **Move { from, to }** - Command that moves an item so it ends up at position `to`.
 /// * **Delete(usize)** - Command that deletes an item from history given its pos.
+/// * **DeleteRange { start, end }** - Command that deletes positions `start..end` (end exclusive).
+/// * **DeleteThis(ClipboardItem)** - Command that deletes the given item from history,
+///   only its most recent copy if there are several.
+/// * **DeleteAt { pos, item }** - Command that deletes the entry at `pos` if it holds
+///   `item`, otherwise the most recent copy of `item`. For positions from a snapshot.
 /// * **Snapshot** - Command that retrieves the snapshot of the current Clipboard History
+/// * **SnapshotRange { offset, limit, filter, by_frequency }** - Command that retrieves a page
+///   of the entries `filter` lists, the most copied first with `by_frequency`, see
+///   `ClipboardHistory::query`. The response's `positions` say where each entry is in the
+///   whole history, `total` how many entries are listed in all.
+/// * **Search { text, offset, limit }** - Command that retrieves a page of the entries
+///   containing `text`, see `ClipboardHistory::search`. `positions` and `total` are set
+///   like for SnapshotRange.
 /// * **Clear** - Command that clears the entire clipboard History.
+/// * **ClearMatching(ClipboardFilter)** - Command that deletes every entry the filter lists,
+///   in one step Undo reverts. Sets `count` to how many went.
+/// * **Subscribe** - Command that keeps the stream open and pushes a new snapshot
+///   every time the Clipboard History changes.
+/// * **Batch(Vec<CmdIPC>)** - Command that applies several edits in order as one change.
+/// * **Stats** - Command that returns item counts and approximate size as JSON in `message`.
+/// * **ExpectNext(u64)** - Hint sent right before a client puts an entry back on the
+///   clipboard, with its `ClipboardItem::clipboard_hash`. The next clipboard change with
+///   that hash is not captured, the client promotes the entry itself (`PromoteThis`).
+///   A hint that no change matched within a few seconds is dropped.
+/// * **MarkSensitive(usize)** - Command that marks an entry as sensitive: it is never
+///   written to disk and is dropped when the daemon stops, see `EntryMeta::sensitive`.
+/// * **Export** - Command that returns the whole history as a JSON backup in `message`,
+///   see `backup::history_to_json`.
+/// * **Import { json, replace }** - Command that adds the entries of a JSON backup on top
+///   of history, or in place of it with `replace`. Dedup and capacity apply, see
+///   `ClipboardHistory::import`. A backup that doesn't validate imports nothing.
+///
+/// Prefer the value-based variants when the index came from an earlier snapshot:
+/// the poller may have added items since, shifting every index.
 #[allow(unused)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum CmdIPC {
+    Add(ClipboardItem),
+    Copy(ClipboardItem),
     Promote(usize),
+    PromoteThis(ClipboardItem),
+    Move {
+        from: usize,
+        to: usize,
+    },
     Delete(usize),
+    DeleteRange {
+        start: usize,
+        end: usize,
+    },
     DeleteThis(ClipboardItem),
+    DeleteAt {
+        pos: usize,
+        item: ClipboardItem,
+    },
     Snapshot,
+    SnapshotRange {
+        offset: usize,
+        limit: usize,
+        #[serde(default)]
+        filter: ClipboardFilter,
+        #[serde(default)]
+        by_frequency: bool,
+    },
+    Search {
+        text: String,
+        offset: usize,
+        limit: usize,
+    },
+    Get(usize),
     Clear,
+    ClearMatching(ClipboardFilter),
+    Undo,
     Stop,
+    Subscribe,
+    Batch(Vec<CmdIPC>),
+    Stats,
+    ExpectNext(u64),
+    MarkSensitive(usize),
+    Export,
+    Import {
+        json: String,
+        replace: bool,
+    },
+}
+
+// Short form for logs. Never prints item contents, they may be secrets.
+impl fmt::Display for CmdIPC {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CmdIPC::Add(item) => write!(f, "Add({})", item.kind()),
+            CmdIPC::Copy(item) => write!(f, "Copy({})", item.kind()),
+            CmdIPC::Promote(pos) => write!(f, "Promote({pos})"),
+            CmdIPC::PromoteThis(item) => write!(f, "PromoteThis({})", item.kind()),
+            CmdIPC::Move { from, to } => write!(f, "Move({from} -> {to})"),
+            CmdIPC::Delete(pos) => write!(f, "Delete({pos})"),
+            CmdIPC::DeleteRange { start, end } => write!(f, "DeleteRange({start}..{end})"),
+            CmdIPC::DeleteThis(item) => write!(f, "DeleteThis({})", item.kind()),
+            CmdIPC::DeleteAt { pos, item } => write!(f, "DeleteAt({pos}, {})", item.kind()),
+            CmdIPC::Snapshot => write!(f, "Snapshot"),
+            CmdIPC::SnapshotRange {
+                offset,
+                limit,
+                filter,
+                by_frequency,
+            } => {
+                let order = if *by_frequency { ", most copied" } else { "" };
+                write!(
+                    f,
+                    "SnapshotRange({offset}, {limit} entries, {filter:?}{order})"
+                )
+            }
+            CmdIPC::Search {
+                text,
+                offset,
+                limit,
+            } => write!(
+                f,
+                "Search({} chars, {offset}, {limit} entries)",
+                text.chars().count()
+            ),
+            CmdIPC::Get(index) => write!(f, "Get({index})"),
+            CmdIPC::Clear => write!(f, "Clear"),
+            CmdIPC::ClearMatching(filter) => write!(f, "ClearMatching({filter:?})"),
+            CmdIPC::Undo => write!(f, "Undo"),
+            CmdIPC::Stop => write!(f, "Stop"),
+            CmdIPC::Subscribe => write!(f, "Subscribe"),
+            CmdIPC::Batch(cmds) => write!(f, "Batch({} commands)", cmds.len()),
+            CmdIPC::Stats => write!(f, "Stats"),
+            CmdIPC::ExpectNext(hash) => write!(f, "ExpectNext({hash:016x})"),
+            CmdIPC::MarkSensitive(pos) => write!(f, "MarkSensitive({pos})"),
+            CmdIPC::Export => write!(f, "Export"),
+            CmdIPC::Import { json, replace } => {
+                let mode = if *replace { "replace" } else { "merge" };
+                write!(f, "Import({} bytes, {mode})", json.len())
+            }
+        }
+    }
+}
+
+/// Stable error codes the daemon answers with. Match on these, not on `message`.
+///
+/// * **IndexOutOfBounds** - A position in the command was past the end of history.
+/// * **ItemNotFound** - The item given by value is not in history.
+/// * **WrongPayload** - The daemon was sent a response instead of a request.
+/// * **InvalidBatch** - A batch contained a command that is not allowed in one.
+/// * **NothingToUndo** - Undo was sent but no delete or clear is left to revert.
+/// * **InvalidImport** - The JSON sent with Import is not a valid backup.
+/// * **ClipboardUnavailable** - The daemon could not write the system clipboard.
+/// * **RequestTooLarge** - The request is over the daemon's size limit and wasn't read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IpcErrorCode {
+    IndexOutOfBounds,
+    ItemNotFound,
+    WrongPayload,
+    InvalidBatch,
+    NothingToUndo,
+    InvalidImport,
+    ClipboardUnavailable,
+    RequestTooLarge,
 }
 
 /// A data structure representing the Response of IPC.
 ///
 /// **Contains**:
 /// * **history_snapshot** - A snapshot of the current ClipboardHistory from the Clipboard Manager Daemon
-/// * **message** - Optional message if there are any errors.
+/// * **message** - Optional human readable message, for display only.
+/// * **error** - Set when the command failed.
+/// * **total** - Entries in the whole history, set when history_snapshot holds only a page of it.
+///   For SnapshotRange, the entries its filter lists.
+/// * **index** - Where the entry ended up, set by Promote, PromoteThis and Move.
+/// * **positions** - Where each entry of a SnapshotRange or Search page is in the whole history.
+/// * **count** - How many entries ClearMatching removed, or Import imported.
 #[allow(unused)]
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct IPCResponse {
     pub history_snapshot: Option<ClipboardHistory>,
     pub message: Option<String>,
+    pub error: Option<IpcErrorCode>,
+    #[serde(default)]
+    pub total: Option<usize>,
+    #[serde(default)]
+    pub index: Option<usize>,
+    #[serde(default)]
+    pub positions: Option<Vec<usize>>,
+    #[serde(default)]
+    pub count: Option<usize>,
 }
 
 // In case another data or id is to be sent
@@ -86,26 +263,35 @@ impl Payload {
 }
 // -------------------------------------------------------------------
 
-/// Creates and binds a new Unix domain socket listener at SOCKET_PATH.
+/// Permission bits applied to the socket file so only the owning user can connect.
+pub const SOCKET_MODE: u32 = 0o600;
+
+/// Creates and binds a new Unix domain socket listener at the socket path of `profile`.
 ///
 /// # Behavior
 /// - If an existing server is already bound to the socket path, it returns an error.
 /// - If a stale socket file exists, it removes it before rebinding.
+/// - Restricts the socket file to `SOCKET_MODE` (owner-only) before returning,
+///   so no other local user can read the clipboard history through it.
 /// - On success, returns a bound `UnixListener`.
 ///
 /// # Errors
 /// - Returns `IPCServerError::BindError` if the socket cannot be bound.
 /// - Returns `IPCServerError::BindError` if an existing IPC server is detected.
+/// - Returns `IPCServerError::BindError` if the socket permissions cannot be restricted.
 ///
 /// # Example
 /// ```no_run
+/// use super_v::common::DEFAULT_PROFILE;
 /// use super_v::services::clipboard_ipc_server::create_bind;
-/// let listener = create_bind().expect("Failed to bind IPC server");
+/// let listener = create_bind(DEFAULT_PROFILE).expect("Failed to bind IPC server");
 /// ```
-pub fn create_bind() -> Result<UnixListener, IPCServerError> {
+pub fn create_bind(profile: &str) -> Result<UnixListener, IPCServerError> {
+    let path = socket_path(profile);
+
     // Check if we can connect to server.
     // If yes, then server already running and a new server should not start
-    let try_conn = create_default_stream();
+    let try_conn = create_stream(profile);
 
     let Err(IPCServerError::FileNotFound | IPCServerError::ConnectionError(_)) = try_conn else {
         return Err(IPCServerError::BindError(
@@ -114,21 +300,65 @@ pub fn create_bind() -> Result<UnixListener, IPCServerError> {
     };
 
     // Remove the old sock file
-    let _ = remove_file(SOCKET_PATH);
+    let _ = remove_file(&path);
 
     // Create a new listener
-    let listener = match UnixListener::bind(SOCKET_PATH) {
+    let listener = match UnixListener::bind(&path) {
         Ok(listener) => listener,
         Err(err) => {
             return Err(IPCServerError::BindError(format!("{:?}", err)));
         }
     };
 
+    // Restrict the socket to the owner before anyone gets a chance to connect
+    if let Err(err) = restrict_socket_permissions(&path) {
+        drop(listener);
+        let _ = remove_file(&path);
+        return Err(err);
+    }
+
     // Return Listener
     Ok(listener)
 }
 
-/// Attempts to connect to the default Unix socket at SOCKET_PATH.
+/// Applies `SOCKET_MODE` to the socket file and verifies that it stuck.
+///
+/// # Errors
+/// - Returns `IPCServerError::BindError` if the mode could not be set or read back.
+fn restrict_socket_permissions(path: &Path) -> Result<(), IPCServerError> {
+    set_permissions(path, Permissions::from_mode(SOCKET_MODE))
+        .map_err(|err| IPCServerError::BindError(format!("{:?}", err)))?;
+
+    let mode = metadata(path)
+        .map_err(|err| IPCServerError::BindError(format!("{:?}", err)))?
+        .permissions()
+        .mode()
+        & 0o777;
+
+    if mode != SOCKET_MODE {
+        return Err(IPCServerError::BindError(format!(
+            "Socket permissions are {:o}, expected {:o}",
+            mode, SOCKET_MODE
+        )));
+    }
+
+    Ok(())
+}
+
+/// Attempts to connect to the Unix socket of the default profile.
+///
+/// Shorthand for `create_stream(DEFAULT_PROFILE)`.
+///
+/// # Example
+/// ```no_run
+/// use super_v::services::clipboard_ipc_server::create_default_stream;
+/// let mut stream = create_default_stream().expect("Unable to connect to IPC server");
+/// ```
+pub fn create_default_stream() -> Result<UnixStream, IPCServerError> {
+    create_stream(DEFAULT_PROFILE)
+}
+
+/// Attempts to connect to the Unix socket of the given profile.
 ///
 /// # Behavior
 /// - Returns a connected `UnixStream` if the socket is active.
@@ -141,11 +371,11 @@ pub fn create_bind() -> Result<UnixListener, IPCServerError> {
 ///
 /// # Example
 /// ```no_run
-/// use super_v::services::clipboard_ipc_server::create_default_stream;
-/// let mut stream = create_default_stream().expect("Unable to connect to IPC server");
+/// use super_v::services::clipboard_ipc_server::create_stream;
+/// let mut stream = create_stream("work").expect("Unable to connect to IPC server");
 /// ```
-pub fn create_default_stream() -> Result<UnixStream, IPCServerError> {
-    match UnixStream::connect(SOCKET_PATH) {
+pub fn create_stream(profile: &str) -> Result<UnixStream, IPCServerError> {
+    match UnixStream::connect(socket_path(profile)) {
         Ok(stream) => Ok(stream),
         Err(err) => {
             if let Some(err_code) = err.raw_os_error() {
@@ -172,7 +402,7 @@ pub fn create_default_stream() -> Result<UnixStream, IPCServerError> {
 /// - Flushes the stream to ensure all data is written.
 ///
 /// # Panics
-/// - Panics if the stream fails to write or flush.
+/// - Panics if the stream fails to write or flush. Use `try_send_payload` to handle that instead.
 ///
 /// # Example
 /// ```no_run
@@ -181,6 +411,17 @@ pub fn create_default_stream() -> Result<UnixStream, IPCServerError> {
 /// send_payload(&mut stream, Payload::Request(IPCRequest{cmd: CmdIPC::Clear}));
 /// ```
 pub fn send_payload(stream: &mut UnixStream, item: Payload) {
+    try_send_payload(stream, item).unwrap();
+}
+
+/// Sends a serialized `Payload` over a connected Unix stream without panicking.
+///
+/// Same framing as `send_payload`.
+///
+/// # Errors
+/// - Returns `IPCServerError::SendError` if the stream fails to write or flush
+///   (e.g. the peer disconnected).
+pub fn try_send_payload(stream: &mut UnixStream, item: Payload) -> Result<(), IPCServerError> {
     // Serialize command
     let payload = item.to_payload();
 
@@ -189,13 +430,12 @@ pub fn send_payload(stream: &mut UnixStream, item: Payload) {
     // Using that, we can extract the length of actual message (x)
     // and read for that len.
     // This way sending message of changing length works.
-    stream.write_all(&payload.len).unwrap();
-
-    // Send data
-    stream.write_all(&payload.buf).unwrap();
-
-    // Ensure all buffer is written
-    stream.flush().unwrap();
+    // Then send data and ensure all buffer is written
+    stream
+        .write_all(&payload.len)
+        .and_then(|_| stream.write_all(&payload.buf))
+        .and_then(|_| stream.flush())
+        .map_err(|err| IPCServerError::SendError(format!("{:?}", err)))
 }
 
 /// Reads and deserializes a `Payload` from a connected Unix stream.
@@ -209,6 +449,8 @@ pub fn send_payload(stream: &mut UnixStream, item: Payload) {
 /// - Panics if reading from the stream fails.
 /// - Panics if deserialization fails.
 ///
+/// Use `try_read_payload` to handle those instead.
+///
 /// # Example
 /// ```no_run
 /// use super_v::services::clipboard_ipc_server::{create_default_stream, read_payload};
@@ -217,15 +459,143 @@ pub fn send_payload(stream: &mut UnixStream, item: Payload) {
 /// println!("{:?}", payload);
 /// ```
 pub fn read_payload(stream: &mut UnixStream) -> Payload {
+    try_read_payload(stream).unwrap()
+}
+
+/// Reads and deserializes a `Payload` from a connected Unix stream without panicking.
+///
+/// Same framing as `read_payload`. For responses from the daemon, which may hold the
+/// whole history, so any length the prefix can express is read. The daemon reads its
+/// requests with `try_read_payload_within`.
+///
+/// # Errors
+/// - Returns `IPCServerError::RecieveError` if reading fails (e.g. the peer disconnected
+///   before sending the whole payload) or the payload cannot be deserialized.
+pub fn try_read_payload(stream: &mut UnixStream) -> Result<Payload, IPCServerError> {
+    try_read_payload_within(stream, u32::MAX as usize)
+}
+
+/// Like `try_read_payload`, but rejects a payload longer than `max_len` bytes.
+///
+/// The buffer grows as bytes arrive instead of being allocated up front, so a peer
+/// only costs the memory it actually sends.
+///
+/// # Errors
+/// - Same as `try_read_payload`.
+/// - Returns `IPCServerError::PayloadTooLarge` if the length prefix exceeds `max_len`.
+///   The payload is read and thrown away, so the peer finishes sending and can still be
+///   answered.
+pub fn try_read_payload_within(
+    stream: &mut UnixStream,
+    max_len: usize,
+) -> Result<Payload, IPCServerError> {
     // Read length of message (u32)
     let mut len_buf = [0u8; 4];
-    stream.read_exact(&mut len_buf).unwrap();
-    let req_len = u32::from_be_bytes(len_buf) as usize;
+    stream
+        .read_exact(&mut len_buf)
+        .map_err(|err| IPCServerError::RecieveError(format!("{:?}", err)))?;
+    let req_len = payload_len(len_buf, max_len);
+    if let Err(IPCServerError::PayloadTooLarge { len, .. }) = req_len {
+        let _ = io::copy(&mut Read::by_ref(stream).take(len as u64), &mut io::sink());
+    }
+    let req_len = req_len?;
 
     // Read payload
-    let mut payload = vec![0u8; req_len];
-    stream.read_exact(&mut payload).unwrap();
+    let mut payload = Vec::new();
+    stream
+        .take(req_len as u64)
+        .read_to_end(&mut payload)
+        .map_err(|err| IPCServerError::RecieveError(format!("{:?}", err)))?;
+
+    decode_payload(&payload, req_len)
+}
+
+/// Reads a `Payload` from an async stream. Same framing and limit as
+/// `try_read_payload_within`.
+///
+/// Only built with the `tokio` feature.
+///
+/// # Errors
+/// - Same as `try_read_payload_within`.
+#[cfg(feature = "tokio")]
+pub async fn read_payload_async<S>(
+    stream: &mut S,
+    max_len: usize,
+) -> Result<Payload, IPCServerError>
+where
+    S: tokio::io::AsyncRead + Unpin,
+{
+    use tokio::io::{self, AsyncReadExt};
+
+    let mut len_buf = [0u8; 4];
+    stream
+        .read_exact(&mut len_buf)
+        .await
+        .map_err(|err| IPCServerError::RecieveError(format!("{:?}", err)))?;
+    let req_len = payload_len(len_buf, max_len);
+    if let Err(IPCServerError::PayloadTooLarge { len, .. }) = req_len {
+        let _ = io::copy(&mut (&mut *stream).take(len as u64), &mut io::sink()).await;
+    }
+    let req_len = req_len?;
+
+    let mut payload = Vec::new();
+    stream
+        .take(req_len as u64)
+        .read_to_end(&mut payload)
+        .await
+        .map_err(|err| IPCServerError::RecieveError(format!("{:?}", err)))?;
+
+    decode_payload(&payload, req_len)
+}
+
+/// Sends a `Payload` over an async stream. Same framing as `try_send_payload`.
+///
+/// Only built with the `tokio` feature.
+///
+/// # Errors
+/// - Same as `try_send_payload`.
+#[cfg(feature = "tokio")]
+pub async fn send_payload_async<S>(stream: &mut S, item: Payload) -> Result<(), IPCServerError>
+where
+    S: tokio::io::AsyncWrite + Unpin,
+{
+    use tokio::io::AsyncWriteExt;
+
+    let payload = item.to_payload();
+    let result = async {
+        stream.write_all(&payload.len).await?;
+        stream.write_all(&payload.buf).await?;
+        stream.flush().await
+    };
+    result
+        .await
+        .map_err(|err| IPCServerError::SendError(format!("{:?}", err)))
+}
+
+/// Parses a length prefix, rejecting anything over `max_len`.
+fn payload_len(len_buf: [u8; 4], max_len: usize) -> Result<usize, IPCServerError> {
+    let req_len = u32::from_be_bytes(len_buf) as usize;
+
+    // Don't trust the peer with our memory
+    if req_len > max_len {
+        return Err(IPCServerError::PayloadTooLarge {
+            len: req_len,
+            limit: max_len,
+        });
+    }
+
+    Ok(req_len)
+}
+
+/// Deserializes a payload read for a prefix of `len` bytes.
+fn decode_payload(payload: &[u8], len: usize) -> Result<Payload, IPCServerError> {
+    if payload.len() < len {
+        return Err(IPCServerError::RecieveError(format!(
+            "stream ended after {} of {len} bytes",
+            payload.len()
+        )));
+    }
 
-    // deserialize
-    rmp_serde::from_slice(&payload).expect("failed to deserialize")
+    rmp_serde::from_slice(payload)
+        .map_err(|err| IPCServerError::RecieveError(format!("failed to deserialize: {:?}", err)))
 }
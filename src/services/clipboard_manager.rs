@@ -1,45 +1,629 @@
 // System Crates
 use std::{
     fs::{File, OpenOptions, remove_file},
-    io::Write,
+    io::{Read, Seek, Write},
     os::unix::net::{UnixListener, UnixStream},
     sync::{
-        Arc, Mutex,
-        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard, TryLockError,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc,
     },
     thread::{self, JoinHandle, sleep},
-    time::Duration,
+    time::{Duration, Instant, SystemTime},
 };
 
 // External Crates
-use arboard::Clipboard;
 use fs2::FileExt;
+use tracing::{debug, error, info, trace, warn};
 
 // My Crates
+#[cfg(feature = "ocr")]
+use crate::services::ocr;
+#[cfg(feature = "systemd")]
+use crate::services::systemd;
+#[cfg(feature = "tokio")]
+use crate::services::tokio_server;
 use crate::{
-    common::{ClipboardItem, DaemonError, GetItem, LOCK_PATH, SOCKET_PATH},
-    history::ClipboardHistory,
+    backup::{history_from_json, history_to_json},
+    common::{
+        ClipboardBackend, ClipboardError, ClipboardItem, DEFAULT_PROFILE, DaemonError,
+        IPCServerError, PrimarySelection, Selection, TextNormalization, lock_path,
+        max_request_bytes, socket_path, system_clipboard,
+    },
+    config::Config,
+    history::{ClipboardHistory, HistoryStore},
     services::clipboard_ipc_server::{
-        CmdIPC, IPCResponse, Payload, create_bind, read_payload, send_payload,
+        CmdIPC, IPCResponse, IpcErrorCode, Payload, create_bind, create_stream,
+        try_read_payload_within, try_send_payload,
     },
+    services::poller::{Capture, Poller},
 };
 
+/// Lock a mutex, recovering it if a thread panicked while holding it.
+///
+/// A panic mid-operation can leave the data half updated, but refusing every later
+/// command would wedge the daemon for good. The poison flag is cleared so the
+/// warning is only logged once per panic.
+fn lock_recovering<T: ?Sized>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| {
+        warn!("Recovering from a poisoned lock");
+        mutex.clear_poison();
+        poisoned.into_inner()
+    })
+}
+
+/// Like `lock_recovering`, but gives up with `None` if the mutex is busy.
+fn try_lock_recovering<T: ?Sized>(mutex: &Mutex<T>) -> Option<MutexGuard<'_, T>> {
+    match mutex.try_lock() {
+        Ok(guard) => Some(guard),
+        Err(TryLockError::Poisoned(poisoned)) => {
+            warn!("Recovering from a poisoned lock");
+            mutex.clear_poison();
+            Some(poisoned.into_inner())
+        }
+        Err(TryLockError::WouldBlock) => None,
+    }
+}
+
+/// Work for the notifier thread.
+enum Notice {
+    /// Push the snapshot to every subscriber.
+    Changed(ClipboardHistory),
+
+    /// Send the snapshot to the stream, then push it every later change.
+    Subscribe(UnixStream, ClipboardHistory),
+}
+
+/// Streams subscribed to history changes, and the thread that writes to them.
+///
+/// Pushes are queued while the history lock is held, so they go out in the order of
+/// the changes, and written by the notifier thread once it is released. A subscriber
+/// that is slow to read delays the other subscribers, not the poller or commands.
+/// The thread exits when the `Subscribers` is dropped.
+pub struct Subscribers {
+    streams: Arc<Mutex<Vec<UnixStream>>>,
+    queue: mpsc::Sender<Notice>,
+
+    // Subscribed and queued streams, so changes nobody listens to aren't snapshotted
+    count: Arc<AtomicUsize>,
+}
+
+impl Subscribers {
+    // Subscribers that can't take a push within this time are dropped
+    const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// Creates an empty list and starts its notifier thread.
+    pub fn new() -> Self {
+        let streams: Arc<Mutex<Vec<UnixStream>>> = Arc::new(Mutex::new(Vec::new()));
+        let count = Arc::new(AtomicUsize::new(0));
+        let (queue, notices) = mpsc::channel();
+
+        let (notifier_streams, notifier_count) = (streams.clone(), count.clone());
+        thread::spawn(move || {
+            for notice in notices {
+                match notice {
+                    Notice::Changed(snapshot) => {
+                        lock_recovering(&notifier_streams).retain_mut(|stream| {
+                            let payload = Payload::Response(snapshot_response(snapshot.clone()));
+                            let sent = try_send_payload(stream, payload).is_ok();
+                            if !sent {
+                                notifier_count.fetch_sub(1, Ordering::SeqCst);
+                            }
+                            sent
+                        });
+                    }
+                    Notice::Subscribe(mut stream, snapshot) => {
+                        let _ = stream.set_write_timeout(Some(Self::WRITE_TIMEOUT));
+                        let payload = Payload::Response(snapshot_response(snapshot));
+                        match try_send_payload(&mut stream, payload) {
+                            Ok(()) => lock_recovering(&notifier_streams).push(stream),
+                            Err(err) => {
+                                warn!("Could not send snapshot to subscriber: {err}");
+                                notifier_count.fetch_sub(1, Ordering::SeqCst);
+                            }
+                        }
+                    }
+                }
+            }
+        });
+
+        Self {
+            streams,
+            queue,
+            count,
+        }
+    }
+
+    /// Queues a push of the snapshot `snapshot` takes to every subscriber. Without
+    /// subscribers no snapshot is taken.
+    ///
+    /// Call with the history lock still held, so no later change is pushed first.
+    fn notify(&self, snapshot: impl FnOnce() -> ClipboardHistory) {
+        if self.count.load(Ordering::SeqCst) > 0 {
+            let _ = self.queue.send(Notice::Changed(snapshot()));
+        }
+    }
+
+    /// Queues sending `snapshot` to `stream` and subscribing it to later changes.
+    ///
+    /// Call with the history lock held, like `notify`, so no change is missed.
+    fn subscribe(&self, stream: UnixStream, snapshot: ClipboardHistory) {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        let _ = self.queue.send(Notice::Subscribe(stream, snapshot));
+    }
+
+    /// Number of subscribed streams. Pushes still queued are not accounted for.
+    pub fn len(&self) -> usize {
+        lock_recovering(&self.streams).len()
+    }
+
+    /// Whether no stream is subscribed.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for Subscribers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What a command handler shares with the daemon.
+///
+/// Cloned into every connection, by the threaded server and the tokio one alike,
+/// so both apply commands the same way.
+#[derive(Clone)]
+pub(crate) struct CommandContext {
+    pub(crate) history: Arc<Mutex<Box<dyn HistoryStore>>>,
+    pub(crate) subscribers: Arc<Subscribers>,
+    pub(crate) stop_signal: Arc<AtomicBool>,
+    pub(crate) profile: String,
+    pub(crate) compress_images: bool,
+    pub(crate) expected_paste: Arc<Mutex<Option<u64>>>,
+    pub(crate) clipboard: Arc<Mutex<Box<dyn ClipboardBackend>>>,
+    pub(crate) max_request_bytes: usize,
+    pub(crate) max_age: Option<Duration>,
+}
+
+/// What the server does with a connection once its request is handled.
+pub(crate) enum Reply {
+    /// Send the response and close the connection.
+    Respond(Box<IPCResponse>),
+
+    /// Keep the connection open for pushes, see `CommandContext::subscribe`.
+    Subscribe,
+}
+
+fn snapshot_response(snapshot: ClipboardHistory) -> IPCResponse {
+    IPCResponse {
+        history_snapshot: Some(snapshot),
+        ..Default::default()
+    }
+}
+
+fn message_response(msg: &str) -> IPCResponse {
+    IPCResponse {
+        message: Some(msg.to_string()),
+        ..Default::default()
+    }
+}
+
+/// Characters of text quoted in response messages.
+const MESSAGE_PREVIEW_CHARS: usize = 60;
+
+/// "Promoted: <text preview>" or "Deleted: Image 3x3", naming the item an edit touched.
+fn describe_item(action: &str, item: &ClipboardItem) -> String {
+    format!("{action}: {}", item.preview(MESSAGE_PREVIEW_CHARS))
+}
+
+fn page_response(page: ClipboardHistory, positions: Vec<usize>, total: usize) -> IPCResponse {
+    IPCResponse {
+        history_snapshot: Some(page),
+        total: Some(total),
+        positions: Some(positions),
+        ..Default::default()
+    }
+}
+
+fn index_response(index: usize) -> IPCResponse {
+    IPCResponse {
+        index: Some(index),
+        ..Default::default()
+    }
+}
+
+fn error_response(code: IpcErrorCode, msg: &str) -> IPCResponse {
+    IPCResponse {
+        message: Some(msg.to_string()),
+        error: Some(code),
+        ..Default::default()
+    }
+}
+
+/// The answer to a request that couldn't be read, if the peer is still listening.
+///
+/// Only a request over the size limit gets one, it was read past so the peer can
+/// take it. Any other read error leaves the connection unusable.
+pub(crate) fn read_error_response(err: &IPCServerError) -> Option<IPCResponse> {
+    match err {
+        IPCServerError::PayloadTooLarge { len, limit } => Some(error_response(
+            IpcErrorCode::RequestTooLarge,
+            &format!("Request of {len} bytes is over the daemon's limit of {limit} bytes."),
+        )),
+        _ => None,
+    }
+}
+
+impl CommandContext {
+    /// Apply a request to the shared history.
+    ///
+    /// **Behavior**:
+    /// - Edits are answered with a snapshot taken under the same lock, which is also
+    ///   pushed to subscribers. Failed edits are answered with an error code.
+    /// - A Batch is applied atomically under one lock (see `Manager::apply_batch`) and answered with one snapshot.
+    /// - Snapshot, SnapshotRange, Search, Stats and Export only read. Stats also carries the configured max age.
+    /// - Import is validated whole before anything is added, an invalid backup is answered
+    ///   with `IpcErrorCode::InvalidImport`. Images are compressed like added ones.
+    /// - ExpectNext hands its hash to the poller (see `Poller::expect`) and changes nothing.
+    /// - Copy adds like Add, then puts the item on the clipboard the poller reads, see
+    ///   `CommandContext::set_clipboard`. The daemon keeps serving it after the client exits.
+    /// - Subscribe is left to the caller, which owns the stream.
+    /// - Stop sets the stop flag and wakes the accept loop so it sees it.
+    /// - A Response payload is answered with `IpcErrorCode::WrongPayload`.
+    pub(crate) fn handle(&self, payload: Payload) -> Reply {
+        let ipc_request = match payload {
+            Payload::Request(ipc_request) => ipc_request,
+            Payload::Response(_) => {
+                warn!("Wrong payload type recieved from client");
+                return Reply::Respond(Box::new(error_response(
+                    IpcErrorCode::WrongPayload,
+                    "Wrong Payload type recieved. Expected CmdIpc but got IPCResponse.",
+                )));
+            }
+        };
+
+        info!("IPC command: {}", ipc_request.cmd);
+        let response = match ipc_request.cmd {
+            CmdIPC::Copy(item) => {
+                let added = self.handle_add(item.clone());
+                match self.set_clipboard(&item) {
+                    Ok(()) => added,
+                    Err(err) => {
+                        warn!("Could not set the clipboard: {err}");
+                        error_response(
+                            IpcErrorCode::ClipboardUnavailable,
+                            &format!("Added to history, but not to the clipboard: {err}"),
+                        )
+                    }
+                }
+            }
+            CmdIPC::Add(item) => self.handle_add(item),
+            CmdIPC::Clear => self.edit(|history| {
+                history.clear();
+                Ok(())
+            }),
+            CmdIPC::ClearMatching(filter) => self.edit_responding(|history| {
+                Ok(IPCResponse {
+                    count: Some(history.clear_matching(filter)),
+                    ..Default::default()
+                })
+            }),
+            CmdIPC::Undo => self.edit(|history| {
+                history.undo().map_err(|_| {
+                    (
+                        IpcErrorCode::NothingToUndo,
+                        "Could not undo. No delete or clear left to revert.".into(),
+                    )
+                })
+            }),
+            CmdIPC::Delete(pos) => self.edit_reporting(|history| {
+                history
+                    .delete(pos)
+                    .map(|item| describe_item("Deleted", &item))
+                    .map_err(|_| {
+                        (
+                            IpcErrorCode::IndexOutOfBounds,
+                            "Could not delete item. Index out of bounds.".into(),
+                        )
+                    })
+            }),
+            CmdIPC::DeleteRange { start, end } => self.edit(|history| {
+                history.delete_range(start, end).map_err(|_| {
+                    (
+                        IpcErrorCode::IndexOutOfBounds,
+                        "Could not delete items. Range out of bounds.".into(),
+                    )
+                })
+            }),
+            CmdIPC::DeleteThis(item) => self.edit(|history| {
+                history.delete_this(item).map_err(|_| {
+                    (
+                        IpcErrorCode::ItemNotFound,
+                        "Could not delete item. Item not found.".into(),
+                    )
+                })
+            }),
+            CmdIPC::DeleteAt { pos, item } => self.edit(|history| {
+                history.delete_at(pos, &item).map_err(|_| {
+                    (
+                        IpcErrorCode::ItemNotFound,
+                        "Could not delete item. Item not found.".into(),
+                    )
+                })
+            }),
+            CmdIPC::Promote(pos) => self.edit_responding(|history| {
+                history
+                    .promote(pos)
+                    .map(|index| IPCResponse {
+                        message: history
+                            .preview(index, MESSAGE_PREVIEW_CHARS)
+                            .map(|preview| format!("Promoted: {preview}")),
+                        index: Some(index),
+                        ..Default::default()
+                    })
+                    .map_err(|_| {
+                        (
+                            IpcErrorCode::IndexOutOfBounds,
+                            "Could not promote item. Index out of bounds.".into(),
+                        )
+                    })
+            }),
+            CmdIPC::MarkSensitive(pos) => self.edit(|history| {
+                history.mark_sensitive(pos).map_err(|_| {
+                    (
+                        IpcErrorCode::IndexOutOfBounds,
+                        "Could not mark item as sensitive. Index out of bounds.".into(),
+                    )
+                })
+            }),
+            CmdIPC::Move { from, to } => self.edit_responding(|history| {
+                history.move_to(from, to).map(index_response).map_err(|_| {
+                    (
+                        IpcErrorCode::IndexOutOfBounds,
+                        "Could not move item. Index out of bounds.".into(),
+                    )
+                })
+            }),
+            CmdIPC::PromoteThis(item) => self.edit_responding(|history| {
+                history.promote_this(item).map(index_response).map_err(|_| {
+                    (
+                        IpcErrorCode::ItemNotFound,
+                        "Could not promote item. Item not found.".into(),
+                    )
+                })
+            }),
+            CmdIPC::Snapshot => snapshot_response(lock_recovering(&self.history).snapshot()),
+            CmdIPC::SnapshotRange {
+                offset,
+                limit,
+                filter,
+                by_frequency,
+            } => {
+                let (page, positions, total) =
+                    lock_recovering(&self.history).query(offset, limit, filter, by_frequency);
+                page_response(page, positions, total)
+            }
+            CmdIPC::Search {
+                text,
+                offset,
+                limit,
+            } => {
+                let (page, positions, total) =
+                    lock_recovering(&self.history).search(&text, offset, limit);
+                page_response(page, positions, total)
+            }
+            CmdIPC::Get(index) => {
+                // A one entry page, so the item keeps its metadata
+                let (entry, total) = lock_recovering(&self.history).snapshot_range(index, 1);
+                if entry.is_empty() {
+                    let msg = if total == 0 {
+                        "History is empty.".to_string()
+                    } else {
+                        format!("No item at index {index}, the history has {total} items.")
+                    };
+                    error_response(IpcErrorCode::IndexOutOfBounds, &msg)
+                } else {
+                    IPCResponse {
+                        history_snapshot: Some(entry),
+                        total: Some(total),
+                        ..Default::default()
+                    }
+                }
+            }
+            CmdIPC::Stats => {
+                // Counts only, no item data leaves the lock
+                let mut stats = lock_recovering(&self.history).stats();
+                stats.max_age_secs = self.max_age.map(|max_age| max_age.as_secs());
+                message_response(&stats.to_json())
+            }
+            CmdIPC::Export => {
+                message_response(&history_to_json(&lock_recovering(&self.history).snapshot()))
+            }
+            CmdIPC::Import { json, replace } => match history_from_json(&json) {
+                Ok(entries) => {
+                    let entries = entries
+                        .into_iter()
+                        .map(|(item, meta)| {
+                            let item = if self.compress_images {
+                                item.compress()
+                            } else {
+                                item
+                            };
+                            (item, meta)
+                        })
+                        .collect();
+
+                    self.edit_responding(|history| {
+                        let imported = history.import(entries, replace);
+                        Ok(IPCResponse {
+                            message: Some(format!("Imported {imported} entries.")),
+                            count: Some(imported),
+                            ..Default::default()
+                        })
+                    })
+                }
+                Err(err) => {
+                    warn!("Rejected import: {err}");
+                    error_response(IpcErrorCode::InvalidImport, &err.to_string())
+                }
+            },
+            CmdIPC::ExpectNext(hash) => {
+                *lock_recovering(&self.expected_paste) = Some(hash);
+                message_response("Next clipboard change with this hash won't be captured.")
+            }
+            CmdIPC::Subscribe => return Reply::Subscribe,
+            CmdIPC::Batch(cmds) => {
+                let cmds = cmds
+                    .into_iter()
+                    .map(|cmd| match cmd {
+                        CmdIPC::Add(item) if self.compress_images => CmdIPC::Add(item.compress()),
+                        cmd => cmd,
+                    })
+                    .collect();
+
+                // Apply all commands under this one lock
+                self.edit(|history| Manager::apply_batch(history, cmds))
+            }
+            CmdIPC::Stop => {
+                info!("Stop requested over IPC");
+                self.stop_signal.store(true, Ordering::SeqCst);
+
+                // The accept loop only sees the flag on its next connection, so give it one
+                let _ = create_stream(&self.profile);
+                message_response("Stop Signal recieved.")
+            }
+        };
+
+        Reply::Respond(Box::new(response))
+    }
+
+    /// Run `edit` under the history lock.
+    ///
+    /// On success the snapshot is pushed to subscribers and returned, otherwise the error is.
+    fn edit(
+        &self,
+        edit: impl FnOnce(&mut Box<dyn HistoryStore>) -> Result<(), (IpcErrorCode, String)>,
+    ) -> IPCResponse {
+        self.edit_reporting(|history| edit(history).map(|()| String::new()))
+    }
+
+    /// Adds `item` on top, compressed first if the daemon compresses images.
+    fn handle_add(&self, item: ClipboardItem) -> IPCResponse {
+        let item = if self.compress_images {
+            item.compress()
+        } else {
+            item
+        };
+
+        self.edit(|history| {
+            // Same rule as the poller, blank text is dropped
+            if !item.is_empty_text() {
+                history.add(item);
+            }
+            Ok(())
+        })
+    }
+
+    /// Puts `item` on the clipboard the poller reads, and has the poller skip that change
+    /// like an `ExpectNext` one. The lock is held until the hint is in, so the poller
+    /// can't see the change first, and a write that failed leaves no hint behind.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClipboardError::Backend` if the clipboard can't be written, or the item is
+    /// a compressed image that doesn't decode.
+    fn set_clipboard(&self, item: &ClipboardItem) -> Result<(), ClipboardError> {
+        let mut clipboard = lock_recovering(&self.clipboard);
+        match item {
+            ClipboardItem::Text(text) => clipboard.set_text(text)?,
+            ClipboardItem::Image { width, height, .. }
+            | ClipboardItem::CompressedImage { width, height, .. } => {
+                let pixels = item
+                    .pixels()
+                    .ok_or_else(|| ClipboardError::Backend("image does not decode".into()))?;
+                clipboard.set_image(*width, *height, &pixels)?;
+            }
+        }
+
+        *lock_recovering(&self.expected_paste) = Some(item.clipboard_hash());
+        Ok(())
+    }
+
+    /// Like `edit`, but the edit also returns a message for the response, e.g. which item it touched.
+    /// An empty message is left out.
+    fn edit_reporting(
+        &self,
+        edit: impl FnOnce(&mut Box<dyn HistoryStore>) -> Result<String, (IpcErrorCode, String)>,
+    ) -> IPCResponse {
+        self.edit_responding(|history| {
+            edit(history).map(|msg| IPCResponse {
+                message: (!msg.is_empty()).then_some(msg),
+                ..Default::default()
+            })
+        })
+    }
+
+    /// Like `edit`, but the edit also fills in the response, e.g. the index an entry moved to.
+    /// The snapshot is set here.
+    fn edit_responding(
+        &self,
+        edit: impl FnOnce(&mut Box<dyn HistoryStore>) -> Result<IPCResponse, (IpcErrorCode, String)>,
+    ) -> IPCResponse {
+        // Get mutex guard
+        let mut unlocked_history = lock_recovering(&self.history);
+
+        match edit(&mut unlocked_history) {
+            Ok(response) => {
+                let snapshot = unlocked_history.snapshot();
+                self.subscribers.notify(|| snapshot.clone());
+                IPCResponse {
+                    history_snapshot: Some(snapshot),
+                    ..response
+                }
+            }
+            Err((code, msg)) => error_response(code, &msg),
+        }
+    }
+
+    /// Send the current snapshot on `stream`, then keep it to push every later change.
+    pub(crate) fn subscribe(&self, stream: UnixStream) {
+        // Queued under the history lock so no change is missed
+        let unlocked_history = lock_recovering(&self.history);
+        self.subscribers
+            .subscribe(stream, unlocked_history.snapshot());
+    }
+}
+
 /// # Manager
 ///  Holds shared services and thread handles for the clipboard manager.
 ///
 /// Fields:
-/// - _clipboard_service: Arc-wrapped clipboard service used to read the system clipboard.
-/// - _shared_history: Arc-wrapped ClipboardHistory shared between threads.
+/// - _clipboard_service: Arc-wrapped clipboard backend the poller reads, the system clipboard by default.
+/// - _shared_history: Arc-wrapped HistoryStore shared between threads.
 /// - _stop_signal: Atomic flag used to request worker threads to stop.
 /// - _polling_handle: Optional JoinHandle for the polling thread.
 /// - _command_handle: Optional JoinHandle for the command-handling thread.
+/// - _max_age: Optional age after which history entries are removed.
+/// - _poll_interval: Time between clipboard polls.
+/// - _compress_images: Whether images are PNG-compressed before they are stored.
+/// - _normalize_text: How the whitespace of captured text is cleaned up before it is stored.
+/// - _ocr_images: Whether the text in captured images is recognized (`ocr` feature only).
+/// - _track_primary: Whether the primary selection (middle-click paste) is captured too.
+/// - _adaptive_poll: Whether polling slows down while the clipboard is idle.
+/// - _max_text_bytes / _max_image_bytes: Largest text and image the poller captures.
+/// - _subscribers: Streams that asked to be pushed a snapshot whenever history changes.
+/// - _expected_paste: Clipboard hash from the last ExpectNext, until the poller takes it.
+/// - _profile: Name of the profile, which picks the socket and lock paths.
+/// - _server: Listener the command service accepts IPC connections on.
+/// - _socket_activated: Whether _server was passed in by systemd instead of bound here.
 ///
 /// These fields are internal to the implementation and not intended for public API use.
 /// Check implementation of Manager for usage.
 pub struct Manager {
     // Needed for operation
-    pub _clipboard_service: Arc<Mutex<Clipboard>>,
-    pub _shared_history: Arc<Mutex<ClipboardHistory>>,
+    pub _clipboard_service: Arc<Mutex<Box<dyn ClipboardBackend>>>,
+    pub _shared_history: Arc<Mutex<Box<dyn HistoryStore>>>,
     pub _stop_signal: Arc<AtomicBool>,
 
     // Thread handles
@@ -49,41 +633,134 @@ pub struct Manager {
     // Lock file to prevent multiple starts.
     pub _lock_file: Option<File>,
 
+    // Entries older than this are swept from history
+    pub _max_age: Option<Duration>,
+
+    // Time between clipboard polls
+    pub _poll_interval: Duration,
+
+    // Poll less often while nothing is copied
+    pub _adaptive_poll: bool,
+
+    // Store images PNG-compressed
+    pub _compress_images: bool,
+
+    // Clean up the whitespace of captured text
+    pub _normalize_text: TextNormalization,
+
+    // Read the text in captured images
+    pub _ocr_images: bool,
+
+    // Capture the primary selection as well
+    pub _track_primary: bool,
+
+    // Bigger copies are skipped
+    pub _max_text_bytes: usize,
+    pub _max_image_bytes: usize,
+
+    // Streams subscribed to history changes
+    pub _subscribers: Arc<Subscribers>,
+
+    // Paste-back announced over IPC, handed to the poller on its next tick
+    pub _expected_paste: Arc<Mutex<Option<u64>>>,
+
+    // Profile this manager serves
+    pub _profile: String,
+
     // IPC
     pub _server: UnixListener,
+
+    // Listener came from systemd, which keeps the socket file
+    pub _socket_activated: bool,
 }
 
 impl Manager {
-    // Clipboard Size
-    const CLIPBOARD_SIZE: usize = 25;
+    // How often the poller sweeps expired entries
+    const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+    // Captures waiting for the history writer before the poller waits too
+    const CAPTURE_QUEUE: usize = 64;
+
+    // With adaptive polling, the clipboard counts as idle after this long without a change
+    const IDLE_AFTER: Duration = Duration::from_secs(30);
+
+    // and is then polled at most this rarely
+    const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+    /// Create a new Manager instance backed by an in-memory ClipboardHistory of the default size.
+    ///
+    /// See `with_profile` for the full behavior.
+    pub fn new() -> Result<Self, DaemonError> {
+        Self::with_store(Box::new(ClipboardHistory::new(
+            Config::default().history_size,
+        )))
+    }
 
-    /// Create a new Manager instance and configure global handlers.
+    /// Create a new Manager for the default profile on top of the given history store.
+    ///
+    /// See `with_profile` for the full behavior.
+    pub fn with_store(store: Box<dyn HistoryStore>) -> Result<Self, DaemonError> {
+        Self::with_profile(store, DEFAULT_PROFILE)
+    }
+
+    /// The pid in a lock file, or `None` if it's empty or holds something else.
+    fn read_pid(lock_file: &mut File) -> Option<u32> {
+        let mut contents = String::new();
+        lock_file.read_to_string(&mut contents).ok()?;
+        contents.trim().parse().ok()
+    }
+
+    /// Create a new Manager for `profile` on top of the given history store, reading the
+    /// system clipboard.
+    ///
+    /// See `with_backend` for the full behavior.
+    ///
+    /// **Panics / errors**:
+    /// - Panics if the system clipboard can't be opened, e.g. there is no display.
+    /// - Otherwise the same errors as `with_backend`.
+    pub fn with_profile(store: Box<dyn HistoryStore>, profile: &str) -> Result<Self, DaemonError> {
+        let backend = match system_clipboard() {
+            Ok(clipboard) => clipboard,
+            Err(err) => {
+                panic!("ERROR: {:?}", err);
+            }
+        };
+        Self::with_backend(store, profile, backend)
+    }
+
+    /// Create a new Manager instance for `profile` around the given history store and
+    /// clipboard backend, and configure global handlers.
     ///
     /// **Behavior**:
-    /// - Allocates a ClipboardHistory with a fixed capacity.
-    /// - Creates and wraps a Clipboard service in an Arc<Mutex<...>>.
+    /// - Wraps the history store so it can be shared between threads.
+    /// - Wraps the clipboard backend in an Arc<Mutex<...>>. A `MockBackend` lets the
+    ///   daemon run without a display.
     /// - Creates an Arc<AtomicBool> stop signal used by worker threads.
     /// - Installs a ctrl-c handler that updates the stop signal.
     /// - Has a process lock so duplicate processes can't be run.
+    ///   Lock and socket paths come from `profile`, so different profiles can run side by side.
+    /// - The lock file holds the owner's pid. `stop` removes it, so a pid left behind by a
+    ///   previous instance means it didn't shut down cleanly, which is logged as a warning.
+    /// - With the `systemd` feature, uses the socket systemd passed in if socket activated,
+    ///   and sends READY=1 once the socket is listening.
     ///
     /// **Panics / errors**:
-    /// - This constructor unwraps the clipboard creation and will panic if the clipboard cannot be initialized.
+    /// - Returns `DaemonError::LockFileOpen` if the lock file can't be opened.
+    /// - Returns `DaemonError::ManagerMultiSpawn` with the holder's pid if another Manager holds the lock.
     ///
     /// **Returns**:
     /// - A fully constructed Manager with no active thread handles.
-    pub fn new() -> Result<Self, DaemonError> {
-        // New history
-        let _shared_history: Arc<Mutex<ClipboardHistory>> =
-            Arc::new(Mutex::new(ClipboardHistory::new(Self::CLIPBOARD_SIZE)));
+    pub fn with_backend(
+        store: Box<dyn HistoryStore>,
+        profile: &str,
+        backend: Box<dyn ClipboardBackend>,
+    ) -> Result<Self, DaemonError> {
+        // Shared history
+        let _shared_history: Arc<Mutex<Box<dyn HistoryStore>>> = Arc::new(Mutex::new(store));
 
         // Clipboard service
-        let _clipboard_service: Arc<Mutex<Clipboard>> =
-            Arc::new(Mutex::new(match Clipboard::new() {
-                Ok(clipboard) => clipboard,
-                Err(err) => {
-                    panic!("ERROR: {:?}", err);
-                }
-            }));
+        let _clipboard_service: Arc<Mutex<Box<dyn ClipboardBackend>>> =
+            Arc::new(Mutex::new(backend));
 
         // Stop signal
         let _stop_signal: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
@@ -95,28 +772,55 @@ impl Manager {
             ss_clone.store(true, Ordering::SeqCst);
         });
 
-        // Try lock
-        let lock_file = OpenOptions::new()
+        // Try lock. Not truncated yet, the pid in it belongs to whoever had it last.
+        let mut lock_file = OpenOptions::new()
             .create(true)
-            .truncate(true)
+            .truncate(false)
+            .read(true)
             .write(true)
-            .open(LOCK_PATH)
-            .expect("Failed to open lock file");
+            .open(lock_path(profile))
+            .map_err(DaemonError::LockFileOpen)?;
+        let previous_pid = Self::read_pid(&mut lock_file);
 
         // Return error if lock fails
         if lock_file.try_lock_exclusive().is_err() {
-            return Err(DaemonError::ManagerMultiSpawn);
+            return Err(DaemonError::ManagerMultiSpawn { pid: previous_pid });
         }
 
-        // Write pid for reference
-        let _ = lock_file.set_len(0);
-        let _ = write!(&lock_file, "{}", std::process::id());
-        let _ = lock_file.sync_all();
+        if let Some(pid) = previous_pid {
+            warn!("Found lock file of pid {pid}. The previous instance did not shut down cleanly.");
+        }
+
+        // Write pid for reference. The lock works without it, so only warn.
+        if let Err(err) = lock_file
+            .set_len(0)
+            .and_then(|_| lock_file.rewind())
+            .and_then(|_| write!(&lock_file, "{}", std::process::id()))
+            .and_then(|_| lock_file.sync_all())
+        {
+            warn!("Could not write pid to lock file: {err}");
+        }
+
+        // Under socket activation systemd owns the socket file, so use its listener as is
+        #[cfg(feature = "systemd")]
+        let activated = systemd::activated_listener();
+        #[cfg(not(feature = "systemd"))]
+        let activated: Option<UnixListener> = None;
+        let _socket_activated = activated.is_some();
 
-        // Once file lock is gotten, create a new IPC Server
-        // But first clear the previous sock file. Since we know we are the main owner of the manager.
-        let _ = remove_file(SOCKET_PATH);
-        let _server = create_bind().map_err(DaemonError::IPCErr)?;
+        let _server = match activated {
+            Some(listener) => listener,
+            None => {
+                // Once file lock is gotten, create a new IPC Server
+                // But first clear the previous sock file. Since we know we are the main owner of the manager.
+                let _ = remove_file(socket_path(profile));
+                create_bind(profile).map_err(DaemonError::IPCErr)?
+            }
+        };
+
+        // Clients can connect from here on, let a Type=notify unit report started
+        #[cfg(feature = "systemd")]
+        systemd::notify_ready();
 
         // Return the manager object
         Ok(Self {
@@ -131,22 +835,154 @@ impl Manager {
             // New Listener
             _lock_file: Some(lock_file),
 
+            // Defaults until a config is applied
+            _max_age: None,
+            _poll_interval: Config::default().poll_interval(),
+            _adaptive_poll: Config::default().adaptive_poll,
+            _compress_images: Config::default().compress_images,
+            _normalize_text: Config::default().normalize_text,
+            _ocr_images: Config::default().ocr_images,
+            _track_primary: Config::default().track_primary,
+            _max_text_bytes: Config::default().max_text_bytes,
+            _max_image_bytes: Config::default().max_image_bytes,
+
+            // No subscribers yet
+            _subscribers: Arc::new(Subscribers::new()),
+            _expected_paste: Arc::new(Mutex::new(None)),
+
+            _profile: profile.to_string(),
+
             // Ipc Server
             _server,
+            _socket_activated,
         })
     }
 
+    /// Set the age after which history entries are removed.
+    ///
+    /// `None` (the default) keeps entries until they are evicted by capacity.
+    /// Must be called before the services are started.
+    pub fn set_max_age(&mut self, max_age: Option<Duration>) {
+        self._max_age = max_age;
+    }
+
+    /// Apply the runtime settings from a Config.
+    ///
+    /// Sets the max age, poll interval and adaptive polling, image compression, text normalization, OCR, primary
+    /// selection tracking and item size limits. The history size, dedup window
+    /// and database are part of the store, which is created before the Manager.
+    /// Must be called before the services are started.
+    pub fn apply_config(&mut self, config: &Config) {
+        self._max_age = config.max_age();
+        self._poll_interval = config.poll_interval();
+        self._adaptive_poll = config.adaptive_poll;
+        self._compress_images = config.compress_images;
+        self._normalize_text = config.normalize_text;
+        self._track_primary = config.track_primary;
+        self._max_text_bytes = config.max_text_bytes;
+        self._max_image_bytes = config.max_image_bytes;
+
+        #[cfg(not(feature = "ocr"))]
+        if config.ocr_images {
+            warn!("Ignoring ocr_images: built without the ocr feature");
+        }
+        self._ocr_images = config.ocr_images;
+    }
+
+    /// Remove history entries older than the configured max age.
+    ///
+    /// Does nothing if no max age is set. Blocks until the history lock is available.
+    ///
+    /// **Returns**:
+    /// - The number of removed entries.
+    pub fn sweep_expired(&self) -> usize {
+        let Some(max_age) = self._max_age else {
+            return 0;
+        };
+
+        lock_recovering(&self._shared_history).remove_expired(max_age, SystemTime::now())
+    }
+
+    /// Recognize the text in `raw` in a new thread and attach it to `stored`.
+    ///
+    /// `raw` holds the pixels, `stored` is the item as it went into history (it may be
+    /// compressed). If `stored` left the history in the meantime the text is dropped.
+    /// Subscribers get a snapshot once the text is attached.
+    #[cfg(feature = "ocr")]
+    fn spawn_ocr(
+        shared_history: Arc<Mutex<Box<dyn HistoryStore>>>,
+        subscribers: Arc<Subscribers>,
+        raw: ClipboardItem,
+        stored: ClipboardItem,
+    ) {
+        thread::spawn(move || {
+            let ClipboardItem::Image {
+                width,
+                height,
+                bytes,
+            } = &raw
+            else {
+                return;
+            };
+
+            let Some(text) = ocr::extract_text(*width, *height, bytes) else {
+                return;
+            };
+
+            let mut unlocked_history = lock_recovering(&shared_history);
+            if unlocked_history.set_ocr_text(&stored, text).is_ok() {
+                subscribers.notify(|| unlocked_history.snapshot());
+            } else {
+                debug!("Image left history before OCR finished");
+            }
+        });
+    }
+
+    /// Time to wait before the next poll with adaptive polling.
+    ///
+    /// **Behavior**:
+    /// - Returns `base` until the clipboard has been idle for IDLE_AFTER (30 s).
+    /// - Then doubles `current` on every poll, up to IDLE_POLL_INTERVAL (1 s).
+    /// - Never returns less than `base`, so a slow configured interval stays as it is.
+    ///
+    /// **Arguments**:
+    /// - base: The configured poll interval.
+    /// - current: The interval used for the last poll.
+    /// - idle_for: Time since the clipboard last changed.
+    pub fn adaptive_poll_interval(
+        base: Duration,
+        current: Duration,
+        idle_for: Duration,
+    ) -> Duration {
+        if idle_for < Self::IDLE_AFTER {
+            return base;
+        }
+        (current * 2).min(Self::IDLE_POLL_INTERVAL).max(base)
+    }
+
     /// Start the polling service in a new background thread.
     ///
     /// **Behavior**:
     /// - Returns early with a log if a polling thread is already running.
     /// - Clones required Arcs for use inside the spawned thread.
     /// - The thread repeatedly:
-    ///     * Sleeps for a fixed interval (500 ms).
-    ///     * Attempts to read the current clipboard item (falling back to an empty item on error).
-    ///     * Compares it with the last seen item and, if different, attempts to push it into ClipboardHistory.
-    /// - Uses try_lock on locks to avoid blocking other threads; if a lock is unavailable it skips that iteration.
-    /// - Exits when the stop signal is set.
+    ///     * Sleeps for _poll_interval, or with _adaptive_poll for longer while the clipboard
+    ///       is idle (see `adaptive_poll_interval`). The next change snaps it back.
+    ///     * Passes a hint from ExpectNext on to its `Poller`, which then skips the announced
+    ///       change (see `Poller::skip_expected`).
+    ///     * Asks its `Poller` whether the clipboard holds a new item (see `Poller::check`).
+    ///     * Does the same for the primary selection if _track_primary is set. Text that is on
+    ///       the clipboard already isn't captured again from the primary selection.
+    ///     * Sends new items to the history writer over a channel and moves on. The channel
+    ///       holds CAPTURE_QUEUE captures, once full the poller waits for the writer.
+    ///     * Sweeps expired entries every SWEEP_INTERVAL if a max age is set.
+    /// - The history writer, a second thread, stores captures in ClipboardHistory in the order
+    ///   they were sent, waiting for the history lock as long as needed. So a busy history delays
+    ///   captures but can neither drop nor reorder them.
+    /// - Queues a snapshot for subscribers whenever either thread changed the history, the
+    ///   `Subscribers` thread writes it after the lock is released.
+    /// - Uses try_lock on the clipboard and for sweeping; if a lock is unavailable it skips that step.
+    /// - Exits when the stop signal is set, after the writer stored what was already sent.
     ///
     /// **Notes**:
     /// - This function stores the JoinHandle in _polling_handle.
@@ -154,7 +990,7 @@ impl Manager {
     pub fn _polling_service(&mut self) {
         // Check if polling thread is already started
         let None = self._polling_handle else {
-            eprintln!("Polling service is already running");
+            warn!("Polling service is already running");
             return;
         };
 
@@ -162,267 +998,374 @@ impl Manager {
         let clipboard_service = self._clipboard_service.clone();
         let stop_signal = self._stop_signal.clone();
         let shared_history = self._shared_history.clone();
+        let subscribers = self._subscribers.clone();
+        let expected_paste = self._expected_paste.clone();
+        let max_age = self._max_age;
+        let poll_interval = self._poll_interval;
+        let adaptive_poll = self._adaptive_poll;
+        let mut poller = Poller::default()
+            .with_compress_images(self._compress_images)
+            .with_normalize_text(self._normalize_text)
+            .with_max_bytes(self._max_text_bytes, self._max_image_bytes);
+        let mut primary_poller = self
+            ._track_primary
+            .then(|| poller.clone().with_selection(Selection::Primary));
+        #[cfg(feature = "ocr")]
+        let ocr_images = self._ocr_images;
 
         // Start the polling in a thread and store the handle
         self._polling_handle = Some(thread::spawn(move || {
-            let empty_item = ClipboardItem::Text("".to_string());
-
-            // Get the current item in clipboard. This will be compared with and edited
-            let mut last_item = match clipboard_service.try_lock() {
-                Ok(mut unlocked_clipboard) => match unlocked_clipboard.get_item() {
-                    Ok(item) => item,
-                    Err(_) => empty_item.clone(),
-                },
-                Err(_) => empty_item.clone(),
+            let mut last_sweep = Instant::now();
+            let mut last_change = Instant::now();
+            let mut interval = poll_interval;
+
+            // Detection and storing are split, so captures are stored strictly in the order
+            // they were seen, however long the history lock is held elsewhere.
+            // Bounded, so a writer stuck behind the lock holds back the poller instead of
+            // queueing copies without limit
+            let (capture_tx, capture_rx) = mpsc::sync_channel::<Capture>(Self::CAPTURE_QUEUE);
+            let writer = {
+                let shared_history = shared_history.clone();
+                let subscribers = subscribers.clone();
+                thread::spawn(move || {
+                    for capture in capture_rx {
+                        let mut unlocked_history = lock_recovering(&shared_history);
+
+                        // Recognizing text takes a while, so it is attached once done
+                        #[cfg(feature = "ocr")]
+                        if ocr_images && capture.raw.is_image() {
+                            Self::spawn_ocr(
+                                shared_history.clone(),
+                                subscribers.clone(),
+                                capture.raw.clone(),
+                                capture.stored.clone(),
+                            );
+                        }
+
+                        capture.add_to(&mut **unlocked_history);
+                        subscribers.notify(|| unlocked_history.snapshot());
+                    }
+                })
             };
 
+            // What is on the clipboard now was copied before the daemon started
+            if let Some(mut unlocked_clipboard) = try_lock_recovering(&clipboard_service) {
+                poller.ignore_current(&mut **unlocked_clipboard);
+                if let Some(primary_poller) = &mut primary_poller {
+                    primary_poller.ignore_current(&mut PrimarySelection(&mut **unlocked_clipboard));
+                }
+            }
+
+            info!("Polling service started");
             while !stop_signal.load(Ordering::SeqCst) {
-                // Item Checking
-                let current_item = match clipboard_service.try_lock() {
-                    Ok(mut unlocked_clipboard) => match unlocked_clipboard.get_item() {
-                        Ok(item) => item,
-                        Err(_) => empty_item.clone(),
-                    },
-                    Err(_) => empty_item.clone(),
-                };
+                trace!("Poll tick");
 
                 // This should be fine since _polling_service and _command_service both exist in the same process.
                 // So no need for thread-to-thread communication management and can purely focus on IPC management.
-                // Checks if item is new or not.
-                if current_item != last_item {
-                    // Check if the item is worth adding (not an empty text string)
-                    let is_empty_text = if let ClipboardItem::Text(text) = &current_item {
-                        text.trim().is_empty()
-                    } else {
-                        false // It's an Image, so it's not empty text
-                    };
+                // Checks if item is new or not. Compressing happens here, outside the history lock,
+                // PNG encoding a large image takes a while.
+                let mut captures = Vec::new();
+                if let Some(mut unlocked_clipboard) = try_lock_recovering(&clipboard_service) {
+                    // A client putting an entry back on the clipboard has promoted it already
+                    if let Some(hash) = lock_recovering(&expected_paste).take() {
+                        poller.expect(hash);
+                    }
 
-                    if !is_empty_text {
-                        // It's either an Image or non-empty Text.
-                        // Acquire Lock and add it.
-                        match shared_history.try_lock() {
-                            Ok(mut unlocked_history) => {
-                                // Add item to history
-                                unlocked_history.add(current_item.clone());
-
-                                // Update the last item within this
-                                last_item = current_item
-                                // So last item wont be written if mutex fails
-                            }
-                            Err(_) => { /* Failed To Get Lock, Skip */ }
+                    if poller.skip_expected(&mut **unlocked_clipboard) {
+                        trace!("Clipboard holds the announced paste-back");
+                        last_change = Instant::now();
+                    } else if let Some(capture) = poller.check(&mut **unlocked_clipboard) {
+                        // Remembered right away, the writer stores it in order
+                        poller.remember(&capture);
+                        captures.push(capture);
+                    }
+
+                    // Selecting text and then copying it puts it in both selections, keep one
+                    if let Some(primary_poller) = &mut primary_poller
+                        && let Some(capture) =
+                            primary_poller.check(&mut PrimarySelection(&mut **unlocked_clipboard))
+                    {
+                        primary_poller.remember(&capture);
+                        if &capture.raw != poller.last_item() {
+                            captures.push(capture);
                         }
                     }
-                    // else: It's an empty text item, so we skip adding it.
                 }
 
-                // Poll every 100ms
-                sleep(Duration::from_millis(100));
+                if !captures.is_empty() {
+                    last_change = Instant::now();
+                }
+                if captures
+                    .into_iter()
+                    .any(|capture| capture_tx.send(capture).is_err())
+                {
+                    error!("History writer is gone, stopping the poller");
+                    break;
+                }
+
+                // Drop expired entries. Skipped if the lock is busy, next tick will retry.
+                if let Some(max_age) = max_age
+                    && last_sweep.elapsed() >= Self::SWEEP_INTERVAL
+                    && let Some(mut unlocked_history) = try_lock_recovering(&shared_history)
+                {
+                    let removed = unlocked_history.remove_expired(max_age, SystemTime::now());
+                    if removed > 0 {
+                        debug!("Removed {removed} expired entries");
+                        subscribers.notify(|| unlocked_history.snapshot());
+                    }
+                    last_sweep = Instant::now();
+                }
+
+                // Wait for the next poll
+                if adaptive_poll {
+                    let next = Self::adaptive_poll_interval(
+                        poll_interval,
+                        interval,
+                        last_change.elapsed(),
+                    );
+                    if interval == poll_interval && next > poll_interval {
+                        debug!("Clipboard idle, polling less often");
+                    } else if interval > poll_interval && next == poll_interval {
+                        debug!("Clipboard changed, polling at full speed again");
+                    }
+                    interval = next;
+                }
+                sleep(interval);
+            }
+
+            // Let the writer store what is still queued
+            drop(capture_tx);
+            if writer.join().is_err() {
+                error!("History writer panicked");
             }
+            info!("Polling service stopped");
         }));
     }
 
+    /// Apply a batch of commands to the history as a single change.
+    ///
+    /// **Behavior**:
+    /// - Commands run in the order given. Each one sees the history left by the previous,
+    ///   so indices of later commands must account for earlier deletes and moves.
+    /// - Only history edits are allowed, Snapshot is accepted and does nothing. Commands
+    ///   that read, control the daemon or replace the history wholesale are rejected before
+    ///   anything is applied; the `matches!` below is the list.
+    /// - If any command fails, the history is restored to how it was before the batch.
+    ///
+    /// **Returns**:
+    /// - Err with the error code of the first offending command and a message naming it.
+    ///   Disallowed commands give `InvalidBatch`, failing ones their own code.
+    fn apply_batch(
+        history: &mut Box<dyn HistoryStore>,
+        cmds: Vec<CmdIPC>,
+    ) -> Result<(), (IpcErrorCode, String)> {
+        // Validate everything up front so nothing is half applied
+        if let Some((idx, cmd)) = cmds.iter().enumerate().find(|(_, cmd)| {
+            matches!(
+                cmd,
+                CmdIPC::Batch(_)
+                    | CmdIPC::Stop
+                    | CmdIPC::Subscribe
+                    | CmdIPC::Stats
+                    | CmdIPC::SnapshotRange { .. }
+                    | CmdIPC::Search { .. }
+                    | CmdIPC::Get(_)
+                    | CmdIPC::ExpectNext(_)
+                    | CmdIPC::Copy(_)
+                    | CmdIPC::Export
+                    | CmdIPC::Import { .. }
+            )
+        }) {
+            return Err((
+                IpcErrorCode::InvalidBatch,
+                format!("Could not apply batch. Command {idx} ({cmd}) is not allowed in a batch."),
+            ));
+        }
+
+        let before = history.snapshot();
+        for (idx, cmd) in cmds.into_iter().enumerate() {
+            let label = cmd.to_string();
+            let result = match cmd {
+                CmdIPC::Promote(pos) => history
+                    .promote(pos)
+                    .map(|_| ())
+                    .map_err(|_| IpcErrorCode::IndexOutOfBounds),
+                CmdIPC::PromoteThis(item) => history
+                    .promote_this(item)
+                    .map(|_| ())
+                    .map_err(|_| IpcErrorCode::ItemNotFound),
+                CmdIPC::Move { from, to } => history
+                    .move_to(from, to)
+                    .map(|_| ())
+                    .map_err(|_| IpcErrorCode::IndexOutOfBounds),
+                CmdIPC::Delete(pos) => history
+                    .delete(pos)
+                    .map(|_| ())
+                    .map_err(|_| IpcErrorCode::IndexOutOfBounds),
+                CmdIPC::DeleteRange { start, end } => history
+                    .delete_range(start, end)
+                    .map_err(|_| IpcErrorCode::IndexOutOfBounds),
+                CmdIPC::DeleteThis(item) => history
+                    .delete_this(item)
+                    .map_err(|_| IpcErrorCode::ItemNotFound),
+                CmdIPC::DeleteAt { pos, item } => history
+                    .delete_at(pos, &item)
+                    .map_err(|_| IpcErrorCode::ItemNotFound),
+                CmdIPC::Add(item) => {
+                    if !item.is_empty_text() {
+                        history.add(item);
+                    }
+                    Ok(())
+                }
+                CmdIPC::Clear => {
+                    history.clear();
+                    Ok(())
+                }
+                CmdIPC::ClearMatching(filter) => {
+                    history.clear_matching(filter);
+                    Ok(())
+                }
+                CmdIPC::Undo => history.undo().map_err(|_| IpcErrorCode::NothingToUndo),
+                CmdIPC::MarkSensitive(pos) => history
+                    .mark_sensitive(pos)
+                    .map_err(|_| IpcErrorCode::IndexOutOfBounds),
+                CmdIPC::Snapshot => Ok(()),
+                CmdIPC::Batch(_)
+                | CmdIPC::Stop
+                | CmdIPC::Subscribe
+                | CmdIPC::Stats
+                | CmdIPC::SnapshotRange { .. }
+                | CmdIPC::Search { .. }
+                | CmdIPC::Get(_)
+                | CmdIPC::ExpectNext(_)
+                | CmdIPC::Copy(_)
+                | CmdIPC::Export
+                | CmdIPC::Import { .. } => {
+                    unreachable!()
+                }
+            };
+
+            if let Err(code) = result {
+                // Roll back everything applied so far
+                history.replace(before);
+                return Err((
+                    code,
+                    format!("Could not apply batch. Command {idx} ({label}) failed."),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
     /// Start the command-handling service in a background thread.
     ///
     /// **Behavior**:
     /// - Listens for incoming IPC messages from external processes.
-    /// - Parses commands serialized as CmdIPC variants (e.g., Promote, Delete, Snapshot, Clear).
-    /// - Executes the requested operation on the shared ClipboardHistory instance.
-    /// - Constructs an IPCResponse containing:
-    ///     - A current snapshot of the ClipboardHistory.
-    ///     - An optional message describing the operation result.
-    /// - Sends the serialized IPCResponse back through IPC to the caller.
+    /// - Each connection is handed to `CommandContext::handle`, which parses the CmdIPC,
+    ///   applies it to the shared history and builds the IPCResponse.
+    /// - By default every connection gets its own OS thread. Built with the `tokio` feature,
+    ///   connections are tasks on a tokio runtime instead (see `services::tokio_server`).
     ///
     /// **Notes**:
     /// - This service runs concurrently and in the same process with the clipboard polling thread (or it won't work).
     /// - Should store the thread JoinHandle in _command_handle.
     pub fn _command_service(&mut self) {
-        // Clone the items needed.
-        let stop_signal_reader = self._stop_signal.clone();
-        let shared_history: Arc<Mutex<ClipboardHistory>> = self._shared_history.clone();
+        let ctx = CommandContext {
+            history: self._shared_history.clone(),
+            subscribers: self._subscribers.clone(),
+            stop_signal: self._stop_signal.clone(),
+            profile: self._profile.clone(),
+            compress_images: self._compress_images,
+            expected_paste: self._expected_paste.clone(),
+            clipboard: self._clipboard_service.clone(),
+            max_request_bytes: max_request_bytes(
+                self._max_text_bytes,
+                self._max_image_bytes,
+                lock_recovering(&self._shared_history).capacity(),
+            ),
+            max_age: self._max_age,
+        };
 
         // Find another way to just own the server instead of cloning.
         let ipc_server = self._server.try_clone().unwrap();
 
-        // Helper functions to send snapshot and err
-        fn _send_snapshot(s: &mut UnixStream, snapshot: ClipboardHistory) {
-            send_payload(
-                s,
-                Payload::Response(IPCResponse {
-                    history_snapshot: Some(snapshot),
-                    message: None,
-                }),
-            );
-        }
+        #[cfg(feature = "tokio")]
+        let handle = tokio_server::spawn(ipc_server, ctx);
+        #[cfg(not(feature = "tokio"))]
+        let handle = Self::spawn_accept_loop(ipc_server, ctx);
 
-        fn _send_msg(s: &mut UnixStream, msg: &str) {
-            send_payload(
-                s,
-                Payload::Response(IPCResponse {
-                    history_snapshot: None,
-                    message: Some(msg.to_string()),
-                }),
-            );
-        }
+        self._command_handle = Some(handle);
+    }
+
+    /// Accept connections on `ipc_server`, handling each one in its own thread.
+    ///
+    /// The loop ends on the first connection after the stop flag is set.
+    #[cfg_attr(feature = "tokio", allow(dead_code))]
+    fn spawn_accept_loop(ipc_server: UnixListener, ctx: CommandContext) -> JoinHandle<()> {
+        thread::spawn(move || {
+            info!("Command service started");
 
-        // Run the command service in a new thread
-        // The thread will consume the only UnixListener (since it's not an Arc) which is fine
-        // Then it will listen for streams which send CmdIpc as Payload
-        // Parse the Cmd and apply operation on the clipboard history
-        // Finally, send a snapshot of the history
-        self._command_handle = Some(thread::spawn(move || {
             // Handle incoming messages
             for stream in ipc_server.incoming() {
                 // Break the loop if stop_signal is found
-                let stop_signal_writer = stop_signal_reader.clone();
-                if stop_signal_reader.load(Ordering::SeqCst) {
+                if ctx.stop_signal.load(Ordering::SeqCst) {
                     break;
                 }
 
                 match stream {
                     Ok(mut s) => {
-                        let history_for_thread = shared_history.clone();
+                        let ctx = ctx.clone();
 
                         // Handle payload in another thread
                         thread::spawn(move || {
                             // Read the payload
-                            let payload = read_payload(&mut s);
-
-                            // Match the payload and execute command
-                            match payload {
-                                Payload::Request(ipc_request) => {
-                                    match ipc_request.cmd {
-                                        CmdIPC::Clear => {
-                                            // Get mutex guard
-                                            match history_for_thread.lock() {
-                                                Ok(mut unlocked_history) => {
-                                                    // Clear the history
-                                                    unlocked_history.clear();
-
-                                                    // Create snapshot, drop guard, send snapshot
-                                                    let snapshot = unlocked_history.clone();
-                                                    _send_snapshot(&mut s, snapshot);
-                                                }
-                                                Err(_) => {
-                                                    _send_msg(&mut s, "Could not unlock history");
-                                                }
-                                            }
-                                        }
-                                        CmdIPC::Delete(pos) => {
-                                            // Get mutex guard
-                                            match history_for_thread.lock() {
-                                                Ok(mut unlocked_history) => {
-                                                    // Delete the item
-                                                    match unlocked_history.delete(pos) {
-                                                        Ok(_) => {
-                                                            // Create snapshot, drop guard, send snapshot
-                                                            let snapshot = unlocked_history.clone();
-                                                            _send_snapshot(&mut s, snapshot);
-                                                        }
-                                                        Err(_) => {
-                                                            _send_msg(
-                                                                &mut s,
-                                                                "Could not delete item. Index out of bounds.",
-                                                            );
-                                                        }
-                                                    };
-                                                }
-                                                Err(_) => {
-                                                    _send_msg(&mut s, "Could not unlock history");
-                                                }
-                                            }
-                                        }
-                                        CmdIPC::DeleteThis(item) => {
-                                            // Get mutex guard
-                                            match history_for_thread.lock() {
-                                                Ok(mut unlocked_history) => {
-                                                    // Delete the item
-                                                    match unlocked_history.delete_this(item) {
-                                                        Ok(_) => {
-                                                            // Create snapshot, drop guard, send snapshot
-                                                            let snapshot = unlocked_history.clone();
-                                                            _send_snapshot(&mut s, snapshot);
-                                                        }
-                                                        Err(_) => {
-                                                            _send_msg(
-                                                                &mut s,
-                                                                "Could not delete item. Index out of bounds.",
-                                                            );
-                                                        }
-                                                    };
-                                                }
-                                                Err(_) => {
-                                                    _send_msg(&mut s, "Could not unlock history");
-                                                }
-                                            }
-                                        }
-                                        CmdIPC::Promote(pos) => {
-                                            // Get mutex guard
-                                            match history_for_thread.lock() {
-                                                Ok(mut unlocked_history) => {
-                                                    // Promote the item
-                                                    match unlocked_history.promote(pos) {
-                                                        Ok(_) => {
-                                                            // Create snapshot, drop guard, send snapshot
-                                                            let snapshot = unlocked_history.clone();
-                                                            _send_snapshot(&mut s, snapshot);
-                                                        }
-                                                        Err(_) => {
-                                                            _send_msg(
-                                                                &mut s,
-                                                                "Could not promote item. Index out of bounds.",
-                                                            );
-                                                        }
-                                                    };
-                                                }
-                                                Err(_) => {
-                                                    _send_msg(&mut s, "Could not unlock history");
-                                                }
-                                            }
-                                        }
-                                        CmdIPC::Snapshot => {
-                                            // Get mutex guard
-                                            match history_for_thread.lock() {
-                                                Ok(unlocked_history) => {
-                                                    // Create snapshot, drop guard, send snapshot
-                                                    let snapshot = unlocked_history.clone();
-                                                    _send_snapshot(&mut s, snapshot);
-                                                }
-                                                Err(_) => {
-                                                    // Send err if could not unlock
-                                                    _send_msg(&mut s, "Could not unlock history");
-                                                }
-                                            }
-                                        }
-                                        CmdIPC::Stop => {
-                                            stop_signal_writer.store(true, Ordering::SeqCst);
-                                            _send_msg(&mut s, "Stop Signal recieved.");
+                            let payload =
+                                match try_read_payload_within(&mut s, ctx.max_request_bytes) {
+                                    Ok(payload) => payload,
+                                    Err(err) => {
+                                        error!("Could not read request: {err}");
+                                        if let Some(response) = read_error_response(&err) {
+                                            let _ = try_send_payload(
+                                                &mut s,
+                                                Payload::Response(response),
+                                            );
                                         }
+                                        return;
+                                    }
+                                };
+
+                            match ctx.handle(payload) {
+                                Reply::Respond(response) => {
+                                    if let Err(err) =
+                                        try_send_payload(&mut s, Payload::Response(*response))
+                                    {
+                                        warn!("Could not send response: {err}");
                                     }
                                 }
-                                Payload::Response(_) => {
-                                    _send_msg(
-                                        &mut s,
-                                        "Wrong Payload type recieved. Expected CmdIpc but got IPCResponse.",
-                                    );
-                                }
+                                Reply::Subscribe => ctx.subscribe(s),
                             }
                         });
                     }
                     Err(e) => {
-                        eprintln!("Accept Error: {e}");
+                        error!("Accept Error: {e}");
                     }
                 }
             }
-        }));
+        })
     }
 
     /// Start all configured background services.
     ///
     /// **Behavior**:
+    /// - Sweeps expired entries once before any service starts.
     /// - Calls _polling_service to start the clipboard poller.
     /// - Calls _command_service to start command handling.
     /// - Each service checks whether it is already running and will not start duplicate
     pub fn start_daemon(&mut self) {
+        // Drop anything that expired before the daemon started
+        self.sweep_expired();
+
         // Start the polling service
         self._polling_service();
 
@@ -445,13 +1388,20 @@ impl Manager {
     ///
     /// **Behavior**:
     /// - Sets the stop signal to request all worker threads to exit.
-    /// - Takes ownership of the stored thread handles and attempts to join them.
-    /// - Joining is performed from a short-lived helper thread to avoid blocking the caller.
+    /// - Connects to its own socket once so the command thread wakes from accept().
+    /// - Takes ownership of the stored thread handles and joins the polling thread, which
+    ///   waits for its writer to store every capture still queued. That takes at most one
+    ///   poll interval plus the time to store them.
+    /// - The command thread is joined from a short-lived helper thread, a connection that is
+    ///   still being served must not block the caller.
+    /// - Drops the entries marked sensitive, see `EntryMeta::sensitive`.
     ///
     /// **Notes**:
     /// - After stop returns, worker threads will have been requested to stop and any existing handles will be joined.
     /// - This method swallows join errors and does not return a failure result.
     pub fn stop(&mut self) {
+        info!("Stopping service");
+
         // Signal threads to stop
         self._stop_signal.store(true, Ordering::SeqCst);
 
@@ -459,23 +1409,39 @@ impl Manager {
         let _polling_handle = self._polling_handle.take();
         let _command_handle = self._command_handle.take();
 
-        // Spawn a short-lived thread to join them so main thread is not blocked
+        // The accept loop is parked in accept(), connect once so it sees the flag
+        if _command_handle.is_some() {
+            let _ = create_stream(&self._profile);
+        }
+
+        // Captures the poller already took must be stored before the history is dropped
+        if let Some(h) = _polling_handle
+            && h.join().is_err()
+        {
+            error!("Polling service panicked");
+        }
+
+        // Spawn a short-lived thread to join the rest so main thread is not blocked
         // All errors are swallowed
         let _ = thread::spawn(move || {
-            if let Some(h) = _polling_handle {
-                let _ = h.join();
-            }
             if let Some(h) = _command_handle {
                 let _ = h.join();
             }
         });
 
+        let dropped = lock_recovering(&self._shared_history).drop_sensitive();
+        if dropped > 0 {
+            info!("Dropped {dropped} sensitive entries");
+        }
+
         // Unlock the lock file
         // Swallows the error.
         if let Some(lockfile) = &self._lock_file {
             let _ = lockfile.unlock();
-            let _ = remove_file(SOCKET_PATH);
-            let _ = remove_file(LOCK_PATH);
+            if !self._socket_activated {
+                let _ = remove_file(socket_path(&self._profile));
+            }
+            let _ = remove_file(lock_path(&self._profile));
         }
     }
 }
@@ -1,4 +1,11 @@
 // Daemon Services
 pub mod clipboard_ipc_server;
 pub mod clipboard_manager;
+#[cfg(feature = "ocr")]
+pub mod ocr;
+pub mod poller;
+#[cfg(feature = "systemd")]
+pub mod systemd;
+#[cfg(feature = "tokio")]
+pub mod tokio_server;
 pub mod ydotool;
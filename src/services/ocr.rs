@@ -0,0 +1,70 @@
+// OCR
+// Only built with the `ocr` feature.
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use tracing::{debug, error, warn};
+
+use crate::image_codec::encode_png;
+
+/// Extracts the text in an image by running the `tesseract` command.
+///
+/// The image is handed over as PNG on stdin, so nothing touches the disk. Takes
+/// anywhere from a fraction of a second to several seconds for a 4K screenshot, so
+/// call it off the poller and outside the history lock.
+///
+/// # Arguments
+///
+/// * `width`, `height` - Size of the image in pixels
+/// * `rgba` - Raw RGBA pixels
+///
+/// # Returns
+///
+/// * `Some(text)` - The recognized text, trimmed
+/// * `None` - If there is no text, or `tesseract` is missing or failed
+pub fn extract_text(width: usize, height: usize, rgba: &[u8]) -> Option<String> {
+    let png = encode_png(width, height, rgba)?;
+
+    let mut child = match Command::new("tesseract")
+        .args(["stdin", "stdout"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => {
+            error!("Failed to execute tesseract: {}", e);
+            return None;
+        }
+    };
+
+    // Dropping stdin closes it, so tesseract knows the image is complete
+    if let Some(mut stdin) = child.stdin.take()
+        && let Err(e) = stdin.write_all(&png)
+    {
+        warn!("Could not send image to tesseract: {}", e);
+    }
+
+    let output = match child.wait_with_output() {
+        Ok(output) => output,
+        Err(e) => {
+            error!("Failed to execute tesseract: {}", e);
+            return None;
+        }
+    };
+
+    if !output.status.success() {
+        error!(
+            "tesseract failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        return None;
+    }
+
+    let text = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    debug!("OCR found {} characters", text.len());
+    (!text.is_empty()).then_some(text)
+}
@@ -0,0 +1,360 @@
+// Clipboard Poller
+// The capture step of the daemon, usable without it.
+use std::{
+    cell::Cell,
+    time::{Duration, Instant, SystemTime},
+};
+
+use tracing::{debug, trace, warn};
+
+use crate::{
+    common::{ClipboardItem, GetItem, Selection, TextNormalization, is_storable_text},
+    history::HistoryStore,
+};
+
+/// A new clipboard item, read by `Poller::check` and not stored yet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capture {
+    /// The item as it was read from the clipboard.
+    pub raw: ClipboardItem,
+
+    /// The item as it goes into history: normalized, and compressed if asked to.
+    pub stored: ClipboardItem,
+
+    /// HTML the clipboard offered next to text, see `EntryMeta::html`.
+    pub html: Option<String>,
+
+    /// Selection the item was read from, see `Poller::with_selection`.
+    pub selection: Selection,
+
+    /// When the item was found on the clipboard. Recorded as its copy time, so a
+    /// capture that waits before it is stored keeps its place.
+    pub copied_at: SystemTime,
+}
+
+impl Capture {
+    /// Adds the capture to a history, without a `Poller` involved.
+    ///
+    /// A text capture also replaces the HTML of an earlier copy of the same text, and
+    /// the entry is tagged with the selection it was last copied from.
+    ///
+    /// # Arguments
+    ///
+    /// * `history` - The history to add it to
+    pub fn add_to(self, history: &mut dyn HistoryStore) {
+        debug!(
+            "Captured new {:?} item: {}",
+            self.selection,
+            self.raw.kind()
+        );
+        // Added or promoted, the entry is at the front now
+        history.add_at(self.stored.clone(), self.copied_at);
+        if history
+            .set_selection(0, &self.stored, self.selection)
+            .is_err()
+        {
+            debug!("Item left history before its selection was stored");
+        }
+        if !self.stored.is_image() && history.set_html(0, &self.stored, self.html).is_err() {
+            debug!("Text left history before its HTML was stored");
+        }
+    }
+}
+
+/// Turns clipboard contents into history entries, one check at a time.
+///
+/// Remembers the last item it stored, so the same clipboard contents are only
+/// captured once. Empty text, text that looks binary (see `is_storable_text`), items
+/// over the size limits (see `with_max_bytes`) and a change announced with `expect`
+/// are never captured.
+///
+/// Embedders can call `poll_once`. The daemon calls `check` and `remember` on its
+/// polling thread and hands each capture to a writer thread, which adds them in the
+/// order they were found (see `Capture::add_to`).
+///
+/// ```no_run
+/// use arboard::Clipboard;
+/// use super_v::{history::ClipboardHistory, services::poller::Poller};
+///
+/// let mut clipboard = Clipboard::new().unwrap();
+/// let mut history = ClipboardHistory::new(25);
+/// let mut poller = Poller::default();
+///
+/// // Whatever is on the clipboard now isn't captured
+/// poller.ignore_current(&mut clipboard);
+/// loop {
+///     if poller.poll_once(&mut clipboard, &mut history) {
+///         println!("{history}");
+///     }
+///     std::thread::sleep(std::time::Duration::from_millis(100));
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Poller {
+    last_item: ClipboardItem,
+    compress_images: bool,
+    normalize_text: TextNormalization,
+    selection: Selection,
+    // Clipboard hash of a change a client announced, and when
+    expected: Option<(u64, Instant)>,
+    max_text_bytes: usize,
+    max_image_bytes: usize,
+    // Kind and size of the last oversized item, so it is warned about once
+    oversized: Cell<Option<(bool, usize)>>,
+}
+
+impl Default for Poller {
+    fn default() -> Self {
+        Self {
+            last_item: ClipboardItem::Text(String::new()),
+            compress_images: false,
+            normalize_text: TextNormalization::Off,
+            selection: Selection::Clipboard,
+            expected: None,
+            max_text_bytes: usize::MAX,
+            max_image_bytes: usize::MAX,
+            oversized: Cell::new(None),
+        }
+    }
+}
+
+impl Poller {
+    /// How long an announced change is waited for before the hint is dropped.
+    pub const EXPECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Stores captured images PNG-compressed, see `ClipboardItem::compress`.
+    pub fn with_compress_images(mut self, compress_images: bool) -> Self {
+        self.compress_images = compress_images;
+        self
+    }
+
+    /// Cleans up the whitespace of captured text, see `ClipboardItem::normalize`.
+    pub fn with_normalize_text(mut self, normalize_text: TextNormalization) -> Self {
+        self.normalize_text = normalize_text;
+        self
+    }
+
+    /// Skips text (or the HTML that comes with it) longer than `max_text_bytes`, and
+    /// images with more than `max_image_bytes` of raw pixels. Default: no limit.
+    pub fn with_max_bytes(mut self, max_text_bytes: usize, max_image_bytes: usize) -> Self {
+        self.max_text_bytes = max_text_bytes;
+        self.max_image_bytes = max_image_bytes;
+        self
+    }
+
+    /// Tags captures with the selection they come from. Default: `Selection::Clipboard`.
+    ///
+    /// Only a tag: the poller reads whatever `GetItem` it is given, so pair
+    /// `Selection::Primary` with `PrimarySelection`.
+    pub fn with_selection(mut self, selection: Selection) -> Self {
+        self.selection = selection;
+        self
+    }
+
+    /// The item last captured or ignored, as read from the clipboard.
+    pub fn last_item(&self) -> &ClipboardItem {
+        &self.last_item
+    }
+
+    /// Treats what is on the clipboard now as already captured.
+    ///
+    /// # Arguments
+    ///
+    /// * `clipboard` - The clipboard to read
+    pub fn ignore_current(&mut self, clipboard: &mut (impl GetItem + ?Sized)) {
+        if let Ok(item) = clipboard.get_item() {
+            self.last_item = item;
+        }
+    }
+
+    /// Announces that the next clipboard change will have this `ClipboardItem::clipboard_hash`
+    /// and is not to be captured, e.g. because a client is putting an entry that is in
+    /// history already back on the clipboard. Replaces an earlier hint.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - Clipboard hash of the item about to be put on the clipboard
+    pub fn expect(&mut self, hash: u64) {
+        self.expected = Some((hash, Instant::now()));
+    }
+
+    /// Consumes the hint given to `expect` once the clipboard changes.
+    ///
+    /// Call before `check`. The hint is dropped when the change doesn't match it, or
+    /// when nothing changed within `EXPECT_TIMEOUT`, so a wrong hint skips nothing.
+    ///
+    /// # Arguments
+    ///
+    /// * `clipboard` - The clipboard to read
+    ///
+    /// # Returns
+    ///
+    /// * `true` if the clipboard changed to the announced item, which is now remembered
+    ///   as the last item instead of being captured
+    pub fn skip_expected(&mut self, clipboard: &mut (impl GetItem + ?Sized)) -> bool {
+        let Some((hash, since)) = self.expected else {
+            return false;
+        };
+        if since.elapsed() > Self::EXPECT_TIMEOUT {
+            debug!("Dropped clipboard hint, no change matched it");
+            self.expected = None;
+            return false;
+        }
+
+        let Ok(raw) = clipboard.get_item() else {
+            return false;
+        };
+        if raw == self.last_item {
+            // Not changed yet
+            return false;
+        }
+
+        self.expected = None;
+        if raw.clipboard_hash() != hash {
+            debug!("Clipboard changed to something other than the hinted item");
+            return false;
+        }
+        debug!("Skipped announced {} paste-back", raw.kind());
+        self.last_item = raw;
+        true
+    }
+
+    /// Reads the clipboard and prepares its contents for the history if they are new.
+    ///
+    /// Normalizing and compressing happen here, so callers can do this outside the
+    /// history lock. Nothing is remembered until `store` is called, so a capture that
+    /// couldn't be stored is returned again by the next check.
+    ///
+    /// # Arguments
+    ///
+    /// * `clipboard` - The clipboard to read
+    ///
+    /// # Returns
+    ///
+    /// * `Some(Capture)` - The clipboard holds something not stored yet
+    /// * `None` - The clipboard is unchanged, empty, holds binary junk or something too
+    ///   big, or can't be read
+    pub fn check(&self, clipboard: &mut (impl GetItem + ?Sized)) -> Option<Capture> {
+        let raw = clipboard.get_item().ok()?;
+        if raw == self.last_item || raw.is_empty_text() {
+            return None;
+        }
+        if self.is_oversized(&raw) {
+            return None;
+        }
+        if let ClipboardItem::Text(text) = &raw
+            && !is_storable_text(text)
+        {
+            trace!("Skipped binary-looking text of {} bytes", text.len());
+            return None;
+        }
+
+        // Normalized first, so dedup sees the cleaned up text
+        let stored = raw.clone().normalize(self.normalize_text);
+        let stored = if self.compress_images {
+            stored.compress()
+        } else {
+            stored
+        };
+
+        // Text may come with HTML, kept so a paste can offer both again
+        let html = if raw.is_image() {
+            None
+        } else {
+            clipboard.get_html()
+        };
+        let html = html.filter(|html| {
+            let fits = html.len() <= self.max_text_bytes;
+            if !fits {
+                warn!("Dropped {} bytes of HTML over max_text_bytes", html.len());
+            }
+            fits
+        });
+
+        Some(Capture {
+            raw,
+            stored,
+            html,
+            selection: self.selection,
+            copied_at: SystemTime::now(),
+        })
+    }
+
+    /// Whether `raw` is over the size limits. Warns once per oversized item, the poller
+    /// sees it again on every check until the clipboard changes.
+    fn is_oversized(&self, raw: &ClipboardItem) -> bool {
+        let (size, limit, setting) = match raw {
+            ClipboardItem::Text(text) => (text.len(), self.max_text_bytes, "max_text_bytes"),
+            ClipboardItem::Image { bytes, .. } => {
+                (bytes.len(), self.max_image_bytes, "max_image_bytes")
+            }
+            ClipboardItem::CompressedImage { width, height, .. } => {
+                // Dimensions come from outside, too big to multiply is too big to store
+                let size = width.saturating_mul(*height).saturating_mul(4);
+                (size, self.max_image_bytes, "max_image_bytes")
+            }
+        };
+        if size <= limit {
+            return false;
+        }
+
+        let key = Some((raw.is_image(), size));
+        if self.oversized.replace(key) != key {
+            warn!(
+                "Skipped {} of {size} bytes, over {setting} ({limit})",
+                raw.kind()
+            );
+        }
+        true
+    }
+
+    /// Adds a capture to the history and remembers it as the last item.
+    ///
+    /// See `Capture::add_to`.
+    ///
+    /// # Arguments
+    ///
+    /// * `capture` - What `check` returned
+    /// * `history` - The history to add it to
+    pub fn store(&mut self, capture: Capture, history: &mut dyn HistoryStore) {
+        self.remember(&capture);
+        capture.add_to(history);
+    }
+
+    /// Remembers a capture as the last item without storing it, for callers that store
+    /// it elsewhere. The next `check` only returns something once the clipboard changes.
+    ///
+    /// # Arguments
+    ///
+    /// * `capture` - What `check` returned
+    pub fn remember(&mut self, capture: &Capture) {
+        self.last_item = capture.raw.clone();
+    }
+
+    /// Checks the clipboard once and stores what is new.
+    ///
+    /// # Arguments
+    ///
+    /// * `clipboard` - The clipboard to read
+    /// * `history` - The history to add to
+    ///
+    /// # Returns
+    ///
+    /// * `true` if an item was added to the history
+    pub fn poll_once(
+        &mut self,
+        clipboard: &mut (impl GetItem + ?Sized),
+        history: &mut dyn HistoryStore,
+    ) -> bool {
+        if self.skip_expected(clipboard) {
+            return false;
+        }
+        match self.check(clipboard) {
+            Some(capture) => {
+                self.store(capture, history);
+                true
+            }
+            None => false,
+        }
+    }
+}
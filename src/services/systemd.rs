@@ -0,0 +1,42 @@
+// SYSTEMD
+// Only built with the `systemd` feature.
+use std::os::{fd::FromRawFd, unix::net::UnixListener};
+
+use sd_notify::NotifyState;
+use tracing::{debug, info, warn};
+
+/// Takes the listening socket systemd passed in, if the daemon was socket activated.
+///
+/// Only the first descriptor is used. The unit's `ListenStream=` should point at the
+/// profile's socket path (see `common::socket_path`) so clients still find it.
+///
+/// # Returns
+///
+/// * `Some(listener)` - If `LISTEN_FDS`/`LISTEN_PID` hand this process a socket
+/// * `None` - If not socket activated, or the variables are invalid
+pub fn activated_listener() -> Option<UnixListener> {
+    let mut fds = match sd_notify::listen_fds() {
+        Ok(fds) => fds,
+        Err(err) => {
+            warn!("Ignoring invalid socket activation variables: {err}");
+            return None;
+        }
+    };
+
+    let fd = fds.next()?;
+    info!("Using socket passed in by systemd (fd {fd})");
+
+    // SAFETY: systemd passes us ownership of these descriptors, and nothing else
+    // in the process uses them.
+    Some(unsafe { UnixListener::from_raw_fd(fd) })
+}
+
+/// Tells systemd the daemon is ready to take connections.
+///
+/// Does nothing when not started by a `Type=notify` unit.
+pub fn notify_ready() {
+    match sd_notify::notify(&[NotifyState::Ready]) {
+        Ok(_) => debug!("Sent readiness notification"),
+        Err(err) => warn!("Could not notify systemd: {err}"),
+    }
+}
@@ -0,0 +1,143 @@
+// TOKIO
+// Only built with the `tokio` feature.
+use std::{
+    os::unix::net::UnixListener as StdUnixListener,
+    sync::atomic::Ordering,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use tokio::{
+    net::{UnixListener, UnixStream},
+    runtime::Builder,
+    task::{self, JoinSet},
+    time,
+};
+use tracing::{error, info, warn};
+
+use crate::services::{
+    clipboard_ipc_server::{Payload, read_payload_async, send_payload_async},
+    clipboard_manager::{CommandContext, Reply, read_error_response},
+};
+
+/// How long connections still open at stop get to finish, e.g. to send the reply to `Stop`.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(1);
+
+/// Serves IPC connections on a tokio runtime running in its own thread.
+///
+/// Replaces the thread per connection of the default command service with a task per
+/// connection, which is cheaper for clients that connect often (e.g. polling `Stats`).
+/// Requests are applied by `CommandContext::handle`, same as the threaded server.
+///
+/// The loop ends on the first connection after the stop flag is set. Connections already
+/// accepted then get up to `SHUTDOWN_GRACE` to finish before the runtime is dropped.
+pub(crate) fn spawn(listener: StdUnixListener, ctx: CommandContext) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let runtime = match Builder::new_multi_thread()
+            .enable_io()
+            .enable_time()
+            .build()
+        {
+            Ok(runtime) => runtime,
+            Err(err) => {
+                error!("Could not start tokio runtime: {err}");
+                return;
+            }
+        };
+
+        runtime.block_on(serve(listener, ctx));
+    })
+}
+
+async fn serve(listener: StdUnixListener, ctx: CommandContext) {
+    let listener = match listener
+        .set_nonblocking(true)
+        .and_then(|_| UnixListener::from_std(listener))
+    {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!("Could not register listener with tokio: {err}");
+            return;
+        }
+    };
+
+    info!("Command service started (tokio)");
+
+    // Connections being served, so the reply to the one that asked to stop isn't cut off
+    let mut connections = JoinSet::new();
+
+    loop {
+        let accepted = listener.accept().await;
+
+        // Break the loop if stop_signal is found
+        if ctx.stop_signal.load(Ordering::SeqCst) {
+            break;
+        }
+
+        match accepted {
+            Ok((stream, _)) => {
+                connections.spawn(handle_connection(stream, ctx.clone()));
+            }
+            Err(err) => error!("Accept Error: {err}"),
+        }
+
+        // Forget the ones that are done
+        while connections.try_join_next().is_some() {}
+    }
+
+    // A client that never sends anything shouldn't keep the runtime alive
+    let drained = time::timeout(SHUTDOWN_GRACE, async {
+        while connections.join_next().await.is_some() {}
+    })
+    .await;
+    if drained.is_err() {
+        warn!(
+            "Dropping {} connections still open at stop",
+            connections.len()
+        );
+    }
+}
+
+async fn handle_connection(mut stream: UnixStream, ctx: CommandContext) {
+    let payload = match read_payload_async(&mut stream, ctx.max_request_bytes).await {
+        Ok(payload) => payload,
+        Err(err) => {
+            error!("Could not read request: {err}");
+            if let Some(response) = read_error_response(&err) {
+                let _ = send_payload_async(&mut stream, Payload::Response(response)).await;
+            }
+            return;
+        }
+    };
+
+    // The history sits behind a std mutex and may be backed by SQLite,
+    // so keep the work off the runtime's worker threads
+    let handler = ctx.clone();
+    let reply = match task::spawn_blocking(move || handler.handle(payload)).await {
+        Ok(reply) => reply,
+        Err(err) => {
+            error!("Command handler failed: {err}");
+            return;
+        }
+    };
+
+    match reply {
+        Reply::Respond(response) => {
+            if let Err(err) = send_payload_async(&mut stream, Payload::Response(*response)).await {
+                warn!("Could not send response: {err}");
+            }
+        }
+        Reply::Subscribe => {
+            // Pushes come from whichever thread changed the history, so hand over a std stream
+            match stream
+                .into_std()
+                .and_then(|stream| stream.set_nonblocking(false).map(|_| stream))
+            {
+                Ok(stream) => {
+                    task::spawn_blocking(move || ctx.subscribe(stream));
+                }
+                Err(err) => warn!("Could not register subscriber: {err}"),
+            }
+        }
+    }
+}
@@ -1,35 +1,78 @@
 // YDOTOOL
-use std::path::Path;
+use std::env;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
-pub fn send_shift_insert() {
+use tracing::{debug, error, info};
+
+/// Socket the ydotoold daemon listens on.
+pub const SOCKET_PATH: &str = "/tmp/.ydotool_socket";
+
+/// Key events for Shift+Insert (paste), as `ydotool key` arguments.
+const SHIFT_INSERT: [&str; 4] = [
+    "42:1",  // Shift down
+    "110:1", // Insert down
+    "110:0", // Insert up
+    "42:0",  // Shift up
+];
+
+/// Finds an executable on `PATH`, like `which`.
+///
+/// # Returns
+///
+/// * `Some(path)` - The first match
+/// * `None` - If it isn't on `PATH`, or `PATH` is unset
+pub fn find_program(name: &str) -> Option<PathBuf> {
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// Pastes into the focused window by sending Shift+Insert through ydotool.
+///
+/// Each step is logged at debug level (`SUPER_V_LOG=debug`), failures as errors.
+///
+/// # Arguments
+///
+/// * `dry_run` - Log the command that would run instead of running it
+pub fn send_shift_insert(dry_run: bool) {
+    debug!("Pasting with ydotool");
+
     // Check if socket exists
-    let socket_path = "/tmp/.ydotool_socket";
-    if !Path::new(socket_path).exists() {
-        eprintln!("ydotool socket not found at {}", socket_path);
+    if !Path::new(SOCKET_PATH).exists() {
+        error!("ydotool socket not found at {}", SOCKET_PATH);
+        return;
+    }
+    debug!("Found ydotool socket at {}", SOCKET_PATH);
+
+    if dry_run {
+        info!(
+            "Dry run, not running: YDOTOOL_SOCKET={} ydotool key {}",
+            SOCKET_PATH,
+            SHIFT_INSERT.join(" ")
+        );
         return;
     }
 
     // Simulate Shift+Insert (paste)
     let result = Command::new("ydotool")
-        .env("YDOTOOL_SOCKET", socket_path)
-        .args([
-            "key", "42:1",  // Shift down
-            "110:1", // Insert down
-            "110:0", // Insert up
-            "42:0",  // Shift up
-        ])
+        .env("YDOTOOL_SOCKET", SOCKET_PATH)
+        .arg("key")
+        .args(SHIFT_INSERT)
         .output();
 
     match result {
         Ok(output) => {
-            if !output.status.success() {
-                eprintln!(
+            debug!("ydotool exited with {}", output.status);
+            if output.status.success() {
+                debug!("Sent Shift+Insert through ydotool");
+            } else {
+                error!(
                     "ydotool failed: {}",
                     String::from_utf8_lossy(&output.stderr)
                 );
             }
         }
-        Err(e) => eprintln!("Failed to execute ydotool: {}", e),
+        Err(e) => error!("Failed to execute ydotool: {}", e),
     }
 }
@@ -0,0 +1,897 @@
+// Standard Crates
+use std::{
+    collections::{HashMap, HashSet},
+    mem,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+// External Crates
+use rusqlite::{Connection, OptionalExtension, Row, ToSql, params};
+use tracing::error;
+
+// My Crates
+use crate::{
+    common::{ClipboardError, ClipboardFilter, ClipboardItem, DedupScope, Selection},
+    history::{
+        ClipboardHistory, EntryMeta, HistoryStats, HistoryStore, UndoStack, is_recent_duplicate,
+    },
+};
+
+// ---------------------- SQLite History Store ------------------------
+/// A clipboard history store backed by a SQLite database.
+///
+/// Every entry is a row holding its type, content, dimensions, copy time, OCR text
+/// and copy count.
+/// Rows are ordered by `seq`, where a higher value means more recent, so promoting
+/// an entry is a single update instead of shifting every row. Listing (`query`) and
+/// searching (`search`) run in SQL, so only the rows of the page are read.
+///
+/// There is no pin flag: no store has pinned entries yet.
+///
+/// Removals that `undo` can revert are kept in memory only, so they don't survive a restart.
+///
+/// Sensitive entries (see `EntryMeta::sensitive`) keep their row, so they keep their
+/// place, but its content, OCR text and HTML are blanked and only held in memory.
+/// `secure_delete` is on, so the blanked content is overwritten in the database file
+/// too. Rows of sensitive entries left by a daemon that didn't stop cleanly are removed
+/// on open.
+///
+/// Only available with the `sqlite` cargo feature.
+pub struct SqliteStore {
+    conn: Connection,
+    max_size: usize,
+    dedup_window: Option<Duration>,
+    dedup_scope: DedupScope,
+    max_total_bytes: Option<usize>,
+    undo: UndoStack,
+    secrets: HashMap<i64, Secret>,
+}
+
+/// What a sensitive entry's row doesn't hold on disk.
+struct Secret {
+    item: ClipboardItem,
+    ocr_text: Option<String>,
+    html: Option<String>,
+}
+
+/// Columns of a stored row: (kind, content, width, height)
+type EncodedItem = (&'static str, Vec<u8>, i64, i64);
+
+impl SqliteStore {
+    const SCHEMA: &str = "
+        CREATE TABLE IF NOT EXISTS history (
+            id        INTEGER PRIMARY KEY AUTOINCREMENT,
+            seq       INTEGER NOT NULL,
+            kind      TEXT    NOT NULL,
+            content   BLOB    NOT NULL,
+            width     INTEGER NOT NULL DEFAULT 0,
+            height    INTEGER NOT NULL DEFAULT 0,
+            copied_at INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS history_seq ON history (seq);
+    ";
+
+    /// Columns `read_entry` expects, in order.
+    const ENTRY_COLUMNS: &str = "kind, content, width, height, copied_at, ocr_text, copy_count, html, selection, id, sensitive";
+
+    /// Opens (or creates) the database at `path`, keeping at most `max_size` entries.
+    ///
+    /// # Errors
+    ///
+    /// Returns the underlying `rusqlite::Error` if the database cannot be opened or
+    /// the schema cannot be created.
+    pub fn open(path: impl AsRef<Path>, max_size: usize) -> rusqlite::Result<Self> {
+        Self::with_connection(Connection::open(path)?, max_size)
+    }
+
+    /// Opens a private in-memory database. Mostly useful for tests.
+    ///
+    /// # Errors
+    ///
+    /// Returns the underlying `rusqlite::Error` if the schema cannot be created.
+    pub fn open_in_memory(max_size: usize) -> rusqlite::Result<Self> {
+        Self::with_connection(Connection::open_in_memory()?, max_size)
+    }
+
+    fn with_connection(conn: Connection, max_size: usize) -> rusqlite::Result<Self> {
+        conn.execute_batch(Self::SCHEMA)?;
+
+        // Databases from older versions lack these columns
+        Self::add_missing_column(&conn, "ocr_text", "TEXT")?;
+        Self::add_missing_column(&conn, "copy_count", "INTEGER NOT NULL DEFAULT 1")?;
+        Self::add_missing_column(&conn, "html", "TEXT")?;
+        Self::add_missing_column(&conn, "selection", "TEXT NOT NULL DEFAULT 'clipboard'")?;
+        Self::add_missing_column(&conn, "sensitive", "INTEGER NOT NULL DEFAULT 0")?;
+
+        // Their content only lived in the memory of the daemon that wrote them
+        conn.pragma_update(None, "secure_delete", true)?;
+        conn.execute("DELETE FROM history WHERE sensitive = 1", [])?;
+
+        Ok(Self {
+            conn,
+            max_size,
+            dedup_window: None,
+            dedup_scope: DedupScope::All,
+            max_total_bytes: None,
+            undo: UndoStack::default(),
+            secrets: HashMap::new(),
+        })
+    }
+
+    fn add_missing_column(conn: &Connection, name: &str, definition: &str) -> rusqlite::Result<()> {
+        let exists = conn
+            .prepare("SELECT 1 FROM pragma_table_info('history') WHERE name = ?1")?
+            .exists(params![name])?;
+        if !exists {
+            conn.execute(
+                &format!("ALTER TABLE history ADD COLUMN {name} {definition}"),
+                [],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Limits duplicate promotion to recent copies.
+    /// See `ClipboardHistory::with_dedup_window`.
+    pub fn with_dedup_window(mut self, window: Option<Duration>) -> Self {
+        self.dedup_window = window;
+        self
+    }
+
+    /// Picks which kinds of entries are promoted when copied again.
+    /// See `ClipboardHistory::with_dedup_scope`.
+    pub fn with_dedup_scope(mut self, scope: DedupScope) -> Self {
+        self.dedup_scope = scope;
+        self
+    }
+
+    /// Caps the total size of the stored content, on top of the entry count.
+    /// See `ClipboardHistory::with_max_total_bytes`.
+    pub fn with_max_total_bytes(mut self, max_total_bytes: Option<usize>) -> Self {
+        self.max_total_bytes = max_total_bytes;
+        self
+    }
+
+    fn encode(item: &ClipboardItem) -> EncodedItem {
+        match item {
+            ClipboardItem::Text(text) => ("text", text.as_bytes().to_vec(), 0, 0),
+            ClipboardItem::Image {
+                width,
+                height,
+                bytes,
+            } => ("image", bytes.clone(), *width as i64, *height as i64),
+            ClipboardItem::CompressedImage { width, height, png } => {
+                ("png", png.clone(), *width as i64, *height as i64)
+            }
+        }
+    }
+
+    fn decode(kind: &str, content: Vec<u8>, width: i64, height: i64) -> Option<ClipboardItem> {
+        match kind {
+            "text" => String::from_utf8(content).ok().map(ClipboardItem::Text),
+            "image" => Some(ClipboardItem::Image {
+                width: width as usize,
+                height: height as usize,
+                bytes: content,
+            }),
+            "png" => Some(ClipboardItem::CompressedImage {
+                width: width as usize,
+                height: height as usize,
+                png: content,
+            }),
+            _ => None,
+        }
+    }
+
+    fn to_millis(time: SystemTime) -> i64 {
+        time.duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as i64)
+            .unwrap_or(0)
+    }
+
+    fn from_millis(millis: i64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(millis.max(0) as u64)
+    }
+
+    /// Reads an item and its meta from a row selecting `ENTRY_COLUMNS` first.
+    ///
+    /// `None` for rows of an unknown kind, and sensitive rows whose content isn't in memory.
+    fn read_entry(&self, row: &Row<'_>) -> rusqlite::Result<Option<(ClipboardItem, EntryMeta)>> {
+        let mut meta = Self::read_meta(row)?;
+        if row.get::<_, bool>(10)? {
+            let Some(secret) = self.secrets.get(&row.get(9)?) else {
+                return Ok(None);
+            };
+            meta.ocr_text = secret.ocr_text.clone();
+            meta.html = secret.html.clone();
+            meta.sensitive = true;
+            return Ok(Some((secret.item.clone(), meta)));
+        }
+
+        let item = Self::decode(
+            &row.get::<_, String>(0)?,
+            row.get(1)?,
+            row.get(2)?,
+            row.get(3)?,
+        );
+        Ok(item.map(|item| (item, meta)))
+    }
+
+    /// Reads the meta columns of a row selecting `ENTRY_COLUMNS`, as stored on disk.
+    fn read_meta(row: &Row<'_>) -> rusqlite::Result<EntryMeta> {
+        Ok(EntryMeta {
+            copied_at: Self::from_millis(row.get(4)?),
+            ocr_text: row.get(5)?,
+            copy_count: u32::try_from(row.get::<_, i64>(6)?).unwrap_or(u32::MAX),
+            html: row.get(7)?,
+            selection: match row.get::<_, String>(8)?.as_str() {
+                "primary" => Selection::Primary,
+                _ => Selection::Clipboard,
+            },
+            sensitive: false,
+        })
+    }
+
+    /// Returns the row id, item and meta of the entry at `pos` (0 = most recent).
+    fn entry_at(&self, pos: usize) -> rusqlite::Result<Option<(i64, ClipboardItem, EntryMeta)>> {
+        let row = self
+            .conn
+            .query_row(
+                &format!(
+                    "SELECT {} FROM history ORDER BY seq DESC LIMIT 1 OFFSET ?1",
+                    Self::ENTRY_COLUMNS
+                ),
+                params![pos as i64],
+                |row| Ok((self.read_entry(row)?, row.get::<_, i64>(9)?)),
+            )
+            .optional()?;
+
+        Ok(row.and_then(|(entry, id)| entry.map(|(item, meta)| (id, item, meta))))
+    }
+
+    /// Returns the row id of the entry at `pos`, if that entry is `item`.
+    fn id_holding(&self, pos: usize, item: &ClipboardItem) -> Option<i64> {
+        match self.entry_at(pos) {
+            Ok(Some((id, found, _))) if found == *item => Some(id),
+            _ => None,
+        }
+    }
+
+    /// Returns the position (0 = most recent) of the most recent entry equal to `item`.
+    fn position_of(&self, item: &ClipboardItem) -> rusqlite::Result<Option<usize>> {
+        let Some(id) = self.id_of(item)? else {
+            return Ok(None);
+        };
+
+        self.conn
+            .query_row(
+                "SELECT COUNT(*) FROM history WHERE seq > (SELECT seq FROM history WHERE id = ?1)",
+                params![id],
+                |row| row.get::<_, i64>(0),
+            )
+            .map(|newer| Some(newer as usize))
+    }
+
+    /// Returns the row id of the most recent entry equal to `item`.
+    fn id_of(&self, item: &ClipboardItem) -> rusqlite::Result<Option<i64>> {
+        Ok(self.latest_copy_of(item)?.map(|(id, _)| id))
+    }
+
+    /// Id and copy time of the most recent row holding `item`.
+    fn latest_copy_of(&self, item: &ClipboardItem) -> rusqlite::Result<Option<(i64, i64)>> {
+        let (kind, content, width, height) = Self::encode(item);
+        let mut latest: Option<(i64, i64, i64)> = self
+            .conn
+            .query_row(
+                "SELECT id, copied_at, seq FROM history
+                 WHERE kind = ?1 AND content = ?2 AND width = ?3 AND height = ?4 AND sensitive = 0
+                 ORDER BY seq DESC LIMIT 1",
+                params![kind, content, width, height],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()?;
+
+        // Sensitive rows hold no content to match, what they hold is in memory
+        for (id, secret) in &self.secrets {
+            if secret.item != *item {
+                continue;
+            }
+            let row: (i64, i64, i64) = self.conn.query_row(
+                "SELECT id, copied_at, seq FROM history WHERE id = ?1",
+                params![id],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )?;
+            if latest.is_none_or(|(_, _, seq)| row.2 > seq) {
+                latest = Some(row);
+            }
+        }
+
+        Ok(latest.map(|(id, copied_at, _)| (id, copied_at)))
+    }
+
+    /// Forgets the content of sensitive rows that are gone, e.g. evicted or expired.
+    fn prune_secrets(&mut self) -> rusqlite::Result<()> {
+        let ids: HashSet<i64> = self
+            .conn
+            .prepare("SELECT id FROM history WHERE sensitive = 1")?
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        self.secrets.retain(|id, _| ids.contains(id));
+        Ok(())
+    }
+
+    /// Moves the row with `id` to the front.
+    fn promote_id(&self, id: i64) -> rusqlite::Result<()> {
+        let seq = self.next_seq()?;
+        self.conn.execute(
+            "UPDATE history SET seq = ?1 WHERE id = ?2",
+            params![seq, id],
+        )?;
+        Ok(())
+    }
+
+    fn next_seq(&self) -> rusqlite::Result<i64> {
+        self.conn
+            .query_row("SELECT COALESCE(MAX(seq), 0) + 1 FROM history", [], |row| {
+                row.get(0)
+            })
+    }
+
+    fn try_add_at(&mut self, item: &ClipboardItem, copied_at: SystemTime) -> rusqlite::Result<()> {
+        let seq = self.next_seq()?;
+
+        // Recent duplicates are promoted, same as the in-memory history
+        if self.dedup_scope.covers(item)
+            && let Some((id, previous)) = self.latest_copy_of(item)?
+            && is_recent_duplicate(self.dedup_window, Self::from_millis(previous), copied_at)
+        {
+            self.conn.execute(
+                "UPDATE history SET seq = ?1, copied_at = ?2, copy_count = copy_count + 1 WHERE id = ?3",
+                params![seq, Self::to_millis(copied_at), id],
+            )?;
+            return Ok(());
+        }
+
+        let copied_at = Self::to_millis(copied_at);
+
+        let (kind, content, width, height) = Self::encode(item);
+        self.conn.execute(
+            "INSERT INTO history (seq, kind, content, width, height, copied_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![seq, kind, content, width, height, copied_at],
+        )?;
+
+        // Evict everything past capacity
+        self.conn.execute(
+            "DELETE FROM history WHERE id IN
+             (SELECT id FROM history ORDER BY seq DESC LIMIT -1 OFFSET ?1)",
+            params![self.max_size as i64],
+        )?;
+
+        // Then the oldest entries until the content fits, always keeping the newest
+        if let Some(budget) = self.max_total_bytes {
+            loop {
+                let (rows, total): (i64, i64) = self.conn.query_row(
+                    "SELECT COUNT(*), COALESCE(SUM(LENGTH(content)), 0) FROM history",
+                    [],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )?;
+                if rows <= 1 || total as u64 <= budget as u64 {
+                    break;
+                }
+                self.conn.execute(
+                    "DELETE FROM history WHERE id = (SELECT id FROM history ORDER BY seq ASC LIMIT 1)",
+                    [],
+                )?;
+            }
+        }
+        self.prune_secrets()
+    }
+
+    fn try_snapshot(&self) -> rusqlite::Result<ClipboardHistory> {
+        self.try_snapshot_range(0, usize::MAX)
+    }
+
+    /// Reads only the entries at `offset..offset + limit`, so a page of a large history
+    /// doesn't load every blob.
+    fn try_snapshot_range(
+        &self,
+        offset: usize,
+        limit: usize,
+    ) -> rusqlite::Result<ClipboardHistory> {
+        let mut history = self.empty_history();
+        let mut statement = self.conn.prepare(&format!(
+            "SELECT {} FROM
+             (SELECT * FROM history ORDER BY seq DESC LIMIT ?1 OFFSET ?2)
+             ORDER BY seq ASC",
+            Self::ENTRY_COLUMNS
+        ))?;
+        // A negative limit means no limit to SQLite
+        let rows = statement.query_map(
+            params![
+                i64::try_from(limit).unwrap_or(-1),
+                i64::try_from(offset).unwrap_or(i64::MAX)
+            ],
+            |row| self.read_entry(row),
+        )?;
+
+        // Oldest first, so the most recent ends up at the front
+        for row in rows {
+            if let Some((item, meta)) = row? {
+                // Rows may hold old copies of the same item, keep them all
+                history.push_at(item, meta);
+            }
+        }
+
+        Ok(history)
+    }
+
+    /// Listed in SQL, so only the blobs of the page leave the database.
+    fn try_query(
+        &self,
+        offset: usize,
+        limit: usize,
+        filter: ClipboardFilter,
+        by_frequency: bool,
+    ) -> rusqlite::Result<(ClipboardHistory, Vec<usize>, usize)> {
+        let kinds = match filter {
+            ClipboardFilter::All => "1",
+            ClipboardFilter::Text => "kind = 'text'",
+            ClipboardFilter::Images => "kind IN ('image', 'png')",
+        };
+        let order = if by_frequency {
+            "copy_count DESC, seq DESC"
+        } else {
+            "seq DESC"
+        };
+        self.try_page(kinds, order, &[], offset, limit)
+    }
+
+    /// Matched in SQL, like `try_query`. SQLite's `lower` only folds ASCII, which is why
+    /// `ClipboardHistory::search` ignores the case of ASCII letters only.
+    fn try_search(
+        &self,
+        text: &str,
+        offset: usize,
+        limit: usize,
+    ) -> rusqlite::Result<(ClipboardHistory, Vec<usize>, usize)> {
+        if text.is_empty() {
+            return self.try_query(offset, limit, ClipboardFilter::All, false);
+        }
+
+        // Sensitive rows are blanked, their text is only in memory
+        let matching = "sensitive = 0 AND (
+            (kind = 'text' AND instr(lower(CAST(content AS TEXT)), :needle) > 0)
+            OR instr(lower(COALESCE(ocr_text, '')), :needle) > 0)";
+        let needle = text.to_ascii_lowercase();
+        self.try_page(matching, "seq DESC", &[(":needle", &needle)], offset, limit)
+    }
+
+    /// The rows matching `condition` in `order`, from `offset` on, with the position of
+    /// each in the whole history and how many match in all. `args` are the named
+    /// parameters `condition` uses.
+    fn try_page(
+        &self,
+        condition: &str,
+        order: &str,
+        args: &[(&str, &dyn ToSql)],
+        offset: usize,
+        limit: usize,
+    ) -> rusqlite::Result<(ClipboardHistory, Vec<usize>, usize)> {
+        let limit = i64::try_from(limit).unwrap_or(-1);
+        let offset = i64::try_from(offset).unwrap_or(i64::MAX);
+        let mut page_args = args.to_vec();
+        page_args.extend([(":limit", &limit as &dyn ToSql), (":offset", &offset)]);
+
+        let mut statement = self.conn.prepare(&format!(
+            "SELECT {}, pos FROM
+             (SELECT *, ROW_NUMBER() OVER (ORDER BY seq DESC) - 1 AS pos FROM history)
+             WHERE {condition} ORDER BY {order} LIMIT :limit OFFSET :offset",
+            Self::ENTRY_COLUMNS
+        ))?;
+        let rows = statement.query_map(page_args.as_slice(), |row| {
+            Ok((self.read_entry(row)?, row.get::<_, i64>(11)?))
+        })?;
+
+        let mut entries = Vec::new();
+        for row in rows {
+            if let (Some(entry), pos) = row? {
+                entries.push((entry, pos as usize));
+            }
+        }
+
+        // Each entry goes in front, so start from the last
+        let mut history = self.empty_history();
+        let positions = entries.iter().map(|(_, pos)| *pos).collect();
+        for ((item, meta), _) in entries.into_iter().rev() {
+            history.push_at(item, meta);
+        }
+
+        let total = self.conn.query_row(
+            &format!("SELECT COUNT(*) FROM history WHERE {condition}"),
+            args,
+            |row| row.get::<_, i64>(0),
+        )?;
+        Ok((history, positions, total as usize))
+    }
+
+    /// A history with this store's settings and no entries.
+    fn empty_history(&self) -> ClipboardHistory {
+        ClipboardHistory::new(self.max_size)
+            .with_dedup_window(self.dedup_window)
+            .with_dedup_scope(self.dedup_scope)
+            .with_max_total_bytes(self.max_total_bytes)
+    }
+
+    fn count(&self) -> rusqlite::Result<usize> {
+        self.conn
+            .query_row("SELECT COUNT(*) FROM history", [], |row| {
+                row.get::<_, i64>(0)
+            })
+            .map(|count| count as usize)
+    }
+
+    fn try_replace(&mut self, history: &ClipboardHistory) -> rusqlite::Result<()> {
+        let tx = self.conn.transaction()?;
+        tx.execute("DELETE FROM history", [])?;
+        let mut secrets = HashMap::new();
+
+        // Oldest first, so the most recent gets the highest seq
+        let entries = history.get_items().iter().zip(history.get_meta().iter());
+        for (seq, (item, meta)) in entries.rev().enumerate() {
+            let (kind, mut content, width, height) = Self::encode(item);
+            let (mut ocr_text, mut html) = (meta.ocr_text.as_deref(), meta.html.as_deref());
+            if meta.sensitive {
+                content.clear();
+                (ocr_text, html) = (None, None);
+            }
+
+            tx.execute(
+                "INSERT INTO history (seq, kind, content, width, height, copied_at, ocr_text, copy_count, html, selection, sensitive)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                params![
+                    seq as i64 + 1,
+                    kind,
+                    content,
+                    width,
+                    height,
+                    Self::to_millis(meta.copied_at),
+                    ocr_text,
+                    meta.copy_count,
+                    html,
+                    meta.selection.as_str(),
+                    meta.sensitive
+                ],
+            )?;
+
+            if meta.sensitive {
+                let secret = Secret {
+                    item: item.clone(),
+                    ocr_text: meta.ocr_text.clone(),
+                    html: meta.html.clone(),
+                };
+                secrets.insert(tx.last_insert_rowid(), secret);
+            }
+        }
+
+        tx.commit()?;
+        self.secrets = secrets;
+        Ok(())
+    }
+}
+
+impl HistoryStore for SqliteStore {
+    fn capacity(&self) -> usize {
+        self.max_size
+    }
+
+    fn snapshot(&self) -> ClipboardHistory {
+        self.try_snapshot().unwrap_or_else(|err| {
+            error!("Could not read history from database: {err}");
+            ClipboardHistory::new(self.max_size)
+        })
+    }
+
+    fn snapshot_range(&self, offset: usize, limit: usize) -> (ClipboardHistory, usize) {
+        let page = self
+            .try_snapshot_range(offset, limit)
+            .and_then(|page| Ok((page, self.count()?)));
+        page.unwrap_or_else(|err| {
+            error!("Could not read history from database: {err}");
+            (ClipboardHistory::new(self.max_size), 0)
+        })
+    }
+
+    fn query(
+        &self,
+        offset: usize,
+        limit: usize,
+        filter: ClipboardFilter,
+        by_frequency: bool,
+    ) -> (ClipboardHistory, Vec<usize>, usize) {
+        self.try_query(offset, limit, filter, by_frequency)
+            .unwrap_or_else(|err| {
+                error!("Could not read history from database: {err}");
+                (self.empty_history(), Vec::new(), 0)
+            })
+    }
+
+    fn search(
+        &self,
+        text: &str,
+        offset: usize,
+        limit: usize,
+    ) -> (ClipboardHistory, Vec<usize>, usize) {
+        self.try_search(text, offset, limit).unwrap_or_else(|err| {
+            error!("Could not search history in database: {err}");
+            (self.empty_history(), Vec::new(), 0)
+        })
+    }
+
+    // A rewrite may move anything, so the recorded removals are dropped
+    fn replace(&mut self, history: ClipboardHistory) {
+        self.undo.clear();
+        if let Err(err) = self.try_replace(&history) {
+            error!("Could not write history to database: {err}");
+        }
+    }
+
+    fn stats(&self) -> HistoryStats {
+        // Counted in SQL so the blobs never leave the database
+        self.conn
+            .query_row(
+                "SELECT COUNT(*),
+                        COALESCE(SUM(kind = 'text'), 0),
+                        COALESCE(SUM(kind IN ('image', 'png')), 0),
+                        COALESCE(SUM(LENGTH(content)), 0)
+                 FROM history",
+                [],
+                |row| {
+                    Ok(HistoryStats {
+                        total: row.get::<_, i64>(0)? as usize,
+                        text: row.get::<_, i64>(1)? as usize,
+                        images: row.get::<_, i64>(2)? as usize,
+                        bytes: row.get::<_, i64>(3)? as usize,
+                        max_age_secs: None,
+                    })
+                },
+            )
+            .unwrap_or_else(|err| {
+                error!("Could not read stats from database: {err}");
+                HistoryStats::default()
+            })
+    }
+
+    fn add_at(&mut self, item: ClipboardItem, copied_at: SystemTime) {
+        self.undo.clear();
+        if let Err(err) = self.try_add_at(&item, copied_at) {
+            error!("Could not add item to database: {err}");
+        }
+    }
+
+    fn promote(&mut self, pos: usize) -> Result<usize, ClipboardError> {
+        let Ok(Some((id, _, _))) = self.entry_at(pos) else {
+            return Err(ClipboardError::IndexOutOfBound);
+        };
+
+        // Same as the in-memory history, promoting the front item keeps the removals
+        if pos != 0 {
+            self.undo.clear();
+        }
+        self.promote_id(id)
+            .map(|_| 0)
+            .map_err(|_| ClipboardError::IndexOutOfBound)
+    }
+
+    fn promote_this(&mut self, item: ClipboardItem) -> Result<usize, ClipboardError> {
+        let Ok(Some(pos)) = self.position_of(&item) else {
+            return Err(ClipboardError::IndexOutOfBound);
+        };
+
+        self.promote(pos)
+    }
+
+    fn set_ocr_text(&mut self, item: &ClipboardItem, text: String) -> Result<(), ClipboardError> {
+        let Ok(Some(id)) = self.id_of(item) else {
+            return Err(ClipboardError::IndexOutOfBound);
+        };
+        if let Some(secret) = self.secrets.get_mut(&id) {
+            secret.ocr_text = Some(text);
+            return Ok(());
+        }
+
+        self.conn
+            .execute(
+                "UPDATE history SET ocr_text = ?1 WHERE id = ?2",
+                params![text, id],
+            )
+            .map(|_| ())
+            .map_err(|_| ClipboardError::IndexOutOfBound)
+    }
+
+    fn set_html(
+        &mut self,
+        pos: usize,
+        item: &ClipboardItem,
+        html: Option<String>,
+    ) -> Result<(), ClipboardError> {
+        let Some(id) = self.id_holding(pos, item) else {
+            return Err(ClipboardError::IndexOutOfBound);
+        };
+        if let Some(secret) = self.secrets.get_mut(&id) {
+            secret.html = html;
+            return Ok(());
+        }
+
+        self.conn
+            .execute(
+                "UPDATE history SET html = ?1 WHERE id = ?2",
+                params![html, id],
+            )
+            .map(|_| ())
+            .map_err(|_| ClipboardError::IndexOutOfBound)
+    }
+
+    fn set_selection(
+        &mut self,
+        pos: usize,
+        item: &ClipboardItem,
+        selection: Selection,
+    ) -> Result<(), ClipboardError> {
+        let Some(id) = self.id_holding(pos, item) else {
+            return Err(ClipboardError::IndexOutOfBound);
+        };
+
+        self.conn
+            .execute(
+                "UPDATE history SET selection = ?1 WHERE id = ?2",
+                params![selection.as_str(), id],
+            )
+            .map(|_| ())
+            .map_err(|_| ClipboardError::IndexOutOfBound)
+    }
+
+    fn delete(&mut self, pos: usize) -> Result<ClipboardItem, ClipboardError> {
+        let Ok(Some((id, item, meta))) = self.entry_at(pos) else {
+            return Err(ClipboardError::IndexOutOfBound);
+        };
+
+        self.conn
+            .execute("DELETE FROM history WHERE id = ?1", params![id])
+            .map_err(|_| ClipboardError::IndexOutOfBound)?;
+        self.secrets.remove(&id);
+        self.undo.record(pos, vec![(item.clone(), meta)]);
+        Ok(item)
+    }
+
+    fn delete_range(&mut self, start: usize, end: usize) -> Result<(), ClipboardError> {
+        let mut history = self.snapshot();
+        history.delete_range(start, end)?;
+
+        // replace drops the removals, so record this one after it
+        let removal = history.take_removal();
+        let undo = mem::take(&mut self.undo);
+        self.replace(history);
+        self.undo = undo;
+        if let Some(removal) = removal {
+            self.undo.push(removal);
+        }
+        Ok(())
+    }
+
+    fn delete_this(&mut self, item: ClipboardItem) -> Result<(), ClipboardError> {
+        let Ok(Some(pos)) = self.position_of(&item) else {
+            return Err(ClipboardError::IndexOutOfBound);
+        };
+
+        self.delete(pos).map(|_| ())
+    }
+
+    fn delete_at(&mut self, pos: usize, item: &ClipboardItem) -> Result<(), ClipboardError> {
+        let pos = match self.id_holding(pos, item) {
+            Some(_) => pos,
+            None => match self.position_of(item) {
+                Ok(Some(pos)) => pos,
+                _ => return Err(ClipboardError::IndexOutOfBound),
+            },
+        };
+
+        self.delete(pos).map(|_| ())
+    }
+
+    fn clear(&mut self) {
+        // Read everything first so undo can put it back
+        let mut history = self.snapshot();
+        history.clear();
+
+        if let Err(err) = self.conn.execute("DELETE FROM history", []) {
+            error!("Could not clear database: {err}");
+            return;
+        }
+        self.secrets.clear();
+        if let Some(removal) = history.take_removal() {
+            self.undo.push(removal);
+        }
+    }
+
+    fn clear_matching(&mut self, filter: ClipboardFilter) -> usize {
+        let mut history = self.snapshot();
+        let removed = history.clear_matching(filter);
+
+        // replace drops the removals, so record this one after it
+        let removal = history.take_removal();
+        let undo = mem::take(&mut self.undo);
+        self.replace(history);
+        self.undo = undo;
+        if let Some(removal) = removal {
+            self.undo.push(removal);
+        }
+        removed
+    }
+
+    fn undo(&mut self) -> Result<(), ClipboardError> {
+        let removal = self.undo.pop().ok_or(ClipboardError::NothingToUndo)?;
+        let mut history = self.snapshot();
+        history.restore(removal);
+
+        // Keep the older removals, replace drops them
+        let undo = mem::take(&mut self.undo);
+        self.replace(history);
+        self.undo = undo;
+        Ok(())
+    }
+
+    fn remove_expired(&mut self, max_age: Duration, now: SystemTime) -> usize {
+        let cutoff = Self::to_millis(now) - max_age.as_millis() as i64;
+        let removed = self
+            .conn
+            .execute("DELETE FROM history WHERE copied_at < ?1", params![cutoff])
+            .and_then(|removed| self.prune_secrets().map(|()| removed));
+        removed.unwrap_or_else(|err| {
+            error!("Could not remove expired items from database: {err}");
+            0
+        })
+    }
+
+    fn mark_sensitive(&mut self, pos: usize) -> Result<(), ClipboardError> {
+        let Ok(Some((id, item, meta))) = self.entry_at(pos) else {
+            return Err(ClipboardError::IndexOutOfBound);
+        };
+        if meta.sensitive {
+            return Ok(());
+        }
+
+        self.conn
+            .execute(
+                "UPDATE history SET content = x'', ocr_text = NULL, html = NULL, sensitive = 1
+                 WHERE id = ?1",
+                params![id],
+            )
+            .map_err(|err| ClipboardError::Backend(err.to_string()))?;
+        let secret = Secret {
+            item,
+            ocr_text: meta.ocr_text,
+            html: meta.html,
+        };
+        self.secrets.insert(id, secret);
+        Ok(())
+    }
+
+    fn drop_sensitive(&mut self) -> usize {
+        match self
+            .conn
+            .execute("DELETE FROM history WHERE sensitive = 1", [])
+        {
+            Ok(dropped) => {
+                self.secrets.clear();
+                dropped
+            }
+            Err(err) => {
+                error!("Could not drop sensitive items from database: {err}");
+                0
+            }
+        }
+    }
+}
+// -------------------------------------------------------------------
@@ -0,0 +1,141 @@
+#[cfg(test)]
+mod backup_tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super_v::{
+        backup::{history_from_json, history_to_json},
+        common::{ClipboardItem, ImportError, Selection},
+        history::{ClipboardHistory, EntryMeta},
+    };
+
+    fn at_millis(millis: u64) -> EntryMeta {
+        EntryMeta::new(UNIX_EPOCH + Duration::from_millis(millis))
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let raw = ClipboardItem::Image {
+            width: 2,
+            height: 1,
+            bytes: vec![255, 0, 0, 255, 0, 0, 255, 255],
+        };
+        let compressed = ClipboardItem::Image {
+            width: 32,
+            height: 32,
+            bytes: vec![7; 32 * 32 * 4],
+        }
+        .compress();
+        assert!(matches!(compressed, ClipboardItem::CompressedImage { .. }));
+
+        let mut history = ClipboardHistory::new(5);
+        history.add_at(compressed.clone(), UNIX_EPOCH + Duration::from_millis(1));
+        history.add_at(raw.clone(), UNIX_EPOCH + Duration::from_millis(2));
+        history.add_at(
+            ClipboardItem::Text("Say \"hi\"\n\t\u{1} ✓ 🎉".into()),
+            UNIX_EPOCH + Duration::from_millis(3),
+        );
+        let text = history.get_items()[0].clone();
+        history
+            .set_html(0, &text, Some("<b>hi</b>".into()))
+            .unwrap();
+        history.set_selection(0, &text, Selection::Primary).unwrap();
+        history.set_ocr_text(&raw, "red".into()).unwrap();
+
+        let json = history_to_json(&history);
+        assert_eq!(json.lines().count(), 5);
+
+        let entries = history_from_json(&json).unwrap();
+        let expected: Vec<_> = history
+            .get_items()
+            .iter()
+            .cloned()
+            .zip(history.get_meta().iter().cloned())
+            .collect();
+        assert_eq!(entries, expected);
+    }
+
+    #[test]
+    fn test_optional_fields_default() {
+        let json = r#"{"version":1,"entries":[{"kind":"text","text":"aé🎉","copied_at":5}]}"#;
+        let entries = history_from_json(json).unwrap();
+
+        assert_eq!(
+            entries,
+            vec![(ClipboardItem::Text("aé🎉".into()), at_millis(5))]
+        );
+    }
+
+    #[test]
+    fn test_invalid_json() {
+        for json in [
+            "",
+            "{",
+            r#"{"version":1,"entries":[]"#,
+            r#"{"version":1,"entries":[]} x"#,
+            r#"{"version":1,"entries":["\x"]}"#,
+            r#"{"version":1,"entries":["\ud83c"]}"#,
+        ] {
+            assert!(
+                matches!(history_from_json(json), Err(ImportError::Syntax(_))),
+                "{json}"
+            );
+        }
+
+        // Deeper than serde_json follows
+        let nested = format!("{}{}", "[".repeat(200), "]".repeat(200));
+        assert!(matches!(
+            history_from_json(&nested),
+            Err(ImportError::Syntax(_))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_backup() {
+        let invalid = |json: &str| match history_from_json(json) {
+            Err(ImportError::Invalid(msg)) => msg,
+            other => panic!("{json} gave {other:?}"),
+        };
+
+        assert!(invalid("[]").contains("version"));
+        assert!(invalid(r#"{"version":2,"entries":[]}"#).contains("version 2"));
+        assert!(invalid(r#"{"version":1}"#).contains("entries"));
+
+        // The first bad entry is named
+        let msg = invalid(
+            r#"{"version":1,"entries":[{"kind":"text","text":"a","copied_at":1},{"kind":"text","copied_at":1}]}"#,
+        );
+        assert!(
+            msg.starts_with("entry 1:") && msg.contains("`text`"),
+            "{msg}"
+        );
+
+        for entry in [
+            r#"{"kind":"file","copied_at":1}"#,
+            r#"{"kind":"text","text":"a"}"#,
+            r#"{"kind":"text","text":"a","copied_at":1,"copy_count":0}"#,
+            r#"{"kind":"text","text":"a","copied_at":1,"selection":"other"}"#,
+            r#"{"kind":"image","width":1,"height":1,"encoding":"rgba","data":"AAAA","copied_at":1}"#,
+            r#"{"kind":"image","width":1,"height":1,"encoding":"png","data":"AAAAAA==","copied_at":1}"#,
+            r#"{"kind":"image","width":1,"height":1,"encoding":"bmp","data":"AAAAAA==","copied_at":1}"#,
+            r#"{"kind":"image","width":1,"height":1,"encoding":"rgba","data":"not base64","copied_at":1}"#,
+        ] {
+            let msg = invalid(&format!(r#"{{"version":1,"entries":[{entry}]}}"#));
+            assert!(msg.starts_with("entry 0:"), "{msg}");
+        }
+    }
+
+    #[test]
+    fn test_sensitive_entries_not_exported() {
+        let mut history = ClipboardHistory::new(5);
+        history.add(ClipboardItem::Text("public".into()));
+        history.add(ClipboardItem::Text("secret".into()));
+        history.mark_sensitive(0).unwrap();
+
+        let json = history_to_json(&history);
+        assert!(!json.contains("secret"));
+
+        let entries = history_from_json(&json).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].0, ClipboardItem::Text("public".into()));
+    }
+}
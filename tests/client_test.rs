@@ -0,0 +1,267 @@
+mod harness;
+
+#[cfg(test)]
+mod client_tests {
+    use std::time::Duration;
+
+    use super_v::{
+        client::Client,
+        common::{ClientError, ClipboardFilter, ClipboardItem},
+        config::Config,
+        history::{ClipboardHistory, HistoryStats},
+        services::clipboard_ipc_server::IpcErrorCode,
+    };
+
+    use crate::harness::TestDaemon;
+
+    // Runs `test` against a fresh daemon on its own profile
+    fn with_daemon(test: impl FnOnce(&Client)) {
+        let daemon = TestDaemon::start();
+        test(&daemon.client());
+    }
+
+    fn text(s: &str) -> ClipboardItem {
+        ClipboardItem::Text(s.into())
+    }
+
+    #[test]
+    fn test_client_edits() {
+        with_daemon(|client| {
+            assert!(client.snapshot().unwrap().get_items().is_empty());
+
+            client.add(text("one")).unwrap();
+            client.add(text("two")).unwrap();
+            let history = client.add(text("three")).unwrap();
+            assert_eq!(
+                history.get_items(),
+                &[text("three"), text("two"), text("one")]
+            );
+
+            let history = client.promote(2).unwrap();
+            assert_eq!(
+                history.get_items(),
+                &[text("one"), text("three"), text("two")]
+            );
+
+            let history = client.delete(0).unwrap();
+            assert_eq!(history.get_items(), &[text("three"), text("two")]);
+
+            let history = client.delete_this(text("two")).unwrap();
+            assert_eq!(history.get_items(), &[text("three")]);
+
+            // A stale position still finds the item
+            client.add(text("four")).unwrap();
+            let history = client.delete_at(0, text("three")).unwrap();
+            assert_eq!(history.get_items(), &[text("four")]);
+
+            assert!(client.clear().unwrap().get_items().is_empty());
+        });
+    }
+
+    #[test]
+    fn test_client_query() {
+        with_daemon(|client| {
+            client.add(text("one")).unwrap();
+            client.add(text("two")).unwrap();
+            client.add(text("one")).unwrap();
+            client.add(text("three")).unwrap();
+
+            let (page, positions, total) = client.query(0, 1, ClipboardFilter::Text, true).unwrap();
+            assert_eq!(page.get_items(), &[text("one")]);
+            assert_eq!(positions, vec![1]);
+            assert_eq!(total, 3);
+
+            // Every text entry goes, and one undo brings them back
+            assert_eq!(client.clear_matching(ClipboardFilter::Text).unwrap(), 3);
+            assert!(client.snapshot().unwrap().is_empty());
+            assert_eq!(client.undo().unwrap().len(), 3);
+        });
+    }
+
+    #[test]
+    fn test_client_search() {
+        with_daemon(|client| {
+            client.add(text("apple pie")).unwrap();
+            client.add(text("banana")).unwrap();
+            client.add(text("Apple juice")).unwrap();
+
+            let (page, positions, total) = client.search("apple", 0, 1).unwrap();
+            assert_eq!(page.get_items(), &[text("Apple juice")]);
+            assert_eq!(positions, vec![0]);
+            assert_eq!(total, 2);
+
+            let (page, positions, _) = client.search("apple", 1, 1).unwrap();
+            assert_eq!(page.get_items(), &[text("apple pie")]);
+            assert_eq!(positions, vec![2]);
+        });
+    }
+
+    #[test]
+    fn test_client_copy() {
+        let daemon = TestDaemon::start();
+        let client = daemon.client();
+
+        // Only added, the clipboard is left alone
+        client.add(text("one")).unwrap();
+        assert_eq!(daemon.clipboard().contents(), None);
+        assert_eq!(daemon.expected_paste(), None);
+
+        // The daemon sets it, and tells its poller not to capture it again
+        let history = client.copy(text("two")).unwrap();
+        assert_eq!(history.get_items(), &[text("two"), text("one")]);
+        assert_eq!(daemon.clipboard().contents(), Some(text("two")));
+        assert_eq!(daemon.expected_paste(), Some(text("two").clipboard_hash()));
+    }
+
+    #[test]
+    fn test_client_get() {
+        with_daemon(|client| {
+            assert!(matches!(
+                client.get(0),
+                Err(ClientError::Rejected {
+                    code: Some(IpcErrorCode::IndexOutOfBounds),
+                    ..
+                })
+            ));
+
+            client.add(text("one")).unwrap();
+            client.add(text("two")).unwrap();
+            client.add(text("one")).unwrap();
+
+            let (item, meta) = client.get(0).unwrap();
+            assert_eq!(item, text("one"));
+            assert_eq!(meta.copy_count, 2);
+            assert_eq!(client.get(1).unwrap().0, text("two"));
+
+            match client.get(2) {
+                Err(ClientError::Rejected {
+                    code: Some(IpcErrorCode::IndexOutOfBounds),
+                    message,
+                }) => assert_eq!(message, "No item at index 2, the history has 2 items."),
+                other => panic!("expected IndexOutOfBounds, got {other:?}"),
+            }
+        });
+    }
+
+    #[test]
+    fn test_client_stats() {
+        with_daemon(|client| {
+            client.add(text("hello")).unwrap();
+
+            assert_eq!(
+                client.stats().unwrap(),
+                HistoryStats {
+                    total: 1,
+                    text: 1,
+                    images: 0,
+                    bytes: 5,
+                    max_age_secs: None,
+                }
+            );
+        });
+
+        // The daemon reports its max age
+        let daemon = TestDaemon::with_max_age(Duration::from_secs(3600));
+        assert_eq!(daemon.client().stats().unwrap().max_age_secs, Some(3600));
+    }
+
+    #[test]
+    fn test_client_rejected_command() {
+        with_daemon(|client| {
+            assert!(matches!(
+                client.delete(5),
+                Err(ClientError::Rejected {
+                    code: Some(IpcErrorCode::IndexOutOfBounds),
+                    ..
+                })
+            ));
+        });
+    }
+
+    #[test]
+    fn test_client_without_daemon() {
+        let client = Client::with_profile("client_test_missing");
+        assert!(matches!(client.snapshot(), Err(ClientError::IPCErr(_))));
+    }
+
+    #[test]
+    fn test_daemons_are_isolated() {
+        let first = TestDaemon::start();
+        let second = TestDaemon::start();
+        assert_ne!(first.profile(), second.profile());
+
+        first.client().add(text("only here")).unwrap();
+        assert_eq!(
+            first.client().snapshot().unwrap().get_items(),
+            &[text("only here")]
+        );
+        assert!(second.client().snapshot().unwrap().get_items().is_empty());
+    }
+
+    #[test]
+    fn test_client_add_image_at_the_size_limit() {
+        // A 500x500 image is exactly max_image_bytes, a one entry history caps the
+        // request near it
+        let config = Config {
+            max_text_bytes: 1_000,
+            max_image_bytes: 1_000_000,
+            ..Config::default()
+        };
+        let daemon = TestDaemon::with_config(Box::new(ClipboardHistory::new(1)), &config);
+        let client = daemon.client();
+
+        // Bytes over 0x7f are the ones that grow when sent as a list of numbers
+        let image = ClipboardItem::Image {
+            width: 500,
+            height: 500,
+            bytes: vec![0xff; 1_000_000],
+        };
+        assert_eq!(client.add(image.clone()).unwrap().get_items(), &[image]);
+
+        // A request over the limit is answered, not dropped
+        let too_big = ClipboardItem::Image {
+            width: 1000,
+            height: 1000,
+            bytes: vec![0xff; 4_000_000],
+        };
+        assert!(matches!(
+            client.add(too_big),
+            Err(ClientError::Rejected {
+                code: Some(IpcErrorCode::RequestTooLarge),
+                ..
+            })
+        ));
+        assert_eq!(client.snapshot().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_client_import_full_history_of_images() {
+        let config = Config {
+            max_text_bytes: 1_000,
+            max_image_bytes: 1_000_000,
+            ..Config::default()
+        };
+        let daemon = TestDaemon::with_config(Box::new(ClipboardHistory::new(5)), &config);
+        let client = daemon.client();
+
+        // Five images at the size limit, each one different
+        for shade in 0..5u8 {
+            let image = ClipboardItem::Image {
+                width: 500,
+                height: 500,
+                bytes: vec![0xff - shade; 1_000_000],
+            };
+            client.add(image).unwrap();
+        }
+        let before = client.snapshot().unwrap();
+        assert_eq!(before.len(), 5);
+
+        // The backup is far bigger than one image, the daemon still reads it
+        let json = client.export().unwrap();
+        assert!(json.len() > 5 * 1_000_000);
+        client.clear().unwrap();
+        assert_eq!(client.import(&json, false).unwrap(), 5);
+
+        assert_eq!(client.snapshot().unwrap().get_items(), before.get_items());
+    }
+}
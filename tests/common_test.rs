@@ -0,0 +1,214 @@
+#[cfg(test)]
+mod common_tests {
+    use std::{borrow::Cow, env, path::PathBuf};
+
+    use super_v::common::{
+        ClipboardBackend, ClipboardError, ClipboardItem, GetItem, LOCK_ENV, MockBackend,
+        PrimarySelection, SOCKET_ENV, TextNormalization, is_storable_text, lock_path,
+        looks_like_url, parse_color, preview_text, socket_path,
+    };
+
+    #[test]
+    fn test_preview_short_text_unchanged() {
+        assert!(matches!(preview_text("Hello", 60), Cow::Borrowed("Hello")));
+        assert_eq!(preview_text("", 60), "");
+
+        // Exactly max_chars is not cut
+        assert_eq!(preview_text("abcde", 5), "abcde");
+    }
+
+    #[test]
+    fn test_preview_cuts_long_text() {
+        assert_eq!(preview_text("abcdef", 5), "abcde...");
+    }
+
+    #[test]
+    fn test_preview_multibyte_text() {
+        // Byte 60 falls inside a 4-byte emoji, slicing by bytes would panic
+        let emoji = "😀".repeat(40);
+        let preview = preview_text(&emoji, 60);
+        assert_eq!(preview, emoji);
+
+        let preview = preview_text(&emoji, 15);
+        assert_eq!(preview, format!("{}...", "😀".repeat(15)));
+
+        // 3-byte CJK characters mixed with ASCII
+        let mixed = format!("a{}", "漢字".repeat(40));
+        assert_eq!(preview_text(&mixed, 3), "a漢字...");
+    }
+
+    #[test]
+    fn test_env_overrides_socket_and_lock_paths() {
+        // SAFETY: no other test in this binary reads the environment
+        unsafe {
+            env::set_var(SOCKET_ENV, "/run/test/clip.sock");
+            env::set_var(LOCK_ENV, "/run/test/clip.lock");
+        }
+
+        assert_eq!(socket_path("default"), PathBuf::from("/run/test/clip.sock"));
+        assert_eq!(lock_path("default"), PathBuf::from("/run/test/clip.lock"));
+
+        // Named profiles still get their own files
+        assert_eq!(
+            socket_path("work"),
+            PathBuf::from("/run/test/clip-work.sock")
+        );
+        assert_eq!(lock_path("work"), PathBuf::from("/run/test/clip-work.lock"));
+
+        // Empty means unset
+        unsafe {
+            env::set_var(SOCKET_ENV, "");
+            env::remove_var(LOCK_ENV);
+        }
+        assert_ne!(socket_path("default"), PathBuf::from(""));
+        assert!(socket_path("default").to_string_lossy().ends_with(".sock"));
+        assert!(lock_path("default").to_string_lossy().ends_with(".lock"));
+
+        unsafe {
+            env::remove_var(SOCKET_ENV);
+        }
+    }
+
+    #[test]
+    fn test_normalize_text() {
+        let text = |s: &str| ClipboardItem::Text(s.to_string());
+        let messy = text("  foo \n\t bar\n\n");
+
+        assert_eq!(messy.clone().normalize(TextNormalization::Off), messy);
+        assert_eq!(
+            messy.clone().normalize(TextNormalization::Trim),
+            text("foo \n\t bar")
+        );
+        assert_eq!(
+            messy.normalize(TextNormalization::Collapse),
+            text("foo bar")
+        );
+
+        // Images are left alone
+        let image = ClipboardItem::Image {
+            width: 1,
+            height: 1,
+            bytes: vec![0u8; 4],
+        };
+        assert_eq!(image.clone().normalize(TextNormalization::Collapse), image);
+    }
+
+    #[test]
+    fn test_mock_backend_round_trip() {
+        let mock = MockBackend::default();
+        let mut backend: Box<dyn ClipboardBackend> = Box::new(mock.clone());
+        assert_eq!(backend.get_item(), Err(ClipboardError::ClipboardEmpty));
+
+        backend.set_html("<b>Hi</b>", "Hi").unwrap();
+        assert_eq!(mock.contents(), Some(ClipboardItem::Text("Hi".to_string())));
+        assert_eq!(backend.get_html().as_deref(), Some("<b>Hi</b>"));
+
+        // Setting plain text drops the HTML
+        backend.set_text("Plain").unwrap();
+        assert_eq!(
+            backend.get_item(),
+            Ok(ClipboardItem::Text("Plain".to_string()))
+        );
+        assert_eq!(backend.get_html(), None);
+
+        backend.set_image(1, 1, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(
+            mock.contents(),
+            Some(ClipboardItem::Image {
+                width: 1,
+                height: 1,
+                bytes: vec![1, 2, 3, 4],
+            })
+        );
+    }
+
+    #[test]
+    fn test_mock_backend_primary_selection() {
+        let mock = MockBackend::default();
+        let mut backend: Box<dyn ClipboardBackend> = Box::new(mock.clone());
+
+        mock.select(ClipboardItem::Text("Selected".to_string()));
+        assert_eq!(backend.get_item(), Err(ClipboardError::ClipboardEmpty));
+        assert_eq!(
+            PrimarySelection(&mut *backend).get_item(),
+            Ok(ClipboardItem::Text("Selected".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_is_storable_text() {
+        assert!(is_storable_text("Hello, world"));
+        assert!(is_storable_text("line 1\r\n\tline 2\n"));
+        assert!(is_storable_text("héllo 👋 日本語"));
+        assert!(is_storable_text(""));
+
+        // A stray control character in real text is fine
+        assert!(is_storable_text("bell\u{7} in a long enough sentence"));
+
+        // Binary blobs
+        assert!(!is_storable_text("text\0with a nul"));
+        assert!(!is_storable_text("\u{1}\u{2}\u{3}ab\u{fffd}\u{fffd}"));
+        assert!(!is_storable_text(&"\u{fffd}".repeat(20)));
+    }
+
+    #[test]
+    fn test_looks_like_url() {
+        assert!(looks_like_url("https://example.com/page?q=1"));
+        assert!(looks_like_url("  www.example.com\n"));
+        assert!(looks_like_url("mailto:me@example.com"));
+
+        assert!(!looks_like_url("https://"));
+        assert!(!looks_like_url("see https://example.com"));
+        assert!(!looks_like_url("example.com"));
+    }
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(parse_color("#ff8800"), Some((255, 136, 0)));
+        assert_eq!(parse_color(" #F80 "), Some((255, 136, 0)));
+        assert_eq!(parse_color("#00000080"), Some((0, 0, 0)));
+
+        assert_eq!(parse_color("ff8800"), None);
+        assert_eq!(parse_color("#ff88"), None);
+        assert_eq!(parse_color("#gg0000"), None);
+        assert_eq!(parse_color("#ff8800 is orange"), None);
+    }
+
+    #[test]
+    fn test_clipboard_hash() {
+        let image = ClipboardItem::Image {
+            width: 2,
+            height: 1,
+            bytes: vec![1, 2, 3, 255, 4, 5, 6, 255],
+        };
+        assert_eq!(
+            image.clone().compress().clipboard_hash(),
+            image.clipboard_hash()
+        );
+
+        let text = ClipboardItem::Text("a".to_string());
+        assert_eq!(text.clipboard_hash(), text.clone().clipboard_hash());
+        assert_ne!(
+            text.clipboard_hash(),
+            ClipboardItem::Text("b".to_string()).clipboard_hash()
+        );
+    }
+
+    #[test]
+    fn test_parse_rgb_color() {
+        assert_eq!(parse_color("rgb(255, 136, 0)"), Some((255, 136, 0)));
+        assert_eq!(parse_color("RGB(255,136,0)"), Some((255, 136, 0)));
+        assert_eq!(parse_color("Rgba(255, 136, 0, 0.5)"), Some((255, 136, 0)));
+        // The prefix check must not slice inside a multi-byte character
+        assert_eq!(parse_color("rgé(1, 2, 3)"), None);
+        assert_eq!(parse_color("rgba(255, 136, 0, 0.5)"), Some((255, 136, 0)));
+        assert_eq!(parse_color("rgb(255 136 0 / 50%)"), Some((255, 136, 0)));
+        assert_eq!(parse_color("rgb(100%, 50%, 0%)"), Some((255, 128, 0)));
+
+        assert_eq!(parse_color("rgb(256, 0, 0)"), None);
+        assert_eq!(parse_color("rgb(255, 136)"), None);
+        assert_eq!(parse_color("rgb(255, 136, 0, x)"), None);
+        assert_eq!(parse_color("rgb(255, 136, 0"), None);
+        assert_eq!(parse_color("rgb(150%, 0, 0)"), None);
+    }
+}
@@ -0,0 +1,151 @@
+#[cfg(test)]
+mod config_tests {
+    use std::{
+        fs::{remove_file, write},
+        path::PathBuf,
+        time::Duration,
+    };
+
+    use super_v::{
+        common::{ConfigError, DedupScope, PopupAnchor, TextNormalization},
+        config::Config,
+    };
+
+    #[test]
+    fn test_empty_config_is_default() {
+        let config = Config::from_toml("").unwrap();
+        assert_eq!(config, Config::default());
+        assert_eq!(config.history_size, 25);
+        assert_eq!(config.poll_interval(), Duration::from_millis(100));
+        assert_eq!(config.max_age(), None);
+        assert_eq!(config.db_path, None);
+        assert_eq!(config.dedup_window(), None);
+        assert_eq!(config.max_total_bytes, None);
+        assert_eq!(config.max_text_bytes, 10_000_000);
+        assert_eq!(config.max_image_bytes, 100_000_000);
+        assert_eq!(config.normalize_text, TextNormalization::Off);
+        assert!(config.type_icons);
+        assert!(!config.track_primary);
+        assert!(!config.adaptive_poll);
+        assert!(config.auto_paste);
+        assert_eq!(config.dedup_scope, DedupScope::All);
+        assert!(config.window_modal && config.grab_focus && config.close_on_focus_loss);
+        assert_eq!(config.paste_delay(), None);
+        assert!(!config.layer_shell);
+        assert!(!config.emoji_trailing_space);
+        assert_eq!(config.popup_anchor, PopupAnchor::Center);
+    }
+
+    #[test]
+    fn test_partial_config_keeps_other_defaults() {
+        let config = Config::from_toml("history_size = 50\nmax_age_secs = 60\n").unwrap();
+
+        assert_eq!(config.history_size, 50);
+        assert_eq!(config.max_age(), Some(Duration::from_secs(60)));
+        assert_eq!(config.poll_interval_ms, Config::default().poll_interval_ms);
+
+        let config = Config::from_toml("dedup_window_secs = 600\n").unwrap();
+        assert_eq!(config.dedup_window(), Some(Duration::from_secs(600)));
+
+        let config = Config::from_toml("track_primary = true\n").unwrap();
+        assert!(config.track_primary);
+
+        let config = Config::from_toml("adaptive_poll = true\n").unwrap();
+        assert!(config.adaptive_poll);
+
+        let config = Config::from_toml("type_icons = false\n").unwrap();
+        assert!(!config.type_icons);
+
+        let config = Config::from_toml("max_total_bytes = 1000000\n").unwrap();
+        assert_eq!(config.max_total_bytes, Some(1_000_000));
+
+        let config = Config::from_toml("max_text_bytes = 1000\nmax_image_bytes = 2000\n").unwrap();
+        assert_eq!(
+            (config.max_text_bytes, config.max_image_bytes),
+            (1000, 2000)
+        );
+
+        let config = Config::from_toml("normalize_text = \"collapse\"\n").unwrap();
+        assert_eq!(config.normalize_text, TextNormalization::Collapse);
+
+        let config = Config::from_toml("dedup_scope = \"text\"\n").unwrap();
+        assert_eq!(config.dedup_scope, DedupScope::Text);
+
+        let config = Config::from_toml("close_on_focus_loss = false\n").unwrap();
+        assert!(!config.close_on_focus_loss);
+        assert!(config.grab_focus);
+
+        let config = Config::from_toml("emoji_trailing_space = true\n").unwrap();
+        assert!(config.emoji_trailing_space);
+
+        let config = Config::from_toml("auto_paste = false\npaste_delay_ms = 50\n").unwrap();
+        assert!(!config.auto_paste);
+        assert_eq!(config.paste_delay(), Some(Duration::from_millis(50)));
+
+        let config =
+            Config::from_toml("layer_shell = true\npopup_anchor = \"bottom-right\"\n").unwrap();
+        assert!(config.layer_shell);
+        assert_eq!(config.popup_anchor, PopupAnchor::BottomRight);
+        assert_eq!(config.popup_margin, 12);
+    }
+
+    #[test]
+    fn test_invalid_configs_rejected() {
+        // Typos should not be silently ignored
+        assert!(matches!(
+            Config::from_toml("histroy_size = 50"),
+            Err(ConfigError::ParseError(_))
+        ));
+
+        // Wrong type
+        assert!(matches!(
+            Config::from_toml("history_size = \"lots\""),
+            Err(ConfigError::ParseError(_))
+        ));
+
+        // Would busy loop the poller
+        assert!(matches!(
+            Config::from_toml("poll_interval_ms = 0"),
+            Err(ConfigError::ParseError(_))
+        ));
+
+        // Would show nothing but "..."
+        assert!(matches!(
+            Config::from_toml("preview_chars = 0"),
+            Err(ConfigError::ParseError(_))
+        ));
+
+        // Unlike those, no history is a valid choice
+        assert_eq!(
+            Config::from_toml("history_size = 0").unwrap().history_size,
+            0
+        );
+
+        // Not a mode
+        assert!(matches!(
+            Config::from_toml("normalize_text = \"squash\""),
+            Err(ConfigError::ParseError(_))
+        ));
+
+        // Anchors are kebab-case
+        assert!(matches!(
+            Config::from_toml("popup_anchor = \"top_left\""),
+            Err(ConfigError::ParseError(_))
+        ));
+    }
+
+    #[test]
+    fn test_load_from_file() {
+        let path = PathBuf::from("/tmp/super_v_config_test.toml");
+
+        // Missing file means defaults
+        let _ = remove_file(&path);
+        assert_eq!(Config::load_from(&path), Ok(Config::default()));
+
+        write(&path, "db_path = \"/tmp/history.db\"\n").unwrap();
+        let config = Config::load_from(&path).unwrap();
+        let _ = remove_file(&path);
+
+        assert_eq!(config.db_path, Some(PathBuf::from("/tmp/history.db")));
+    }
+}
@@ -0,0 +1,148 @@
+//! Test daemons that can run in parallel.
+//!
+//! Every profile has its own socket and lock file (see `common::socket_path`), so a
+//! daemon on a profile no other test uses is isolated from the rest of the suite and
+//! needs no `#[serial]`. The `SUPER_V_SOCKET` override is not used for this, since the
+//! environment is shared by every test in a binary.
+//!
+//! Daemons run on a `MockBackend`, so no display is needed. Only the command service
+//! is started unless the test asks for the poller with `TestDaemon::polling`.
+//!
+//! Include with `mod harness;` from a test file.
+#![allow(dead_code)]
+
+use std::{
+    os::unix::net::UnixStream,
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use super_v::{
+    client::Client,
+    common::MockBackend,
+    config::Config,
+    history::{ClipboardHistory, HistoryStore},
+    services::{clipboard_ipc_server::create_stream, clipboard_manager::Manager},
+};
+
+/// A daemon on its own profile, stopped when dropped.
+pub struct TestDaemon {
+    manager: Manager,
+    profile: String,
+    clipboard: MockBackend,
+}
+
+impl TestDaemon {
+    /// Starts a daemon around an empty in-memory history of 10 items.
+    pub fn start() -> Self {
+        Self::with_store(Box::new(ClipboardHistory::new(10)))
+    }
+
+    /// Starts a daemon around `store`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the daemon can't be created, e.g. its lock file can't be opened.
+    pub fn with_store(store: Box<dyn HistoryStore>) -> Self {
+        Self::spawn(store, |_| {})
+    }
+
+    /// Starts a daemon around `store` with the runtime settings of `config`, see
+    /// `Manager::apply_config`.
+    pub fn with_config(store: Box<dyn HistoryStore>, config: &Config) -> Self {
+        Self::spawn(store, |manager| manager.apply_config(config))
+    }
+
+    /// Starts a daemon around an empty in-memory history of 10 items, configured to
+    /// expire entries older than `max_age`.
+    pub fn with_max_age(max_age: Duration) -> Self {
+        Self::spawn(Box::new(ClipboardHistory::new(10)), |manager| {
+            manager.set_max_age(Some(max_age))
+        })
+    }
+
+    /// Starts a daemon around `store` that also polls its clipboard.
+    ///
+    /// Whatever is on the clipboard when the poller starts isn't captured, so give it a
+    /// moment before copying.
+    pub fn polling(store: Box<dyn HistoryStore>) -> Self {
+        let mut daemon = Self::with_store(store);
+        daemon.manager._polling_service();
+        daemon
+    }
+
+    fn spawn(store: Box<dyn HistoryStore>, setup: impl FnOnce(&mut Manager)) -> Self {
+        let profile = unique_profile();
+        let clipboard = MockBackend::default();
+        let mut manager =
+            Manager::with_backend(store, &profile, Box::new(clipboard.clone())).unwrap();
+        setup(&mut manager);
+        manager._command_service();
+
+        Self {
+            manager,
+            profile,
+            clipboard,
+        }
+    }
+
+    /// Client talking to this daemon.
+    pub fn client(&self) -> Client {
+        Client::with_profile(&self.profile)
+    }
+
+    /// A new connection to this daemon, for sending raw payloads.
+    pub fn stream(&self) -> UnixStream {
+        create_stream(&self.profile).unwrap()
+    }
+
+    /// Profile the daemon runs on, for talking to it without a `Client`.
+    pub fn profile(&self) -> &str {
+        &self.profile
+    }
+
+    /// The clipboard the daemon reads and writes.
+    pub fn clipboard(&self) -> &MockBackend {
+        &self.clipboard
+    }
+
+    /// The daemon itself, for tests of its threads and shared state.
+    pub fn manager(&self) -> &Manager {
+        &self.manager
+    }
+
+    /// Mutable access to the daemon, e.g. to take a service's handle or stop it early.
+    /// Stopping again when dropped does no harm.
+    pub fn manager_mut(&mut self) -> &mut Manager {
+        &mut self.manager
+    }
+
+    /// A copy of the history as it is right now.
+    pub fn snapshot(&self) -> ClipboardHistory {
+        self.manager._shared_history.lock().unwrap().snapshot()
+    }
+
+    /// Hash of the clipboard change the poller was told to skip, if it hasn't taken it.
+    pub fn expected_paste(&self) -> Option<u64> {
+        *self.manager._expected_paste.lock().unwrap()
+    }
+}
+
+impl Drop for TestDaemon {
+    // Also removes the socket and lock file
+    fn drop(&mut self) {
+        self.manager.stop();
+    }
+}
+
+/// A profile name no other daemon in this run uses. The pid keeps test binaries
+/// running at the same time apart.
+pub fn unique_profile() -> String {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    format!(
+        "test-{}-{}",
+        process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    )
+}
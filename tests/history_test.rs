@@ -1,10 +1,15 @@
 #[cfg(test)]
 mod history_tests {
-    use std::collections::VecDeque;
+    use std::{
+        collections::VecDeque,
+        time::{Duration, SystemTime},
+    };
 
     use super_v::{
-        common::{ClipboardError, ClipboardItem},
-        history::ClipboardHistory,
+        common::{ClipboardError, ClipboardFilter, ClipboardItem, DedupScope, Selection},
+        history::{
+            ClipboardHistory, EntryMeta, HistoryStats, HistoryStore, UNDO_LIMIT, entry_to_json,
+        },
     };
 
     #[test]
@@ -26,6 +31,8 @@ mod history_tests {
     fn test_capacity_fill_and_overflow() {
         // Create history
         let mut history = ClipboardHistory::new(5);
+        assert!(history.is_empty());
+        assert_eq!(history.capacity(), 5);
 
         // Create items
         let item1 = ClipboardItem::Text("Item 1".to_string());
@@ -45,10 +52,12 @@ mod history_tests {
 
         // Check if history auto manages the size by popping the oldest entry
         assert!(
-            history.get_items().len() == 5,
+            history.len() == 5,
             "Length must be 5, but got {}",
-            history.get_items().len()
+            history.len()
         );
+        assert!(!history.is_empty());
+        assert_eq!(history.capacity(), 5);
         assert_eq!(
             history.get_items(),
             &VecDeque::from([item6, item5, item4, item3, item2])
@@ -72,7 +81,8 @@ mod history_tests {
 
         // Promote item 2
         // This should re-order from 3->2->1 to 2->3->1
-        history.promote(1).unwrap();
+        assert_eq!(history.promote(1), Ok(0));
+        assert_eq!(history.preview(0, 60), Some("Item 2".to_string()));
 
         // Compare
         assert_eq!(history.get_items(), &VecDeque::from([item2, item3, item1]));
@@ -130,6 +140,58 @@ mod history_tests {
         assert_eq!(history.get_items().len(), 0);
     }
 
+    #[test]
+    fn test_byte_budget_evicts_oldest() {
+        let mut history = ClipboardHistory::new(10).with_max_total_bytes(Some(10));
+
+        let item1 = ClipboardItem::Text("aaaa".to_string());
+        let item2 = ClipboardItem::Text("bbbb".to_string());
+        let item3 = ClipboardItem::Text("cccc".to_string());
+
+        history.add(item1);
+        history.add(item2.clone());
+        assert_eq!(history.stats().bytes, 8);
+
+        // 12 bytes is over budget, only the oldest has to go
+        history.add(item3.clone());
+        assert_eq!(history.get_items(), &VecDeque::from([item3, item2]));
+        assert_eq!(history.get_meta().len(), 2);
+    }
+
+    #[test]
+    fn test_byte_budget_keeps_newest() {
+        let mut history = ClipboardHistory::new(10).with_max_total_bytes(Some(4));
+
+        let small = ClipboardItem::Text("abc".to_string());
+        let large = ClipboardItem::Image {
+            width: 2,
+            height: 2,
+            bytes: vec![0; 16],
+        };
+
+        history.add(small);
+        history.add(large.clone());
+
+        // Bigger than the whole budget, but the latest copy stays
+        assert_eq!(history.get_items(), &VecDeque::from([large]));
+    }
+
+    #[test]
+    fn test_byte_budget_and_count_cap() {
+        let mut history = ClipboardHistory::new(2).with_max_total_bytes(Some(1000));
+
+        let item1 = ClipboardItem::Text("Item 1".to_string());
+        let item2 = ClipboardItem::Text("Item 2".to_string());
+        let item3 = ClipboardItem::Text("Item 3".to_string());
+
+        history.add(item1);
+        history.add(item2.clone());
+        history.add(item3.clone());
+
+        // Well under budget, the count still applies
+        assert_eq!(history.get_items(), &VecDeque::from([item3, item2]));
+    }
+
     #[test]
     fn test_large_image_data() {
         // Test with large image data to ensure no memory issues
@@ -336,9 +398,17 @@ mod history_tests {
         history.add(item2.clone());
 
         // Promote first item (index 0) - should remain at top
-        history.promote(0).unwrap();
+        let meta_before = history.get_meta().clone();
+        assert_eq!(history.promote(0), Ok(0));
 
         assert_eq!(history.get_items(), &VecDeque::from([item2, item1]));
+        assert_eq!(history.get_meta(), &meta_before);
+
+        // Still an error when there is no front item
+        assert_eq!(
+            ClipboardHistory::new(3).promote(0),
+            Err(ClipboardError::IndexOutOfBound)
+        );
     }
 
     #[test]
@@ -356,7 +426,7 @@ mod history_tests {
 
         // Delete the middle item (current order: 3,2,1)
         let result = history.delete(1);
-        assert_eq!(result, Ok(()));
+        assert_eq!(result, Ok(item2));
         assert_eq!(history.get_items(), &VecDeque::from([item3, item1]));
     }
 
@@ -396,5 +466,934 @@ mod history_tests {
 
         let result = history.delete_this(ClipboardItem::Text("Missing".to_string()));
         assert_eq!(result, Err(ClipboardError::IndexOutOfBound));
+        assert_eq!(history.get_items().len(), 1);
+    }
+
+    #[test]
+    fn test_delete_this_removes_only_first_duplicate() {
+        // Without dedup the same text can be in history twice
+        let mut history = ClipboardHistory::new(5).with_dedup_scope(DedupScope::Off);
+        history.add(text("a"));
+        history.add(text("b"));
+        history.add(text("a"));
+
+        // a,b,a -> b,a
+        history.delete_this(text("a")).unwrap();
+        assert_eq!(history.get_items(), &VecDeque::from([text("b"), text("a")]));
+
+        // b,a -> b
+        history.delete_this(text("a")).unwrap();
+        assert_eq!(history.get_items(), &VecDeque::from([text("b")]));
+        assert_eq!(
+            history.delete_this(text("a")),
+            Err(ClipboardError::IndexOutOfBound)
+        );
+    }
+
+    #[test]
+    fn test_delete_at_picks_the_given_duplicate() {
+        let mut history = ClipboardHistory::new(5).with_dedup_scope(DedupScope::Off);
+        history.add(text("a"));
+        history.add(text("b"));
+        history.add(text("a"));
+
+        // a,b,a -> a,b: the older copy, not the first match
+        history.delete_at(2, &text("a")).unwrap();
+        assert_eq!(history.get_items(), &VecDeque::from([text("a"), text("b")]));
+
+        // A position that no longer holds the item falls back to its most recent copy
+        history.add(text("c"));
+        history.delete_at(1, &text("b")).unwrap();
+        assert_eq!(history.get_items(), &VecDeque::from([text("c"), text("a")]));
+
+        assert_eq!(
+            history.delete_at(0, &text("b")),
+            Err(ClipboardError::IndexOutOfBound)
+        );
+    }
+
+    #[test]
+    fn test_remove_expired_items() {
+        // Create history
+        let mut history = ClipboardHistory::new(5);
+        let now = SystemTime::now();
+
+        let old = ClipboardItem::Text("Old".to_string());
+        let fresh = ClipboardItem::Text("Fresh".to_string());
+
+        // One item copied two days ago, one a minute ago
+        history.add_at(old.clone(), now - Duration::from_secs(2 * 24 * 60 * 60));
+        history.add_at(fresh.clone(), now - Duration::from_secs(60));
+
+        // Sweep with a 24 hour limit
+        let removed = history.remove_expired(Duration::from_secs(24 * 60 * 60), now);
+
+        assert_eq!(removed, 1);
+        assert_eq!(history.get_items(), &VecDeque::from([fresh]));
+        assert_eq!(history.get_meta().len(), 1);
+    }
+
+    #[test]
+    fn test_remove_expired_keeps_boundary_item() {
+        // Create history
+        let mut history = ClipboardHistory::new(5);
+        let now = SystemTime::now();
+        let max_age = Duration::from_secs(60);
+
+        // Exactly max_age old is not expired yet
+        let item = ClipboardItem::Text("Boundary".to_string());
+        history.add_at(item.clone(), now - max_age);
+
+        assert_eq!(history.remove_expired(max_age, now), 0);
+        assert_eq!(history.get_items(), &VecDeque::from([item]));
+    }
+
+    #[test]
+    fn test_duplicate_refreshes_timestamp() {
+        // Create history
+        let mut history = ClipboardHistory::new(5);
+        let now = SystemTime::now();
+        let max_age = Duration::from_secs(60);
+
+        let item1 = ClipboardItem::Text("Item 1".to_string());
+        let item2 = ClipboardItem::Text("Item 2".to_string());
+
+        // Item 1 is old, but gets copied again just now
+        history.add_at(item1.clone(), now - Duration::from_secs(600));
+        history.add_at(item2.clone(), now - Duration::from_secs(600));
+        history.add_at(item1.clone(), now);
+
+        // Only item 2 should expire
+        assert_eq!(history.remove_expired(max_age, now), 1);
+        assert_eq!(history.get_items(), &VecDeque::from([item1]));
+        assert_eq!(history.get_meta()[0].copied_at, now);
+    }
+
+    #[test]
+    fn test_dedup_window_boundary() {
+        // Create history
+        let window = Duration::from_secs(60);
+        let mut history = ClipboardHistory::new(5).with_dedup_window(Some(window));
+        let now = SystemTime::now();
+
+        let item1 = ClipboardItem::Text("Item 1".to_string());
+        let item2 = ClipboardItem::Text("Item 2".to_string());
+
+        // Exactly window old still collapses into the existing entry
+        history.add_at(item1.clone(), now - window);
+        history.add_at(item2.clone(), now - window);
+        history.add_at(item1.clone(), now);
+
+        assert_eq!(
+            history.get_items(),
+            &VecDeque::from([item1.clone(), item2.clone()])
+        );
+        assert_eq!(history.get_meta()[0].copied_at, now);
+
+        // A moment older is a new event, the old entry stays where it was
+        let mut history = ClipboardHistory::new(5).with_dedup_window(Some(window));
+        history.add_at(item1.clone(), now - window - Duration::from_millis(1));
+        history.add_at(item2.clone(), now - window);
+        history.add_at(item1.clone(), now);
+
+        assert_eq!(
+            history.get_items(),
+            &VecDeque::from([item1.clone(), item2, item1.clone()])
+        );
+        assert_eq!(history.get_meta()[0].copied_at, now);
+
+        // Copying again right away promotes the new entry, not the old one
+        history.add_at(item1.clone(), now);
+        assert_eq!(history.get_items().len(), 3);
+    }
+
+    #[test]
+    fn test_no_dedup_window_always_promotes() {
+        // Create history
+        let mut history = ClipboardHistory::new(5);
+        let now = SystemTime::now();
+
+        let item = ClipboardItem::Text("Item".to_string());
+        history.add_at(item.clone(), now - Duration::from_secs(365 * 24 * 3600));
+        history.add_at(item.clone(), now);
+
+        assert_eq!(history.get_items(), &VecDeque::from([item]));
+    }
+
+    #[test]
+    fn test_ocr_text_follows_item() {
+        // Create history
+        let mut history = ClipboardHistory::new(5);
+
+        let image = ClipboardItem::Image {
+            width: 1,
+            height: 1,
+            bytes: vec![0u8; 4],
+        };
+        let text = ClipboardItem::Text("Item".to_string());
+
+        history.add(image.clone());
+        history.add(text.clone());
+        history.set_ocr_text(&image, "Hello".to_string()).unwrap();
+        assert_eq!(history.get_meta()[1].ocr_text.as_deref(), Some("Hello"));
+        assert_eq!(history.get_meta()[0].ocr_text, None);
+
+        // Copying the image again keeps its text
+        history.add(image.clone());
+        assert_eq!(history.get_meta()[0].ocr_text.as_deref(), Some("Hello"));
+
+        // Items that are gone can't get text
+        assert_eq!(
+            history.set_ocr_text(&ClipboardItem::Text("Missing".to_string()), "x".to_string()),
+            Err(ClipboardError::IndexOutOfBound)
+        );
+    }
+
+    #[test]
+    fn test_set_html() {
+        let mut history = ClipboardHistory::new(5);
+
+        let text = ClipboardItem::Text("Hello".to_string());
+        history.add(text.clone());
+        history.add(ClipboardItem::Text("Other".to_string()));
+
+        history
+            .set_html(1, &text, Some("<b>Hello</b>".to_string()))
+            .unwrap();
+        assert_eq!(history.get_meta()[1].html.as_deref(), Some("<b>Hello</b>"));
+        assert_eq!(history.get_meta()[0].html, None);
+
+        // A later copy without HTML replaces it
+        history.set_html(1, &text, None).unwrap();
+        assert_eq!(history.get_meta()[1].html, None);
+
+        // The entry at the position has to be the item
+        assert_eq!(
+            history.set_html(0, &text, None),
+            Err(ClipboardError::IndexOutOfBound)
+        );
+        assert_eq!(
+            history.set_html(5, &text, None),
+            Err(ClipboardError::IndexOutOfBound)
+        );
+    }
+
+    #[test]
+    fn test_set_selection() {
+        let mut history = ClipboardHistory::new(5);
+
+        let text = ClipboardItem::Text("Selected".to_string());
+        history.add(text.clone());
+        assert_eq!(history.get_meta()[0].selection, Selection::Clipboard);
+
+        history.set_selection(0, &text, Selection::Primary).unwrap();
+        assert_eq!(history.get_meta()[0].selection, Selection::Primary);
+
+        assert_eq!(
+            history.set_selection(
+                0,
+                &ClipboardItem::Text("Missing".to_string()),
+                Selection::Primary
+            ),
+            Err(ClipboardError::IndexOutOfBound)
+        );
+    }
+
+    #[test]
+    fn test_contains_and_position() {
+        // Create history
+        let mut history = ClipboardHistory::new(5);
+
+        let text = ClipboardItem::Text("Item".to_string());
+        let image = ClipboardItem::Image {
+            width: 1,
+            height: 1,
+            bytes: vec![255u8; 4],
+        };
+        history.add(text.clone());
+        history.add(image.clone());
+
+        assert!(history.contains(&text));
+        assert!(history.contains(&image));
+        assert_eq!(history.position(&image), Some(0));
+        assert_eq!(history.position(&text), Some(1));
+
+        // Same pixels at another size is a different image
+        let other_image = ClipboardItem::Image {
+            width: 2,
+            height: 1,
+            bytes: vec![255u8; 4],
+        };
+        let missing = ClipboardItem::Text("Missing".to_string());
+        assert!(!history.contains(&other_image));
+        assert!(!history.contains(&missing));
+        assert_eq!(history.position(&missing), None);
+    }
+
+    #[test]
+    fn test_copy_count_increments_on_recopy() {
+        // Create history
+        let mut history = ClipboardHistory::new(5);
+
+        let item1 = ClipboardItem::Text("Item 1".to_string());
+        let item2 = ClipboardItem::Text("Item 2".to_string());
+
+        history.add(item1.clone());
+        assert_eq!(history.get_meta()[0].copy_count, 1);
+
+        history.add(item2.clone());
+        history.add(item1.clone());
+        history.add(item2.clone());
+        history.add(item1.clone());
+
+        // Re-copies add up instead of resetting
+        assert_eq!(history.get_items(), &VecDeque::from([item1, item2]));
+        assert_eq!(history.get_meta()[0].copy_count, 3);
+        assert_eq!(history.get_meta()[1].copy_count, 2);
+
+        // Moving an entry around is not a copy
+        history.promote(1).unwrap();
+        history.move_to(0, 1).unwrap();
+        assert_eq!(history.get_meta()[0].copy_count, 3);
+        assert_eq!(history.get_meta()[1].copy_count, 2);
+    }
+
+    #[test]
+    fn test_old_duplicate_starts_new_count() {
+        // Outside the dedup window a re-copy is a new entry with its own count
+        let window = Duration::from_secs(60);
+        let mut history = ClipboardHistory::new(5).with_dedup_window(Some(window));
+        let now = SystemTime::now();
+
+        let item = ClipboardItem::Text("Item".to_string());
+        history.add_at(item.clone(), now - Duration::from_secs(120));
+        history.add_at(item.clone(), now - Duration::from_secs(110));
+        history.add_at(item.clone(), now);
+
+        assert_eq!(history.get_meta()[0].copy_count, 1);
+        assert_eq!(history.get_meta()[1].copy_count, 2);
+    }
+
+    #[test]
+    fn test_promote_this_item_success() {
+        // Create history
+        let mut history = ClipboardHistory::new(3);
+
+        let item1 = ClipboardItem::Text("Item 1".to_string());
+        let item2 = ClipboardItem::Text("Item 2".to_string());
+
+        history.add(item1.clone());
+        history.add(item2.clone());
+
+        // Promote a specific item by value
+        // and get told where it landed
+        let index = history.promote_this(item1.clone()).unwrap();
+        assert_eq!(history.position(&item1), Some(index));
+        assert_eq!(history.get_items(), &VecDeque::from([item1, item2]));
+    }
+
+    #[test]
+    fn test_promote_this_missing_item_returns_err() {
+        // Create history with a single entry and attempt to promote an unknown item
+        let mut history = ClipboardHistory::new(2);
+        history.add(ClipboardItem::Text("Known".to_string()));
+
+        let result = history.promote_this(ClipboardItem::Text("Missing".to_string()));
+        assert_eq!(result, Err(ClipboardError::IndexOutOfBound));
+    }
+
+    #[test]
+    fn test_value_based_ops_survive_concurrent_insert() {
+        // Create history
+        let mut history = ClipboardHistory::new(5);
+
+        let item1 = ClipboardItem::Text("Item 1".to_string());
+        let item2 = ClipboardItem::Text("Item 2".to_string());
+        let new_item = ClipboardItem::Text("New".to_string());
+
+        history.add(item1.clone());
+        history.add(item2.clone());
+
+        // Client renders a snapshot and picks item 1, at index 1
+        let snapshot = history.clone();
+        let picked = snapshot.get_items()[1].clone();
+        assert_eq!(picked, item1);
+
+        // Poller inserts a new item before the client's command arrives: New, 2, 1
+        history.add(new_item.clone());
+
+        // Index 1 now points at item 2, but deleting by value still hits item 1
+        assert_eq!(history.get_items()[1], item2);
+        history.delete_this(picked).unwrap();
+        assert_eq!(
+            history.get_items(),
+            &VecDeque::from([new_item.clone(), item2.clone()])
+        );
+
+        // Same for promotion
+        let picked = history.get_items()[1].clone();
+        history.add(item1.clone());
+        history.promote_this(picked).unwrap();
+        assert_eq!(
+            history.get_items(),
+            &VecDeque::from([item2, item1, new_item])
+        );
+    }
+
+    // Helper: history with items "0".."n-1", index i holding "i"
+    fn numbered_history(n: usize) -> ClipboardHistory {
+        let mut history = ClipboardHistory::new(n);
+        for i in (0..n).rev() {
+            history.add(ClipboardItem::Text(i.to_string()));
+        }
+        history
+    }
+
+    fn texts(history: &ClipboardHistory) -> Vec<String> {
+        history
+            .get_items()
+            .iter()
+            .map(|item| item.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_delete_range_middle_and_full() {
+        let mut history = numbered_history(5);
+
+        // End is exclusive: 0,1,2,3,4 -> 0,3,4
+        history.delete_range(1, 3).unwrap();
+        assert_eq!(texts(&history), ["0", "3", "4"]);
+        assert_eq!(history.get_meta().len(), 3);
+
+        // Everything
+        history.delete_range(0, 3).unwrap();
+        assert!(history.get_items().is_empty());
+        assert!(history.get_meta().is_empty());
+    }
+
+    #[test]
+    fn test_delete_range_empty_range_is_noop() {
+        let mut history = numbered_history(3);
+
+        assert_eq!(history.delete_range(1, 1), Ok(()));
+        assert_eq!(history.delete_range(3, 3), Ok(()));
+        assert_eq!(texts(&history), ["0", "1", "2"]);
+
+        let mut empty = ClipboardHistory::new(3);
+        assert_eq!(empty.delete_range(0, 0), Ok(()));
+    }
+
+    #[test]
+    fn test_delete_range_out_of_bounds() {
+        let mut history = numbered_history(3);
+
+        assert_eq!(
+            history.delete_range(0, 4),
+            Err(ClipboardError::IndexOutOfBound)
+        );
+        assert_eq!(
+            history.delete_range(2, 1),
+            Err(ClipboardError::IndexOutOfBound)
+        );
+        assert_eq!(
+            history.delete_range(4, 4),
+            Err(ClipboardError::IndexOutOfBound)
+        );
+
+        // Nothing is deleted on error
+        assert_eq!(texts(&history), ["0", "1", "2"]);
+    }
+
+    #[test]
+    fn test_move_forward() {
+        let mut history = numbered_history(5);
+
+        // 0,1,2,3,4 -> 0,2,3,1,4
+        let index = history.move_to(1, 3).unwrap();
+        assert_eq!(texts(&history), ["0", "2", "3", "1", "4"]);
+        assert_eq!(texts(&history)[index], "1");
+        assert_eq!(history.get_meta().len(), 5);
+    }
+
+    #[test]
+    fn test_move_backward() {
+        let mut history = numbered_history(5);
+
+        // 0,1,2,3,4 -> 0,3,1,2,4
+        let index = history.move_to(3, 1).unwrap();
+        assert_eq!(texts(&history), ["0", "3", "1", "2", "4"]);
+        assert_eq!(texts(&history)[index], "3");
+    }
+
+    #[test]
+    fn test_move_to_ends() {
+        let mut history = numbered_history(5);
+
+        // To the back: 0,1,2,3,4 -> 1,2,3,4,0
+        history.move_to(0, 4).unwrap();
+        assert_eq!(texts(&history), ["1", "2", "3", "4", "0"]);
+
+        // To the front: 1,2,3,4,0 -> 0,1,2,3,4
+        history.move_to(4, 0).unwrap();
+        assert_eq!(texts(&history), ["0", "1", "2", "3", "4"]);
+    }
+
+    #[test]
+    fn test_move_same_position_is_noop() {
+        let mut history = numbered_history(3);
+
+        assert_eq!(history.move_to(1, 1), Ok(1));
+        assert_eq!(texts(&history), ["0", "1", "2"]);
+    }
+
+    #[test]
+    fn test_move_out_of_bounds() {
+        let mut history = numbered_history(3);
+
+        assert_eq!(history.move_to(3, 0), Err(ClipboardError::IndexOutOfBound));
+        assert_eq!(history.move_to(0, 3), Err(ClipboardError::IndexOutOfBound));
+        assert_eq!(texts(&history), ["0", "1", "2"]);
+
+        // Even a no-op move needs a valid index
+        let mut empty = ClipboardHistory::new(3);
+        assert_eq!(empty.move_to(0, 0), Err(ClipboardError::IndexOutOfBound));
+    }
+
+    #[test]
+    fn test_stats_counts_and_bytes() {
+        let mut history = ClipboardHistory::new(5);
+        assert_eq!(history.stats(), HistoryStats::default());
+
+        history.add(ClipboardItem::Text("Hello".to_string()));
+        history.add(ClipboardItem::Text("ü".to_string())); // 2 bytes in UTF-8
+        history.add(ClipboardItem::Image {
+            width: 2,
+            height: 1,
+            bytes: vec![0u8; 8],
+        });
+
+        let stats = history.stats();
+        assert_eq!(
+            stats,
+            HistoryStats {
+                total: 3,
+                text: 2,
+                images: 1,
+                bytes: 15,
+                max_age_secs: None,
+            }
+        );
+        assert_eq!(
+            stats.to_json(),
+            r#"{"total":3,"text":2,"images":1,"bytes":15,"max_age_secs":null}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<HistoryStats>(&stats.to_json()).unwrap(),
+            stats
+        );
+
+        let expiring = HistoryStats {
+            max_age_secs: Some(3600),
+            ..stats
+        };
+        assert!(expiring.to_json().ends_with(r#""max_age_secs":3600}"#));
+        assert_eq!(
+            serde_json::from_str::<HistoryStats>(&expiring.to_json()).unwrap(),
+            expiring
+        );
+
+        // Written before max_age_secs was reported
+        assert_eq!(
+            serde_json::from_str::<HistoryStats>(r#"{"total":3,"text":2,"images":1,"bytes":15}"#)
+                .unwrap(),
+            stats
+        );
+    }
+
+    #[test]
+    fn test_entry_is_new_since() {
+        let pasted_at = SystemTime::UNIX_EPOCH + Duration::from_secs(10);
+        let mut history = ClipboardHistory::new(5);
+
+        // Copied before the paste, then promoted by it
+        history.add_at(text("a"), pasted_at - Duration::from_secs(5));
+        history.add_at(text("a"), pasted_at + Duration::from_secs(1));
+        assert!(!history.get_meta()[0].is_new_since(pasted_at));
+
+        // Only copied by the paste
+        history.add_at(text("b"), pasted_at + Duration::from_secs(1));
+        assert!(history.get_meta()[0].is_new_since(pasted_at));
+
+        // Copied once, long before
+        history.add_at(text("c"), pasted_at - Duration::from_secs(1));
+        assert!(!history.get_meta()[0].is_new_since(pasted_at));
+    }
+
+    #[test]
+    fn test_entry_to_json() {
+        let meta = EntryMeta::new(SystemTime::UNIX_EPOCH + Duration::from_millis(1500));
+
+        assert_eq!(
+            entry_to_json(&text("Say \"hi\"\n\tC:\\ \u{7}"), &meta),
+            r#"{"kind":"text","text":"Say \"hi\"\n\tC:\\ \u0007","copied_at":1500,"copy_count":1,"selection":"clipboard"}"#
+        );
+        assert_eq!(
+            entry_to_json(
+                &ClipboardItem::Image {
+                    width: 2,
+                    height: 3,
+                    bytes: vec![0; 24],
+                },
+                &meta
+            ),
+            r#"{"kind":"image","width":2,"height":3,"copied_at":1500,"copy_count":1,"selection":"clipboard"}"#
+        );
+    }
+
+    fn text(s: &str) -> ClipboardItem {
+        ClipboardItem::Text(s.to_string())
+    }
+
+    #[test]
+    fn test_undo_delete_restores_position() {
+        let mut history = ClipboardHistory::new(5);
+        history.add(text("1"));
+        history.add(text("2"));
+        history.add(text("3"));
+        let before = history.get_meta().clone();
+
+        // 3,2,1 -> 3,1 -> 3,2,1
+        history.delete(1).unwrap();
+        history.undo().unwrap();
+        assert_eq!(
+            history.get_items(),
+            &VecDeque::from([text("3"), text("2"), text("1")])
+        );
+        assert_eq!(history.get_meta(), &before);
+
+        assert_eq!(history.undo(), Err(ClipboardError::NothingToUndo));
+    }
+
+    #[test]
+    fn test_undo_clear_and_range_in_reverse_order() {
+        let mut history = ClipboardHistory::new(5);
+        for i in 1..=4 {
+            history.add(text(&i.to_string()));
+        }
+        let full = history.get_items().clone();
+
+        // 4,3,2,1 -> 4,3 -> []
+        history.delete_range(2, 4).unwrap();
+        history.clear();
+
+        history.undo().unwrap();
+        assert_eq!(history.get_items(), &VecDeque::from([text("4"), text("3")]));
+        history.undo().unwrap();
+        assert_eq!(history.get_items(), &full);
+    }
+
+    #[test]
+    fn test_clear_matching_is_undone_in_one_step() {
+        let image = ClipboardItem::Image {
+            width: 1,
+            height: 1,
+            bytes: vec![1, 2, 3, 4],
+        };
+
+        let mut history = ClipboardHistory::new(5);
+        history.add(text("1"));
+        history.add(image.clone());
+        history.add(text("2"));
+        history.add(text("3"));
+        let full = history.clone();
+
+        // 3,2,image,1 -> image
+        assert_eq!(history.clear_matching(ClipboardFilter::Text), 3);
+        assert_eq!(history.get_items(), &VecDeque::from([image]));
+        assert_eq!(history.get_meta().len(), 1);
+
+        // Every entry back where it was
+        history.undo().unwrap();
+        assert_eq!(history.get_items(), full.get_items());
+        assert_eq!(history.get_meta(), full.get_meta());
+        assert_eq!(history.undo(), Err(ClipboardError::NothingToUndo));
+
+        // Nothing listed, nothing to undo
+        assert_eq!(history.clear_matching(ClipboardFilter::Images), 1);
+        assert_eq!(history.clear_matching(ClipboardFilter::Images), 0);
+        history.undo().unwrap();
+        assert_eq!(history.get_items(), full.get_items());
+    }
+
+    #[test]
+    fn test_undo_forgotten_after_add_or_promote() {
+        let mut history = ClipboardHistory::new(5);
+        history.add(text("1"));
+        history.add(text("2"));
+        history.add(text("3"));
+
+        history.delete(0).unwrap();
+        history.add(text("4"));
+        assert_eq!(history.undo(), Err(ClipboardError::NothingToUndo));
+
+        // A no-op promote keeps it, a real one doesn't
+        history.delete(0).unwrap();
+        history.promote(0).unwrap();
+        history.undo().unwrap();
+        history.delete(0).unwrap();
+        history.promote(1).unwrap();
+        assert_eq!(history.undo(), Err(ClipboardError::NothingToUndo));
+    }
+
+    #[test]
+    fn test_undo_is_bounded() {
+        let mut history = ClipboardHistory::new(UNDO_LIMIT + 5);
+        for i in 0..UNDO_LIMIT + 2 {
+            history.add(text(&i.to_string()));
+        }
+        for _ in 0..UNDO_LIMIT + 2 {
+            history.delete(0).unwrap();
+        }
+
+        for _ in 0..UNDO_LIMIT {
+            history.undo().unwrap();
+        }
+        assert_eq!(history.undo(), Err(ClipboardError::NothingToUndo));
+        assert_eq!(history.get_items().len(), UNDO_LIMIT);
+    }
+
+    #[test]
+    fn test_undo_not_sent_over_ipc() {
+        let mut history = ClipboardHistory::new(5);
+        history.add(text("1"));
+        history.clear();
+
+        let bytes = rmp_serde::to_vec(&history).unwrap();
+        let mut received: ClipboardHistory = rmp_serde::from_slice(&bytes).unwrap();
+        assert_eq!(received.undo(), Err(ClipboardError::NothingToUndo));
+    }
+
+    #[test]
+    fn test_range_pages_through_history() {
+        let mut history = ClipboardHistory::new(10);
+        for n in 1..=5 {
+            history.add(text(&n.to_string()));
+        }
+
+        let page = history.range(1, 2);
+        assert_eq!(page.get_items(), &VecDeque::from([text("4"), text("3")]));
+        assert_eq!(page.get_meta().len(), 2);
+
+        // Cut off at the end of the history
+        assert_eq!(history.range(3, 10).get_items().len(), 2);
+        assert!(history.range(7, 2).get_items().is_empty());
+
+        let (page, total) = history.snapshot_range(0, 2);
+        assert_eq!(page.get_items(), &VecDeque::from([text("5"), text("4")]));
+        assert_eq!(total, 5);
+    }
+
+    #[test]
+    fn test_query_filters_and_sorts_whole_history() {
+        let image = ClipboardItem::Image {
+            width: 1,
+            height: 1,
+            bytes: vec![1, 2, 3, 4],
+        };
+
+        let mut history = ClipboardHistory::new(10);
+        history.add(text("old"));
+        history.add(text("old"));
+        history.add(image.clone());
+        for n in 1..=3 {
+            history.add(text(&n.to_string()));
+        }
+        // 3,2,1,image,old(x2)
+
+        // Past the first page too, and with positions in the whole history
+        let (page, positions, total) = history.query(0, 2, ClipboardFilter::All, true);
+        assert_eq!(page.get_items(), &VecDeque::from([text("old"), text("3")]));
+        assert_eq!(positions, vec![4, 0]);
+        assert_eq!(total, 5);
+
+        let (page, positions, total) = history.query(1, 10, ClipboardFilter::Text, false);
+        assert_eq!(
+            page.get_items(),
+            &VecDeque::from([text("2"), text("1"), text("old")])
+        );
+        assert_eq!(positions, vec![1, 2, 4]);
+        assert_eq!(total, 4);
+
+        let (page, positions, total) = history.query(0, 10, ClipboardFilter::Images, false);
+        assert_eq!(page.get_items(), &VecDeque::from([image]));
+        assert_eq!(positions, vec![3]);
+        assert_eq!(total, 1);
+    }
+
+    #[test]
+    fn test_search_matches_text_and_ocr() {
+        let image = ClipboardItem::Image {
+            width: 1,
+            height: 1,
+            bytes: vec![1, 2, 3, 4],
+        };
+
+        let mut history = ClipboardHistory::new(10);
+        history.add(image.clone());
+        history
+            .set_ocr_text(&image, "Invoice total".into())
+            .unwrap();
+        history.add(text("invoice.pdf"));
+        history.add(text("password"));
+        history.add(text("INVOICE draft"));
+        history.mark_sensitive(1).unwrap();
+        // INVOICE draft, password (sensitive), invoice.pdf, image
+
+        // ASCII case is ignored, and the image is found by its OCR text
+        let (page, positions, total) = history.search("invoice", 0, 2);
+        assert_eq!(
+            page.get_items(),
+            &VecDeque::from([text("INVOICE draft"), text("invoice.pdf")])
+        );
+        assert_eq!(positions, vec![0, 2]);
+        assert_eq!(total, 3);
+
+        let (page, positions, _) = history.search("invoice", 2, 10);
+        assert_eq!(page.get_items(), &VecDeque::from([image]));
+        assert_eq!(positions, vec![3]);
+
+        // Sensitive entries never match, an empty search lists everything
+        assert_eq!(history.search("pass", 0, 10).2, 0);
+        assert_eq!(history.search("", 0, 10).2, 4);
+    }
+
+    #[test]
+    fn test_dedup_scope_keeps_images_apart() {
+        let image = ClipboardItem::Image {
+            width: 1,
+            height: 1,
+            bytes: vec![1, 2, 3, 4],
+        };
+
+        let mut history = ClipboardHistory::new(10).with_dedup_scope(DedupScope::Text);
+        history.add(image.clone());
+        history.add(text("a"));
+        history.add(image.clone());
+        history.add(text("a"));
+
+        // Byte-identical images stay two entries, the text is merged
+        assert_eq!(
+            history.get_items(),
+            &VecDeque::from([text("a"), image.clone(), image.clone()])
+        );
+        assert_eq!(history.get_meta()[0].copy_count, 2);
+        assert_eq!(history.get_meta()[1].copy_count, 1);
+
+        let mut history = ClipboardHistory::new(10).with_dedup_scope(DedupScope::Images);
+        history.add(text("a"));
+        history.add(image.clone());
+        history.add(text("a"));
+        history.add(image.clone());
+        assert_eq!(
+            history.get_items(),
+            &VecDeque::from([image.clone(), text("a"), text("a")])
+        );
+
+        let mut history = ClipboardHistory::new(10).with_dedup_scope(DedupScope::Off);
+        history.add(text("a"));
+        history.add(text("a"));
+        assert_eq!(history.get_items().len(), 2);
+
+        // The scope stays with the daemon's copy of the history
+        let mut page = history.range(0, 10);
+        page.add(text("a"));
+        assert_eq!(page.get_items().len(), 3);
+    }
+
+    #[test]
+    fn test_import_merge_and_replace() {
+        let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        let (a, b, c) = (
+            ClipboardItem::Text("a".into()),
+            ClipboardItem::Text("b".into()),
+            ClipboardItem::Text("c".into()),
+        );
+        let mut history = ClipboardHistory::new(3);
+        history.add_at(a.clone(), at(10));
+        history.add_at(b.clone(), at(20));
+
+        // Most recent first, like a backup. a is merged, the blank one skipped
+        let mut backup_a = EntryMeta::new(at(5));
+        backup_a.copy_count = 2;
+        backup_a.html = Some("<i>a</i>".into());
+        let entries = vec![
+            (c.clone(), EntryMeta::new(at(30))),
+            (ClipboardItem::Text("  ".into()), EntryMeta::new(at(30))),
+            (a.clone(), backup_a),
+        ];
+        assert_eq!(history.import(entries.clone(), false), 2);
+
+        assert_eq!(
+            history.get_items(),
+            &VecDeque::from([c.clone(), a.clone(), b])
+        );
+        let merged = &history.get_meta()[1];
+        assert_eq!(merged.copy_count, 3);
+        assert_eq!(merged.copied_at, at(10));
+        assert_eq!(merged.html.as_deref(), Some("<i>a</i>"));
+
+        // Replacing keeps only the backup, and can't be undone
+        history.import(entries, true);
+        assert_eq!(history.get_items(), &VecDeque::from([c, a]));
+        assert_eq!(history.get_meta()[1].copy_count, 2);
+        assert_eq!(history.undo(), Err(ClipboardError::NothingToUndo));
+    }
+
+    #[test]
+    fn test_import_respects_capacity() {
+        let mut history = ClipboardHistory::new(2);
+        history.add(ClipboardItem::Text("old".into()));
+
+        let now = SystemTime::now();
+        let entries = (0..3)
+            .map(|i| (ClipboardItem::Text(i.to_string()), EntryMeta::new(now)))
+            .collect();
+        assert_eq!(history.import(entries, false), 3);
+
+        // The oldest entries went, imported or not
+        assert_eq!(
+            history.get_items(),
+            &VecDeque::from([
+                ClipboardItem::Text("0".into()),
+                ClipboardItem::Text("1".into())
+            ])
+        );
+    }
+
+    #[test]
+    fn test_sensitive_entries() {
+        let (a, b) = (
+            ClipboardItem::Text("a".into()),
+            ClipboardItem::Text("b".into()),
+        );
+        let mut history = ClipboardHistory::new(5);
+        history.add(a.clone());
+        history.add(b.clone());
+
+        assert_eq!(
+            history.mark_sensitive(2),
+            Err(ClipboardError::IndexOutOfBound)
+        );
+        history.mark_sensitive(1).unwrap();
+
+        // Copying it again keeps the mark
+        history.add(a.clone());
+        assert!(history.get_meta()[0].sensitive);
+        assert!(!history.get_meta()[1].sensitive);
+
+        assert_eq!(history.drop_sensitive(), 1);
+        assert_eq!(history.get_items(), &VecDeque::from([b]));
+        assert_eq!(history.drop_sensitive(), 0);
     }
 }
@@ -0,0 +1,93 @@
+#[cfg(test)]
+mod image_codec_tests {
+    use std::borrow::Cow;
+
+    use super_v::{
+        common::ClipboardItem,
+        image_codec::{decode_png, encode_png},
+    };
+
+    // Flat colour with a stripe, like a UI screenshot
+    fn screenshot(width: usize, height: usize) -> Vec<u8> {
+        let mut rgba = vec![0xEEu8; width * height * 4];
+        for x in 0..width {
+            rgba[x * 4..x * 4 + 4].copy_from_slice(&[0x20, 0x40, 0x80, 0xFF]);
+        }
+        rgba
+    }
+
+    #[test]
+    fn test_png_round_trip() {
+        let rgba = screenshot(64, 32);
+        let png = encode_png(64, 32, &rgba).unwrap();
+
+        assert!(png.len() < rgba.len() / 10);
+        assert_eq!(decode_png(&png), Some((64, 32, rgba)));
+    }
+
+    #[test]
+    fn test_png_rejects_bad_input() {
+        // Too few bytes for the dimensions
+        assert_eq!(encode_png(2, 2, &[0; 15]), None);
+        assert_eq!(encode_png(0, 0, &[]), None);
+
+        // Not a PNG
+        assert_eq!(decode_png(b"not a png"), None);
+    }
+
+    #[test]
+    fn test_compress_and_pixels() {
+        let rgba = screenshot(16, 16);
+        let image = ClipboardItem::Image {
+            width: 16,
+            height: 16,
+            bytes: rgba.clone(),
+        };
+        assert_eq!(image.pixels(), Some(Cow::Borrowed(rgba.as_slice())));
+
+        let compressed = image.compress();
+        assert!(matches!(
+            compressed,
+            ClipboardItem::CompressedImage {
+                width: 16,
+                height: 16,
+                ..
+            }
+        ));
+        assert!(compressed.is_image());
+        assert_eq!(compressed.pixels().as_deref(), Some(rgba.as_slice()));
+
+        // Same pixels compress to the same item, so duplicates are still detected
+        let again = ClipboardItem::Image {
+            width: 16,
+            height: 16,
+            bytes: rgba,
+        }
+        .compress();
+        assert_eq!(compressed, again);
+
+        // Tiny images would grow, so they stay raw
+        let tiny = ClipboardItem::Image {
+            width: 1,
+            height: 1,
+            bytes: vec![1, 2, 3, 4],
+        };
+        assert_eq!(tiny.clone().compress(), tiny);
+
+        // Text is left alone
+        let text = ClipboardItem::Text("hi".into());
+        assert_eq!(text.clone().compress(), text);
+        assert_eq!(text.pixels(), None);
+    }
+
+    #[test]
+    fn test_pixels_rejects_mismatched_size() {
+        let png = encode_png(2, 2, &[0; 16]).unwrap();
+        let lying = ClipboardItem::CompressedImage {
+            width: 4,
+            height: 4,
+            png,
+        };
+        assert_eq!(lying.pixels(), None);
+    }
+}
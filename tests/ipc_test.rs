@@ -1,29 +1,94 @@
+mod harness;
+
 #[cfg(test)]
 mod ipc_tests {
-    use std::fs::remove_file;
+    use std::{
+        fs::{metadata, remove_file},
+        io::Write,
+        os::unix::{
+            fs::{MetadataExt, PermissionsExt},
+            net::UnixStream,
+        },
+        path::PathBuf,
+    };
 
-    use serial_test::serial;
+    use crate::harness::unique_profile;
     use super_v::{
-        common::{IPCServerError, SOCKET_PATH},
-        services::clipboard_ipc_server::{create_bind, create_default_stream},
+        common::{
+            ClipboardItem, DEFAULT_PROFILE, ENTRY_OVERHEAD_BYTES, IPCServerError,
+            PAYLOAD_OVERHEAD_BYTES, lock_path, max_request_bytes, socket_path,
+        },
+        history::ClipboardHistory,
+        services::clipboard_ipc_server::{
+            CmdIPC, IPCRequest, IPCResponse, IpcErrorCode, Payload, SOCKET_MODE, create_bind,
+            create_stream, read_payload, send_payload, try_read_payload, try_read_payload_within,
+        },
     };
 
     #[test]
-    #[serial]
     fn test_create_bind_success() {
         // Create a new listener
-        let listener = create_bind();
+        let profile = unique_profile();
+        let listener = create_bind(&profile);
         assert!(listener.is_ok(), "Failed to create and bind listener");
+
+        let _ = remove_file(socket_path(&profile));
+    }
+
+    #[test]
+    fn test_create_bind_owner_only_permissions() {
+        // Create a new listener
+        let profile = unique_profile();
+        let _listener = create_bind(&profile).unwrap();
+
+        // Socket should only be accessible by the owner
+        let mode = metadata(socket_path(&profile))
+            .unwrap()
+            .permissions()
+            .mode()
+            & 0o777;
+        let _ = remove_file(socket_path(&profile));
+        assert_eq!(mode, SOCKET_MODE, "Socket mode was {:o}", mode);
+    }
+
+    #[test]
+    fn test_profiles_bind_side_by_side() {
+        // Different profiles use different sockets
+        assert_ne!(socket_path(DEFAULT_PROFILE), socket_path("work"));
+
+        let (home, work) = (unique_profile(), unique_profile());
+        let _home_listener = create_bind(&home).unwrap();
+        let work_listener = create_bind(&work);
+        let _ = remove_file(socket_path(&home));
+        let _ = remove_file(socket_path(&work));
+        assert!(work_listener.is_ok(), "Profiles should not collide");
+    }
+
+    #[test]
+    fn test_socket_path_is_per_user() {
+        let path = socket_path(DEFAULT_PROFILE);
+
+        match std::env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+            Some(dir) => assert!(path.starts_with(dir)),
+            None => {
+                let uid = metadata("/proc/self").unwrap().uid();
+                assert_eq!(path, PathBuf::from(format!("/tmp/super_v-{uid}.sock")));
+            }
+        }
+
+        // Lock sits next to the socket
+        assert_eq!(lock_path(DEFAULT_PROFILE).parent(), path.parent());
     }
 
     #[test]
-    #[serial]
     fn test_create_bind_already_running() {
         // Create first listener
-        let _listener1 = create_bind().unwrap();
+        let profile = unique_profile();
+        let _listener1 = create_bind(&profile).unwrap();
 
         // Try to create second listener - should fail
-        let listener2 = create_bind();
+        let listener2 = create_bind(&profile);
+        let _ = remove_file(socket_path(&profile));
         match listener2 {
             Ok(_) => {
                 panic!("Server should not be created. Two instances running!");
@@ -38,11 +103,8 @@ mod ipc_tests {
     }
 
     #[test]
-    #[serial]
     fn test_stream_connect_no_file() {
-        let _ = remove_file(SOCKET_PATH);
-
-        let stream = create_default_stream();
+        let stream = create_stream(&unique_profile());
 
         match stream {
             Ok(_) => {
@@ -57,15 +119,16 @@ mod ipc_tests {
     }
 
     #[test]
-    #[serial]
     fn test_stream_connect_server_not_running() {
         // Create listener out of scope
+        let profile = unique_profile();
         {
-            let _ = create_bind();
+            let _ = create_bind(&profile);
         }
         // Now the server should be stopped
 
-        let stream = create_default_stream();
+        let stream = create_stream(&profile);
+        let _ = remove_file(socket_path(&profile));
 
         match stream {
             Ok(_) => {
@@ -80,6 +143,127 @@ mod ipc_tests {
         }
     }
 
-    // Sending and reading payload should already be tested via the Manager tests,
-    // So no need for that here...
+    #[test]
+    fn test_request_payload_round_trip() {
+        // Connected pair, no daemon needed
+        let (mut client, mut server) = UnixStream::pair().unwrap();
+
+        let item = ClipboardItem::Image {
+            width: 1,
+            height: 1,
+            bytes: vec![1, 2, 3, 4],
+        };
+        send_payload(
+            &mut client,
+            Payload::Request(IPCRequest {
+                cmd: CmdIPC::DeleteThis(item.clone()),
+            }),
+        );
+
+        match read_payload(&mut server) {
+            Payload::Request(IPCRequest {
+                cmd: CmdIPC::DeleteThis(received),
+            }) => assert_eq!(received, item),
+            other => panic!("Unexpected payload: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_response_payload_round_trip() {
+        // Connected pair, no daemon needed
+        let (mut client, mut server) = UnixStream::pair().unwrap();
+
+        let mut history = ClipboardHistory::new(5);
+        history.add(ClipboardItem::Text("Item".into()));
+        send_payload(
+            &mut server,
+            Payload::Response(IPCResponse {
+                history_snapshot: Some(history.clone()),
+                message: Some("Message".into()),
+                error: Some(IpcErrorCode::ItemNotFound),
+                total: Some(1),
+                index: Some(0),
+                positions: Some(vec![3]),
+                count: Some(2),
+            }),
+        );
+
+        match read_payload(&mut client) {
+            Payload::Response(response) => {
+                assert_eq!(response.history_snapshot, Some(history));
+                assert_eq!(response.message, Some("Message".into()));
+                assert_eq!(response.error, Some(IpcErrorCode::ItemNotFound));
+                assert_eq!(response.total, Some(1));
+                assert_eq!(response.positions, Some(vec![3]));
+                assert_eq!(response.count, Some(2));
+            }
+            other => panic!("Unexpected payload: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_oversized_length_prefix_rejected() {
+        // Connected pair, no daemon needed
+        let (mut client, mut server) = UnixStream::pair().unwrap();
+
+        // Claim a 4GB payload and hang up without sending it, the rest is read and
+        // thrown away until then
+        client.write_all(&[0xFF; 4]).unwrap();
+        drop(client);
+
+        let limit = max_request_bytes(1000, 3000, 1);
+        match try_read_payload_within(&mut server, limit) {
+            Err(err) => assert_eq!(
+                err,
+                IPCServerError::PayloadTooLarge {
+                    len: 0xFFFF_FFFF,
+                    limit
+                }
+            ),
+            Ok(payload) => panic!("Oversized payload was accepted: {:?}", payload),
+        }
+    }
+
+    #[test]
+    fn test_max_request_bytes() {
+        // An image as base64, or a text and its HTML escaped, whichever is bigger
+        let entry = 4000 + ENTRY_OVERHEAD_BYTES;
+        assert_eq!(
+            max_request_bytes(100, 3000, 1),
+            entry + PAYLOAD_OVERHEAD_BYTES
+        );
+        let entry = 12000 + ENTRY_OVERHEAD_BYTES;
+        assert_eq!(
+            max_request_bytes(1000, 3000, 1),
+            entry + PAYLOAD_OVERHEAD_BYTES
+        );
+
+        // Room for importing a full history, and for one item without history
+        assert_eq!(
+            max_request_bytes(1000, 3000, 25),
+            25 * entry + PAYLOAD_OVERHEAD_BYTES
+        );
+        assert_eq!(
+            max_request_bytes(1000, 3000, 0),
+            max_request_bytes(1000, 3000, 1)
+        );
+        assert_eq!(max_request_bytes(usize::MAX, 0, 25), usize::MAX);
+    }
+
+    #[test]
+    fn test_truncated_payload_rejected() {
+        let (mut client, mut server) = UnixStream::pair().unwrap();
+
+        // Claim 100 bytes, send 3 and hang up
+        client.write_all(&100u32.to_be_bytes()).unwrap();
+        client.write_all(&[1, 2, 3]).unwrap();
+        drop(client);
+
+        assert!(matches!(
+            try_read_payload(&mut server),
+            Err(IPCServerError::RecieveError(_))
+        ));
+    }
+
+    // Sending and reading payload against a running daemon is tested via the Manager tests.
 }
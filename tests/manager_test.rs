@@ -1,15 +1,24 @@
+mod harness;
+
 #[cfg(test)]
 mod clipboard_manager_test {
-    use arboard::{Clipboard, ImageData};
+    use crate::harness::{TestDaemon, unique_profile};
     use core::panic;
-    use serial_test::serial;
-    use std::{borrow::Cow, sync::atomic::Ordering, thread, time::Duration};
+    use std::{
+        collections::VecDeque,
+        sync::{
+            Arc,
+            atomic::{AtomicBool, Ordering},
+        },
+        thread,
+        time::Duration,
+    };
     use super_v::{
-        common::{ClipboardItem, DaemonError},
+        common::{ClientError, ClipboardItem, DaemonError, DedupScope, MockBackend, lock_path},
+        history::ClipboardHistory,
         services::{
             clipboard_ipc_server::{
-                CmdIPC, IPCRequest, IPCResponse, Payload, create_default_stream, read_payload,
-                send_payload,
+                CmdIPC, IPCRequest, IPCResponse, IpcErrorCode, Payload, read_payload, send_payload,
             },
             clipboard_manager::Manager,
         },
@@ -29,46 +38,27 @@ mod clipboard_manager_test {
     }
 
     fn beam_payload(payload: Payload) -> Payload {
-        // Create manager and start services
-        let mut manager = Manager::new().unwrap();
-        manager._polling_service();
-        manager._command_service();
-
-        // Create a new clipboard instance and update it with items
-        // Since Clipboard is synced across device, updating it here should update the manager as well
-        let mut clipboard_service = Clipboard::new().unwrap();
-        thread::sleep(Duration::from_millis(250));
-
-        // Update the clipboard history to have some things...
-        let _ = clipboard_service.set_image(ImageData {
-            width: 1,
-            height: 1,
-            bytes: Cow::from([0u8; 4].as_ref()),
-        });
-        thread::sleep(Duration::from_millis(250));
-
-        let _ = clipboard_service.set_text("item3");
-        thread::sleep(Duration::from_millis(250));
-
-        let _ = clipboard_service.set_text("item2");
-        thread::sleep(Duration::from_millis(250));
-
-        let _ = clipboard_service.set_text("item1");
-        thread::sleep(Duration::from_millis(250));
-
-        // Create a new default stream
-        let mut stream = create_default_stream().unwrap();
+        // A daemon holding the hopeful history, added oldest first
+        let daemon = TestDaemon::start();
+        let client = daemon.client();
+        for item in get_hopeful_history().into_iter().rev() {
+            client.add(item).unwrap();
+        }
 
-        // Sending the response as input should fail
+        let mut stream = daemon.stream();
         send_payload(&mut stream, payload);
 
-        let recieved_payload = read_payload(&mut stream);
-
-        // Cleanup
-        manager.stop();
+        // The daemon is stopped when dropped
+        read_payload(&mut stream)
+    }
 
-        // Return the recieved payload
-        recieved_payload
+    /// A manager for `profile` on a mock clipboard, with no services started.
+    fn mock_manager(profile: &str) -> Result<Manager, DaemonError> {
+        Manager::with_backend(
+            Box::new(ClipboardHistory::new(5)),
+            profile,
+            Box::new(MockBackend::default()),
+        )
     }
 
     fn check_payload_message(payload: Payload, checker: &str) {
@@ -79,6 +69,14 @@ mod clipboard_manager_test {
         }
     }
 
+    fn check_payload_error(payload: Payload, checker: IpcErrorCode) {
+        if let Payload::Response(returned_response) = payload {
+            assert_eq!(returned_response.error, Some(checker));
+        } else {
+            panic!("Returned payload type was not correct?");
+        }
+    }
+
     fn check_payload_history(payload: Payload, checker: Vec<ClipboardItem>) {
         if let Payload::Response(returned_response) = payload {
             match returned_response.history_snapshot {
@@ -96,25 +94,21 @@ mod clipboard_manager_test {
     // ----------------------------------------------------------
 
     #[test]
-    #[serial]
     fn test_poller_stops_on_signal() {
-        // Create new manager
-        let mut manager = Manager::new().unwrap();
-
-        // start polling
-        manager._polling_service();
+        // Create new daemon and start polling
+        let mut daemon = TestDaemon::polling(Box::new(ClipboardHistory::new(5)));
 
         // Give time
         thread::sleep(Duration::from_millis(50));
 
         // Send stop signal
-        manager._stop_signal.store(true, Ordering::SeqCst);
+        daemon.manager()._stop_signal.store(true, Ordering::SeqCst);
 
         // Give time for the poller to check the signal and exit
         thread::sleep(Duration::from_millis(200));
 
         // Take the handle and match
-        match manager._polling_handle.take() {
+        match daemon.manager_mut()._polling_handle.take() {
             Some(p_handle) => {
                 // Check if it's still running. It should not be.
                 assert!(
@@ -129,19 +123,46 @@ mod clipboard_manager_test {
                 panic!("POLLING HANDLE EMPTY WHEN IT SHOULD NOT HAVE BEEN!");
             }
         }
+    }
 
-        // Close the manager
-        manager.stop();
+    #[test]
+    fn test_command_service_stops_on_ipc_stop() {
+        let mut daemon = TestDaemon::start();
+        thread::sleep(Duration::from_millis(50));
+
+        // Ask the daemon to stop over IPC, like `super_v stop` does
+        let mut stream = daemon.stream();
+        send_payload(
+            &mut stream,
+            Payload::Request(IPCRequest { cmd: CmdIPC::Stop }),
+        );
+        let _ = read_payload(&mut stream);
+
+        // No other client connects, the loop has to notice on its own
+        thread::sleep(Duration::from_millis(200));
+
+        match daemon.manager_mut()._command_handle.take() {
+            Some(c_handle) => {
+                assert!(
+                    c_handle.is_finished(),
+                    "Uh-oh! Command service still running after Stop"
+                );
+                let _ = c_handle.join();
+            }
+            None => {
+                panic!("COMMAND HANDLE EMPTY WHEN IT SHOULD NOT HAVE BEEN!");
+            }
+        }
     }
 
     #[test]
-    #[serial]
     fn test_manager_multi_spawn() {
         // Spawn a manager
-        let manager = Manager::new();
+        let profile = unique_profile();
+        let manager = mock_manager(&profile);
 
-        // Spawn another manager
-        let err_manager = Manager::new();
+        // Spawn another manager on the same profile
+        let err_manager = mock_manager(&profile);
 
         // Check if
         match err_manager {
@@ -149,7 +170,11 @@ mod clipboard_manager_test {
                 panic!("MANAGER SHOULD NOT HAVE BEEN STARTED. MULTIPLE MANAGERS SPAWNED!")
             }
             Err(err) => {
-                assert_eq!(err, DaemonError::ManagerMultiSpawn);
+                // Names the running instance
+                assert!(matches!(
+                    err,
+                    DaemonError::ManagerMultiSpawn { pid: Some(pid) } if pid == std::process::id()
+                ));
             }
         }
 
@@ -158,17 +183,30 @@ mod clipboard_manager_test {
     }
 
     #[test]
-    #[serial]
+    fn test_lock_file_open_error() {
+        // A directory where the lock file should be can't be opened as a file
+        let profile = unique_profile();
+        let path = lock_path(&profile);
+        let _ = std::fs::create_dir(&path);
+
+        let result = mock_manager(&profile);
+        let _ = std::fs::remove_dir(&path);
+
+        assert!(matches!(result, Err(DaemonError::LockFileOpen(_))));
+    }
+
+    #[test]
     fn test_manager_unlock() {
         // Spawn a manager
-        let mut manager: Manager = Manager::new().unwrap();
+        let profile = unique_profile();
+        let mut manager: Manager = mock_manager(&profile).unwrap();
 
         // close the manager
         manager.stop();
 
         // Spawn a second manager
-        match Manager::new() {
-            Ok(_) => { /* Passed */ }
+        match mock_manager(&profile) {
+            Ok(mut manager) => manager.stop(),
             Err(_) => {
                 panic!("MANAGER DID NOT SPAWN! PREVIOUS MANAGER NOT CLEANED!")
             }
@@ -176,7 +214,6 @@ mod clipboard_manager_test {
     }
 
     #[test]
-    #[serial]
     fn test_poller_clipboard_history_and_snapshot() {
         let recieved_payload = beam_payload(Payload::Request(IPCRequest {
             cmd: CmdIPC::Snapshot,
@@ -185,45 +222,29 @@ mod clipboard_manager_test {
     }
 
     #[test]
-    #[serial]
     fn test_invalid_ipc_command() {
-        let recieved_payload = beam_payload(Payload::Response(IPCResponse {
-            history_snapshot: None,
-            message: None,
-        }));
+        let recieved_payload = beam_payload(Payload::Response(IPCResponse::default()));
 
-        check_payload_message(
-            recieved_payload,
-            "Wrong Payload type recieved. Expected CmdIpc but got IPCResponse.",
-        );
+        check_payload_error(recieved_payload, IpcErrorCode::WrongPayload);
     }
 
     #[test]
-    #[serial]
     fn test_promote_out_of_bound() {
         let recieved_payload = beam_payload(Payload::Request(IPCRequest {
             cmd: CmdIPC::Promote(100), // <- 100 should exceed 0... cuz history empty...
         }));
-        check_payload_message(
-            recieved_payload,
-            "Could not promote item. Index out of bounds.",
-        );
+        check_payload_error(recieved_payload, IpcErrorCode::IndexOutOfBounds);
     }
 
     #[test]
-    #[serial]
     fn test_delete_out_of_bound() {
         let recieved_payload = beam_payload(Payload::Request(IPCRequest {
             cmd: CmdIPC::Delete(100), // <- 100 should exceed 0... cuz history empty...
         }));
-        check_payload_message(
-            recieved_payload,
-            "Could not delete item. Index out of bounds.",
-        );
+        check_payload_error(recieved_payload, IpcErrorCode::IndexOutOfBounds);
     }
 
     #[test]
-    #[serial]
     fn test_promote_command() {
         let recieved_payload = beam_payload(Payload::Request(IPCRequest {
             cmd: CmdIPC::Promote(1), // 1,2,3,i -> 2,1,3,i
@@ -236,7 +257,28 @@ mod clipboard_manager_test {
     }
 
     #[test]
-    #[serial]
+    fn test_add_command() {
+        let snippet = ClipboardItem::Text("snippet".into());
+        let recieved_payload = beam_payload(Payload::Request(IPCRequest {
+            cmd: CmdIPC::Add(snippet.clone()), // 1,2,3,i -> s,1,2,3,i
+        }));
+
+        let mut hopeful_history = get_hopeful_history();
+        hopeful_history.insert(0, snippet);
+
+        check_payload_history(recieved_payload, hopeful_history);
+    }
+
+    #[test]
+    fn test_add_blank_text_ignored() {
+        let recieved_payload = beam_payload(Payload::Request(IPCRequest {
+            cmd: CmdIPC::Add(ClipboardItem::Text(" \n\t".into())),
+        }));
+
+        check_payload_history(recieved_payload, get_hopeful_history());
+    }
+
+    #[test]
     fn test_delete_command() {
         let recieved_payload = beam_payload(Payload::Request(IPCRequest {
             cmd: CmdIPC::Delete(0), // 1,2,3,i -> 2,3,i
@@ -249,7 +291,415 @@ mod clipboard_manager_test {
     }
 
     #[test]
-    #[serial]
+    fn test_edits_report_affected_item() {
+        let recieved_payload = beam_payload(Payload::Request(IPCRequest {
+            cmd: CmdIPC::Promote(1),
+        }));
+        check_payload_message(recieved_payload, "Promoted: item2");
+
+        let recieved_payload = beam_payload(Payload::Request(IPCRequest {
+            cmd: CmdIPC::Delete(3),
+        }));
+        check_payload_message(recieved_payload, "Deleted: Image 1x1");
+    }
+
+    #[test]
+    fn test_poisoned_history_recovers() {
+        let daemon = TestDaemon::start();
+
+        // Panic while holding the history lock
+        let history = daemon.manager()._shared_history.clone();
+        let _ = thread::spawn(move || {
+            let _guard = history.lock().unwrap();
+            panic!("poison the history");
+        })
+        .join();
+        assert!(daemon.manager()._shared_history.is_poisoned());
+
+        // The daemon should keep serving
+        let mut stream = daemon.stream();
+        send_payload(
+            &mut stream,
+            Payload::Request(IPCRequest {
+                cmd: CmdIPC::Snapshot,
+            }),
+        );
+        let recieved_payload = read_payload(&mut stream);
+
+        check_payload_history(recieved_payload, Vec::new());
+        assert!(!daemon.manager()._shared_history.is_poisoned());
+    }
+
+    #[test]
+    fn test_poller_captures_under_lock_contention() {
+        let daemon = TestDaemon::polling(Box::new(ClipboardHistory::new(25)));
+        thread::sleep(Duration::from_millis(250));
+
+        // Keep the history lock busy nearly all the time, like a burst of commands would
+        let history = daemon.manager()._shared_history.clone();
+        let contender_stop = Arc::new(AtomicBool::new(false));
+        let contender = {
+            let contender_stop = contender_stop.clone();
+            thread::spawn(move || {
+                while !contender_stop.load(Ordering::SeqCst) {
+                    let guard = history.lock().unwrap();
+                    thread::sleep(Duration::from_millis(30));
+                    drop(guard);
+                    thread::sleep(Duration::from_millis(1));
+                }
+            })
+        };
+
+        let items: Vec<ClipboardItem> = (0..10)
+            .map(|i| ClipboardItem::Text(format!("contended{i}")))
+            .collect();
+        for item in &items {
+            daemon.clipboard().copy(item.clone());
+            thread::sleep(Duration::from_millis(600));
+        }
+
+        contender_stop.store(true, Ordering::SeqCst);
+        contender.join().unwrap();
+        let snapshot = daemon.snapshot();
+
+        // Every value was captured, newest first
+        let expected: Vec<ClipboardItem> = items.into_iter().rev().collect();
+        assert_eq!(snapshot.get_items(), &expected);
+    }
+
+    #[test]
+    fn test_rapid_copies_keep_their_order() {
+        let mut daemon = TestDaemon::polling(Box::new(ClipboardHistory::new(25)));
+        let mock = daemon.clipboard().clone();
+
+        let history = daemon.manager()._shared_history.clone();
+        let wait_until = |done: &dyn Fn(usize) -> bool| {
+            for attempt in 0..100 {
+                if done(attempt) {
+                    return;
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+            panic!("timed out");
+        };
+
+        // Copy until something is captured, the poller is past its startup read then
+        let captured = |item: Option<ClipboardItem>| {
+            item.is_some_and(|item| history.lock().unwrap().snapshot().position(&item).is_some())
+        };
+        wait_until(&|attempt| {
+            let done = history.lock().unwrap().snapshot_range(0, 0).1 > 0;
+            if !done {
+                mock.copy(ClipboardItem::Text(format!("started{attempt}")));
+            }
+            done
+        });
+        wait_until(&|_| captured(mock.contents()));
+        let started = history.lock().unwrap().snapshot();
+
+        // Hold the history lock longer than it takes to copy the next item,
+        // so several captures queue up behind it
+        let contender_stop = Arc::new(AtomicBool::new(false));
+        let contender = {
+            let contender_stop = contender_stop.clone();
+            let history = history.clone();
+            thread::spawn(move || {
+                while !contender_stop.load(Ordering::SeqCst) {
+                    let guard = history.lock().unwrap();
+                    thread::sleep(Duration::from_millis(500));
+                    drop(guard);
+                    thread::sleep(Duration::from_millis(1));
+                }
+            })
+        };
+
+        // Every poll reads the next scripted item, however long each poll takes
+        let items: Vec<ClipboardItem> = (0..20)
+            .map(|i| ClipboardItem::Text(format!("rapid{i}")))
+            .collect();
+        mock.script(items.clone());
+        let last = items.last().cloned();
+        wait_until(&|_| mock.contents() == last);
+
+        contender_stop.store(true, Ordering::SeqCst);
+        contender.join().unwrap();
+        // Stopping stores what is still queued
+        daemon.manager_mut().stop();
+        let snapshot = history.lock().unwrap().snapshot();
+
+        // Every value was captured, newest first
+        let mut expected: Vec<ClipboardItem> = items.into_iter().rev().collect();
+        expected.extend(started.get_items().iter().cloned());
+        assert_eq!(snapshot.get_items(), &expected);
+    }
+
+    #[test]
+    fn test_mock_backend_needs_no_display() {
+        let mut daemon = TestDaemon::with_store(Box::new(ClipboardHistory::new(5)));
+        let mock = daemon.clipboard().clone();
+        mock.copy(ClipboardItem::Text("before".into()));
+
+        daemon.manager_mut()._polling_service();
+        thread::sleep(Duration::from_millis(250));
+
+        // Only the primary selection changes, which isn't tracked by default
+        mock.select(ClipboardItem::Text("selected".into()));
+        thread::sleep(Duration::from_millis(250));
+
+        let items: Vec<ClipboardItem> = get_hopeful_history().into_iter().rev().collect();
+        for item in &items {
+            mock.copy(item.clone());
+            thread::sleep(Duration::from_millis(250));
+        }
+
+        let snapshot = daemon.snapshot();
+
+        // What was on the clipboard before the start isn't captured
+        assert_eq!(snapshot.get_items(), &get_hopeful_history());
+    }
+
+    #[test]
+    fn test_expect_next_skips_paste_back() {
+        let a = ClipboardItem::Text("paste a".into());
+        let b = ClipboardItem::Text("paste b".into());
+
+        // Without dedup a paste-back would show up twice
+        let daemon = TestDaemon::polling(Box::new(
+            ClipboardHistory::new(5).with_dedup_scope(DedupScope::Off),
+        ));
+        let mock = daemon.clipboard();
+        thread::sleep(Duration::from_millis(250));
+
+        for item in [&a, &b] {
+            mock.copy(item.clone());
+            thread::sleep(Duration::from_millis(250));
+        }
+
+        // What the GUI does when a is picked
+        let client = daemon.client();
+        client.paste_back(&a, &a).unwrap();
+        mock.copy(a.clone());
+        thread::sleep(Duration::from_millis(250));
+        let hinted = daemon.snapshot();
+
+        // Unannounced, the same copy is captured again
+        mock.copy(b.clone());
+        thread::sleep(Duration::from_millis(250));
+        let unhinted = daemon.snapshot();
+
+        assert_eq!(hinted.get_items(), &VecDeque::from([a.clone(), b.clone()]));
+        assert_eq!(unhinted.get_items(), &VecDeque::from([b.clone(), a, b]));
+    }
+
+    #[test]
+    fn test_paste_back_of_missing_item_is_recorded() {
+        let kept = ClipboardItem::Text("kept".into());
+        let gone = ClipboardItem::Text("deleted elsewhere".into());
+
+        let daemon = TestDaemon::polling(Box::new(ClipboardHistory::new(5)));
+        let mock = daemon.clipboard();
+        thread::sleep(Duration::from_millis(250));
+
+        mock.copy(kept.clone());
+        thread::sleep(Duration::from_millis(250));
+
+        // Pasting a row that is no longer in history announces nothing...
+        let client = daemon.client();
+        assert!(matches!(
+            client.paste_back(&gone, &gone),
+            Err(ClientError::Rejected {
+                code: Some(IpcErrorCode::ItemNotFound),
+                ..
+            })
+        ));
+
+        // ...so the paste is captured like any other copy
+        mock.copy(gone.clone());
+        thread::sleep(Duration::from_millis(250));
+        let snapshot = daemon.snapshot();
+
+        assert_eq!(snapshot.get_items(), &VecDeque::from([gone, kept]));
+    }
+
+    #[test]
+    fn test_edit_responses_report_index() {
+        let daemon = TestDaemon::with_store(Box::new(ClipboardHistory::new(5)));
+        let client = daemon.client();
+        for item in get_hopeful_history().into_iter().rev() {
+            client.add(item).unwrap();
+        }
+
+        let moved = client.request(CmdIPC::Move { from: 0, to: 2 }).unwrap();
+        let promoted = client.request(CmdIPC::Promote(3)).unwrap();
+        let image = get_hopeful_history().pop().unwrap();
+        let promoted_this = client.request(CmdIPC::PromoteThis(image.clone())).unwrap();
+        let deleted = client.request(CmdIPC::Delete(0)).unwrap();
+
+        // The index points at the entry in the snapshot sent along
+        let item1 = ClipboardItem::Text("item1".into());
+        let position = |response: &IPCResponse, item: &ClipboardItem| {
+            response.history_snapshot.as_ref().unwrap().position(item)
+        };
+        assert_eq!(moved.index, Some(2));
+        assert_eq!(position(&moved, &item1), moved.index);
+        assert_eq!(promoted.index, Some(0));
+        assert_eq!(position(&promoted, &image), promoted.index);
+        assert_eq!(promoted_this.index, Some(0));
+        assert_eq!(position(&promoted_this, &image), promoted_this.index);
+        assert_eq!(deleted.index, None);
+    }
+
+    #[test]
+    fn test_export_import_round_trip() {
+        let daemon = TestDaemon::with_store(Box::new(ClipboardHistory::new(5)));
+        let client = daemon.client();
+        for item in get_hopeful_history().into_iter().rev() {
+            client.add(item).unwrap();
+        }
+        let before = client.snapshot().unwrap();
+        let json = client.export().unwrap();
+
+        client.clear().unwrap();
+        assert_eq!(client.import(&json, false).unwrap(), 4);
+        let restored = client.snapshot().unwrap();
+
+        // Merging the same backup again only bumps the copy counts
+        assert_eq!(client.import(&json, false).unwrap(), 4);
+        let merged = client.snapshot().unwrap();
+
+        let rejected = client.import("{\"version\":1}", true);
+        let after_rejected = client.snapshot().unwrap();
+
+        // copied_at is exported in milliseconds, so only compare the rest
+        assert_eq!(restored.get_items(), before.get_items());
+        assert!(restored.get_meta().iter().all(|meta| meta.copy_count == 1));
+        assert_eq!(merged.get_items(), before.get_items());
+        assert!(merged.get_meta().iter().all(|meta| meta.copy_count == 2));
+        assert!(matches!(
+            rejected,
+            Err(ClientError::Rejected {
+                code: Some(IpcErrorCode::InvalidImport),
+                ..
+            })
+        ));
+        assert_eq!(after_rejected, merged);
+    }
+
+    #[test]
+    fn test_tiny_capacity_snapshot_and_delete() {
+        // What the GUI does: fetch a page, delete a row by value, undo the delete
+        for capacity in [0, 1] {
+            let daemon = TestDaemon::polling(Box::new(ClipboardHistory::new(capacity)));
+            let mock = daemon.clipboard();
+            thread::sleep(Duration::from_millis(250));
+
+            let client = daemon.client();
+            let (a, b) = (
+                ClipboardItem::Text("tiny a".into()),
+                ClipboardItem::Text("tiny b".into()),
+            );
+            mock.copy(a.clone());
+            thread::sleep(Duration::from_millis(250));
+            mock.copy(b.clone());
+            thread::sleep(Duration::from_millis(250));
+
+            let (page, total) = client.snapshot_range(0, 50).unwrap();
+            let deleted = client.delete_this(b.clone());
+            let after_delete = client.snapshot().unwrap();
+            let undone = client.undo();
+            let after_undo = client.snapshot().unwrap();
+
+            assert_eq!(page.capacity(), capacity);
+            assert_eq!(total, page.len());
+            assert_eq!(after_delete.len(), 0);
+            if capacity == 0 {
+                assert!(page.is_empty());
+                assert!(deleted.is_err());
+                assert!(undone.is_err());
+                assert!(after_undo.is_empty());
+            } else {
+                assert_eq!(page.get_items().front(), Some(&b));
+                assert_eq!(page.len(), 1);
+                assert!(deleted.is_ok());
+                assert!(undone.is_ok());
+                assert_eq!(after_undo.get_items(), &[b]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_adaptive_poll_interval() {
+        let base = Duration::from_millis(100);
+        let idle = Duration::from_secs(31);
+
+        // Busy: full speed, also right after a slow stretch
+        assert_eq!(
+            Manager::adaptive_poll_interval(base, base, Duration::from_secs(5)),
+            base
+        );
+        assert_eq!(
+            Manager::adaptive_poll_interval(base, Duration::from_secs(1), Duration::ZERO),
+            base
+        );
+
+        // Idle: ramps up to a second and stays there
+        let mut interval = base;
+        let mut steps = Vec::new();
+        for _ in 0..6 {
+            interval = Manager::adaptive_poll_interval(base, interval, idle);
+            steps.push(interval.as_millis());
+        }
+        assert_eq!(steps, [200, 400, 800, 1000, 1000, 1000]);
+
+        // A configured interval past the idle one is left alone
+        let slow = Duration::from_secs(2);
+        assert_eq!(Manager::adaptive_poll_interval(slow, slow, idle), slow);
+    }
+
+    #[test]
+    fn test_stats_command() {
+        let recieved_payload = beam_payload(Payload::Request(IPCRequest { cmd: CmdIPC::Stats }));
+
+        // 3 five byte strings and a 1x1 image
+        check_payload_message(
+            recieved_payload,
+            r#"{"total":4,"text":3,"images":1,"bytes":19,"max_age_secs":null}"#,
+        );
+    }
+
+    #[test]
+    fn test_batch_command() {
+        let recieved_payload = beam_payload(Payload::Request(IPCRequest {
+            cmd: CmdIPC::Batch(vec![
+                CmdIPC::Delete(0),  // 1,2,3,i -> 2,3,i
+                CmdIPC::Promote(1), // 2,3,i -> 3,2,i
+            ]),
+        }));
+
+        let mut hopeful_history = get_hopeful_history();
+        hopeful_history.remove(0);
+        hopeful_history.swap(0, 1);
+
+        check_payload_history(recieved_payload, hopeful_history);
+    }
+
+    #[test]
+    fn test_batch_failure_reports_command() {
+        let recieved_payload = beam_payload(Payload::Request(IPCRequest {
+            cmd: CmdIPC::Batch(vec![CmdIPC::Delete(0), CmdIPC::Delete(100)]),
+        }));
+        check_payload_error(recieved_payload, IpcErrorCode::IndexOutOfBounds);
+    }
+
+    #[test]
+    fn test_nested_batch_rejected() {
+        let recieved_payload = beam_payload(Payload::Request(IPCRequest {
+            cmd: CmdIPC::Batch(vec![CmdIPC::Clear, CmdIPC::Batch(vec![])]),
+        }));
+        check_payload_error(recieved_payload, IpcErrorCode::InvalidBatch);
+    }
+
+    #[test]
     fn test_clear_command() {
         let recieved_payload = beam_payload(Payload::Request(IPCRequest {
             cmd: CmdIPC::Clear, // 1,2,3,i -> []
@@ -257,4 +707,144 @@ mod clipboard_manager_test {
 
         check_payload_history(recieved_payload, vec![]);
     }
+
+    #[test]
+    fn test_undo_command() {
+        let recieved_payload = beam_payload(Payload::Request(IPCRequest {
+            cmd: CmdIPC::Batch(vec![
+                CmdIPC::Delete(1),
+                CmdIPC::Clear,
+                CmdIPC::Undo,
+                CmdIPC::Undo,
+            ]),
+        }));
+        check_payload_history(recieved_payload, get_hopeful_history());
+
+        let recieved_payload = beam_payload(Payload::Request(IPCRequest { cmd: CmdIPC::Undo }));
+        check_payload_error(recieved_payload, IpcErrorCode::NothingToUndo);
+    }
+
+    #[test]
+    fn test_subscribe_pushes_changes() {
+        // Create daemon and start services
+        let daemon = TestDaemon::polling(Box::new(ClipboardHistory::new(5)));
+        thread::sleep(Duration::from_millis(250));
+
+        daemon
+            .clipboard()
+            .copy(ClipboardItem::Text("before".into()));
+        thread::sleep(Duration::from_millis(250));
+
+        // Subscribe. First payload is the current snapshot
+        let mut stream = daemon.stream();
+        send_payload(
+            &mut stream,
+            Payload::Request(IPCRequest {
+                cmd: CmdIPC::Subscribe,
+            }),
+        );
+        check_payload_history(
+            read_payload(&mut stream),
+            vec![ClipboardItem::Text("before".into())],
+        );
+
+        // A new copy should be pushed without asking
+        daemon.clipboard().copy(ClipboardItem::Text("after".into()));
+        check_payload_history(
+            read_payload(&mut stream),
+            vec![
+                ClipboardItem::Text("after".into()),
+                ClipboardItem::Text("before".into()),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_slow_subscriber_does_not_block_commands() {
+        let daemon = TestDaemon::with_store(Box::new(ClipboardHistory::new(50)));
+
+        // Subscribes, then never reads, so its socket buffer fills up
+        let mut slow = daemon.stream();
+        send_payload(
+            &mut slow,
+            Payload::Request(IPCRequest {
+                cmd: CmdIPC::Subscribe,
+            }),
+        );
+        let mut reader = daemon.stream();
+        send_payload(
+            &mut reader,
+            Payload::Request(IPCRequest {
+                cmd: CmdIPC::Subscribe,
+            }),
+        );
+        let _ = read_payload(&mut reader);
+
+        // Each push is about as big as the history, waiting on the slow one would take seconds
+        let client = daemon.client();
+        let started = std::time::Instant::now();
+        for n in 0..5 {
+            client
+                .add(ClipboardItem::Text(format!("{n}{}", "x".repeat(500_000))))
+                .unwrap();
+        }
+        let elapsed = started.elapsed();
+
+        // Pushes still arrive in order for a subscriber that reads
+        let mut latest = Vec::new();
+        for _ in 0..5 {
+            if let Payload::Response(IPCResponse {
+                history_snapshot: Some(history),
+                ..
+            }) = read_payload(&mut reader)
+            {
+                latest.push(history.len());
+            }
+        }
+
+        assert!(
+            elapsed < Duration::from_secs(1),
+            "commands took {elapsed:?}"
+        );
+        assert_eq!(latest, [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_subscriber_disconnect_is_dropped() {
+        // Create daemon and start services
+        let daemon = TestDaemon::polling(Box::new(ClipboardHistory::new(5)));
+        thread::sleep(Duration::from_millis(250));
+
+        // Subscribe and hang up right away
+        {
+            let mut stream = daemon.stream();
+            send_payload(
+                &mut stream,
+                Payload::Request(IPCRequest {
+                    cmd: CmdIPC::Subscribe,
+                }),
+            );
+            let _ = read_payload(&mut stream);
+        }
+
+        // Trigger a push to the dead subscriber
+        daemon
+            .clipboard()
+            .copy(ClipboardItem::Text("after disconnect".into()));
+        thread::sleep(Duration::from_millis(250));
+
+        // The subscriber is gone and the daemon still answers
+        assert!(daemon.manager()._subscribers.is_empty());
+        let mut stream = daemon.stream();
+        send_payload(
+            &mut stream,
+            Payload::Request(IPCRequest {
+                cmd: CmdIPC::Snapshot,
+            }),
+        );
+        check_payload_history(
+            read_payload(&mut stream),
+            vec![ClipboardItem::Text("after disconnect".into())],
+        );
+    }
 }
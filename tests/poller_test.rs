@@ -0,0 +1,321 @@
+#[cfg(test)]
+mod poller_tests {
+    use std::collections::VecDeque;
+
+    use super_v::{
+        common::{ClipboardError, ClipboardItem, GetItem, Selection, TextNormalization},
+        history::ClipboardHistory,
+        services::poller::Poller,
+    };
+
+    /// Stands in for the system clipboard, so no display is needed.
+    #[derive(Default)]
+    struct FakeClipboard {
+        item: Option<ClipboardItem>,
+        html: Option<String>,
+    }
+
+    impl FakeClipboard {
+        fn copy(&mut self, item: ClipboardItem) {
+            self.item = Some(item);
+            self.html = None;
+        }
+    }
+
+    impl GetItem for FakeClipboard {
+        fn get_item(&mut self) -> Result<ClipboardItem, ClipboardError> {
+            self.item.clone().ok_or(ClipboardError::ClipboardEmpty)
+        }
+
+        fn get_html(&mut self) -> Option<String> {
+            self.html.clone()
+        }
+    }
+
+    #[test]
+    fn test_poll_once_captures_new_items_once() {
+        let mut clipboard = FakeClipboard::default();
+        let mut history = ClipboardHistory::new(5);
+        let mut poller = Poller::default();
+
+        // Nothing to read yet
+        assert!(!poller.poll_once(&mut clipboard, &mut history));
+
+        let item = ClipboardItem::Text("Hello".to_string());
+        clipboard.copy(item.clone());
+        assert!(poller.poll_once(&mut clipboard, &mut history));
+        assert!(!poller.poll_once(&mut clipboard, &mut history));
+
+        // Empty text is never captured
+        clipboard.copy(ClipboardItem::Text(String::new()));
+        assert!(!poller.poll_once(&mut clipboard, &mut history));
+
+        assert_eq!(history.get_items(), &VecDeque::from([item]));
+    }
+
+    #[test]
+    fn test_ignore_current() {
+        let mut clipboard = FakeClipboard::default();
+        let mut history = ClipboardHistory::new(5);
+        let mut poller = Poller::default();
+
+        clipboard.copy(ClipboardItem::Text("Before".to_string()));
+        poller.ignore_current(&mut clipboard);
+        assert!(!poller.poll_once(&mut clipboard, &mut history));
+        assert!(history.get_items().is_empty());
+    }
+
+    #[test]
+    fn test_ignore_current_on_empty_clipboard() {
+        let mut clipboard = FakeClipboard::default();
+        let mut history = ClipboardHistory::new(5);
+        let mut poller = Poller::default();
+
+        // Nothing copied at startup, e.g. the owning app has exited
+        poller.ignore_current(&mut clipboard);
+        assert!(!poller.poll_once(&mut clipboard, &mut history));
+
+        let item = ClipboardItem::Text("First".to_string());
+        clipboard.copy(item.clone());
+        assert!(poller.poll_once(&mut clipboard, &mut history));
+        assert_eq!(history.get_items(), &VecDeque::from([item]));
+    }
+
+    #[test]
+    fn test_check_until_stored() {
+        let mut clipboard = FakeClipboard::default();
+        let mut history = ClipboardHistory::new(5);
+        let mut poller = Poller::default().with_normalize_text(TextNormalization::Trim);
+
+        clipboard.copy(ClipboardItem::Text("  Hello \n".to_string()));
+        clipboard.html = Some("<p>Hello</p>".to_string());
+
+        // Until it is stored, the same capture comes back
+        let capture = poller.check(&mut clipboard).unwrap();
+        assert_eq!(
+            poller.check(&mut clipboard).map(|again| again.stored),
+            Some(capture.stored.clone())
+        );
+        assert_eq!(capture.stored, ClipboardItem::Text("Hello".to_string()));
+
+        poller.store(capture, &mut history);
+        assert_eq!(poller.check(&mut clipboard), None);
+        assert_eq!(
+            history.get_items(),
+            &VecDeque::from([ClipboardItem::Text("Hello".to_string())])
+        );
+        assert_eq!(history.get_meta()[0].html.as_deref(), Some("<p>Hello</p>"));
+    }
+
+    #[test]
+    fn test_remember_without_storing() {
+        let mut clipboard = FakeClipboard::default();
+        let mut history = ClipboardHistory::new(5);
+        let mut poller = Poller::default();
+
+        // Captures stored elsewhere, in the order they were found
+        let mut captures = Vec::new();
+        for text in ["first", "second", "third"] {
+            clipboard.copy(ClipboardItem::Text(text.to_string()));
+            let capture = poller.check(&mut clipboard).unwrap();
+            poller.remember(&capture);
+            assert_eq!(poller.check(&mut clipboard), None);
+            captures.push(capture);
+        }
+
+        for capture in captures {
+            capture.add_to(&mut history);
+        }
+        assert_eq!(
+            history.get_items(),
+            &VecDeque::from([
+                ClipboardItem::Text("third".to_string()),
+                ClipboardItem::Text("second".to_string()),
+                ClipboardItem::Text("first".to_string()),
+            ])
+        );
+        assert!(history.get_meta()[0].copied_at >= history.get_meta()[2].copied_at);
+    }
+
+    #[test]
+    fn test_selection_tag() {
+        let mut clipboard = FakeClipboard::default();
+        let mut history = ClipboardHistory::new(5);
+        let mut poller = Poller::default().with_selection(Selection::Primary);
+
+        let item = ClipboardItem::Text("Selected".to_string());
+        clipboard.copy(item.clone());
+        assert!(poller.poll_once(&mut clipboard, &mut history));
+        assert_eq!(poller.last_item(), &item);
+        assert_eq!(history.get_meta()[0].selection, Selection::Primary);
+
+        // Copying it from the clipboard retags it
+        Poller::default().poll_once(&mut clipboard, &mut history);
+        assert_eq!(history.get_meta()[0].selection, Selection::Clipboard);
+        assert_eq!(history.get_items().len(), 1);
+    }
+
+    #[test]
+    fn test_compress_images() {
+        let mut clipboard = FakeClipboard::default();
+        let mut history = ClipboardHistory::new(5);
+        let mut poller = Poller::default().with_compress_images(true);
+
+        let image = ClipboardItem::Image {
+            width: 16,
+            height: 16,
+            bytes: vec![0; 16 * 16 * 4],
+        };
+        clipboard.copy(image.clone());
+        clipboard.html = Some("<img>".to_string());
+
+        let capture = poller.check(&mut clipboard).unwrap();
+        assert!(matches!(
+            capture.stored,
+            ClipboardItem::CompressedImage { .. }
+        ));
+        // Images never carry HTML
+        assert_eq!(capture.html, None);
+
+        poller.store(capture, &mut history);
+        assert_eq!(history.get_items()[0].pixels(), image.pixels());
+    }
+
+    #[test]
+    fn test_binary_text_skipped() {
+        let mut clipboard = FakeClipboard::default();
+        let mut history = ClipboardHistory::new(5);
+        let mut poller = Poller::default();
+
+        clipboard.copy(ClipboardItem::Text(
+            "\u{1}\u{2}\u{fffd}\u{fffd}x".to_string(),
+        ));
+        assert!(!poller.poll_once(&mut clipboard, &mut history));
+        assert!(history.get_items().is_empty());
+    }
+
+    #[test]
+    fn test_oversized_items_skipped() {
+        let mut clipboard = FakeClipboard::default();
+        let mut history = ClipboardHistory::new(5);
+        let mut poller = Poller::default().with_max_bytes(5, 16 * 4);
+
+        clipboard.copy(ClipboardItem::Text("Too long".to_string()));
+        assert!(!poller.poll_once(&mut clipboard, &mut history));
+
+        clipboard.copy(ClipboardItem::Image {
+            width: 4,
+            height: 8,
+            bytes: vec![0; 4 * 8 * 4],
+        });
+        assert!(!poller.poll_once(&mut clipboard, &mut history));
+
+        // Dimensions that overflow when multiplied count as too big, not as a panic
+        clipboard.copy(ClipboardItem::CompressedImage {
+            width: usize::MAX,
+            height: 2,
+            png: vec![0; 8],
+        });
+        assert!(!poller.poll_once(&mut clipboard, &mut history));
+        assert!(history.is_empty());
+
+        // Items at the limit still fit
+        clipboard.copy(ClipboardItem::Image {
+            width: 4,
+            height: 4,
+            bytes: vec![0; 4 * 4 * 4],
+        });
+        assert!(poller.poll_once(&mut clipboard, &mut history));
+        clipboard.copy(ClipboardItem::Text("Short".to_string()));
+        assert!(poller.poll_once(&mut clipboard, &mut history));
+        assert_eq!(history.len(), 2);
+    }
+
+    #[test]
+    fn test_oversized_html_dropped() {
+        let mut clipboard = FakeClipboard::default();
+        let poller = Poller::default().with_max_bytes(8, usize::MAX);
+
+        clipboard.copy(ClipboardItem::Text("Hello".to_string()));
+        clipboard.html = Some("<p>Hello</p>".to_string());
+
+        let capture = poller.check(&mut clipboard).unwrap();
+        assert_eq!(capture.stored, ClipboardItem::Text("Hello".to_string()));
+        assert_eq!(capture.html, None);
+    }
+
+    #[test]
+    fn test_expected_change_skipped() {
+        let mut clipboard = FakeClipboard::default();
+        let mut history = ClipboardHistory::new(5);
+        let mut poller = Poller::default();
+
+        let a = ClipboardItem::Text("a".to_string());
+        let b = ClipboardItem::Text("b".to_string());
+        clipboard.copy(a.clone());
+        poller.poll_once(&mut clipboard, &mut history);
+        clipboard.copy(b.clone());
+        poller.poll_once(&mut clipboard, &mut history);
+
+        // Hinted before the clipboard changes, like the GUI pasting an old entry
+        poller.expect(a.clipboard_hash());
+        assert!(!poller.skip_expected(&mut clipboard));
+        clipboard.copy(a.clone());
+        assert!(!poller.poll_once(&mut clipboard, &mut history));
+        assert_eq!(poller.last_item(), &a);
+        assert_eq!(history.get_items(), &VecDeque::from([b.clone(), a.clone()]));
+
+        // The hint is used up, copying a again after something else is a real copy
+        clipboard.copy(b.clone());
+        assert!(poller.poll_once(&mut clipboard, &mut history));
+        clipboard.copy(a.clone());
+        assert!(poller.poll_once(&mut clipboard, &mut history));
+        assert_eq!(history.get_items(), &VecDeque::from([a, b]));
+    }
+
+    #[test]
+    fn test_unmatched_hint_dropped() {
+        let mut clipboard = FakeClipboard::default();
+        let mut history = ClipboardHistory::new(5);
+        let mut poller = Poller::default();
+
+        let a = ClipboardItem::Text("a".to_string());
+        let b = ClipboardItem::Text("b".to_string());
+        poller.expect(a.clipboard_hash());
+
+        // Something else is copied first, it is captured and the hint is gone
+        clipboard.copy(b.clone());
+        assert!(poller.poll_once(&mut clipboard, &mut history));
+        clipboard.copy(a.clone());
+        assert!(poller.poll_once(&mut clipboard, &mut history));
+        assert_eq!(history.get_items(), &VecDeque::from([a, b]));
+    }
+
+    #[test]
+    fn test_expected_image_not_compressed_again() {
+        let mut clipboard = FakeClipboard::default();
+        let mut history = ClipboardHistory::new(5);
+        let mut poller = Poller::default().with_compress_images(true);
+
+        let image = ClipboardItem::Image {
+            width: 16,
+            height: 16,
+            bytes: vec![7; 16 * 16 * 4],
+        };
+        clipboard.copy(image.clone());
+        poller.poll_once(&mut clipboard, &mut history);
+        clipboard.copy(ClipboardItem::Text("b".to_string()));
+        poller.poll_once(&mut clipboard, &mut history);
+
+        // The entry in history is compressed, its hash still matches the raw pixels
+        let stored = history.get_items()[1].clone();
+        assert!(matches!(stored, ClipboardItem::CompressedImage { .. }));
+        poller.expect(stored.clipboard_hash());
+
+        clipboard.copy(image);
+        assert!(poller.check(&mut clipboard).is_some());
+        assert!(poller.skip_expected(&mut clipboard));
+        assert!(poller.check(&mut clipboard).is_none());
+        assert_eq!(history.get_items().len(), 2);
+    }
+}
@@ -0,0 +1,703 @@
+#![cfg(feature = "sqlite")]
+
+#[cfg(test)]
+mod sqlite_store_tests {
+    use std::{
+        collections::VecDeque,
+        time::{Duration, SystemTime},
+    };
+
+    use super_v::{
+        common::{ClipboardError, ClipboardFilter, ClipboardItem, DedupScope, Selection},
+        history::{HistoryStats, HistoryStore},
+        image_codec::encode_png,
+        sqlite_store::SqliteStore,
+    };
+
+    #[test]
+    fn test_add_and_snapshot_order() {
+        let mut store = SqliteStore::open_in_memory(5).unwrap();
+
+        let text = ClipboardItem::Text("Hello".to_string());
+        let image = ClipboardItem::Image {
+            width: 1,
+            height: 1,
+            bytes: vec![1, 2, 3, 4],
+        };
+
+        store.add(text.clone());
+        store.add(image.clone());
+
+        // Most recent first, both types round-trip
+        assert_eq!(store.snapshot().get_items(), &VecDeque::from([image, text]));
+    }
+
+    #[test]
+    fn test_stats_match_memory() {
+        let mut store = SqliteStore::open_in_memory(5).unwrap();
+        assert_eq!(store.stats(), HistoryStats::default());
+
+        store.add(ClipboardItem::Text("Hello".to_string()));
+        store.add(ClipboardItem::Image {
+            width: 1,
+            height: 1,
+            bytes: vec![1, 2, 3, 4],
+        });
+
+        // Computed in SQL, must agree with the in-memory count
+        assert_eq!(store.stats(), store.snapshot().stats());
+        assert_eq!(store.stats().bytes, 9);
+    }
+
+    #[test]
+    fn test_compressed_image_round_trip() {
+        let mut store = SqliteStore::open_in_memory(5).unwrap();
+
+        let image = ClipboardItem::CompressedImage {
+            width: 2,
+            height: 2,
+            png: encode_png(2, 2, &[7; 16]).unwrap(),
+        };
+        store.add(image.clone());
+
+        assert_eq!(store.snapshot().get_items(), &VecDeque::from([image]));
+        assert_eq!(store.stats(), store.snapshot().stats());
+        assert_eq!(store.stats().images, 1);
+    }
+
+    #[test]
+    fn test_capacity_evicts_oldest() {
+        let mut store = SqliteStore::open_in_memory(2).unwrap();
+
+        let item1 = ClipboardItem::Text("Item 1".to_string());
+        let item2 = ClipboardItem::Text("Item 2".to_string());
+        let item3 = ClipboardItem::Text("Item 3".to_string());
+
+        store.add(item1);
+        store.add(item2.clone());
+        store.add(item3.clone());
+
+        assert_eq!(
+            store.snapshot().get_items(),
+            &VecDeque::from([item3, item2])
+        );
+    }
+
+    #[test]
+    fn test_byte_budget_evicts_oldest() {
+        let mut store = SqliteStore::open_in_memory(10)
+            .unwrap()
+            .with_max_total_bytes(Some(10));
+
+        let item1 = ClipboardItem::Text("aaaa".to_string());
+        let item2 = ClipboardItem::Text("bbbb".to_string());
+        let item3 = ClipboardItem::Text("cccc".to_string());
+        let large = ClipboardItem::Image {
+            width: 2,
+            height: 2,
+            bytes: vec![0; 16],
+        };
+
+        store.add(item1);
+        store.add(item2.clone());
+        store.add(item3.clone());
+        assert_eq!(
+            store.snapshot().get_items(),
+            &VecDeque::from([item3, item2])
+        );
+
+        // Alone over budget, but the newest entry is kept
+        store.add(large.clone());
+        assert_eq!(store.snapshot().get_items(), &VecDeque::from([large]));
+    }
+
+    #[test]
+    fn test_duplicate_is_promoted() {
+        let mut store = SqliteStore::open_in_memory(5).unwrap();
+
+        let item1 = ClipboardItem::Text("Item 1".to_string());
+        let item2 = ClipboardItem::Text("Item 2".to_string());
+
+        store.add(item1.clone());
+        store.add(item2.clone());
+        store.add(item1.clone());
+
+        assert_eq!(
+            store.snapshot().get_items(),
+            &VecDeque::from([item1, item2])
+        );
+    }
+
+    #[test]
+    fn test_old_duplicate_outside_dedup_window() {
+        let window = Duration::from_secs(60);
+        let mut store = SqliteStore::open_in_memory(5)
+            .unwrap()
+            .with_dedup_window(Some(window));
+        let now = SystemTime::now();
+
+        let item1 = ClipboardItem::Text("Item 1".to_string());
+        let item2 = ClipboardItem::Text("Item 2".to_string());
+
+        store.add_at(item1.clone(), now - Duration::from_secs(120));
+        store.add_at(item2.clone(), now - Duration::from_secs(30));
+        store.add_at(item1.clone(), now);
+
+        // The old copy is kept, and survives the round trip through the snapshot
+        assert_eq!(
+            store.snapshot().get_items(),
+            &VecDeque::from([item1.clone(), item2.clone(), item1.clone()])
+        );
+
+        // Recent duplicates still collapse
+        store.add_at(item2.clone(), now);
+        assert_eq!(
+            store.snapshot().get_items(),
+            &VecDeque::from([item2, item1.clone(), item1])
+        );
+    }
+
+    #[test]
+    fn test_copy_count() {
+        let mut store = SqliteStore::open_in_memory(5).unwrap();
+
+        let item1 = ClipboardItem::Text("Item 1".to_string());
+        let item2 = ClipboardItem::Text("Item 2".to_string());
+
+        store.add(item1.clone());
+        store.add(item2.clone());
+        store.add(item1.clone());
+        store.add(item1.clone());
+
+        let snapshot = store.snapshot();
+        assert_eq!(snapshot.get_meta()[0].copy_count, 3);
+        assert_eq!(snapshot.get_meta()[1].copy_count, 1);
+
+        // Kept when every row is rewritten
+        store.promote(1).unwrap();
+        assert_eq!(store.snapshot().get_meta()[1].copy_count, 3);
+    }
+
+    #[test]
+    fn test_promote_this_and_move_report_index() {
+        let mut store = SqliteStore::open_in_memory(5).unwrap();
+        for text in ["a", "b", "c"] {
+            store.add(ClipboardItem::Text(text.to_string()));
+        }
+        let a = ClipboardItem::Text("a".to_string());
+
+        // c,b,a -> a,c,b
+        let index = store.promote_this(a.clone()).unwrap();
+        assert_eq!(store.snapshot().position(&a), Some(index));
+
+        // a,c,b -> c,b,a
+        let index = store.move_to(0, 2).unwrap();
+        assert_eq!(store.snapshot().position(&a), Some(index));
+    }
+
+    #[test]
+    fn test_promote_delete_and_clear() {
+        let mut store = SqliteStore::open_in_memory(5).unwrap();
+
+        let item1 = ClipboardItem::Text("Item 1".to_string());
+        let item2 = ClipboardItem::Text("Item 2".to_string());
+        let item3 = ClipboardItem::Text("Item 3".to_string());
+
+        store.add(item1.clone());
+        store.add(item2.clone());
+        store.add(item3.clone());
+
+        // 3,2,1 -> 1,3,2
+        assert_eq!(store.promote(2), Ok(0));
+        assert_eq!(
+            store.snapshot().get_items(),
+            &VecDeque::from([item1.clone(), item3.clone(), item2.clone()])
+        );
+
+        // 1,3,2 -> 1,2
+        assert_eq!(store.delete(1), Ok(item3.clone()));
+        assert_eq!(
+            store.snapshot().get_items(),
+            &VecDeque::from([item1.clone(), item2.clone()])
+        );
+
+        // 1,2 -> 2
+        store.delete_this(item1).unwrap();
+        assert_eq!(store.snapshot().get_items(), &VecDeque::from([item2]));
+
+        store.clear();
+        assert!(store.snapshot().get_items().is_empty());
+    }
+
+    #[test]
+    fn test_delete_range() {
+        let mut store = SqliteStore::open_in_memory(5).unwrap();
+        for i in (0..5).rev() {
+            store.add(ClipboardItem::Text(i.to_string()));
+        }
+
+        // Keep the top two: 0,1,2,3,4 -> 0,1
+        store.delete_range(2, 5).unwrap();
+        assert_eq!(
+            store.snapshot().get_items(),
+            &VecDeque::from([
+                ClipboardItem::Text("0".to_string()),
+                ClipboardItem::Text("1".to_string()),
+            ])
+        );
+
+        assert_eq!(
+            store.delete_range(0, 3),
+            Err(ClipboardError::IndexOutOfBound)
+        );
+    }
+
+    #[test]
+    fn test_out_of_bounds_errors() {
+        let mut store = SqliteStore::open_in_memory(5).unwrap();
+        store.add(ClipboardItem::Text("Item".to_string()));
+
+        assert_eq!(store.promote(5), Err(ClipboardError::IndexOutOfBound));
+        assert_eq!(store.delete(5), Err(ClipboardError::IndexOutOfBound));
+        assert_eq!(
+            store.delete_this(ClipboardItem::Text("Missing".to_string())),
+            Err(ClipboardError::IndexOutOfBound)
+        );
+    }
+
+    #[test]
+    fn test_remove_expired() {
+        let mut store = SqliteStore::open_in_memory(5).unwrap();
+        let now = SystemTime::now();
+
+        let old = ClipboardItem::Text("Old".to_string());
+        let fresh = ClipboardItem::Text("Fresh".to_string());
+
+        store.add_at(old, now - Duration::from_secs(600));
+        store.add_at(fresh.clone(), now);
+
+        assert_eq!(store.remove_expired(Duration::from_secs(60), now), 1);
+        assert_eq!(store.snapshot().get_items(), &VecDeque::from([fresh]));
+    }
+
+    #[test]
+    fn test_ocr_text_round_trip() {
+        let mut store = SqliteStore::open_in_memory(5).unwrap();
+
+        let image = ClipboardItem::Image {
+            width: 1,
+            height: 1,
+            bytes: vec![0u8; 4],
+        };
+        store.add(image.clone());
+        store.set_ocr_text(&image, "Hello".to_string()).unwrap();
+        store.add(ClipboardItem::Text("Item".to_string()));
+
+        let snapshot = store.snapshot();
+        assert_eq!(snapshot.get_meta()[1].ocr_text.as_deref(), Some("Hello"));
+        assert_eq!(snapshot.get_meta()[0].ocr_text, None);
+
+        // Survives a rewrite of every row
+        store.promote(1).unwrap();
+        assert_eq!(
+            store.snapshot().get_meta()[0].ocr_text.as_deref(),
+            Some("Hello")
+        );
+    }
+
+    #[test]
+    fn test_html_is_stored() {
+        let mut store = SqliteStore::open_in_memory(5).unwrap();
+
+        let text = ClipboardItem::Text("Hello".to_string());
+        store.add(text.clone());
+        store
+            .set_html(0, &text, Some("<b>Hello</b>".to_string()))
+            .unwrap();
+        store.add(ClipboardItem::Text("Item".to_string()));
+
+        // Survives a rewrite of every row, and a delete that is undone
+        store.promote(1).unwrap();
+        store.delete(0).unwrap();
+        store.undo().unwrap();
+        assert_eq!(
+            store.snapshot().get_meta()[0].html.as_deref(),
+            Some("<b>Hello</b>")
+        );
+    }
+
+    #[test]
+    fn test_selection_is_stored() {
+        let mut store = SqliteStore::open_in_memory(5).unwrap();
+
+        let text = ClipboardItem::Text("Selected".to_string());
+        store.add(text.clone());
+        store.set_selection(0, &text, Selection::Primary).unwrap();
+        store.add(ClipboardItem::Text("Copied".to_string()));
+
+        // Survives a rewrite of every row
+        store.promote(1).unwrap();
+        let snapshot = store.snapshot();
+        assert_eq!(snapshot.get_meta()[0].selection, Selection::Primary);
+        assert_eq!(snapshot.get_meta()[1].selection, Selection::Clipboard);
+    }
+
+    #[test]
+    fn test_adds_new_columns_to_old_database() {
+        let path = std::env::temp_dir().join(format!("super_v_test_old_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        // Schema from before OCR support
+        {
+            let conn = rusqlite::Connection::open(&path).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE history (
+                    id        INTEGER PRIMARY KEY AUTOINCREMENT,
+                    seq       INTEGER NOT NULL,
+                    kind      TEXT    NOT NULL,
+                    content   BLOB    NOT NULL,
+                    width     INTEGER NOT NULL DEFAULT 0,
+                    height    INTEGER NOT NULL DEFAULT 0,
+                    copied_at INTEGER NOT NULL
+                );
+                INSERT INTO history (seq, kind, content, copied_at) VALUES (1, 'text', X'4F6C64', 0);",
+            )
+            .unwrap();
+        }
+
+        let store = SqliteStore::open(&path, 5).unwrap();
+        let snapshot = store.snapshot();
+        assert_eq!(
+            snapshot.get_items(),
+            &VecDeque::from([ClipboardItem::Text("Old".to_string())])
+        );
+        assert_eq!(snapshot.get_meta()[0].ocr_text, None);
+        assert_eq!(snapshot.get_meta()[0].copy_count, 1);
+        assert_eq!(snapshot.get_meta()[0].html, None);
+        assert_eq!(snapshot.get_meta()[0].selection, Selection::Clipboard);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_persists_across_reopen() {
+        let path = std::env::temp_dir().join(format!("super_v_test_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let item = ClipboardItem::Text("Persisted".to_string());
+        {
+            let mut store = SqliteStore::open(&path, 5).unwrap();
+            store.add(item.clone());
+        }
+
+        let store = SqliteStore::open(&path, 5).unwrap();
+        assert_eq!(store.snapshot().get_items(), &VecDeque::from([item]));
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_undo_delete_and_clear() {
+        let mut store = SqliteStore::open_in_memory(5).unwrap();
+        let item1 = ClipboardItem::Text("Item 1".to_string());
+        let item2 = ClipboardItem::Text("Item 2".to_string());
+        let item3 = ClipboardItem::Text("Item 3".to_string());
+        store.add(item1.clone());
+        store.add(item2.clone());
+        store.add(item2.clone());
+        store.add(item3.clone());
+        let full = store.snapshot();
+
+        // 3,2,1 -> 3,1 -> [] -> 3,1 -> 3,2,1
+        store.delete_this(item2).unwrap();
+        store.clear();
+        store.undo().unwrap();
+        assert_eq!(
+            store.snapshot().get_items(),
+            &VecDeque::from([item3, item1])
+        );
+        store.undo().unwrap();
+        assert_eq!(store.snapshot().get_items(), full.get_items());
+        assert_eq!(store.snapshot().get_meta(), full.get_meta());
+
+        assert_eq!(store.undo(), Err(ClipboardError::NothingToUndo));
+    }
+
+    #[test]
+    fn test_clear_matching_and_undo() {
+        let mut store = SqliteStore::open_in_memory(5).unwrap();
+        let text = ClipboardItem::Text("Text".to_string());
+        let image = ClipboardItem::Image {
+            width: 1,
+            height: 1,
+            bytes: vec![1, 2, 3, 4],
+        };
+        store.add(image.clone());
+        store.add(text.clone());
+        let full = store.snapshot();
+
+        assert_eq!(store.clear_matching(ClipboardFilter::Images), 1);
+        assert_eq!(store.snapshot().get_items(), &VecDeque::from([text]));
+
+        store.undo().unwrap();
+        assert_eq!(store.snapshot().get_items(), full.get_items());
+    }
+
+    #[test]
+    fn test_undo_forgotten_after_add() {
+        let mut store = SqliteStore::open_in_memory(5).unwrap();
+        store.add(ClipboardItem::Text("Item 1".to_string()));
+        store.add(ClipboardItem::Text("Item 2".to_string()));
+
+        store.delete_range(0, 1).unwrap();
+        store.add(ClipboardItem::Text("Item 3".to_string()));
+        assert_eq!(store.undo(), Err(ClipboardError::NothingToUndo));
+    }
+
+    #[test]
+    fn test_snapshot_range() {
+        let mut store = SqliteStore::open_in_memory(10).unwrap();
+        for n in 1..=5 {
+            store.add(ClipboardItem::Text(format!("Item {n}")));
+        }
+
+        let (page, total) = store.snapshot_range(1, 2);
+        assert_eq!(total, 5);
+        assert_eq!(
+            page.get_items(),
+            &VecDeque::from([
+                ClipboardItem::Text("Item 4".to_string()),
+                ClipboardItem::Text("Item 3".to_string()),
+            ])
+        );
+
+        let (page, total) = store.snapshot_range(4, 10);
+        assert_eq!(total, 5);
+        assert_eq!(
+            page.get_items(),
+            &VecDeque::from([ClipboardItem::Text("Item 1".to_string())])
+        );
+        assert!(store.snapshot_range(9, 10).0.get_items().is_empty());
+    }
+
+    #[test]
+    fn test_query_filters_and_sorts_in_sql() {
+        let text = |s: &str| ClipboardItem::Text(s.to_string());
+        let image = ClipboardItem::Image {
+            width: 1,
+            height: 1,
+            bytes: vec![1, 2, 3, 4],
+        };
+
+        let mut store = SqliteStore::open_in_memory(10).unwrap();
+        store.add(text("old"));
+        store.add(text("old"));
+        store.add(image.clone());
+        store.add(text("new"));
+        // new,image,old(x2)
+
+        let (page, positions, total) = store.query(0, 2, ClipboardFilter::All, true);
+        assert_eq!(
+            page.get_items(),
+            &VecDeque::from([text("old"), text("new")])
+        );
+        assert_eq!(page.get_meta()[0].copy_count, 2);
+        assert_eq!(positions, vec![2, 0]);
+        assert_eq!(total, 3);
+
+        let (page, positions, total) = store.query(0, 10, ClipboardFilter::Images, false);
+        assert_eq!(page.get_items(), &VecDeque::from([image]));
+        assert_eq!(positions, vec![1]);
+        assert_eq!(total, 1);
+
+        let (page, positions, total) = store.query(1, 10, ClipboardFilter::Text, false);
+        assert_eq!(page.get_items(), &VecDeque::from([text("old")]));
+        assert_eq!(positions, vec![2]);
+        assert_eq!(total, 2);
+
+        // Same answer as the in-memory history
+        assert_eq!(
+            store.query(0, 10, ClipboardFilter::All, true),
+            store.snapshot().query(0, 10, ClipboardFilter::All, true)
+        );
+    }
+
+    #[test]
+    fn test_search_in_sql() {
+        let text = |s: &str| ClipboardItem::Text(s.to_string());
+        let image = ClipboardItem::Image {
+            width: 1,
+            height: 1,
+            bytes: vec![1, 2, 3, 4],
+        };
+
+        let mut store = SqliteStore::open_in_memory(10).unwrap();
+        store.add(image.clone());
+        store.set_ocr_text(&image, "Invoice total".into()).unwrap();
+        store.add(text("invoice.pdf"));
+        store.add(text("password"));
+        store.add(text("INVOICE draft"));
+        store.mark_sensitive(1).unwrap();
+        // INVOICE draft, password (sensitive), invoice.pdf, image
+
+        let (page, positions, total) = store.search("Invoice", 1, 10);
+        assert_eq!(
+            page.get_items(),
+            &VecDeque::from([text("invoice.pdf"), image])
+        );
+        assert_eq!(positions, vec![2, 3]);
+        assert_eq!(total, 3);
+
+        // Same answer as the in-memory history
+        for query in ["invoice", "pass", "draft", "", "nothing"] {
+            assert_eq!(
+                store.search(query, 0, 10),
+                store.snapshot().search(query, 0, 10),
+                "{query}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_dedup_scope() {
+        let image = ClipboardItem::Image {
+            width: 1,
+            height: 1,
+            bytes: vec![1, 2, 3, 4],
+        };
+        let text = ClipboardItem::Text("Hello".to_string());
+
+        let mut store = SqliteStore::open_in_memory(10)
+            .unwrap()
+            .with_dedup_scope(DedupScope::Text);
+        store.add(image.clone());
+        store.add(text.clone());
+        store.add(image.clone());
+        store.add(text.clone());
+
+        assert_eq!(
+            store.snapshot().get_items(),
+            &VecDeque::from([text, image.clone(), image])
+        );
+        assert_eq!(store.snapshot().get_meta()[0].copy_count, 2);
+    }
+
+    #[test]
+    fn test_delete_this_removes_only_first_duplicate() {
+        let a = ClipboardItem::Text("a".to_string());
+        let b = ClipboardItem::Text("b".to_string());
+
+        let mut store = SqliteStore::open_in_memory(5)
+            .unwrap()
+            .with_dedup_scope(DedupScope::Off);
+        store.add(a.clone());
+        store.add(b.clone());
+        store.add(a.clone());
+
+        // a,b,a -> b,a
+        store.delete_this(a.clone()).unwrap();
+        assert_eq!(
+            store.snapshot().get_items(),
+            &VecDeque::from([b.clone(), a.clone()])
+        );
+
+        // The older copy is still found once the newer one is gone
+        store.delete_this(a.clone()).unwrap();
+        assert_eq!(store.snapshot().get_items(), &VecDeque::from([b]));
+        assert_eq!(store.delete_this(a), Err(ClipboardError::IndexOutOfBound));
+    }
+
+    #[test]
+    fn test_delete_at_picks_the_given_duplicate() {
+        let a = ClipboardItem::Text("a".to_string());
+        let b = ClipboardItem::Text("b".to_string());
+
+        let mut store = SqliteStore::open_in_memory(5)
+            .unwrap()
+            .with_dedup_scope(DedupScope::Off);
+        store.add(a.clone());
+        store.add(b.clone());
+        store.add(a.clone());
+
+        // a,b,a -> a,b
+        store.delete_at(2, &a).unwrap();
+        assert_eq!(
+            store.snapshot().get_items(),
+            &VecDeque::from([a.clone(), b.clone()])
+        );
+
+        // Shifted by a new copy, b is still found
+        store.delete_at(0, &b).unwrap();
+        assert_eq!(store.snapshot().get_items(), &VecDeque::from([a.clone()]));
+        assert_eq!(store.delete_at(0, &b), Err(ClipboardError::IndexOutOfBound));
+    }
+
+    #[test]
+    fn test_sensitive_entry_stays_off_disk() {
+        let path =
+            std::env::temp_dir().join(format!("super_v_test_sensitive_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let kept = ClipboardItem::Text("Kept".to_string());
+        let secret = ClipboardItem::Text("hunter2-password".to_string());
+        {
+            let mut store = SqliteStore::open(&path, 5).unwrap();
+            store.add(kept.clone());
+            store.add(secret.clone());
+            store
+                .set_html(0, &secret, Some("<b>hunter2</b>".into()))
+                .unwrap();
+            store.mark_sensitive(0).unwrap();
+
+            // Still usable while the store is open, and in place
+            let snapshot = store.snapshot();
+            assert_eq!(
+                snapshot.get_items(),
+                &VecDeque::from([secret.clone(), kept.clone()])
+            );
+            assert!(snapshot.get_meta()[0].sensitive);
+            assert_eq!(
+                snapshot.get_meta()[0].html.as_deref(),
+                Some("<b>hunter2</b>")
+            );
+
+            // Copying it again promotes it and keeps it off disk
+            store.add(kept.clone());
+            store.add(secret.clone());
+            assert_eq!(store.snapshot().get_meta()[0].copy_count, 2);
+
+            // A rewrite keeps it sensitive too
+            store.move_to(0, 1).unwrap();
+            let snapshot = store.snapshot();
+            assert_eq!(snapshot.get_items()[1], secret);
+            assert!(snapshot.get_meta()[1].sensitive);
+
+            let file = std::fs::read(&path).unwrap();
+            assert!(!file.windows(7).any(|window| window == b"hunter2"));
+        }
+
+        // Never stopped cleanly, so the blank row is still there until the reopen
+        let store = SqliteStore::open(&path, 5).unwrap();
+        assert_eq!(store.snapshot().get_items(), &VecDeque::from([kept]));
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_drop_sensitive() {
+        let mut store = SqliteStore::open_in_memory(5).unwrap();
+        store.add(ClipboardItem::Text("a".to_string()));
+        store.add(ClipboardItem::Text("b".to_string()));
+        store.mark_sensitive(1).unwrap();
+
+        assert_eq!(
+            store.mark_sensitive(2),
+            Err(ClipboardError::IndexOutOfBound)
+        );
+        assert_eq!(store.drop_sensitive(), 1);
+        assert_eq!(
+            store.snapshot().get_items(),
+            &VecDeque::from([ClipboardItem::Text("b".to_string())])
+        );
+    }
+}
@@ -0,0 +1,12 @@
+#[cfg(test)]
+mod ydotool_tests {
+    use super_v::services::ydotool::find_program;
+
+    #[test]
+    fn test_find_program() {
+        let sh = find_program("sh").expect("sh should be on PATH");
+        assert!(sh.ends_with("sh"));
+
+        assert_eq!(find_program("super_v-no-such-program"), None);
+    }
+}